@@ -0,0 +1,11 @@
+CREATE TABLE issue_delivery_queue(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    -- How many times delivering this email has failed with a transient error.
+    n_retries INT NOT NULL DEFAULT 0,
+    -- Retries are postponed with exponential backoff. The worker won't pick up
+    -- the task before this point in time.
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
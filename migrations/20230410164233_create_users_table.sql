@@ -0,0 +1,10 @@
+CREATE TABLE users(
+    user_id uuid NOT NULL PRIMARY KEY,
+    username TEXT COLLATE "case_insensitive" NOT NULL UNIQUE,
+    -- An Argon2id hash in PHC string format, which embeds the parameters and salt.
+    password_hash TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    updated_at timestamptz
+);
+
+SELECT trigger_updated_at('users');
@@ -0,0 +1,8 @@
+-- The initial admin account. Its password is "everythinghastostartsomewhere";
+-- change it as soon as the app is deployed.
+INSERT INTO users (user_id, username, password_hash)
+VALUES (
+    'ddf8994f-d522-4659-8d02-c1d479057be6',
+    'admin',
+    '$argon2id$v=19$m=15000,t=2,p=1$yTylIwSoJUTL4ma5NTdZWw$kqlSXnAz+KGL7ZD9S7ftc7pSqLbklCF1uqX5jbNCeXA'
+);
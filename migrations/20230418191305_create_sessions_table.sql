@@ -0,0 +1,7 @@
+CREATE TABLE sessions(
+    session_id TEXT NOT NULL PRIMARY KEY,
+    data JSONB NOT NULL,
+    expires_at timestamptz NOT NULL
+);
+
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
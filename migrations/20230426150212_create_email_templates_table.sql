@@ -0,0 +1,7 @@
+CREATE TABLE email_templates(
+    name TEXT NOT NULL PRIMARY KEY,
+    html_source TEXT NOT NULL,
+    text_source TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    updated_at timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,2 @@
+-- Templates without a plain text source get one derived from their HTML.
+ALTER TABLE email_templates ALTER COLUMN text_source DROP NOT NULL;
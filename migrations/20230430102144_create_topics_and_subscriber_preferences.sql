@@ -0,0 +1,22 @@
+CREATE TABLE topics(
+    slug TEXT NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    updated_at timestamptz NOT NULL DEFAULT now()
+);
+
+-- Subscribers receive issues on every topic, including ones created after they
+-- subscribed, unless they opt out of it.
+CREATE TABLE topic_opt_outs(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    topic_slug TEXT NOT NULL REFERENCES topics (slug) ON DELETE CASCADE,
+    PRIMARY KEY (subscriber_id, topic_slug)
+);
+
+-- Issues without a topic go to every subscriber.
+ALTER TABLE newsletter_issues
+    ADD COLUMN topic_slug TEXT NULL REFERENCES topics (slug);
+
+ALTER TABLE subscriptions
+    ADD COLUMN digest_frequency TEXT NOT NULL DEFAULT 'immediately'
+    CHECK (digest_frequency IN ('immediately', 'daily', 'weekly'));
@@ -0,0 +1,26 @@
+CREATE TABLE lists(
+    slug TEXT NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    updated_at timestamptz NOT NULL DEFAULT now()
+);
+
+-- The `status` of `subscriptions` remains the subscriber's status for issues
+-- published without a list. Subscribers who only joined lists keep it at
+-- `pending_confirmation`.
+CREATE TABLE list_subscriptions(
+    list_slug TEXT NOT NULL REFERENCES lists (slug) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    status TEXT NOT NULL,
+    subscribed_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (list_slug, subscriber_id)
+);
+
+-- Tokens with a list confirm the subscription to that list only.
+ALTER TABLE subscription_tokens
+    ADD COLUMN list_slug TEXT NULL REFERENCES lists (slug) ON DELETE CASCADE;
+
+-- Issues without a list go to the subscribers confirmed in `subscriptions`, as
+-- before lists existed.
+ALTER TABLE newsletter_issues
+    ADD COLUMN list_slug TEXT NULL REFERENCES lists (slug);
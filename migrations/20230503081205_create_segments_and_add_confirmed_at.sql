@@ -0,0 +1,20 @@
+CREATE TABLE segments(
+    slug TEXT NOT NULL PRIMARY KEY,
+    filter TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    updated_at timestamptz NOT NULL DEFAULT now()
+);
+
+-- When the subscriber first confirmed their email address, for any list.
+ALTER TABLE subscriptions ADD COLUMN confirmed_at timestamptz NULL;
+
+-- The best we can do for subscribers who confirmed before this was recorded.
+UPDATE subscriptions
+SET confirmed_at = created_at
+WHERE status = 'confirmed'
+OR EXISTS (
+    SELECT 1
+    FROM list_subscriptions
+    WHERE list_subscriptions.subscriber_id = subscriptions.id
+    AND list_subscriptions.status = 'confirmed'
+);
@@ -0,0 +1,13 @@
+-- Issues are queued for delivery when they're published, or at `scheduled_at`
+-- by the scheduler. `enqueued_at` keeps them from being queued twice.
+ALTER TABLE newsletter_issues
+    ADD COLUMN scheduled_at timestamptz NULL,
+    ADD COLUMN enqueued_at timestamptz NULL,
+    ADD COLUMN cancelled_at timestamptz NULL,
+    -- The filter of the segment the issue was published with, as it was then.
+    ADD COLUMN segment_filter TEXT NULL;
+
+UPDATE newsletter_issues SET enqueued_at = published_at;
+
+CREATE INDEX newsletter_issues_due_index ON newsletter_issues (scheduled_at)
+    WHERE enqueued_at IS NULL AND cancelled_at IS NULL;
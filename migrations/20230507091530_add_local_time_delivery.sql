@@ -0,0 +1,9 @@
+-- An IANA time zone name, such as `Europe/Paris`.
+ALTER TABLE subscriptions ADD COLUMN time_zone TEXT NULL;
+
+-- Issues with a local delivery time are delivered when it's that time in each
+-- subscriber's time zone, or in the default time zone for subscribers without
+-- one.
+ALTER TABLE newsletter_issues
+    ADD COLUMN local_delivery_time TIMESTAMP NULL,
+    ADD COLUMN default_time_zone TEXT NULL;
@@ -0,0 +1,31 @@
+-- Issues published before tracking existed weren't tracked.
+ALTER TABLE newsletter_issues
+    ADD COLUMN tracking_enabled BOOLEAN NOT NULL DEFAULT false;
+
+-- The links of tracked issues, which point at `/t/c/{token}` in the emails.
+-- Tokens only hold the index of the link, so they can't redirect anywhere
+-- else.
+CREATE TABLE tracked_links(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    link_index INT NOT NULL,
+    url TEXT NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, link_index)
+);
+
+-- Opens and clicks. Those that look automated, like the image prefetching of
+-- Apple Mail Privacy Protection, are kept with the reason in `bot_reason`.
+CREATE TABLE engagement_events(
+    id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL CHECK (kind IN ('open', 'click')),
+    link_index INT NULL,
+    user_agent TEXT NULL,
+    bot_reason TEXT NULL,
+    occurred_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX engagement_events_issue_index
+    ON engagement_events (newsletter_issue_id, kind);
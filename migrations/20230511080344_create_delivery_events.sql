@@ -0,0 +1,19 @@
+-- What happened to each email of an issue: accepted by the email provider
+-- (`sent`), then `delivered` or `bounced` according to the provider.
+CREATE TABLE delivery_events(
+    id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    subscriber_email TEXT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('sent', 'delivered', 'bounced')),
+    occurred_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX delivery_events_issue_index
+    ON delivery_events (newsletter_issue_id, kind);
+
+-- Unsubscribes from the link in an issue are attributed to it.
+ALTER TABLE engagement_events
+    DROP CONSTRAINT engagement_events_kind_check,
+    ADD CONSTRAINT engagement_events_kind_check
+        CHECK (kind IN ('open', 'click', 'unsubscribe'));
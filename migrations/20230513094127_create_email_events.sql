@@ -0,0 +1,27 @@
+-- Addresses that hard bounced or complained about spam aren't emailed anymore.
+ALTER TABLE subscriptions
+    ADD COLUMN suppressed_at timestamptz NULL,
+    ADD COLUMN suppression_reason TEXT NULL;
+
+-- What the email providers reported about each address through their webhooks.
+CREATE TABLE email_events(
+    id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    provider TEXT NOT NULL,
+    -- Providers retry their requests, so events with an ID are only kept once.
+    provider_event_id TEXT NULL,
+    subscriber_email TEXT COLLATE "case_insensitive" NOT NULL,
+    kind TEXT NOT NULL CHECK (
+        kind IN ('delivered', 'hard_bounce', 'soft_bounce', 'complaint', 'dropped')
+    ),
+    reason TEXT NULL,
+    occurred_at timestamptz NOT NULL,
+    received_at timestamptz NOT NULL DEFAULT now(),
+    UNIQUE (provider, provider_event_id)
+);
+
+CREATE INDEX email_events_subscriber_email_index
+    ON email_events (subscriber_email);
+
+-- Deliveries and bounces are attributed to the last issue sent to the address.
+CREATE INDEX delivery_events_subscriber_email_index
+    ON delivery_events (lower(subscriber_email));
@@ -0,0 +1,33 @@
+-- Addresses that aren't emailed at all, whatever their subscriptions.
+CREATE TABLE suppressions(
+    email TEXT COLLATE "case_insensitive" PRIMARY KEY,
+    reason TEXT NOT NULL,
+    -- `admin`, `import` or the email provider that reported the address.
+    source TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+-- Suppressions used to be kept with the subscriptions, and always came from a
+-- webhook.
+INSERT INTO suppressions (email, reason, source, created_at)
+SELECT
+    subscriptions.email,
+    subscriptions.suppression_reason,
+    COALESCE(
+        (
+            SELECT provider
+            FROM email_events
+            WHERE email_events.subscriber_email = subscriptions.email
+            AND email_events.kind = subscriptions.suppression_reason
+            ORDER BY occurred_at DESC
+            LIMIT 1
+        ),
+        'webhook'
+    ),
+    subscriptions.suppressed_at
+FROM subscriptions
+WHERE subscriptions.suppressed_at IS NOT NULL;
+
+ALTER TABLE subscriptions
+    DROP COLUMN suppressed_at,
+    DROP COLUMN suppression_reason;
@@ -0,0 +1,2 @@
+-- Labels brought over from other tools by imports.
+ALTER TABLE subscriptions ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
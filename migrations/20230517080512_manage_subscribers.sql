@@ -0,0 +1,8 @@
+-- Subscribers are listed by creation time, a page after another.
+CREATE INDEX subscriptions_created_at_id_index ON subscriptions (created_at, id);
+
+-- Deleting a subscriber deletes their pending confirmations.
+ALTER TABLE subscription_tokens
+    DROP CONSTRAINT subscription_tokens_subscriber_id_fkey,
+    ADD CONSTRAINT subscription_tokens_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
@@ -0,0 +1,3 @@
+-- Issues are held back until the start of the next day or week, but still
+-- sent one by one, so the setting is a delivery window rather than a digest.
+ALTER TABLE subscriptions RENAME COLUMN digest_frequency TO delivery_window;
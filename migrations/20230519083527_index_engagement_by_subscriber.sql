@@ -0,0 +1,7 @@
+-- Segments look up the last issues sent to each subscriber, and whether they
+-- opened them.
+CREATE INDEX delivery_events_sent_index
+    ON delivery_events (subscriber_email, occurred_at) WHERE kind = 'sent';
+
+CREATE INDEX engagement_events_subscriber_index
+    ON engagement_events (subscriber_id, newsletter_issue_id);
@@ -0,0 +1,11 @@
+-- Mailgun only signs a timestamp and a random token, not the event, so the
+-- tokens of accepted requests are kept to turn away replays with other events.
+-- They're only needed while their signature is recent enough to be accepted.
+CREATE TABLE used_webhook_tokens(
+    provider TEXT NOT NULL,
+    token TEXT NOT NULL,
+    used_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (provider, token)
+);
+
+CREATE INDEX used_webhook_tokens_used_at_index ON used_webhook_tokens (used_at);
@@ -0,0 +1,5 @@
+-- The seeded admin account had a well-known password. Drop it unless that
+-- password was changed; the first admin now comes from the configuration.
+DELETE FROM users
+WHERE user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6'
+    AND password_hash = '$argon2id$v=19$m=15000,t=2,p=1$yTylIwSoJUTL4ma5NTdZWw$kqlSXnAz+KGL7ZD9S7ftc7pSqLbklCF1uqX5jbNCeXA';
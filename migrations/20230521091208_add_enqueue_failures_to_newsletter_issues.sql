@@ -0,0 +1,13 @@
+-- Issues that fail to be queued up are retried later so they don't hold back
+-- the other due issues, and given up on after too many failures.
+ALTER TABLE newsletter_issues
+    ADD COLUMN n_enqueue_failures INT NOT NULL DEFAULT 0,
+    ADD COLUMN enqueue_error TEXT NULL,
+    ADD COLUMN enqueue_retry_after timestamptz NULL,
+    ADD COLUMN enqueue_failed_at timestamptz NULL;
+
+DROP INDEX newsletter_issues_due_index;
+CREATE INDEX newsletter_issues_due_index ON newsletter_issues (scheduled_at)
+    WHERE enqueued_at IS NULL
+    AND cancelled_at IS NULL
+    AND enqueue_failed_at IS NULL;
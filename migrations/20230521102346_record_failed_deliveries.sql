@@ -0,0 +1,7 @@
+-- Emails the worker gave up on, because the provider rejected them or they
+-- failed too many times, with the error they last failed with.
+ALTER TABLE delivery_events
+    ADD COLUMN error TEXT NULL,
+    DROP CONSTRAINT delivery_events_kind_check,
+    ADD CONSTRAINT delivery_events_kind_check
+        CHECK (kind IN ('sent', 'delivered', 'bounced', 'failed'));
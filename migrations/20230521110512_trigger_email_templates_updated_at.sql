@@ -0,0 +1 @@
+SELECT trigger_updated_at('email_templates');
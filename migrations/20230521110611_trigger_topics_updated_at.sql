@@ -0,0 +1 @@
+SELECT trigger_updated_at('topics');
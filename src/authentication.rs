@@ -0,0 +1,227 @@
+use crate::domain::NewPassword;
+use anyhow::Context;
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash,
+    PasswordHasher, PasswordVerifier, Version,
+};
+use inspect_error::InspectError;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use tracing::error;
+use uuid::Uuid;
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("invalid credentials")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Check the credentials against the `users` table and return the ID of the
+/// user they belong to.
+///
+/// The password hash is verified even if the user doesn't exist (against a
+/// dummy hash) so that response times don't reveal which usernames are valid.
+///
+/// # Errors
+///
+/// Will return [`AuthError::InvalidCredentials`] if the username is unknown or
+/// the password doesn't match, and [`AuthError::UnexpectedError`] if something
+/// else goes wrong.
+#[tracing::instrument(name = "Validating credentials", skip_all)]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    connection_pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let mut user_id = None;
+    let mut expected_password_hash = Secret::new(
+        "$argon2id$v=19$m=15000,t=2,p=1$\
+        gZiV/M1gPc22ElAH/Jh1Hw$\
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+            .to_string(),
+    );
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, connection_pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, &credentials.password)
+    })
+    .await
+    .context("failed to spawn a blocking task")??;
+
+    // This is only `None` if the username wasn't found, in which case the
+    // password verification above must have failed against the dummy hash.
+    user_id
+        .ok_or_else(|| anyhow::anyhow!("unknown username"))
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "Getting stored credentials", skip_all)]
+async fn get_stored_credentials(
+    username: &str,
+    connection_pool: &PgPool,
+) -> anyhow::Result<Option<(Uuid, Secret<String>)>> {
+    let row = sqlx::query!(
+        "SELECT user_id, password_hash FROM users WHERE username = $1",
+        username,
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))
+    .context("failed to retrieve the stored credentials")?
+    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+
+    Ok(row)
+}
+
+#[tracing::instrument(name = "Verifying the password hash", skip_all)]
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: &Secret<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash =
+        PasswordHash::new(expected_password_hash.expose_secret())
+            .context("failed to parse the hash in PHC string format")?;
+
+    // Argon2's verification is constant-time with respect to the candidate.
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("invalid password")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+/// Replace the user's password with a new one.
+///
+/// # Errors
+///
+/// Will return an error if hashing the password or updating the database fails.
+#[tracing::instrument(name = "Changing the password", skip_all)]
+pub async fn change_password(
+    user_id: Uuid,
+    password: Secret<String>,
+    connection_pool: &PgPool,
+) -> anyhow::Result<()> {
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(&password))
+            .await
+            .context("failed to spawn a blocking task")??;
+
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))
+    .context("failed to change the user's password in the database")?;
+
+    Ok(())
+}
+
+/// Create a user with the given credentials, unless there are users already.
+/// Returns whether the user was created.
+///
+/// # Errors
+///
+/// Will return an error if the password is too weak, or hashing it or saving
+/// the user fails.
+#[tracing::instrument(
+    name = "Creating the first user",
+    skip(password, connection_pool)
+)]
+pub async fn create_first_user(
+    username: &str,
+    password: &Secret<String>,
+    connection_pool: &PgPool,
+) -> anyhow::Result<bool> {
+    let password = NewPassword::parse(password.expose_secret().clone())
+        .context("the password is too weak")?;
+    let password = Secret::from(password);
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(&password))
+            .await
+            .context("failed to spawn a blocking task")??;
+
+    // Several instances may start at once, hence the conflict handling.
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash)
+        SELECT $1, $2, $3
+        WHERE NOT EXISTS (SELECT 1 FROM users)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        username,
+        password_hash.expose_secret()
+    )
+    .execute(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))
+    .context("failed to save the user in the database")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Get the username of the user with the given ID.
+///
+/// # Errors
+///
+/// Will return an error if the user doesn't exist or the query fails.
+#[tracing::instrument(name = "Getting the username", skip_all)]
+pub async fn get_username(
+    user_id: Uuid,
+    connection_pool: &PgPool,
+) -> anyhow::Result<String> {
+    let row =
+        sqlx::query!("SELECT username FROM users WHERE user_id = $1", user_id)
+            .fetch_one(connection_pool)
+            .await
+            .inspect_error(|error| error!("Failed to execute query: {error}"))
+            .context("failed to retrieve the username")?;
+
+    Ok(row.username)
+}
+
+fn compute_password_hash(
+    password: &Secret<String>,
+) -> anyhow::Result<Secret<String>> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    // The parameters recommended by OWASP for Argon2id.
+    let params = Params::new(15000, 2, 1, None)
+        .context("failed to build the Argon2 parameters")?;
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.expose_secret().as_bytes(), &salt)
+        .context("failed to hash the password")?
+        .to_string();
+
+    Ok(Secret::new(password_hash))
+}
+
+/// Like [`tokio::task::spawn_blocking`], but the closure runs inside the
+/// caller's span. Hashing is CPU-bound, so it shouldn't block the async runtime,
+/// and neither should blocking I/O.
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
@@ -0,0 +1,22 @@
+use anyhow::Context;
+use newsletter::{
+    configuration, issue_delivery_worker::run_worker_until_stopped,
+    telemetry::init_subscriber,
+};
+use tracing::debug;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    init_subscriber(std::io::stdout);
+
+    let configuration = configuration::build()
+        .expect("app configuration should be present and valid");
+
+    debug!("Detected the following configuration: {configuration:?}");
+
+    run_worker_until_stopped(configuration)
+        .await
+        .context("something went wrong running the delivery worker")?;
+
+    Ok(())
+}
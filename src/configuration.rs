@@ -1,9 +1,16 @@
-use crate::domain::SubscriberEmail;
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{
+        EmailSender, MailgunClient, PostmarkClient, RetryingSender,
+        SendGridClient, SesClient, SmtpClient,
+    },
+};
+use anyhow::Context;
 use config::{Config, ConfigError};
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
 use sqlx::{postgres::PgConnectOptions, ConnectOptions};
-use std::{env, time::Duration};
+use std::{env, sync::Arc, time::Duration};
 use validator::ValidationErrors;
 
 /// Load the configuration for the app.
@@ -91,12 +98,31 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
+    pub delivery_worker: DeliveryWorkerSettings,
+    pub scheduler: SchedulerSettings,
+    pub session: SessionSettings,
+    pub idempotency: IdempotencySettings,
+    #[serde(default)]
+    pub webhooks: WebhookSettings,
+    #[serde(default)]
+    pub admin: Option<AdminSettings>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
+    pub base_url: String,
+    /// The key used to sign cookies and the links sent to subscribers. Must be
+    /// at least 64 bytes long.
+    pub hmac_secret: Secret<String>,
+    /// Where the email layouts and partials live (see [`crate::templates`]).
+    #[serde(default = "default_templates_directory")]
+    pub templates_directory: String,
+}
+
+fn default_templates_directory() -> String {
+    "templates".to_string()
 }
 
 #[derive(Deserialize, Debug)]
@@ -124,10 +150,21 @@ impl DatabaseSettings {
 
 #[derive(Deserialize, Debug)]
 pub struct EmailClientSettings {
+    pub provider: EmailProvider,
     pub sender_email: String,
+    /// The URL of the provider's HTTP API. Not used by SMTP.
     pub base_url: String,
+    /// The API token (SendGrid, Postmark) or key (Mailgun). Not used by SES and
+    /// SMTP, which have their own credentials.
     pub authorization_token: Secret<String>,
     pub timeout_in_milliseconds: u64,
+    pub retry: RetrySettings,
+    /// Required when `provider` is `mailgun`.
+    pub mailgun: Option<MailgunSettings>,
+    /// Required when `provider` is `ses`.
+    pub ses: Option<SesSettings>,
+    /// Required when `provider` is `smtp`.
+    pub smtp: Option<SmtpSettings>,
 }
 
 impl EmailClientSettings {
@@ -140,4 +177,319 @@ impl EmailClientSettings {
     pub const fn timeout(&self) -> Duration {
         Duration::from_millis(self.timeout_in_milliseconds)
     }
+
+    /// Build the [`EmailSender`] for the configured provider, retrying failed
+    /// attempts according to the retry settings.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the sender email is invalid or the provider's
+    /// settings are missing or invalid.
+    pub fn client(&self) -> anyhow::Result<Arc<dyn EmailSender>> {
+        let sender = self.sender().context("the sender email is invalid")?;
+        let base_url = self.base_url.clone();
+        let token = self.authorization_token.clone();
+        let timeout = self.timeout();
+
+        let client: Arc<dyn EmailSender> = match self.provider {
+            EmailProvider::SendGrid => {
+                Arc::new(SendGridClient::new(sender, base_url, token, timeout))
+            }
+            EmailProvider::Postmark => {
+                Arc::new(PostmarkClient::new(sender, base_url, token, timeout))
+            }
+            EmailProvider::Mailgun => {
+                let settings = self
+                    .mailgun
+                    .as_ref()
+                    .context("the `mailgun` settings are missing")?;
+                Arc::new(MailgunClient::new(
+                    sender,
+                    base_url,
+                    settings.domain.clone(),
+                    token,
+                    timeout,
+                ))
+            }
+            EmailProvider::Ses => {
+                let settings = self
+                    .ses
+                    .as_ref()
+                    .context("the `ses` settings are missing")?;
+                Arc::new(SesClient::new(
+                    sender,
+                    &base_url,
+                    settings.region.clone(),
+                    settings.access_key_id.clone(),
+                    settings.secret_access_key.clone(),
+                    timeout,
+                )?)
+            }
+            EmailProvider::Smtp => {
+                let settings = self
+                    .smtp
+                    .as_ref()
+                    .context("the `smtp` settings are missing")?;
+                Arc::new(
+                    SmtpClient::new(&sender, settings, timeout)
+                        .context("failed to build the SMTP client")?,
+                )
+            }
+        };
+
+        Ok(Arc::new(RetryingSender::new(client, &self.retry)))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RetrySettings {
+    /// How many times sending an email is attempted in total. `1` disables
+    /// retrying.
+    pub max_attempts: u32,
+    pub base_delay_in_milliseconds: u64,
+    /// Retries are also given up on if the provider asks (with `Retry-After`)
+    /// to wait longer than this.
+    pub max_delay_in_milliseconds: u64,
+    /// Each delay is randomly shortened by up to this fraction (`0.0` to `1.0`).
+    pub jitter_factor: f64,
+}
+
+impl RetrySettings {
+    #[must_use]
+    pub const fn base_delay(&self) -> Duration {
+        Duration::from_millis(self.base_delay_in_milliseconds)
+    }
+
+    #[must_use]
+    pub const fn max_delay(&self) -> Duration {
+        Duration::from_millis(self.max_delay_in_milliseconds)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailProvider {
+    SendGrid,
+    Postmark,
+    Mailgun,
+    Ses,
+    Smtp,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MailgunSettings {
+    /// The sending domain registered with Mailgun.
+    pub domain: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SesSettings {
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: Secret<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SmtpSettings {
+    pub host: String,
+    pub port: u16,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    /// The server is only authenticated with if both of these are set.
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    /// The mechanisms to authenticate with, in order of preference. The first
+    /// one the server supports is used.
+    #[serde(default = "default_auth_mechanisms")]
+    pub auth_mechanisms: Vec<SmtpAuthMechanism>,
+    /// How many connections are kept open to the server at most.
+    #[serde(default = "default_max_connections")]
+    pub max_connections: u32,
+    /// How long an unused connection is kept open before it's closed.
+    #[serde(default = "default_idle_timeout_in_seconds")]
+    pub idle_timeout_in_seconds: u64,
+}
+
+impl SmtpSettings {
+    #[must_use]
+    pub const fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_in_seconds)
+    }
+}
+
+/// How the connection to the SMTP server is encrypted.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain text. Only suitable for relays on a trusted network.
+    None,
+    /// Connect in plain text and upgrade the connection with `STARTTLS`,
+    /// usually on port 587. The upgrade is mandatory.
+    #[default]
+    StartTls,
+    /// Connect over TLS right away ("implicit TLS"), usually on port 465.
+    Tls,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpAuthMechanism {
+    Plain,
+    Login,
+}
+
+// The `smtp` section is optional as a whole, so its defaults can't live in
+// `base.yaml` like the rest.
+fn default_auth_mechanisms() -> Vec<SmtpAuthMechanism> {
+    vec![SmtpAuthMechanism::Plain, SmtpAuthMechanism::Login]
+}
+
+const fn default_max_connections() -> u32 {
+    10
+}
+
+const fn default_idle_timeout_in_seconds() -> u64 {
+    60
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DeliveryWorkerSettings {
+    /// Whether `App::run` should spawn the worker alongside the server. Disable
+    /// this when running the `delivery_worker` binary separately.
+    pub run_in_process: bool,
+    pub poll_interval_in_milliseconds: u64,
+    /// How many due tasks are dequeued and sent together. The email client
+    /// splits them further into chunks its provider accepts in one request.
+    pub batch_size: i64,
+    /// How many times a delivery is retried after a transient failure before
+    /// it's given up on.
+    pub max_retries: i32,
+    pub base_backoff_in_milliseconds: u64,
+    pub max_backoff_in_milliseconds: u64,
+    /// How long other workers leave dequeued tasks alone while they're being
+    /// sent. Tasks of a worker that dies are sent again after that, so it
+    /// should be longer than sending a batch takes, retries included.
+    pub lease_in_milliseconds: u64,
+}
+
+impl DeliveryWorkerSettings {
+    #[must_use]
+    pub const fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_in_milliseconds)
+    }
+
+    #[must_use]
+    pub const fn base_backoff(&self) -> Duration {
+        Duration::from_millis(self.base_backoff_in_milliseconds)
+    }
+
+    #[must_use]
+    pub const fn max_backoff(&self) -> Duration {
+        Duration::from_millis(self.max_backoff_in_milliseconds)
+    }
+
+    #[must_use]
+    pub const fn lease(&self) -> Duration {
+        Duration::from_millis(self.lease_in_milliseconds)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SchedulerSettings {
+    /// Whether `App::run` should spawn the scheduler alongside the server.
+    /// Disable this when running the `issue_scheduler` binary separately.
+    pub run_in_process: bool,
+    pub poll_interval_in_milliseconds: u64,
+    /// How many times queueing an issue up is retried after a failure before
+    /// it's given up on.
+    pub max_retries: i32,
+    pub base_backoff_in_milliseconds: u64,
+    pub max_backoff_in_milliseconds: u64,
+}
+
+impl SchedulerSettings {
+    #[must_use]
+    pub const fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_in_milliseconds)
+    }
+
+    #[must_use]
+    pub const fn base_backoff(&self) -> Duration {
+        Duration::from_millis(self.base_backoff_in_milliseconds)
+    }
+
+    #[must_use]
+    pub const fn max_backoff(&self) -> Duration {
+        Duration::from_millis(self.max_backoff_in_milliseconds)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SessionSettings {
+    pub store: SessionStoreKind,
+    pub ttl_in_seconds: u64,
+    /// How often expired sessions are deleted from the Postgres store.
+    pub purge_interval_in_seconds: u64,
+}
+
+impl SessionSettings {
+    #[must_use]
+    pub const fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_in_seconds)
+    }
+
+    #[must_use]
+    pub const fn purge_interval(&self) -> Duration {
+        Duration::from_secs(self.purge_interval_in_seconds)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct IdempotencySettings {
+    /// How long a saved response is replayed for before the key can be reused.
+    pub expiry_in_seconds: u64,
+    pub purge_interval_in_seconds: u64,
+}
+
+impl IdempotencySettings {
+    #[must_use]
+    pub const fn expiry(&self) -> Duration {
+        Duration::from_secs(self.expiry_in_seconds)
+    }
+
+    #[must_use]
+    pub const fn purge_interval(&self) -> Duration {
+        Duration::from_secs(self.purge_interval_in_seconds)
+    }
+}
+
+/// Where sessions are kept. The in-memory store is lost on restart and isn't
+/// shared between replicas, so it's only suitable for a single instance.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreKind {
+    Postgres,
+    Memory,
+}
+
+/// The user created on startup if there are no users yet, so that a fresh
+/// deployment can be logged into. Its password must satisfy the same rules as
+/// new passwords.
+#[derive(Deserialize, Debug)]
+pub struct AdminSettings {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/// The secrets email providers' webhooks are verified with. The webhook of a
+/// provider without them is disabled.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct WebhookSettings {
+    /// The base64-encoded public key of SendGrid's signed Event Webhook.
+    pub sendgrid_verification_key: Option<String>,
+    /// The Basic auth credentials in the URL of Postmark's webhooks.
+    pub postmark_username: Option<String>,
+    pub postmark_password: Option<Secret<String>>,
+    /// Mailgun's HTTP webhook signing key.
+    pub mailgun_signing_key: Option<Secret<String>>,
 }
@@ -1,6 +1,14 @@
+pub use delivery_window::DeliveryWindow;
+pub use idempotency_key::IdempotencyKey;
+pub use new_password::NewPassword;
 pub use new_subscriber::NewSubscriber;
+pub use slug::Slug;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscription_status::SubscriptionStatus;
+pub use tags::Tags;
+pub use template_name::TemplateName;
+pub use time_zone::TimeZone;
 
 mod subscriber_email {
     use serde::Deserialize;
@@ -74,7 +82,7 @@ mod subscriber_email {
 }
 mod subscriber_name {
     use serde::Deserialize;
-    use validator::{Validate, ValidationError};
+    use validator::{Validate, ValidationError, ValidationErrors};
 
     #[derive(Debug, Deserialize, Validate)]
     pub struct SubscriberName {
@@ -111,6 +119,14 @@ mod subscriber_name {
         Ok(())
     }
 
+    impl SubscriberName {
+        pub fn parse(name: String) -> Result<Self, ValidationErrors> {
+            let subscriber_name = Self { name };
+            subscriber_name.validate()?;
+            Ok(subscriber_name)
+        }
+    }
+
     impl AsRef<str> for SubscriberName {
         fn as_ref(&self) -> &str {
             &self.name
@@ -164,9 +180,9 @@ mod subscriber_name {
 }
 
 mod new_subscriber {
-    use super::{SubscriberEmail, SubscriberName};
+    use super::{SubscriberEmail, SubscriberName, TimeZone};
     use serde::Deserialize;
-    use validator::Validate;
+    use validator::{Validate, ValidationError};
 
     #[derive(Debug, Deserialize, Validate)]
     pub struct NewSubscriber {
@@ -176,5 +192,549 @@ mod new_subscriber {
         #[serde(flatten)]
         #[validate]
         pub email: SubscriberEmail,
+        /// For issues sent at a local time. Forms submit empty fields, so a
+        /// blank time zone counts as none.
+        #[validate(custom = "is_blank_or_known_time_zone")]
+        time_zone: Option<String>,
+        /// Subscribe the address even if it's suppressed, lifting the
+        /// suppression. Only honored for logged-in admins.
+        #[serde(default)]
+        pub override_suppression: bool,
+    }
+
+    impl NewSubscriber {
+        pub fn time_zone(&self) -> Option<TimeZone> {
+            self.time_zone.as_deref().and_then(TimeZone::parse)
+        }
+    }
+
+    fn is_blank_or_known_time_zone(value: &str) -> Result<(), ValidationError> {
+        if value.trim().is_empty() || TimeZone::parse(value).is_some() {
+            Ok(())
+        } else {
+            Err(ValidationError::new("unknown time zone"))
+        }
+    }
+}
+
+mod new_password {
+    use secrecy::Secret;
+    use validator::{Validate, ValidationError, ValidationErrors};
+
+    /// A password that satisfies the strength rules. Existing passwords aren't
+    /// held to them, only new ones.
+    #[derive(Debug, Validate)]
+    pub struct NewPassword {
+        #[validate(
+            length(min = 12, max = 128),
+            custom = "mixes_letters_with_digits_or_symbols"
+        )]
+        password: String,
+    }
+
+    fn mixes_letters_with_digits_or_symbols(
+        value: &str,
+    ) -> Result<(), ValidationError> {
+        let has_letters = value.chars().any(char::is_alphabetic);
+        let has_other_characters = value.chars().any(|character| {
+            !character.is_alphabetic() && !character.is_whitespace()
+        });
+
+        if has_letters && has_other_characters {
+            Ok(())
+        } else {
+            Err(ValidationError::new(
+                "must contain both letters and digits or symbols",
+            ))
+        }
+    }
+
+    impl NewPassword {
+        pub fn parse(password: String) -> Result<Self, ValidationErrors> {
+            let new_password = Self { password };
+            new_password.validate()?;
+            Ok(new_password)
+        }
+    }
+
+    impl From<NewPassword> for Secret<String> {
+        fn from(value: NewPassword) -> Self {
+            Self::new(value.password)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::NewPassword;
+        use k9::{assert_err, assert_ok};
+
+        #[test]
+        fn an_11_character_long_password_is_rejected() {
+            assert_err!(NewPassword::parse("abcdefgh12!".to_string()));
+        }
+
+        #[test]
+        fn a_129_character_long_password_is_rejected() {
+            let password = format!("{}1", "a".repeat(128));
+            assert_err!(NewPassword::parse(password));
+        }
+
+        #[test]
+        fn letters_only_passwords_are_rejected() {
+            assert_err!(NewPassword::parse("abcdefghijklmnop".to_string()));
+        }
+
+        #[test]
+        fn digits_only_passwords_are_rejected() {
+            assert_err!(NewPassword::parse("1234567890123456".to_string()));
+        }
+
+        #[test]
+        fn long_mixed_passwords_are_accepted() {
+            assert_ok!(NewPassword::parse("correct horse battery 9".to_string()));
+        }
+    }
+}
+
+mod idempotency_key {
+    use validator::{Validate, ValidationErrors};
+
+    /// A client-chosen key that identifies retries of the same request.
+    #[derive(Debug, Validate)]
+    pub struct IdempotencyKey {
+        #[validate(length(min = 1, max = 50))]
+        key: String,
+    }
+
+    impl IdempotencyKey {
+        pub fn parse(key: String) -> Result<Self, ValidationErrors> {
+            let idempotency_key = Self { key };
+            idempotency_key.validate()?;
+            Ok(idempotency_key)
+        }
+    }
+
+    impl AsRef<str> for IdempotencyKey {
+        fn as_ref(&self) -> &str {
+            &self.key
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::IdempotencyKey;
+        use k9::{assert_err, assert_ok};
+
+        #[test]
+        fn empty_keys_are_rejected() {
+            assert_err!(IdempotencyKey::parse(String::new()));
+        }
+
+        #[test]
+        fn keys_longer_than_50_characters_are_rejected() {
+            assert_err!(IdempotencyKey::parse("a".repeat(51)));
+        }
+
+        #[test]
+        fn a_uuid_is_accepted() {
+            assert_ok!(IdempotencyKey::parse(uuid::Uuid::new_v4().to_string()));
+        }
+    }
+}
+
+mod template_name {
+    use validator::{Validate, ValidationError, ValidationErrors};
+
+    /// The name an email template is saved under. It's used in URLs, so it's
+    /// limited to ASCII letters, digits, dashes and underscores.
+    #[derive(Debug, Validate)]
+    pub struct TemplateName {
+        #[validate(length(min = 1, max = 64), custom = "is_url_safe")]
+        name: String,
+    }
+
+    fn is_url_safe(value: &str) -> Result<(), ValidationError> {
+        if value.chars().all(|character| {
+            character.is_ascii_alphanumeric() || "-_".contains(character)
+        }) {
+            Ok(())
+        } else {
+            Err(ValidationError::new(
+                "may only contain ASCII letters, digits, dashes and underscores",
+            ))
+        }
+    }
+
+    impl TemplateName {
+        pub fn parse(name: String) -> Result<Self, ValidationErrors> {
+            let template_name = Self { name };
+            template_name.validate()?;
+            Ok(template_name)
+        }
+    }
+
+    impl AsRef<str> for TemplateName {
+        fn as_ref(&self) -> &str {
+            &self.name
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::TemplateName;
+        use k9::{assert_err, assert_ok};
+
+        #[test]
+        fn empty_names_are_rejected() {
+            assert_err!(TemplateName::parse(String::new()));
+        }
+
+        #[test]
+        fn names_longer_than_64_characters_are_rejected() {
+            assert_err!(TemplateName::parse("a".repeat(65)));
+        }
+
+        #[test]
+        fn names_with_spaces_or_slashes_are_rejected() {
+            assert_err!(TemplateName::parse("weekly digest".to_string()));
+            assert_err!(TemplateName::parse("weekly/digest".to_string()));
+        }
+
+        #[test]
+        fn slugs_are_accepted() {
+            assert_ok!(TemplateName::parse("weekly-digest_2".to_string()));
+        }
+    }
+}
+
+mod slug {
+    use validator::{Validate, ValidationError, ValidationErrors};
+
+    /// Identifies a list or a topic. It's used in URLs and forms, so it's
+    /// limited to lower-case ASCII letters, digits and dashes.
+    #[derive(Debug, Validate)]
+    pub struct Slug {
+        #[validate(length(min = 1, max = 64), custom = "is_a_slug")]
+        slug: String,
+    }
+
+    fn is_a_slug(value: &str) -> Result<(), ValidationError> {
+        if value.chars().all(|character| {
+            character.is_ascii_lowercase()
+                || character.is_ascii_digit()
+                || character == '-'
+        }) {
+            Ok(())
+        } else {
+            Err(ValidationError::new(
+                "may only contain lower-case ASCII letters, digits and dashes",
+            ))
+        }
+    }
+
+    impl Slug {
+        pub fn parse(slug: String) -> Result<Self, ValidationErrors> {
+            let slug = Self { slug };
+            slug.validate()?;
+            Ok(slug)
+        }
+    }
+
+    impl AsRef<str> for Slug {
+        fn as_ref(&self) -> &str {
+            &self.slug
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::Slug;
+        use k9::{assert_err, assert_ok};
+
+        #[test]
+        fn empty_slugs_are_rejected() {
+            assert_err!(Slug::parse(String::new()));
+        }
+
+        #[test]
+        fn upper_case_letters_and_spaces_are_rejected() {
+            assert_err!(Slug::parse("Rust".to_string()));
+            assert_err!(Slug::parse("rust news".to_string()));
+        }
+
+        #[test]
+        fn valid_slugs_are_parsed_successfully() {
+            assert_ok!(Slug::parse("rust-2023".to_string()));
+        }
+    }
+}
+
+mod delivery_window {
+    /// When a subscriber wants to get issues. With a daily or weekly window,
+    /// issues are held back until the start of the next day or week, then sent
+    /// one by one: they aren't combined into a digest.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum DeliveryWindow {
+        #[default]
+        Immediately,
+        Daily,
+        Weekly,
+    }
+
+    impl DeliveryWindow {
+        pub const ALL: [Self; 3] = [Self::Immediately, Self::Daily, Self::Weekly];
+
+        /// How it's stored in the database and sent in forms.
+        pub const fn as_str(self) -> &'static str {
+            match self {
+                Self::Immediately => "immediately",
+                Self::Daily => "daily",
+                Self::Weekly => "weekly",
+            }
+        }
+
+        pub fn parse(value: &str) -> Option<Self> {
+            Self::ALL
+                .into_iter()
+                .find(|window| window.as_str() == value)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::DeliveryWindow;
+
+        #[test]
+        fn windows_are_parsed_from_their_names() {
+            for window in DeliveryWindow::ALL {
+                assert_eq!(Some(window), DeliveryWindow::parse(window.as_str()));
+            }
+            assert_eq!(None, DeliveryWindow::parse("hourly"));
+        }
+    }
+}
+
+mod subscription_status {
+    /// Whether a subscriber gets the issues published without a list. Lists
+    /// keep a status of their own for each subscriber.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum SubscriptionStatus {
+        PendingConfirmation,
+        Confirmed,
+        Unsubscribed,
+    }
+
+    impl SubscriptionStatus {
+        pub const ALL: [Self; 3] = [
+            Self::PendingConfirmation,
+            Self::Confirmed,
+            Self::Unsubscribed,
+        ];
+
+        /// How it's stored in the database.
+        pub const fn as_str(self) -> &'static str {
+            match self {
+                Self::PendingConfirmation => "pending_confirmation",
+                Self::Confirmed => "confirmed",
+                Self::Unsubscribed => "unsubscribed",
+            }
+        }
+
+        pub fn parse(value: &str) -> Option<Self> {
+            Self::ALL
+                .into_iter()
+                .find(|status| status.as_str() == value)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::SubscriptionStatus;
+
+        #[test]
+        fn statuses_are_parsed_from_their_names() {
+            for status in SubscriptionStatus::ALL {
+                assert_eq!(
+                    Some(status),
+                    SubscriptionStatus::parse(status.as_str())
+                );
+            }
+            assert_eq!(None, SubscriptionStatus::parse("cleaned"));
+        }
+    }
+}
+
+mod tags {
+    use validator::{Validate, ValidationError, ValidationErrors};
+
+    /// The tags on a subscriber, trimmed, without blank or repeated ones.
+    #[derive(Debug, Validate)]
+    pub struct Tags {
+        #[validate(custom = "are_short_enough")]
+        tags: Vec<String>,
+    }
+
+    fn are_short_enough(tags: &[String]) -> Result<(), ValidationError> {
+        if tags.iter().all(|tag| tag.chars().count() <= 100) {
+            Ok(())
+        } else {
+            Err(ValidationError::new(
+                "tags may not be longer than 100 characters",
+            ))
+        }
+    }
+
+    impl Tags {
+        pub fn parse(
+            tags: impl IntoIterator<Item = impl AsRef<str>>,
+        ) -> Result<Self, ValidationErrors> {
+            let mut parsed: Vec<String> = Vec::new();
+            for tag in tags {
+                let tag = tag.as_ref().trim();
+                if !tag.is_empty()
+                    && !parsed.iter().any(|parsed_tag| parsed_tag == tag)
+                {
+                    parsed.push(tag.to_owned());
+                }
+            }
+            let tags = Self { tags: parsed };
+            tags.validate()?;
+            Ok(tags)
+        }
+    }
+
+    impl AsRef<[String]> for Tags {
+        fn as_ref(&self) -> &[String] {
+            &self.tags
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::Tags;
+        use k9::{assert_err, assert_ok};
+
+        #[test]
+        fn tags_are_trimmed_and_deduplicated() {
+            let tags =
+                Tags::parse([" rust", "", "events ", "rust", "  "]).unwrap();
+            assert_eq!(["rust", "events"].as_slice(), tags.as_ref());
+        }
+
+        #[test]
+        fn a_100_character_long_tag_is_valid() {
+            assert_ok!(Tags::parse(["a".repeat(100)]));
+        }
+
+        #[test]
+        fn tags_longer_than_100_characters_are_rejected() {
+            assert_err!(Tags::parse(["rust".to_string(), "a".repeat(101)]));
+        }
+    }
+}
+
+mod time_zone {
+    use chrono::{
+        DateTime, Duration, LocalResult, NaiveDateTime, Offset, TimeZone as _, Utc,
+    };
+    use chrono_tz::Tz;
+
+    /// A time zone from the IANA database, such as `Europe/Paris`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TimeZone(Tz);
+
+    impl TimeZone {
+        pub const UTC: Self = Self(Tz::UTC);
+
+        pub fn parse(name: &str) -> Option<Self> {
+            name.parse().ok().map(Self)
+        }
+
+        pub fn name(self) -> &'static str {
+            self.0.name()
+        }
+
+        /// The instant at which clocks in the time zone show `local`. When
+        /// they show it twice, because they're turned back, it's the first
+        /// time. When they skip it, because they're turned forward, there's no
+        /// such instant.
+        pub fn to_utc(self, local: &NaiveDateTime) -> Option<DateTime<Utc>> {
+            match self.0.from_local_datetime(local) {
+                LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => {
+                    Some(time.with_timezone(&Utc))
+                }
+                LocalResult::None => None,
+            }
+        }
+
+        /// Like [`TimeZone::to_utc`], except that skipped local times are
+        /// shifted forward by the length of the gap, so 02:30 becomes 03:30
+        /// when clocks go from 02:00 to 03:00.
+        pub fn to_utc_lenient(self, local: &NaiveDateTime) -> DateTime<Utc> {
+            self.to_utc(local).unwrap_or_else(|| {
+                // Use the offset from before clocks were turned forward. A day
+                // earlier is well before the gap, and clocks don't change twice
+                // a day.
+                let before = self
+                    .0
+                    .offset_from_utc_datetime(&(*local - Duration::days(1)))
+                    .fix();
+                let utc =
+                    *local - Duration::seconds(before.local_minus_utc().into());
+                Utc.from_utc_datetime(&utc)
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::TimeZone;
+        use chrono::NaiveDate;
+
+        fn local(day: u32, hour: u32, minute: u32) -> chrono::NaiveDateTime {
+            NaiveDate::from_ymd_opt(2023, 3, day)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+                .unwrap()
+        }
+
+        #[test]
+        fn iana_names_are_parsed() {
+            assert_eq!(
+                Some("Europe/Paris"),
+                TimeZone::parse("Europe/Paris").map(TimeZone::name)
+            );
+            assert_eq!(None, TimeZone::parse("Europe/Atlantis"));
+        }
+
+        #[test]
+        fn local_times_follow_daylight_saving_time() {
+            let paris = TimeZone::parse("Europe/Paris").unwrap();
+
+            // Clocks were turned forward on the 26th of March 2023.
+            assert_eq!(
+                "2023-03-25T08:00:00+00:00",
+                paris.to_utc(&local(25, 9, 0)).unwrap().to_rfc3339()
+            );
+            assert_eq!(
+                "2023-03-27T07:00:00+00:00",
+                paris.to_utc(&local(27, 9, 0)).unwrap().to_rfc3339()
+            );
+            assert_eq!(None, paris.to_utc(&local(26, 2, 30)));
+        }
+
+        #[test]
+        fn skipped_local_times_are_shifted_forward() {
+            let paris = TimeZone::parse("Europe/Paris").unwrap();
+
+            assert_eq!(
+                "2023-03-26T01:30:00+00:00",
+                paris.to_utc_lenient(&local(26, 2, 30)).to_rfc3339()
+            );
+            assert_eq!(
+                "2023-03-26T07:00:00+00:00",
+                paris.to_utc_lenient(&local(26, 9, 0)).to_rfc3339()
+            );
+        }
     }
 }
@@ -1,264 +1,469 @@
-use crate::domain::SubscriberEmail;
-use secrecy::{ExposeSecret, Secret};
-use serde_json::json;
-use std::time::Duration;
-
-pub struct EmailClient {
-    sender: SubscriberEmail,
-    base_url: String,
-    http_client: reqwest::Client,
-    authorization_token: Secret<String>,
-}
+//! Sending emails through one of several providers, each behind the
+//! [`EmailSender`] trait. The provider is picked in the configuration (see
+//! [`crate::configuration::EmailClientSettings::client`]).
+
+pub use mailgun::MailgunClient;
+pub use postmark::PostmarkClient;
+pub use retry::RetryingSender;
+pub use sendgrid::SendGridClient;
+pub use ses::SesClient;
+pub use smtp::SmtpClient;
+
+mod mailgun;
+mod postmark;
+mod retry;
+mod sendgrid;
+mod ses;
+mod smtp;
 
-impl EmailClient {
-    /// Create a new `EmailClient`.
+use crate::domain::SubscriberEmail;
+use async_trait::async_trait;
+use std::{cmp::Reverse, collections::HashMap, ops::Range, time::Duration};
+
+#[async_trait]
+pub trait EmailSender: Send + Sync {
+    /// Send an email with both an HTML and a plain text body. If there's an
+    /// `unsubscribe_url`, the email gets the headers that let mail clients
+    /// offer a one-click unsubscribe button (see [`list_unsubscribe_headers`]).
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panics if the HTTP client cannot be built.
-    #[must_use]
-    #[allow(clippy::unwrap_used)]
-    pub fn new(
-        sender: SubscriberEmail,
-        base_url: String,
-        authorization_token: Secret<String>,
-        timeout: Duration,
-    ) -> Self {
-        Self {
-            sender,
-            base_url,
-            authorization_token,
-            http_client: reqwest::Client::builder()
-                .timeout(timeout)
-                .build()
-                .unwrap(),
-        }
+    /// Returns an error if the email couldn't be built or the provider didn't
+    /// accept it.
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_url: Option<&str>,
+    ) -> Result<(), Error>;
+
+    /// The most recipients the provider accepts in a single request.
+    fn max_chunk_size(&self) -> usize {
+        1
     }
 
-    /// Send an email to the SendGrid API.
+    /// Send the same email to every recipient of the chunk, which holds at most
+    /// [`Self::max_chunk_size`] recipients, applying each recipient's
+    /// substitutions to their copy.
+    ///
+    /// The default implementation sends the emails one by one, stopping at the
+    /// first failure.
     ///
     /// # Errors
     ///
-    /// Returns an error if something goes wrong sending the request.
-    #[allow(clippy::doc_markdown)]
-    pub async fn send_email(
+    /// Returns an error if the emails couldn't be built or the provider didn't
+    /// accept them.
+    async fn send_chunk(
         &self,
-        recipient: &SubscriberEmail,
+        recipients: &[BatchRecipient],
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
-        let url = format!("{}/v3/mail/send", self.base_url);
-        let auth_token =
-            format!("Bearer {}", self.authorization_token.expose_secret());
-        let request_body = SendEmailRequest {
-            from: self.sender.as_ref(),
-            to: recipient.as_ref(),
-            subject,
-            html_content,
-            text_content,
+    ) -> Result<(), Error> {
+        for recipient in recipients {
+            self.send_email(
+                &recipient.email,
+                &recipient.substitute(subject),
+                &recipient.substitute(html_content),
+                &recipient.substitute(text_content),
+                recipient.unsubscribe_url.as_deref(),
+            )
+            .await?;
         }
-        .json();
-
-        self.http_client
-            .post(url)
-            .header("Authorization", auth_token)
-            .json(&request_body)
-            .send()
-            .await?
-            .error_for_status()?;
 
         Ok(())
     }
+
+    /// Send the same email to all recipients, grouped into chunks of at most
+    /// [`Self::max_chunk_size`] recipients. A failed chunk doesn't stop the
+    /// following ones from being sent.
+    async fn send_batch(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Vec<ChunkOutcome> {
+        let chunk_size = self.max_chunk_size().max(1);
+        let mut outcomes = Vec::new();
+
+        for (index, chunk) in recipients.chunks(chunk_size).enumerate() {
+            let start = index * chunk_size;
+            let result = self
+                .send_chunk(chunk, subject, html_content, text_content)
+                .await;
+            outcomes.push(ChunkOutcome {
+                recipients: start..start + chunk.len(),
+                result,
+            });
+        }
+
+        outcomes
+    }
+}
+
+/// A recipient of a batch, along with the values that personalize their copy of
+/// the email.
+#[derive(Debug)]
+pub struct BatchRecipient {
+    pub email: SubscriberEmail,
+    /// Every occurrence of a key in the subject and the content is replaced by
+    /// its value.
+    pub substitutions: HashMap<String, String>,
+    /// Used for the `List-Unsubscribe` headers of the recipient's copy.
+    pub unsubscribe_url: Option<String>,
+}
+
+impl BatchRecipient {
+    #[must_use]
+    pub fn new(email: SubscriberEmail) -> Self {
+        Self {
+            email,
+            substitutions: HashMap::new(),
+            unsubscribe_url: None,
+        }
+    }
+
+    /// Apply the recipient's substitutions to `text`, in one pass from left to
+    /// right. Keys are only looked for in `text` itself, so a value that
+    /// contains a key, such as a name, is never substituted in turn. Where
+    /// several keys start at the same place, the longest one wins.
+    #[must_use]
+    pub fn substitute(&self, text: &str) -> String {
+        let mut substituted = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some((start, key, value)) = self
+            .substitutions
+            .iter()
+            .filter(|(key, _)| !key.is_empty())
+            .filter_map(|(key, value)| {
+                rest.find(key.as_str()).map(|start| (start, key, value))
+            })
+            .min_by_key(|(start, key, _)| (*start, Reverse(key.len())))
+        {
+            substituted.push_str(&rest[..start]);
+            substituted.push_str(value);
+            rest = &rest[start + key.len()..];
+        }
+        substituted.push_str(rest);
+        substituted
+    }
+}
+
+/// The outcome of sending one chunk of a batch.
+#[derive(Debug)]
+pub struct ChunkOutcome {
+    /// The positions of the chunk's recipients in the batch.
+    pub recipients: Range<usize>,
+    pub result: Result<(), Error>,
 }
 
-struct SendEmailRequest<'a> {
-    from: &'a str,
-    to: &'a str,
-    subject: &'a str,
-    html_content: &'a str,
-    text_content: &'a str,
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Timeouts, connection failures, rate limiting and server errors are likely
+    /// to go away on their own, so the email can be sent again later.
+    #[error("the email couldn't be sent, but it may be retried")]
+    Retryable {
+        #[source]
+        source: anyhow::Error,
+        /// How long the provider asked us to wait before retrying.
+        retry_after: Option<Duration>,
+    },
+    /// The provider rejected the email (such as with a `4xx` status code), so
+    /// sending it again won't help.
+    #[error("the provider rejected the email")]
+    Permanent(#[source] anyhow::Error),
+    /// The provider sent some of the chunk's emails but rejected the others,
+    /// which sending again won't help either.
+    #[error("the provider rejected {} of the emails", rejected.len())]
+    PartiallyRejected {
+        /// The positions of the rejected recipients in the chunk.
+        rejected: Vec<usize>,
+        #[source]
+        source: anyhow::Error,
+    },
+    #[error("the email couldn't be built")]
+    InvalidEmail(#[source] anyhow::Error),
 }
 
-impl<'a> SendEmailRequest<'a> {
-    pub fn json(self) -> serde_json::Value {
-        json!({
-            "personalizations": [{ "to": [{ "email": self.to }] }],
-            "from": self.from,
-            "subject": self.subject,
-            "content": [
-                {
-                    "type": "text/html",
-                    "value": self.html_content
-                },
-                {
-                    "type": "text/plain",
-                    "value": self.text_content
-                }
-            ]
-        })
+impl Error {
+    #[must_use]
+    pub const fn is_retryable(&self) -> bool {
+        matches!(self, Self::Retryable { .. })
+    }
+
+    #[must_use]
+    pub const fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Retryable { retry_after, .. } => *retry_after,
+            Self::Permanent(_)
+            | Self::PartiallyRejected { .. }
+            | Self::InvalidEmail(_) => None,
+        }
     }
 }
 
+/// Errors that happen before a response is received. Responses with an error
+/// status are handled by [`error_for_status`].
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() || error.is_connect() {
+            Self::Retryable {
+                source: error.into(),
+                retry_after: None,
+            }
+        } else {
+            Self::Permanent(error.into())
+        }
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for Error {
+    fn from(error: lettre::transport::smtp::Error) -> Self {
+        // `5xx` replies and client-side bugs won't go away. Everything else
+        // (`4xx` replies, network and connection problems) might.
+        if error.is_permanent() || error.is_client() {
+            Self::Permanent(error.into())
+        } else {
+            Self::Retryable {
+                source: error.into(),
+                retry_after: None,
+            }
+        }
+    }
+}
+
+/// The headers that let mail clients offer a one-click unsubscribe button, as
+/// described in RFC 8058. Gmail and Yahoo require them from bulk senders.
+fn list_unsubscribe_headers(unsubscribe_url: &str) -> [(&'static str, String); 2] {
+    [
+        ("List-Unsubscribe", format!("<{unsubscribe_url}>")),
+        (
+            "List-Unsubscribe-Post",
+            "List-Unsubscribe=One-Click".to_string(),
+        ),
+    ]
+}
+
+/// Turn a response with an error status into an [`Error`], taking the
+/// `Retry-After` header into account.
+fn error_for_status(response: &reqwest::Response) -> Result<(), Error> {
+    let status = response.status();
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+
+    match response.error_for_status_ref() {
+        Ok(_) => Ok(()),
+        Err(error)
+            if status.is_server_error()
+                || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                || status == reqwest::StatusCode::REQUEST_TIMEOUT =>
+        {
+            Err(Error::Retryable {
+                source: error.into(),
+                retry_after,
+            })
+        }
+        Err(error) => Err(Error::Permanent(error.into())),
+    }
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let delay = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    // A date in the past means that we can retry right away.
+    Some(delay.to_std().unwrap_or_default())
+}
+
+/// Build the HTTP client shared by the providers with an HTTP API.
+///
+/// # Panics
+///
+/// Panics if the HTTP client cannot be built.
+#[allow(clippy::unwrap_used)]
+fn http_client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder().timeout(timeout).build().unwrap()
+}
+
 #[cfg(test)]
 mod tests {
-    use helpers::{content, email, email_client, subject};
-    use k9::{assert_err, assert_ok};
-    use matchers::email_body_matches;
-    use std::time::Duration;
-    use wiremock::{
-        matchers::{any, header, header_exists, method, path},
-        Mock, MockServer, ResponseTemplate,
+    use super::{
+        parse_retry_after, test_helpers::email, BatchRecipient, EmailSender, Error,
     };
+    use crate::domain::SubscriberEmail;
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use std::{sync::Mutex, time::Duration};
+
+    /// Records the emails it's asked to send and rejects the ones addressed to
+    /// `rejected`.
+    struct FakeSender {
+        rejected: String,
+        sent: Mutex<Vec<(String, String)>>,
+    }
 
-    #[tokio::test]
-    async fn send_email_fires_request_to_base_url() {
-        // Arrange.
-        let mock_server = MockServer::start().await;
-        let email_client = email_client(mock_server.uri());
-
-        Mock::given(header_exists("Authorization"))
-            .and(header("Content-Type", "application/json"))
-            .and(path("/v3/mail/send"))
-            .and(method("POST"))
-            .and(email_body_matches())
-            .respond_with(ResponseTemplate::new(200))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        // Act.
-        let _ = email_client
-            .send_email(&email(), &subject(), &content(), &content())
-            .await;
-
-        // Assert.
-        // Assertion is done automatically by the `MockServer`: if it doesn't
-        // receive the request(s) as specified by `Mock::given(...)...`, it'll
-        // panic in its `drop()` method.
+    #[async_trait]
+    impl EmailSender for FakeSender {
+        async fn send_email(
+            &self,
+            recipient: &SubscriberEmail,
+            subject: &str,
+            _html_content: &str,
+            _text_content: &str,
+            _unsubscribe_url: Option<&str>,
+        ) -> Result<(), Error> {
+            if recipient.as_ref() == self.rejected {
+                return Err(Error::Permanent(anyhow!("rejected")));
+            }
+            self.sent
+                .lock()
+                .unwrap()
+                .push((recipient.as_ref().to_string(), subject.to_string()));
+            Ok(())
+        }
     }
 
-    #[tokio::test]
-    async fn send_email_succeeds_if_server_returns_200() {
-        // Arrange.
-        let mock_server = MockServer::start().await;
-        let email_client = email_client(mock_server.uri());
-
-        Mock::given(any())
-            .respond_with(ResponseTemplate::new(200))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        // Act.
-        let outcome = email_client
-            .send_email(&email(), &subject(), &content(), &content())
-            .await;
-
-        // Assert.
-        assert_ok!(outcome);
+    #[test]
+    fn every_occurrence_of_a_key_is_substituted() {
+        let mut recipient = BatchRecipient::new(email());
+        recipient
+            .substitutions
+            .insert("{name}".to_string(), "Ursula".to_string());
+
+        assert_eq!("Ursula, Ursula!", recipient.substitute("{name}, {name}!"));
+    }
+
+    #[test]
+    fn values_are_not_substituted_in_turn() {
+        let mut recipient = BatchRecipient::new(email());
+        recipient.substitutions.extend([
+            ("{name}".to_string(), "{url} {name}".to_string()),
+            ("{url}".to_string(), "https://example.com".to_string()),
+            ("{url}s".to_string(), "links".to_string()),
+        ]);
+
+        assert_eq!(
+            "{url} {name}: https://example.com, links",
+            recipient.substitute("{name}: {url}, {url}s")
+        );
     }
 
     #[tokio::test]
-    async fn send_email_fails_if_server_returns_500() {
-        // Arrange.
-        let mock_server = MockServer::start().await;
-        let email_client = email_client(mock_server.uri());
-
-        Mock::given(any())
-            .respond_with(ResponseTemplate::new(500))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        // Act.
-        let outcome = email_client
-            .send_email(&email(), &subject(), &content(), &content())
-            .await;
-
-        // Assert.
-        assert_err!(outcome);
+    async fn by_default_every_recipient_is_its_own_chunk() {
+        let recipients: Vec<_> =
+            (0..3).map(|_| BatchRecipient::new(email())).collect();
+        let sender = FakeSender {
+            rejected: recipients[1].email.as_ref().to_string(),
+            sent: Mutex::default(),
+        };
+
+        let outcomes = sender.send_batch(&recipients, "Hi", "", "").await;
+
+        let ranges: Vec<_> = outcomes
+            .iter()
+            .map(|outcome| outcome.recipients.clone())
+            .collect();
+        assert_eq!(vec![0..1, 1..2, 2..3], ranges);
+        assert!(outcomes[0].result.is_ok());
+        assert!(outcomes[1].result.is_err());
+        // The failed chunk didn't stop the one after it.
+        assert!(outcomes[2].result.is_ok());
+        assert_eq!(2, sender.sent.lock().unwrap().len());
     }
 
     #[tokio::test]
-    async fn send_email_times_out_if_server_takes_too_long() {
-        // Arrange.
-        let mock_server = MockServer::start().await;
-        let email_client = email_client(mock_server.uri());
-
-        Mock::given(any())
-            .respond_with(
-                ResponseTemplate::new(500).set_delay(Duration::from_secs(180)),
-            )
-            .expect(1)
-            .mount(&mock_server)
-            .await;
+    async fn the_default_chunk_applies_substitutions_per_recipient() {
+        let recipients: Vec<_> = ["Ursula", "Terry"]
+            .into_iter()
+            .map(|name| {
+                let mut recipient = BatchRecipient::new(email());
+                recipient
+                    .substitutions
+                    .insert("{name}".to_string(), name.to_string());
+                recipient
+            })
+            .collect();
+        let sender = FakeSender {
+            rejected: String::new(),
+            sent: Mutex::default(),
+        };
 
-        // Act.
-        let outcome = email_client
-            .send_email(&email(), &subject(), &content(), &content())
-            .await;
+        sender
+            .send_chunk(&recipients, "Hi, {name}!", "", "")
+            .await
+            .unwrap();
+
+        let subjects: Vec<_> = sender
+            .sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, subject)| subject.clone())
+            .collect();
+        assert_eq!(vec!["Hi, Ursula!", "Hi, Terry!"], subjects);
+    }
 
-        // Assert.
-        assert_err!(outcome);
+    #[test]
+    fn retry_after_can_be_a_number_of_seconds() {
+        assert_eq!(Some(Duration::from_secs(120)), parse_retry_after("120"));
     }
 
-    mod helpers {
-        use std::time::Duration;
+    #[test]
+    fn retry_after_in_the_past_means_no_delay() {
+        assert_eq!(
+            Some(Duration::ZERO),
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT")
+        );
+    }
 
-        use fake::{
-            faker::{
-                internet::en::SafeEmail,
-                lorem::en::{Paragraph, Sentence},
-            },
-            Fake, Faker,
-        };
-        use secrecy::Secret;
+    #[test]
+    fn retry_after_can_be_a_date() {
+        let in_a_minute = chrono::Utc::now() + chrono::Duration::seconds(60);
+        let value = in_a_minute.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
 
-        use crate::{domain::SubscriberEmail, EmailClient};
+        let delay = parse_retry_after(&value).unwrap();
 
-        pub fn subject() -> String {
-            Sentence(1..2).fake()
-        }
+        assert!(delay > Duration::from_secs(50));
+        assert!(delay <= Duration::from_secs(60));
+    }
 
-        pub fn content() -> String {
-            Paragraph(1..10).fake()
-        }
+    #[test]
+    fn invalid_retry_after_is_ignored() {
+        assert_eq!(None, parse_retry_after("soon"));
+    }
+}
 
-        pub fn email() -> SubscriberEmail {
-            #[allow(clippy::unwrap_used)]
-            SubscriberEmail::parse(SafeEmail().fake()).unwrap()
-        }
+#[cfg(test)]
+mod test_helpers {
+    use crate::domain::SubscriberEmail;
+    use fake::{
+        faker::{
+            internet::en::SafeEmail,
+            lorem::en::{Paragraph, Sentence},
+        },
+        Fake,
+    };
 
-        pub fn email_client(base_url: String) -> EmailClient {
-            EmailClient::new(
-                email(),
-                base_url,
-                Secret::new(Faker.fake()),
-                Duration::from_millis(200),
-            )
-        }
+    pub fn subject() -> String {
+        Sentence(1..2).fake()
     }
 
-    mod matchers {
-        pub const fn email_body_matches() -> SendEmailBodyMatcher {
-            SendEmailBodyMatcher
-        }
+    pub fn content() -> String {
+        Paragraph(1..10).fake()
+    }
 
-        pub struct SendEmailBodyMatcher;
-
-        impl wiremock::Match for SendEmailBodyMatcher {
-            fn matches(&self, request: &wiremock::Request) -> bool {
-                serde_json::from_slice(&request.body).map_or(
-                    false,
-                    |body: serde_json::Value| {
-                        body.get("personalizations").is_some()
-                            && body.get("from").is_some()
-                            && body.get("subject").is_some()
-                            && body.get("content").is_some()
-                    },
-                )
-            }
-        }
+    pub fn email() -> SubscriberEmail {
+        #[allow(clippy::unwrap_used)]
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
     }
 }
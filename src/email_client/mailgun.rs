@@ -0,0 +1,338 @@
+use super::{
+    error_for_status, http_client, list_unsubscribe_headers, BatchRecipient,
+    EmailSender, Error,
+};
+use crate::domain::SubscriberEmail;
+use async_trait::async_trait;
+use secrecy::{ExposeSecret, Secret};
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::Duration,
+};
+
+pub struct MailgunClient {
+    sender: SubscriberEmail,
+    base_url: String,
+    domain: String,
+    http_client: reqwest::Client,
+    api_key: Secret<String>,
+}
+
+impl MailgunClient {
+    /// Create a new `MailgunClient` that sends from the given Mailgun domain.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be built.
+    #[must_use]
+    pub fn new(
+        sender: SubscriberEmail,
+        base_url: String,
+        domain: String,
+        api_key: Secret<String>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            sender,
+            base_url,
+            domain,
+            api_key,
+            http_client: http_client(timeout),
+        }
+    }
+
+    async fn post(&self, form: &[(&str, &str)]) -> Result<(), Error> {
+        let url = format!("{}/v3/{}/messages", self.base_url, self.domain);
+
+        // Mailgun takes the API key as the password, with "api" as the user.
+        let response = self
+            .http_client
+            .post(url)
+            .basic_auth("api", Some(self.api_key.expose_secret()))
+            .form(form)
+            .send()
+            .await?;
+
+        error_for_status(&response)
+    }
+}
+
+#[async_trait]
+impl EmailSender for MailgunClient {
+    /// Send an email to the Mailgun API.
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_url: Option<&str>,
+    ) -> Result<(), Error> {
+        let headers = header_fields(unsubscribe_url);
+        let mut form = vec![
+            ("from", self.sender.as_ref()),
+            ("to", recipient.as_ref()),
+            ("subject", subject),
+            ("html", html_content),
+            ("text", text_content),
+        ];
+        form.extend(
+            headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        );
+
+        self.post(&form).await
+    }
+
+    /// Mailgun's batch sending accepts up to 1000 recipients per message.
+    fn max_chunk_size(&self) -> usize {
+        1000
+    }
+
+    /// Send one message to all recipients using Mailgun's batch sending.
+    ///
+    /// Every substitution key is replaced by a `%recipient.vN%` placeholder,
+    /// which Mailgun fills in from the recipient variables. Passing recipient
+    /// variables also makes Mailgun send a separate copy to each recipient, so
+    /// they never see each other's addresses. The unsubscribe URLs are passed
+    /// the same way, as `%recipient.unsubscribe_url%`.
+    async fn send_chunk(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), Error> {
+        let keys: BTreeSet<_> = recipients
+            .iter()
+            .flat_map(|recipient| recipient.substitutions.keys())
+            .collect();
+        let placeholders: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .map(|(index, key)| (*key, format!("%recipient.v{index}%")))
+            .collect();
+        let use_placeholders = |text: &str| {
+            placeholders
+                .iter()
+                .fold(text.to_string(), |text, (key, placeholder)| {
+                    text.replace(key.as_str(), placeholder)
+                })
+        };
+
+        let recipient_variables: HashMap<_, _> = recipients
+            .iter()
+            .map(|recipient| {
+                let mut variables: HashMap<_, _> = keys
+                    .iter()
+                    .enumerate()
+                    .map(|(index, key)| {
+                        // Recipients without a value for the key keep it as is.
+                        let value =
+                            recipient.substitutions.get(*key).unwrap_or(*key);
+                        (format!("v{index}"), value.as_str())
+                    })
+                    .collect();
+                if let Some(unsubscribe_url) = &recipient.unsubscribe_url {
+                    variables.insert(
+                        UNSUBSCRIBE_URL_VARIABLE.to_string(),
+                        unsubscribe_url,
+                    );
+                }
+                (recipient.email.as_ref(), variables)
+            })
+            .collect();
+        let recipient_variables = serde_json::to_string(&recipient_variables)
+            .map_err(|error| Error::InvalidEmail(error.into()))?;
+
+        let subject = use_placeholders(subject);
+        let html_content = use_placeholders(html_content);
+        let text_content = use_placeholders(text_content);
+        let mut form = vec![
+            ("from", self.sender.as_ref()),
+            ("subject", subject.as_str()),
+            ("html", html_content.as_str()),
+            ("text", text_content.as_str()),
+            ("recipient-variables", recipient_variables.as_str()),
+        ];
+        form.extend(
+            recipients
+                .iter()
+                .map(|recipient| ("to", recipient.email.as_ref())),
+        );
+        let unsubscribe_url = recipients
+            .iter()
+            .any(|recipient| recipient.unsubscribe_url.is_some())
+            .then(|| format!("%recipient.{UNSUBSCRIBE_URL_VARIABLE}%"));
+        let headers = header_fields(unsubscribe_url.as_deref());
+        form.extend(
+            headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        );
+
+        self.post(&form).await
+    }
+}
+
+const UNSUBSCRIBE_URL_VARIABLE: &str = "unsubscribe_url";
+
+/// Mailgun takes custom headers as `h:`-prefixed form fields.
+fn header_fields(unsubscribe_url: Option<&str>) -> Vec<(String, String)> {
+    unsubscribe_url
+        .map(list_unsubscribe_headers)
+        .into_iter()
+        .flatten()
+        .map(|(name, value)| (format!("h:{name}"), value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            test_helpers::{content, email, subject},
+            BatchRecipient, EmailSender,
+        },
+        MailgunClient,
+    };
+    use k9::assert_ok;
+    use secrecy::Secret;
+    use std::{collections::HashMap, time::Duration};
+    use wiremock::{
+        matchers::{basic_auth, header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn send_email_posts_a_form_to_the_domains_messages_endpoint() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = MailgunClient::new(
+            email(),
+            mock_server.uri(),
+            "mg.example.com".to_string(),
+            Secret::new("key-123".to_string()),
+            Duration::from_millis(200),
+        );
+
+        Mock::given(basic_auth("api", "key-123"))
+            .and(header("Content-Type", "application/x-www-form-urlencoded"))
+            .and(path("/v3/mg.example.com/messages"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        // Assert.
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_batch_uses_recipient_variables_for_substitutions() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = MailgunClient::new(
+            email(),
+            mock_server.uri(),
+            "mg.example.com".to_string(),
+            Secret::new("key-123".to_string()),
+            Duration::from_millis(200),
+        );
+        let mut ursula = BatchRecipient::new(email());
+        ursula
+            .substitutions
+            .insert("{name}".to_string(), "Ursula".to_string());
+        let terry = BatchRecipient::new(email());
+        let recipients = [ursula, terry];
+
+        Mock::given(path("/v3/mg.example.com/messages"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcomes = email_client
+            .send_batch(&recipients, "Hi, {name}!", &content(), &content())
+            .await;
+
+        // Assert.
+        assert_ok!(&outcomes[0].result);
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let form: Vec<(String, String)> =
+            serde_urlencoded::from_bytes(&requests[0].body).unwrap();
+        let field = |name: &str| {
+            form.iter()
+                .filter(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["Hi, %recipient.v0%!"], field("subject"));
+        assert_eq!(
+            vec![recipients[0].email.as_ref(), recipients[1].email.as_ref()],
+            field("to")
+        );
+
+        let variables: serde_json::Value =
+            serde_json::from_str(field("recipient-variables")[0]).unwrap();
+        assert_eq!("Ursula", variables[recipients[0].email.as_ref()]["v0"]);
+        // Terry has no value for the key, so it's left alone.
+        assert_eq!("{name}", variables[recipients[1].email.as_ref()]["v0"]);
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_urls_are_passed_as_recipient_variables() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = MailgunClient::new(
+            email(),
+            mock_server.uri(),
+            "mg.example.com".to_string(),
+            Secret::new("key-123".to_string()),
+            Duration::from_millis(200),
+        );
+        let mut recipient = BatchRecipient::new(email());
+        recipient.unsubscribe_url =
+            Some("https://example.com/unsubscribe?token=abc".to_string());
+
+        Mock::given(path("/v3/mg.example.com/messages"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcomes = email_client
+            .send_batch(&[recipient], &subject(), &content(), &content())
+            .await;
+
+        // Assert.
+        assert_ok!(&outcomes[0].result);
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let form: HashMap<String, String> =
+            serde_urlencoded::from_bytes(&requests[0].body).unwrap();
+        assert_eq!("<%recipient.unsubscribe_url%>", form["h:List-Unsubscribe"]);
+        assert_eq!(
+            "List-Unsubscribe=One-Click",
+            form["h:List-Unsubscribe-Post"]
+        );
+        let variables: serde_json::Value =
+            serde_json::from_str(&form["recipient-variables"]).unwrap();
+        let variables = variables.as_object().unwrap().values().next().unwrap();
+        assert_eq!(
+            "https://example.com/unsubscribe?token=abc",
+            variables["unsubscribe_url"]
+        );
+    }
+}
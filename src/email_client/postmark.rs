@@ -0,0 +1,404 @@
+use super::{
+    error_for_status, http_client, list_unsubscribe_headers, BatchRecipient,
+    EmailSender, Error,
+};
+use crate::domain::SubscriberEmail;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, time::Duration};
+
+pub struct PostmarkClient {
+    sender: SubscriberEmail,
+    base_url: String,
+    http_client: reqwest::Client,
+    server_token: Secret<String>,
+}
+
+impl PostmarkClient {
+    /// Create a new `PostmarkClient`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be built.
+    #[must_use]
+    pub fn new(
+        sender: SubscriberEmail,
+        base_url: String,
+        server_token: Secret<String>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            sender,
+            base_url,
+            server_token,
+            http_client: http_client(timeout),
+        }
+    }
+
+    async fn post(
+        &self,
+        endpoint: &str,
+        request_body: &impl Serialize,
+    ) -> Result<reqwest::Response, Error> {
+        let url = format!("{}{endpoint}", self.base_url);
+
+        Ok(self
+            .http_client
+            .post(url)
+            .header("X-Postmark-Server-Token", self.server_token.expose_secret())
+            .header("Accept", "application/json")
+            .json(request_body)
+            .send()
+            .await?)
+    }
+}
+
+#[async_trait]
+impl EmailSender for PostmarkClient {
+    /// Send an email to the Postmark API.
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_url: Option<&str>,
+    ) -> Result<(), Error> {
+        let request_body = SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: recipient.as_ref(),
+            subject: subject.into(),
+            html_body: html_content.into(),
+            text_body: text_content.into(),
+            headers: headers(unsubscribe_url),
+        };
+
+        let response = self.post("/email", &request_body).await?;
+        error_for_status(&response)
+    }
+
+    /// Postmark accepts up to 500 messages per batch request.
+    fn max_chunk_size(&self) -> usize {
+        500
+    }
+
+    /// Send one message per recipient, with their substitutions applied, in a
+    /// single batch request.
+    ///
+    /// Postmark accepts the batch as a whole even if some of its messages are
+    /// rejected. If all of them are, the chunk fails permanently. Otherwise, the
+    /// rejected ones are reported with [`Error::PartiallyRejected`], since the
+    /// others were sent.
+    async fn send_chunk(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), Error> {
+        let request_body: Vec<_> = recipients
+            .iter()
+            .map(|recipient| SendEmailRequest {
+                from: self.sender.as_ref(),
+                to: recipient.email.as_ref(),
+                subject: recipient.substitute(subject).into(),
+                html_body: recipient.substitute(html_content).into(),
+                text_body: recipient.substitute(text_content).into(),
+                headers: headers(recipient.unsubscribe_url.as_deref()),
+            })
+            .collect();
+
+        let response = self.post("/email/batch", &request_body).await?;
+        error_for_status(&response)?;
+
+        let verdicts: Vec<BatchResponse> = response.json().await?;
+        let rejected: Vec<_> = verdicts
+            .iter()
+            .enumerate()
+            .filter(|(_, verdict)| verdict.error_code != 0)
+            .collect();
+        if rejected.is_empty() {
+            return Ok(());
+        }
+
+        let source = anyhow!(
+            "Postmark rejected {} of the {} messages: {}",
+            rejected.len(),
+            verdicts.len(),
+            rejected
+                .iter()
+                .map(|(_, verdict)| verdict.message.as_str())
+                .collect::<Vec<_>>()
+                .join("; ")
+        );
+        if rejected.len() == verdicts.len() {
+            Err(Error::Permanent(source))
+        } else {
+            Err(Error::PartiallyRejected {
+                rejected: rejected.into_iter().map(|(index, _)| index).collect(),
+                source,
+            })
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: Cow<'a, str>,
+    html_body: Cow<'a, str>,
+    text_body: Cow<'a, str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<Header>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Header {
+    name: &'static str,
+    value: String,
+}
+
+fn headers(unsubscribe_url: Option<&str>) -> Vec<Header> {
+    unsubscribe_url
+        .map(list_unsubscribe_headers)
+        .into_iter()
+        .flatten()
+        .map(|(name, value)| Header { name, value })
+        .collect()
+}
+
+/// Postmark's verdict on one message of a batch. An error code of `0` means that
+/// the message was accepted.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchResponse {
+    error_code: i64,
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            test_helpers::{content, email, subject},
+            BatchRecipient, EmailSender, Error,
+        },
+        PostmarkClient,
+    };
+    use fake::{Fake, Faker};
+    use k9::assert_ok;
+    use secrecy::Secret;
+    use serde_json::json;
+    use std::time::Duration;
+    use wiremock::{
+        matchers::{header_exists, method, path},
+        Mock, MockServer, Request, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn send_email_posts_the_email_to_the_postmark_api() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = PostmarkClient::new(
+            email(),
+            mock_server.uri(),
+            Secret::new(Faker.fake()),
+            Duration::from_millis(200),
+        );
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(|request: &Request| {
+                serde_json::from_slice(&request.body).is_ok_and(
+                    |body: serde_json::Value| {
+                        ["From", "To", "Subject", "HtmlBody", "TextBody"]
+                            .iter()
+                            .all(|field| body.get(field).is_some())
+                    },
+                )
+            })
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        // Assert.
+        assert_ok!(outcome);
+    }
+
+    fn recipients() -> Vec<BatchRecipient> {
+        ["Ursula", "Terry"]
+            .into_iter()
+            .map(|name| {
+                let mut recipient = BatchRecipient::new(email());
+                recipient
+                    .substitutions
+                    .insert("{name}".to_string(), name.to_string());
+                recipient
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn send_batch_posts_one_personalized_message_per_recipient() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = PostmarkClient::new(
+            email(),
+            mock_server.uri(),
+            Secret::new(Faker.fake()),
+            Duration::from_millis(200),
+        );
+        let recipients = recipients();
+
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "ErrorCode": 0, "Message": "OK" },
+                { "ErrorCode": 0, "Message": "OK" }
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcomes = email_client
+            .send_batch(&recipients, "Hi, {name}!", &content(), &content())
+            .await;
+
+        // Assert.
+        assert_eq!(1, outcomes.len());
+        assert_ok!(&outcomes[0].result);
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value =
+            serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!("Hi, Ursula!", body[0]["Subject"]);
+        assert_eq!("Hi, Terry!", body[1]["Subject"]);
+        assert_eq!(recipients[1].email.as_ref(), body[1]["To"]);
+    }
+
+    #[tokio::test]
+    async fn messages_get_their_recipients_unsubscribe_headers() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = PostmarkClient::new(
+            email(),
+            mock_server.uri(),
+            Secret::new(Faker.fake()),
+            Duration::from_millis(200),
+        );
+        let mut recipients = recipients();
+        recipients[0].unsubscribe_url =
+            Some("https://example.com/unsubscribe?token=abc".to_string());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "ErrorCode": 0, "Message": "OK" },
+                { "ErrorCode": 0, "Message": "OK" }
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcomes = email_client
+            .send_batch(&recipients, &subject(), &content(), &content())
+            .await;
+
+        // Assert.
+        assert_ok!(&outcomes[0].result);
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value =
+            serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(
+            json!([
+                {
+                    "Name": "List-Unsubscribe",
+                    "Value": "<https://example.com/unsubscribe?token=abc>"
+                },
+                {
+                    "Name": "List-Unsubscribe-Post",
+                    "Value": "List-Unsubscribe=One-Click"
+                }
+            ]),
+            body[0]["Headers"]
+        );
+        assert!(body[1].get("Headers").is_none());
+    }
+
+    #[tokio::test]
+    async fn rejected_messages_are_reported_apart_from_the_sent_ones() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = PostmarkClient::new(
+            email(),
+            mock_server.uri(),
+            Secret::new(Faker.fake()),
+            Duration::from_millis(200),
+        );
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "ErrorCode": 0, "Message": "OK" },
+                { "ErrorCode": 406, "Message": "Inactive recipient" }
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcomes = email_client
+            .send_batch(&recipients(), &subject(), &content(), &content())
+            .await;
+
+        // Assert.
+        match &outcomes[0].result {
+            Err(Error::PartiallyRejected { rejected, .. }) => {
+                assert_eq!(&vec![1], rejected);
+            }
+            result => panic!("expected a partial rejection, got {result:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_chunk_whose_messages_are_all_rejected_fails_permanently() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = PostmarkClient::new(
+            email(),
+            mock_server.uri(),
+            Secret::new(Faker.fake()),
+            Duration::from_millis(200),
+        );
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "ErrorCode": 406, "Message": "Inactive recipient" },
+                { "ErrorCode": 406, "Message": "Inactive recipient" }
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcomes = email_client
+            .send_batch(&recipients(), &subject(), &content(), &content())
+            .await;
+
+        // Assert.
+        assert!(matches!(outcomes[0].result, Err(Error::Permanent(_))));
+    }
+}
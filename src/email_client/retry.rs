@@ -0,0 +1,332 @@
+use super::{BatchRecipient, EmailSender, Error};
+use crate::{configuration::RetrySettings, domain::SubscriberEmail};
+use async_trait::async_trait;
+use std::{future::Future, sync::Arc, time::Duration};
+use tracing::warn;
+
+/// Wraps another [`EmailSender`], retrying retryable failures with exponential
+/// backoff.
+pub struct RetryingSender {
+    inner: Arc<dyn EmailSender>,
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter_factor: f64,
+}
+
+impl RetryingSender {
+    #[must_use]
+    pub fn new(inner: Arc<dyn EmailSender>, settings: &RetrySettings) -> Self {
+        Self {
+            inner,
+            max_attempts: settings.max_attempts.max(1),
+            base_delay: settings.base_delay(),
+            max_delay: settings.max_delay(),
+            jitter_factor: settings.jitter_factor.clamp(0.0, 1.0),
+        }
+    }
+
+    /// How long to wait after the given (1-based) attempt failed, or `None` if
+    /// the provider asked us to wait longer than `max_delay`. In that case, it's
+    /// better to give up and let the caller retry much later.
+    fn delay(
+        &self,
+        attempt: u32,
+        retry_after: Option<Duration>,
+    ) -> Option<Duration> {
+        if let Some(retry_after) = retry_after {
+            return (retry_after <= self.max_delay).then_some(retry_after);
+        }
+
+        let factor = 2_u32.saturating_pow(attempt.saturating_sub(1));
+        let backoff = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        // Randomly shorten the delay so that clients don't retry in lockstep.
+        Some(backoff.mul_f64(1.0 - self.jitter_factor * rand::random::<f64>()))
+    }
+
+    /// Call `send` until it succeeds, fails permanently or runs out of attempts.
+    async fn with_retries<'a, F, Fut>(&self, mut send: F) -> Result<(), Error>
+    where
+        F: FnMut() -> Fut + Send,
+        Fut: Future<Output = Result<(), Error>> + Send + 'a,
+    {
+        let mut attempt = 1;
+
+        loop {
+            match send().await {
+                Err(error)
+                    if error.is_retryable() && attempt < self.max_attempts =>
+                {
+                    let Some(delay) = self.delay(attempt, error.retry_after())
+                    else {
+                        return Err(error);
+                    };
+                    warn!(
+                        "Attempt {attempt} to send the email failed, retrying in \
+                        {}ms: {error:#}",
+                        delay.as_millis()
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                outcome => return outcome,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl EmailSender for RetryingSender {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_url: Option<&str>,
+    ) -> Result<(), Error> {
+        self.with_retries(|| {
+            self.inner.send_email(
+                recipient,
+                subject,
+                html_content,
+                text_content,
+                unsubscribe_url,
+            )
+        })
+        .await
+    }
+
+    fn max_chunk_size(&self) -> usize {
+        self.inner.max_chunk_size()
+    }
+
+    async fn send_chunk(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), Error> {
+        self.with_retries(|| {
+            self.inner
+                .send_chunk(recipients, subject, html_content, text_content)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            test_helpers::{content, email, subject},
+            BatchRecipient, EmailSender, SendGridClient,
+        },
+        RetryingSender,
+    };
+    use crate::configuration::RetrySettings;
+    use fake::{Fake, Faker};
+    use k9::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+    use wiremock::{matchers::any, Mock, MockServer, ResponseTemplate};
+
+    fn settings() -> RetrySettings {
+        RetrySettings {
+            max_attempts: 3,
+            base_delay_in_milliseconds: 10,
+            max_delay_in_milliseconds: 2000,
+            jitter_factor: 0.0,
+        }
+    }
+
+    fn sender(base_url: String, settings: &RetrySettings) -> RetryingSender {
+        let inner = SendGridClient::new(
+            email(),
+            base_url,
+            Secret::new(Faker.fake()),
+            Duration::from_millis(200),
+        );
+        RetryingSender::new(Arc::new(inner), settings)
+    }
+
+    async fn send(sender: &RetryingSender) -> Result<(), super::Error> {
+        sender
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried_until_the_email_is_sent() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let sender = sender(mock_server.uri(), &settings());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcome = send(&sender).await;
+
+        // Assert.
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn retrying_stops_after_max_attempts() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let sender = sender(mock_server.uri(), &settings());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcome = send(&sender).await;
+
+        // Assert.
+        let error = assert_err!(outcome);
+        assert!(error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let sender = sender(mock_server.uri(), &settings());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcome = send(&sender).await;
+
+        // Assert.
+        let error = assert_err!(outcome);
+        assert!(!error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn retry_after_is_honored() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let sender = sender(mock_server.uri(), &settings());
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(429).insert_header("Retry-After", "1"),
+            )
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let start = Instant::now();
+        let outcome = send(&sender).await;
+
+        // Assert.
+        assert_ok!(outcome);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn retry_after_longer_than_max_delay_gives_up() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let sender = sender(mock_server.uri(), &settings());
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(429).insert_header("Retry-After", "3600"),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcome = send(&sender).await;
+
+        // Assert.
+        let error = assert_err!(outcome);
+        assert_eq!(Some(Duration::from_secs(3600)), error.retry_after());
+    }
+
+    #[test]
+    fn delay_doubles_with_every_attempt_and_is_capped() {
+        let sender = sender(String::new(), &settings());
+
+        assert_eq!(Some(Duration::from_millis(10)), sender.delay(1, None));
+        assert_eq!(Some(Duration::from_millis(20)), sender.delay(2, None));
+        assert_eq!(Some(Duration::from_millis(2000)), sender.delay(20, None));
+    }
+
+    #[test]
+    fn jitter_only_shortens_the_delay() {
+        let settings = RetrySettings {
+            jitter_factor: 0.5,
+            ..settings()
+        };
+        let sender = sender(String::new(), &settings);
+
+        for _ in 0..100 {
+            let delay = sender.delay(2, None).unwrap();
+            assert!(delay >= Duration::from_millis(10));
+            assert!(delay <= Duration::from_millis(20));
+        }
+    }
+
+    #[tokio::test]
+    async fn chunks_are_retried_as_a_whole() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let sender = sender(mock_server.uri(), &settings());
+        let recipients: Vec<_> =
+            (0..3).map(|_| BatchRecipient::new(email())).collect();
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcomes = sender
+            .send_batch(&recipients, &subject(), &content(), &content())
+            .await;
+
+        // Assert.
+        assert_eq!(1, outcomes.len());
+        assert_ok!(&outcomes[0].result);
+    }
+}
@@ -0,0 +1,393 @@
+use super::{
+    error_for_status, http_client, list_unsubscribe_headers, BatchRecipient,
+    EmailSender, Error,
+};
+use crate::domain::SubscriberEmail;
+use async_trait::async_trait;
+use secrecy::{ExposeSecret, Secret};
+use serde_json::{json, Value};
+use std::{collections::HashMap, time::Duration};
+
+pub struct SendGridClient {
+    sender: SubscriberEmail,
+    base_url: String,
+    http_client: reqwest::Client,
+    authorization_token: Secret<String>,
+}
+
+impl SendGridClient {
+    /// Create a new `SendGridClient`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be built.
+    #[must_use]
+    pub fn new(
+        sender: SubscriberEmail,
+        base_url: String,
+        authorization_token: Secret<String>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            sender,
+            base_url,
+            authorization_token,
+            http_client: http_client(timeout),
+        }
+    }
+
+    async fn send(&self, request_body: Value) -> Result<(), Error> {
+        let url = format!("{}/v3/mail/send", self.base_url);
+        let auth_token =
+            format!("Bearer {}", self.authorization_token.expose_secret());
+
+        let response = self
+            .http_client
+            .post(url)
+            .header("Authorization", auth_token)
+            .json(&request_body)
+            .send()
+            .await?;
+
+        error_for_status(&response)
+    }
+}
+
+#[async_trait]
+impl EmailSender for SendGridClient {
+    /// Send an email to the SendGrid API.
+    #[allow(clippy::doc_markdown)]
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_url: Option<&str>,
+    ) -> Result<(), Error> {
+        let request_body = SendEmailRequest {
+            from: self.sender.as_ref(),
+            personalizations: vec![personalization(recipient, unsubscribe_url)],
+            subject,
+            html_content,
+            text_content,
+        }
+        .json();
+
+        self.send(request_body).await
+    }
+
+    /// SendGrid accepts up to 1000 personalizations per request.
+    fn max_chunk_size(&self) -> usize {
+        1000
+    }
+
+    /// Send one request with a personalization, including its substitutions, for
+    /// every recipient.
+    async fn send_chunk(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), Error> {
+        let personalizations = recipients
+            .iter()
+            .map(|recipient| {
+                let mut personalization = personalization(
+                    &recipient.email,
+                    recipient.unsubscribe_url.as_deref(),
+                );
+                if !recipient.substitutions.is_empty() {
+                    personalization["substitutions"] =
+                        json!(recipient.substitutions);
+                }
+                personalization
+            })
+            .collect();
+        let request_body = SendEmailRequest {
+            from: self.sender.as_ref(),
+            personalizations,
+            subject,
+            html_content,
+            text_content,
+        }
+        .json();
+
+        self.send(request_body).await
+    }
+}
+
+/// The personalization for one recipient. SendGrid sets headers per
+/// personalization, so every recipient can get their own unsubscribe link.
+fn personalization(
+    recipient: &SubscriberEmail,
+    unsubscribe_url: Option<&str>,
+) -> Value {
+    let mut personalization = json!({ "to": [{ "email": recipient.as_ref() }] });
+    if let Some(unsubscribe_url) = unsubscribe_url {
+        let headers: HashMap<_, _> = list_unsubscribe_headers(unsubscribe_url)
+            .into_iter()
+            .collect();
+        personalization["headers"] = json!(headers);
+    }
+    personalization
+}
+
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    personalizations: Vec<Value>,
+    subject: &'a str,
+    html_content: &'a str,
+    text_content: &'a str,
+}
+
+impl<'a> SendEmailRequest<'a> {
+    pub fn json(self) -> Value {
+        json!({
+            "personalizations": self.personalizations,
+            "from": self.from,
+            "subject": self.subject,
+            "content": [
+                {
+                    "type": "text/html",
+                    "value": self.html_content
+                },
+                {
+                    "type": "text/plain",
+                    "value": self.text_content
+                }
+            ]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            test_helpers::{content, email, subject},
+            BatchRecipient, EmailSender,
+        },
+        SendGridClient,
+    };
+    use fake::{Fake, Faker};
+    use k9::{assert_err, assert_ok};
+    use matchers::email_body_matches;
+    use secrecy::Secret;
+    use std::time::Duration;
+    use wiremock::{
+        matchers::{any, header, header_exists, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    fn email_client(base_url: String) -> SendGridClient {
+        SendGridClient::new(
+            email(),
+            base_url,
+            Secret::new(Faker.fake()),
+            Duration::from_millis(200),
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_fires_request_to_base_url() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("Authorization"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/v3/mail/send"))
+            .and(method("POST"))
+            .and(email_body_matches())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        // Assert.
+        // Assertion is done automatically by the `MockServer`: if it doesn't
+        // receive the request(s) as specified by `Mock::given(...)...`, it'll
+        // panic in its `drop()` method.
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_server_returns_200() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        // Assert.
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_server_returns_500() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        // Assert.
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_server_takes_too_long() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(500).set_delay(Duration::from_secs(180)),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        // Assert.
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_batch_puts_every_recipient_of_a_chunk_into_one_request() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<_> = (0..3)
+            .map(|index| {
+                let mut recipient = BatchRecipient::new(email());
+                recipient
+                    .substitutions
+                    .insert("-name-".to_string(), format!("Reader {index}"));
+                recipient
+            })
+            .collect();
+
+        Mock::given(path("/v3/mail/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcomes = email_client
+            .send_batch(&recipients, "Hi, -name-", &content(), &content())
+            .await;
+
+        // Assert.
+        assert_eq!(1, outcomes.len());
+        assert_eq!(0..3, outcomes[0].recipients);
+        assert_ok!(&outcomes[0].result);
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value =
+            serde_json::from_slice(&requests[0].body).unwrap();
+        let personalizations = body["personalizations"].as_array().unwrap();
+        assert_eq!(3, personalizations.len());
+        for (personalization, recipient) in
+            personalizations.iter().zip(&recipients)
+        {
+            assert_eq!(
+                recipient.email.as_ref(),
+                personalization["to"][0]["email"]
+            );
+            assert_eq!(
+                recipient.substitutions["-name-"],
+                personalization["substitutions"]["-name-"]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn every_personalization_gets_its_own_unsubscribe_headers() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let mut recipient = BatchRecipient::new(email());
+        recipient.unsubscribe_url =
+            Some("https://example.com/unsubscribe?token=abc".to_string());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcomes = email_client
+            .send_batch(&[recipient], &subject(), &content(), &content())
+            .await;
+
+        // Assert.
+        assert_ok!(&outcomes[0].result);
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value =
+            serde_json::from_slice(&requests[0].body).unwrap();
+        let headers = &body["personalizations"][0]["headers"];
+        assert_eq!(
+            "<https://example.com/unsubscribe?token=abc>",
+            headers["List-Unsubscribe"]
+        );
+        assert_eq!(
+            "List-Unsubscribe=One-Click",
+            headers["List-Unsubscribe-Post"]
+        );
+    }
+
+    mod matchers {
+        pub const fn email_body_matches() -> SendEmailBodyMatcher {
+            SendEmailBodyMatcher
+        }
+
+        pub struct SendEmailBodyMatcher;
+
+        impl wiremock::Match for SendEmailBodyMatcher {
+            fn matches(&self, request: &wiremock::Request) -> bool {
+                serde_json::from_slice(&request.body).is_ok_and(
+                    |body: serde_json::Value| {
+                        body.get("personalizations").is_some()
+                            && body.get("from").is_some()
+                            && body.get("subject").is_some()
+                            && body.get("content").is_some()
+                    },
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use super::{
+    error_for_status, http_client, list_unsubscribe_headers, EmailSender, Error,
+};
+use crate::domain::SubscriberEmail;
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::Utc;
+use secrecy::{ExposeSecret, Secret};
+use serde_json::json;
+use std::time::Duration;
+
+/// Sends emails through the Amazon SES v2 HTTP API, signing requests with
+/// Signature Version 4.
+pub struct SesClient {
+    sender: SubscriberEmail,
+    base_url: reqwest::Url,
+    region: String,
+    access_key_id: String,
+    secret_access_key: Secret<String>,
+    http_client: reqwest::Client,
+}
+
+impl SesClient {
+    /// Create a new `SesClient`. The `base_url` is usually
+    /// `https://email.{region}.amazonaws.com`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `base_url` isn't a valid URL.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be built.
+    pub fn new(
+        sender: SubscriberEmail,
+        base_url: &str,
+        region: String,
+        access_key_id: String,
+        secret_access_key: Secret<String>,
+        timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        let base_url = reqwest::Url::parse(base_url)
+            .context("the SES base URL is invalid")?;
+
+        Ok(Self {
+            sender,
+            base_url,
+            region,
+            access_key_id,
+            secret_access_key,
+            http_client: http_client(timeout),
+        })
+    }
+
+    /// The `Host` header, which has to be signed.
+    fn host(&self) -> String {
+        let host = self.base_url.host_str().unwrap_or_default();
+        match self.base_url.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl EmailSender for SesClient {
+    /// Send an email to the SES v2 API.
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_url: Option<&str>,
+    ) -> Result<(), Error> {
+        const PATH: &str = "/v2/email/outbound-emails";
+        const CONTENT_TYPE: &str = "application/json";
+
+        let headers: Vec<_> = unsubscribe_url
+            .map(list_unsubscribe_headers)
+            .into_iter()
+            .flatten()
+            .map(|(name, value)| json!({ "Name": name, "Value": value }))
+            .collect();
+        let mut content = json!({
+            "Subject": { "Data": subject, "Charset": "UTF-8" },
+            "Body": {
+                "Html": { "Data": html_content, "Charset": "UTF-8" },
+                "Text": { "Data": text_content, "Charset": "UTF-8" }
+            }
+        });
+        if !headers.is_empty() {
+            content["Headers"] = json!(headers);
+        }
+        let body = serde_json::to_vec(&json!({
+            "FromEmailAddress": self.sender.as_ref(),
+            "Destination": { "ToAddresses": [recipient.as_ref()] },
+            "Content": { "Simple": content }
+        }))
+        .map_err(|error| Error::InvalidEmail(error.into()))?;
+
+        let timestamp = Utc::now();
+        let amz_date = timestamp.format("%Y%m%dT%H%M%SZ").to_string();
+        let host = self.host();
+        let authorization = sigv4::authorization(
+            &sigv4::SigningParams {
+                access_key_id: &self.access_key_id,
+                secret_access_key: self.secret_access_key.expose_secret(),
+                region: &self.region,
+                service: "ses",
+                timestamp,
+            },
+            "POST",
+            PATH,
+            "",
+            &[
+                ("content-type", CONTENT_TYPE),
+                ("host", &host),
+                ("x-amz-date", &amz_date),
+            ],
+            &body,
+        );
+
+        let mut url = self.base_url.clone();
+        url.set_path(PATH);
+
+        let response = self
+            .http_client
+            .post(url)
+            .header("Content-Type", CONTENT_TYPE)
+            .header("X-Amz-Date", amz_date)
+            .header("Authorization", authorization)
+            .body(body)
+            .send()
+            .await?;
+
+        error_for_status(&response)
+    }
+}
+
+/// A minimal implementation of AWS Signature Version 4, enough to sign requests
+/// to the SES API.
+mod sigv4 {
+    use chrono::{DateTime, Utc};
+    use hmac::{Hmac, Mac};
+    use sha2::{Digest, Sha256};
+    use std::fmt::Write;
+
+    pub struct SigningParams<'a> {
+        pub access_key_id: &'a str,
+        pub secret_access_key: &'a str,
+        pub region: &'a str,
+        pub service: &'a str,
+        pub timestamp: DateTime<Utc>,
+    }
+
+    /// Compute the `Authorization` header for the request. The headers must have
+    /// lower-case names, be sorted by name and include `host` and `x-amz-date`.
+    pub fn authorization(
+        params: &SigningParams,
+        method: &str,
+        path: &str,
+        query: &str,
+        headers: &[(&str, &str)],
+        payload: &[u8],
+    ) -> String {
+        let amz_date = params.timestamp.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = params.timestamp.format("%Y%m%d").to_string();
+
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "{method}\n{path}\n{query}\n{canonical_headers}\n{signed_headers}\n{}",
+            hex(&Sha256::digest(payload))
+        );
+
+        let scope =
+            format!("{date}/{}/{}/aws4_request", params.region, params.service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let key = signing_key(
+            params.secret_access_key,
+            &date,
+            params.region,
+            params.service,
+        );
+        let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, \
+            SignedHeaders={signed_headers}, Signature={signature}",
+            params.access_key_id
+        )
+    }
+
+    fn signing_key(
+        secret_access_key: &str,
+        date: &str,
+        region: &str,
+        service: &str,
+    ) -> Vec<u8> {
+        let key = format!("AWS4{secret_access_key}");
+        let key = hmac_sha256(key.as_bytes(), date.as_bytes());
+        let key = hmac_sha256(&key, region.as_bytes());
+        let key = hmac_sha256(&key, service.as_bytes());
+        hmac_sha256(&key, b"aws4_request")
+    }
+
+    fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+        #[allow(clippy::expect_used)]
+        let mut mac = Hmac::<Sha256>::new_from_slice(key)
+            .expect("HMAC should accept keys of any length");
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().fold(String::new(), |mut output, byte| {
+            let _ = write!(output, "{byte:02x}");
+            output
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{authorization, hex, signing_key, SigningParams};
+        use chrono::{TimeZone, Utc};
+
+        const SECRET_ACCESS_KEY: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+
+        // The example from AWS's documentation on deriving a signing key.
+        #[test]
+        fn signing_key_matches_the_aws_example() {
+            let key =
+                signing_key(SECRET_ACCESS_KEY, "20120215", "us-east-1", "iam");
+
+            assert_eq!(
+                "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d",
+                hex(&key)
+            );
+        }
+
+        // The "get-vanilla" case from AWS's Signature Version 4 test suite.
+        #[test]
+        fn authorization_matches_the_aws_test_suite() {
+            let params = SigningParams {
+                access_key_id: "AKIDEXAMPLE",
+                secret_access_key: SECRET_ACCESS_KEY,
+                region: "us-east-1",
+                service: "service",
+                timestamp: Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap(),
+            };
+
+            let authorization = authorization(
+                &params,
+                "GET",
+                "/",
+                "",
+                &[
+                    ("host", "example.amazonaws.com"),
+                    ("x-amz-date", "20150830T123600Z"),
+                ],
+                b"",
+            );
+
+            assert_eq!(
+                "AWS4-HMAC-SHA256 \
+                Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+                SignedHeaders=host;x-amz-date, \
+                Signature=\
+                5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31",
+                authorization
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            test_helpers::{content, email, subject},
+            EmailSender,
+        },
+        SesClient,
+    };
+    use k9::assert_ok;
+    use secrecy::Secret;
+    use std::time::Duration;
+    use wiremock::{
+        matchers::{header_exists, method, path},
+        Mock, MockServer, Request, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn send_email_posts_a_signed_request_to_the_ses_api() {
+        // Arrange.
+        let mock_server = MockServer::start().await;
+        let email_client = SesClient::new(
+            email(),
+            &mock_server.uri(),
+            "eu-west-1".to_string(),
+            "AKIDEXAMPLE".to_string(),
+            Secret::new("secret".to_string()),
+            Duration::from_millis(200),
+        )
+        .unwrap();
+
+        Mock::given(header_exists("X-Amz-Date"))
+            .and(path("/v2/email/outbound-emails"))
+            .and(method("POST"))
+            .and(|request: &Request| {
+                // The matchers split header values on commas, so only the first
+                // part of `Authorization` is checked.
+                request.headers.get(&"Authorization".into()).is_some_and(
+                    |values| {
+                        values.iter().any(|value| {
+                            value.as_str().starts_with(
+                                "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/",
+                            ) && value
+                                .as_str()
+                                .ends_with("/eu-west-1/ses/aws4_request")
+                        })
+                    },
+                )
+            })
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act.
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        // Assert.
+        assert_ok!(outcome);
+    }
+}
@@ -0,0 +1,490 @@
+use super::{list_unsubscribe_headers, EmailSender, Error};
+use crate::{
+    configuration::{SmtpAuthMechanism, SmtpSettings, SmtpTls},
+    domain::SubscriberEmail,
+};
+use async_trait::async_trait;
+use lettre::{
+    message::{
+        header::{HeaderName, HeaderValue},
+        Mailbox, MultiPart,
+    },
+    transport::smtp::{
+        authentication::{Credentials, Mechanism},
+        client::{Tls, TlsParameters},
+        PoolConfig,
+    },
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use secrecy::ExposeSecret;
+use std::time::Duration;
+
+/// Sends emails to an SMTP server, such as a customer's own MTA. Connections are
+/// pooled and kept open between emails.
+pub struct SmtpClient {
+    sender: Mailbox,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpClient {
+    /// Create a new `SmtpClient`. No connection is made until the first email
+    /// is sent.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sender isn't a valid mailbox or the TLS
+    /// parameters can't be built for the host.
+    pub fn new(
+        sender: &SubscriberEmail,
+        settings: &SmtpSettings,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let sender = parse_mailbox(sender)?;
+
+        let tls = match settings.tls {
+            SmtpTls::None => Tls::None,
+            SmtpTls::StartTls => {
+                Tls::Required(TlsParameters::new(settings.host.clone())?)
+            }
+            SmtpTls::Tls => {
+                Tls::Wrapper(TlsParameters::new(settings.host.clone())?)
+            }
+        };
+        let mechanisms = settings
+            .auth_mechanisms
+            .iter()
+            .map(|mechanism| match mechanism {
+                SmtpAuthMechanism::Plain => Mechanism::Plain,
+                SmtpAuthMechanism::Login => Mechanism::Login,
+            })
+            .collect();
+        let pool_config = PoolConfig::new()
+            .max_size(settings.max_connections)
+            .idle_timeout(settings.idle_timeout());
+
+        let mut transport =
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                &settings.host,
+            )
+            .port(settings.port)
+            .tls(tls)
+            .timeout(Some(timeout))
+            .authentication(mechanisms)
+            .pool_config(pool_config);
+        if let (Some(username), Some(password)) =
+            (&settings.username, &settings.password)
+        {
+            transport = transport.credentials(Credentials::new(
+                username.clone(),
+                password.expose_secret().clone(),
+            ));
+        }
+
+        Ok(Self {
+            sender,
+            transport: transport.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl EmailSender for SmtpClient {
+    /// Send the email as a `multipart/alternative` message, with the plain text
+    /// part first so that clients prefer the HTML one.
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_url: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut message = Message::builder()
+            .from(self.sender.clone())
+            .to(parse_mailbox(recipient)?)
+            .subject(subject);
+        for (name, value) in unsubscribe_url
+            .map(list_unsubscribe_headers)
+            .into_iter()
+            .flatten()
+        {
+            message = message.raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str(name),
+                value,
+            ));
+        }
+        let message = message
+            .multipart(MultiPart::alternative_plain_html(
+                text_content.to_string(),
+                html_content.to_string(),
+            ))
+            .map_err(|error| Error::InvalidEmail(error.into()))?;
+
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+}
+
+fn parse_mailbox(email: &SubscriberEmail) -> Result<Mailbox, Error> {
+    email
+        .as_ref()
+        .parse()
+        .map_err(|error: lettre::address::AddressError| {
+            Error::InvalidEmail(error.into())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            test_helpers::{content, email, subject},
+            EmailSender,
+        },
+        SmtpClient,
+    };
+    use crate::configuration::{SmtpAuthMechanism, SmtpSettings, SmtpTls};
+    use fake_smtp_server::FakeSmtpServer;
+    use k9::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use std::time::Duration;
+
+    fn settings(server: &FakeSmtpServer) -> SmtpSettings {
+        SmtpSettings {
+            host: "127.0.0.1".to_string(),
+            port: server.port,
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+            auth_mechanisms: vec![
+                SmtpAuthMechanism::Plain,
+                SmtpAuthMechanism::Login,
+            ],
+            max_connections: 1,
+            idle_timeout_in_seconds: 60,
+        }
+    }
+
+    fn with_credentials(
+        settings: SmtpSettings,
+        auth_mechanisms: Vec<SmtpAuthMechanism>,
+    ) -> SmtpSettings {
+        SmtpSettings {
+            username: Some("user".to_string()),
+            password: Some(Secret::new("pass".to_string())),
+            auth_mechanisms,
+            ..settings
+        }
+    }
+
+    fn client(settings: &SmtpSettings) -> SmtpClient {
+        SmtpClient::new(&email(), settings, Duration::from_secs(5)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_a_multipart_alternative_message() {
+        // Arrange.
+        let server = FakeSmtpServer::start("250 OK").await;
+        let client = client(&settings(&server));
+        let recipient = email();
+
+        // Act.
+        let outcome = client
+            .send_email(&recipient, "Hello", "<p>HTML body</p>", "Text body", None)
+            .await;
+
+        // Assert.
+        assert_ok!(outcome);
+        let commands = server.commands();
+        assert!(commands.contains(&format!("RCPT TO:<{}>", recipient.as_ref())));
+        let messages = server.messages();
+        assert_eq!(1, messages.len());
+        let message = &messages[0];
+        assert!(message.contains("Content-Type: multipart/alternative"));
+        assert!(message.contains("Content-Type: text/plain; charset=utf-8"));
+        assert!(message.contains("Content-Type: text/html; charset=utf-8"));
+        assert!(message.contains("<p>HTML body</p>"));
+        assert!(message.contains("Text body"));
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_urls_are_sent_as_list_unsubscribe_headers() {
+        // Arrange.
+        let server = FakeSmtpServer::start("250 OK").await;
+        let client = client(&settings(&server));
+
+        // Act.
+        let outcome = client
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                Some("https://example.com/unsubscribe?token=abc"),
+            )
+            .await;
+
+        // Assert.
+        assert_ok!(outcome);
+        let message = &server.messages()[0];
+        assert!(message.contains(
+            "List-Unsubscribe: <https://example.com/unsubscribe?token=abc>"
+        ));
+        assert!(
+            message.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click")
+        );
+    }
+
+    #[tokio::test]
+    async fn non_ascii_subjects_are_encoded() {
+        // Arrange.
+        let server = FakeSmtpServer::start("250 OK").await;
+        let client = client(&settings(&server));
+
+        // Act.
+        let outcome = client
+            .send_email(
+                &email(),
+                "Grüße aus Tbilisi",
+                &content(),
+                &content(),
+                None,
+            )
+            .await;
+
+        // Assert.
+        assert_ok!(outcome);
+        let message = &server.messages()[0];
+        assert!(!message.contains("Grüße"));
+        assert!(message.to_lowercase().contains("subject: =?utf-8?"));
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_with_plain() {
+        // Arrange.
+        let server = FakeSmtpServer::start("250 OK").await;
+        let settings =
+            with_credentials(settings(&server), vec![SmtpAuthMechanism::Plain]);
+        let client = client(&settings);
+
+        // Act.
+        let outcome = client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        // Assert.
+        assert_ok!(outcome);
+        // "\0user\0pass", base64-encoded.
+        assert!(server
+            .commands()
+            .contains(&"AUTH PLAIN AHVzZXIAcGFzcw==".to_string()));
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_with_login() {
+        // Arrange.
+        let server = FakeSmtpServer::start("250 OK").await;
+        let settings =
+            with_credentials(settings(&server), vec![SmtpAuthMechanism::Login]);
+        let client = client(&settings);
+
+        // Act.
+        let outcome = client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        // Assert.
+        assert_ok!(outcome);
+        let commands = server.commands();
+        let auth = commands
+            .iter()
+            .position(|command| command == "AUTH LOGIN")
+            .expect("the client should have used AUTH LOGIN");
+        // "user" and "pass", base64-encoded.
+        assert_eq!(["dXNlcg==", "cGFzcw=="], commands[auth + 1..auth + 3]);
+    }
+
+    #[tokio::test]
+    async fn connections_are_reused_between_emails() {
+        // Arrange.
+        let server = FakeSmtpServer::start("250 OK").await;
+        let client = client(&settings(&server));
+
+        // Act.
+        for _ in 0..3 {
+            assert_ok!(
+                client
+                    .send_email(&email(), &subject(), &content(), &content(), None)
+                    .await
+            );
+            // Connections are returned to the pool in the background.
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        // Assert.
+        assert_eq!(3, server.messages().len());
+        assert_eq!(1, server.connections());
+    }
+
+    #[tokio::test]
+    async fn temporary_rejections_are_retryable() {
+        // Arrange.
+        let server = FakeSmtpServer::start("451 4.3.0 Try again later").await;
+        let client = client(&settings(&server));
+
+        // Act.
+        let outcome = client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        // Assert.
+        let error = assert_err!(outcome);
+        assert!(error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn permanent_rejections_are_not_retryable() {
+        // Arrange.
+        let server = FakeSmtpServer::start("550 5.1.1 No such user").await;
+        let client = client(&settings(&server));
+
+        // Act.
+        let outcome = client
+            .send_email(&email(), &subject(), &content(), &content(), None)
+            .await;
+
+        // Assert.
+        let error = assert_err!(outcome);
+        assert!(!error.is_retryable());
+    }
+
+    /// A stand-in for an SMTP server that accepts just enough of the protocol to
+    /// receive emails, recording everything it's sent.
+    mod fake_smtp_server {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        };
+        use tokio::{
+            io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+            net::{TcpListener, TcpStream},
+        };
+
+        #[derive(Default)]
+        struct Recording {
+            commands: Mutex<Vec<String>>,
+            messages: Mutex<Vec<String>>,
+            connections: AtomicUsize,
+        }
+
+        pub struct FakeSmtpServer {
+            pub port: u16,
+            recording: Arc<Recording>,
+        }
+
+        impl FakeSmtpServer {
+            /// Start listening on a random port. Recipients are answered with
+            /// `rcpt_reply`, and everything else is accepted.
+            pub async fn start(rcpt_reply: &'static str) -> Self {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let port = listener.local_addr().unwrap().port();
+                let recording = Arc::new(Recording::default());
+
+                let server_recording = recording.clone();
+                tokio::spawn(async move {
+                    while let Ok((stream, _)) = listener.accept().await {
+                        server_recording
+                            .connections
+                            .fetch_add(1, Ordering::SeqCst);
+                        tokio::spawn(handle_connection(
+                            stream,
+                            server_recording.clone(),
+                            rcpt_reply,
+                        ));
+                    }
+                });
+
+                Self { port, recording }
+            }
+
+            pub fn commands(&self) -> Vec<String> {
+                self.recording.commands.lock().unwrap().clone()
+            }
+
+            pub fn messages(&self) -> Vec<String> {
+                self.recording.messages.lock().unwrap().clone()
+            }
+
+            pub fn connections(&self) -> usize {
+                self.recording.connections.load(Ordering::SeqCst)
+            }
+        }
+
+        async fn handle_connection(
+            stream: TcpStream,
+            recording: Arc<Recording>,
+            rcpt_reply: &'static str,
+        ) -> std::io::Result<()> {
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            let mut line = String::new();
+            // How many more lines of an `AUTH LOGIN` exchange are expected.
+            let mut pending_login_lines = 0;
+
+            writer.write_all(b"220 localhost ESMTP\r\n").await?;
+
+            loop {
+                line.clear();
+                if reader.read_line(&mut line).await? == 0 {
+                    return Ok(());
+                }
+                let command = line.trim_end().to_string();
+                recording.commands.lock().unwrap().push(command.clone());
+                let verb = command.to_ascii_uppercase();
+
+                let reply = if pending_login_lines == 2 {
+                    pending_login_lines = 1;
+                    "334 UGFzc3dvcmQ6"
+                } else if pending_login_lines == 1 {
+                    pending_login_lines = 0;
+                    "235 2.7.0 Authentication successful"
+                } else if verb.starts_with("EHLO") {
+                    "250-localhost\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME"
+                } else if verb.starts_with("AUTH PLAIN") {
+                    "235 2.7.0 Authentication successful"
+                } else if verb == "AUTH LOGIN" {
+                    pending_login_lines = 2;
+                    "334 VXNlcm5hbWU6"
+                } else if verb.starts_with("RCPT") {
+                    rcpt_reply
+                } else if verb == "DATA" {
+                    writer
+                        .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                        .await?;
+                    let mut message = String::new();
+                    loop {
+                        line.clear();
+                        if reader.read_line(&mut line).await? == 0 {
+                            return Ok(());
+                        }
+                        if line == ".\r\n" {
+                            break;
+                        }
+                        message.push_str(&line);
+                    }
+                    recording.messages.lock().unwrap().push(message);
+                    "250 2.0.0 OK"
+                } else if verb == "QUIT" {
+                    writer.write_all(b"221 Bye\r\n").await?;
+                    return Ok(());
+                } else {
+                    // `MAIL`, `RSET` and `NOOP`.
+                    "250 OK"
+                };
+
+                writer.write_all(format!("{reply}\r\n").as_bytes()).await?;
+            }
+        }
+    }
+}
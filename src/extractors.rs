@@ -1,4 +1,7 @@
+pub(crate) use authenticated_user::basic_authentication;
+pub use authenticated_user::AuthenticatedUser;
 pub use validated_form::ValidatedForm;
+pub use validated_json::ValidatedJson;
 
 mod validated_form {
     use async_trait::async_trait;
@@ -70,3 +73,200 @@ mod validated_form {
         }
     }
 }
+
+mod validated_json {
+    use async_trait::async_trait;
+    use axum::{
+        extract::{rejection::JsonRejection, FromRequest},
+        http::{Request, StatusCode},
+        response::{IntoResponse, Response},
+        Json,
+    };
+    use inspect_error::InspectError;
+    use serde::de::DeserializeOwned;
+    use thiserror::Error;
+    use tracing::error;
+    use validator::{Validate, ValidationErrors};
+
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct ValidatedJson<T>(pub T);
+
+    #[async_trait]
+    impl<T, S, B> FromRequest<S, B> for ValidatedJson<T>
+    where
+        T: DeserializeOwned + Validate,
+        S: Send + Sync,
+        Json<T>: FromRequest<S, B, Rejection = JsonRejection>,
+        B: Send + 'static,
+    {
+        type Rejection = Error;
+
+        #[tracing::instrument(name = "Validating JSON input", skip_all)]
+        async fn from_request(
+            request: Request<B>,
+            state: &S,
+        ) -> Result<Self, Self::Rejection> {
+            let Json(value) = Json::<T>::from_request(request, state)
+                .await
+                .inspect_error(|error| {
+                    error!("Failed to deserialize the JSON body: {error}");
+                })?;
+
+            value.validate().inspect_error(|errors| {
+                error!("Failed to validate the JSON body: [{errors}]");
+            })?;
+
+            Ok(Self(value))
+        }
+    }
+
+    #[derive(Debug, Error)]
+    pub enum Error {
+        #[error(transparent)]
+        AxumJsonRejection(#[from] JsonRejection),
+        #[error(transparent)]
+        ValidationError(#[from] ValidationErrors),
+    }
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> Response {
+            match self {
+                Self::ValidationError(_) => {
+                    let message = format!("Input validation error: [{self}]")
+                        .replace('\n', ", ");
+                    (StatusCode::UNPROCESSABLE_ENTITY, message).into_response()
+                }
+                Self::AxumJsonRejection(rejection) => rejection.into_response(),
+            }
+        }
+    }
+}
+
+mod authenticated_user {
+    use crate::{
+        authentication::{validate_credentials, AuthError, Credentials},
+        session::Session,
+    };
+    use anyhow::Context;
+    use async_trait::async_trait;
+    use axum::{
+        extract::{FromRef, FromRequestParts},
+        http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+        response::{IntoResponse, Response},
+    };
+    use base64::Engine;
+    use inspect_error::InspectError;
+    use secrecy::Secret;
+    use sqlx::PgPool;
+    use tracing::{error, field::display, Span};
+    use uuid::Uuid;
+
+    /// The user making the request, authenticated either by their session or,
+    /// failing that, with HTTP Basic auth.
+    ///
+    /// Using this extractor in a handler is what makes the route require
+    /// authentication.
+    #[derive(Debug, Clone, Copy)]
+    pub struct AuthenticatedUser {
+        pub user_id: Uuid,
+    }
+
+    #[async_trait]
+    impl<S> FromRequestParts<S> for AuthenticatedUser
+    where
+        PgPool: FromRef<S>,
+        S: Send + Sync,
+    {
+        type Rejection = Error;
+
+        #[tracing::instrument(
+            name = "Authenticating the user",
+            skip_all,
+            fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+        )]
+        async fn from_request_parts(
+            parts: &mut Parts,
+            state: &S,
+        ) -> Result<Self, Self::Rejection> {
+            // Authenticated already by `reject_anonymous_users`.
+            if let Some(user) = parts.extensions.get::<Self>() {
+                Span::current().record("user_id", display(user.user_id));
+                return Ok(*user);
+            }
+            if let Some(user_id) =
+                parts.extensions.get::<Session>().and_then(Session::user_id)
+            {
+                Span::current().record("user_id", display(user_id));
+                return Ok(Self { user_id });
+            }
+
+            let credentials = basic_authentication(&parts.headers)
+                .map_err(AuthError::InvalidCredentials)
+                .inspect_error(|error| {
+                    error!("Failed to parse the credentials: {error:#}");
+                })?;
+            Span::current().record("username", display(&credentials.username));
+
+            let connection_pool = PgPool::from_ref(state);
+            let user_id = validate_credentials(credentials, &connection_pool)
+                .await
+                .inspect_error(|error| {
+                    error!("Failed to authenticate the user: {error:#}");
+                })?;
+            Span::current().record("user_id", display(user_id));
+
+            Ok(Self { user_id })
+        }
+    }
+
+    /// Read the credentials of the `Authorization` header.
+    pub(crate) fn basic_authentication(
+        headers: &HeaderMap,
+    ) -> anyhow::Result<Credentials> {
+        let header_value = headers
+            .get(header::AUTHORIZATION)
+            .context("the 'Authorization' header was missing")?
+            .to_str()
+            .context("the 'Authorization' header was not a valid UTF-8 string")?;
+        let encoded_segment = header_value
+            .strip_prefix("Basic ")
+            .context("the authorization scheme was not 'Basic'")?;
+        let decoded_bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded_segment)
+            .context("failed to base64-decode the 'Basic' credentials")?;
+        let decoded_credentials = String::from_utf8(decoded_bytes)
+            .context("the decoded credentials are not valid UTF-8")?;
+
+        // Split into two segments, using ':' as the delimiter.
+        let (username, password) = decoded_credentials
+            .split_once(':')
+            .context("the credentials must be of the form 'username:password'")?;
+
+        Ok(Credentials {
+            username: username.to_string(),
+            password: Secret::new(password.to_string()),
+        })
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error(transparent)]
+    pub struct Error(#[from] AuthError);
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> Response {
+            match self.0 {
+                AuthError::InvalidCredentials(_) => {
+                    let mut response = StatusCode::UNAUTHORIZED.into_response();
+                    response.headers_mut().insert(
+                        header::WWW_AUTHENTICATE,
+                        HeaderValue::from_static(r#"Basic realm="publish""#),
+                    );
+                    response
+                }
+                AuthError::UnexpectedError(_) => {
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Preparing the HTML editors write for sending: CSS is inlined, a plain text
+//! version is derived if there isn't one, and emails that mail clients would
+//! cut short are flagged.
+
+pub use inline_css::inline_css;
+pub(crate) use plain_text::decode_entities;
+pub use plain_text::plain_text;
+
+mod inline_css;
+mod plain_text;
+
+use tracing::warn;
+
+/// Gmail clips messages whose HTML is larger than about 102KB, hiding the rest
+/// behind a "View entire message" link.
+pub const GMAIL_CLIPPING_THRESHOLD: usize = 102 * 1024;
+
+#[derive(Debug)]
+pub struct PreparedEmail {
+    pub html: String,
+    pub text: String,
+}
+
+/// Inline the CSS of `html` and derive the plain text version from it, unless
+/// one is given.
+///
+/// # Errors
+///
+/// Will return an error if the HTML couldn't be processed.
+pub fn prepare(
+    html: &str,
+    text: Option<&str>,
+) -> Result<PreparedEmail, lol_html::errors::RewritingError> {
+    let html = inline_css(html)?;
+    let text = match text {
+        Some(text) => text.to_string(),
+        None => plain_text(&html)?,
+    };
+
+    Ok(PreparedEmail { html, text })
+}
+
+/// A warning for the editor if `html` is large enough for Gmail to clip it.
+/// It should be the HTML as it's sent, after anything that adds to it, such as
+/// tracking.
+#[must_use]
+pub fn clipping_warning(html: &str) -> Option<String> {
+    if html.len() <= GMAIL_CLIPPING_THRESHOLD {
+        return None;
+    }
+
+    let warning = format!(
+        "The HTML is {}KB, so Gmail will clip it (the limit is about {}KB).",
+        html.len().div_ceil(1024),
+        GMAIL_CLIPPING_THRESHOLD / 1024
+    );
+    warn!("{warning}");
+    Some(warning)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clipping_warning, prepare, GMAIL_CLIPPING_THRESHOLD};
+
+    #[test]
+    fn the_plain_text_is_derived_if_missing() {
+        let email = prepare("<h1>Hi</h1><p>There</p>", None).unwrap();
+
+        assert_eq!("Hi\n==\n\nThere", email.text);
+    }
+
+    #[test]
+    fn a_given_plain_text_is_kept() {
+        let email = prepare("<p>Hi</p>", Some("Hello")).unwrap();
+
+        assert_eq!("Hello", email.text);
+    }
+
+    #[test]
+    fn large_emails_get_a_warning() {
+        let html = format!("<p>{}</p>", "a".repeat(GMAIL_CLIPPING_THRESHOLD));
+
+        let warning = clipping_warning(&html);
+
+        assert!(warning.unwrap().contains("Gmail"));
+    }
+
+    #[test]
+    fn small_emails_get_no_warnings() {
+        assert_eq!(None, clipping_warning("<p>Hi</p>"));
+    }
+}
@@ -0,0 +1,355 @@
+use lol_html::{
+    element,
+    errors::RewritingError,
+    html_content::{ContentType, Element},
+    rewrite_str, text, ElementContentHandlers, RewriteStrSettings, Selector,
+};
+use std::{borrow::Cow, cell::RefCell, rc::Rc};
+
+/// Where an element's own `style` attribute is kept while the stylesheet's
+/// declarations are applied, so that it can be put back last and win.
+const ORIGINAL_STYLE: &str = "data-original-style";
+
+/// Move the rules in `<style>` blocks into the `style` attributes of the
+/// elements they match, since many mail clients strip `<style>` blocks.
+///
+/// Declarations are applied in order of specificity (and source order for
+/// equally specific rules), and an element's own `style` attribute still takes
+/// precedence. Rules that can't be inlined, such as `@media` queries and
+/// selectors with pseudo-classes like `:hover`, are kept in a `<style>` block
+/// in the `<head>`.
+///
+/// # Errors
+///
+/// Will return an error if the HTML couldn't be processed.
+pub fn inline_css(html: &str) -> Result<String, RewritingError> {
+    let (html, css) = extract_style_blocks(html)?;
+    if css.trim().is_empty() {
+        return Ok(html);
+    }
+
+    let Stylesheet {
+        mut rules,
+        leftover,
+    } = parse_stylesheet(&css);
+    rules.sort_by_key(|rule| rule.specificity);
+
+    let mut handlers = vec![element!("[style]", |element| {
+        if let Some(style) = element.get_attribute("style") {
+            element.set_attribute(ORIGINAL_STYLE, &style)?;
+            element.remove_attribute("style");
+        }
+        Ok(())
+    })];
+    for Rule {
+        selector,
+        declarations,
+        ..
+    } in rules
+    {
+        handlers.push((
+            Cow::Owned(selector),
+            ElementContentHandlers::default().element(move |element| {
+                append_style(element, &declarations)?;
+                Ok(())
+            }),
+        ));
+    }
+    // Selectors are matched before any handler runs, so this can't select
+    // on the attribute set by the first handler.
+    handlers.push(element!("*", |element| {
+        if let Some(style) = element.get_attribute(ORIGINAL_STYLE) {
+            element.remove_attribute(ORIGINAL_STYLE);
+            append_style(element, &style)?;
+        }
+        Ok(())
+    }));
+
+    let leftover = leftover.trim().to_string();
+    let leftover_placed = Rc::new(RefCell::new(leftover.is_empty()));
+    if !leftover.is_empty() {
+        let leftover_placed = leftover_placed.clone();
+        let style_block = format!("<style>{leftover}</style>");
+        handlers.push(element!("head", move |element| {
+            element.append(&style_block, ContentType::Html);
+            *leftover_placed.borrow_mut() = true;
+            Ok(())
+        }));
+    }
+
+    let mut html = rewrite_str(
+        &html,
+        RewriteStrSettings {
+            element_content_handlers: handlers,
+            ..RewriteStrSettings::default()
+        },
+    )?;
+
+    // Fragments without a `<head>` get the block at the top.
+    if !*leftover_placed.borrow() {
+        html.insert_str(0, &format!("<style>{leftover}</style>"));
+    }
+
+    Ok(html)
+}
+
+/// Remove the `<style>` blocks and return their contents.
+fn extract_style_blocks(html: &str) -> Result<(String, String), RewritingError> {
+    let css = Rc::new(RefCell::new(String::new()));
+
+    let html = {
+        let css = css.clone();
+        rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    text!("style", move |chunk| {
+                        css.borrow_mut().push_str(chunk.as_str());
+                        if chunk.last_in_text_node() {
+                            css.borrow_mut().push('\n');
+                        }
+                        Ok(())
+                    }),
+                    element!("style", |element| {
+                        element.remove();
+                        Ok(())
+                    }),
+                ],
+                ..RewriteStrSettings::default()
+            },
+        )?
+    };
+
+    let css = css.take();
+    Ok((html, css))
+}
+
+fn append_style(
+    element: &mut Element,
+    declarations: &str,
+) -> Result<(), lol_html::errors::AttributeNameError> {
+    let declarations = declarations.trim().trim_end_matches(';').trim();
+    if declarations.is_empty() {
+        return Ok(());
+    }
+
+    let style = match element.get_attribute("style") {
+        Some(existing) if !existing.trim().is_empty() => {
+            format!("{}; {declarations}", existing.trim().trim_end_matches(';'))
+        }
+        _ => declarations.to_string(),
+    };
+    element.set_attribute("style", &style)
+}
+
+struct Rule {
+    selector: Selector,
+    declarations: String,
+    specificity: (u32, u32, u32),
+}
+
+struct Stylesheet {
+    rules: Vec<Rule>,
+    /// The CSS that can't be inlined, to be kept in a `<style>` block.
+    leftover: String,
+}
+
+/// A deliberately small CSS parser: it only needs to split the stylesheet into
+/// rules, not understand the declarations.
+fn parse_stylesheet(css: &str) -> Stylesheet {
+    let css = strip_comments(css);
+    let mut rules = Vec::new();
+    let mut leftover = String::new();
+    let mut rest = css.as_str();
+
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+
+        if rest.starts_with('@') {
+            // At-rules are either statements (`@import ...;`) or blocks
+            // (`@media ... { ... }`), which may contain nested blocks.
+            let statement_end = rest.find(';');
+            let block_start = rest.find('{');
+            let end = match (statement_end, block_start) {
+                (Some(statement_end), Some(block_start))
+                    if statement_end < block_start =>
+                {
+                    statement_end + 1
+                }
+                (_, Some(block_start)) => matching_brace(rest, block_start)
+                    .map_or(rest.len(), |end| end + 1),
+                (Some(statement_end), None) => statement_end + 1,
+                (None, None) => rest.len(),
+            };
+            leftover.push_str(rest[..end].trim());
+            leftover.push('\n');
+            rest = &rest[end..];
+            continue;
+        }
+
+        let Some(block_start) = rest.find('{') else {
+            break;
+        };
+        let block_end = rest[block_start..]
+            .find('}')
+            .map_or(rest.len(), |end| block_start + end);
+        let selectors = rest[..block_start].trim();
+        let declarations = rest[block_start + 1..block_end].trim();
+        rest = rest.get(block_end + 1..).unwrap_or_default();
+
+        for selector in selectors.split(',').map(str::trim) {
+            match parse_selector(selector) {
+                Some(parsed) => rules.push(Rule {
+                    selector: parsed,
+                    declarations: declarations.to_string(),
+                    specificity: specificity(selector),
+                }),
+                None => {
+                    leftover
+                        .push_str(&format!("{selector} {{ {declarations} }}\n"));
+                }
+            }
+        }
+    }
+
+    Stylesheet { rules, leftover }
+}
+
+/// Pseudo-classes and pseudo-elements describe states and parts of elements
+/// that don't exist in the markup, so rules with them can't be inlined.
+fn parse_selector(selector: &str) -> Option<Selector> {
+    if selector.is_empty() || selector.contains(':') {
+        return None;
+    }
+    selector.parse().ok()
+}
+
+/// The specificity of a selector as (IDs, classes and attributes, types).
+fn specificity(selector: &str) -> (u32, u32, u32) {
+    let mut specificity = (0, 0, 0);
+
+    for compound in selector.split([' ', '>', '+', '~']) {
+        let compound = compound.trim();
+        if compound.starts_with(|character: char| character.is_ascii_alphabetic())
+        {
+            specificity.2 += 1;
+        }
+        for character in compound.chars() {
+            match character {
+                '#' => specificity.0 += 1,
+                '.' | '[' => specificity.1 += 1,
+                _ => {}
+            }
+        }
+    }
+
+    specificity
+}
+
+fn matching_brace(css: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (index, character) in css[open..].char_indices() {
+        match character {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(open + index);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut stripped = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        stripped.push_str(&rest[..start]);
+        rest = rest[start + 2..]
+            .find("*/")
+            .map_or("", |end| &rest[start + 2 + end + 2..]);
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{inline_css, specificity};
+
+    #[test]
+    fn rules_are_moved_into_style_attributes() {
+        let html = inline_css(
+            "<html><head><style>p { color: red; } .note { font-size: 12px }</style>\
+            </head><body><p class=\"note\">Hi</p></body></html>",
+        )
+        .unwrap();
+
+        assert_eq!(
+            "<html><head></head><body>\
+            <p class=\"note\" style=\"color: red; font-size: 12px\">Hi</p>\
+            </body></html>",
+            html
+        );
+    }
+
+    #[test]
+    fn more_specific_rules_come_later() {
+        let html = inline_css(
+            "<style>#main { color: blue } p.note { color: green } p { color: red }\
+            </style><p id=\"main\" class=\"note\">Hi</p>",
+        )
+        .unwrap();
+
+        assert!(html.contains(r#"style="color: red; color: green; color: blue""#));
+    }
+
+    #[test]
+    fn existing_style_attributes_take_precedence() {
+        let html = inline_css(
+            "<style>p { color: red }</style><p style=\"color: blue;\">Hi</p>",
+        )
+        .unwrap();
+
+        assert!(html.contains(r#"<p style="color: red; color: blue">Hi</p>"#));
+        assert!(!html.contains("data-original-style"));
+    }
+
+    #[test]
+    fn rules_that_cannot_be_inlined_are_kept() {
+        let html = inline_css(
+            "<html><head><style>\
+            /* Links */ a { color: red } a:hover { color: blue }\
+            @media (max-width: 600px) { p { font-size: 18px } }\
+            </style></head><body><a href=\"#\">Link</a></body></html>",
+        )
+        .unwrap();
+
+        assert!(html.contains(r##"<a href="#" style="color: red">Link</a>"##));
+        assert!(html.contains("a:hover { color: blue }"));
+        assert!(
+            html.contains("@media (max-width: 600px) { p { font-size: 18px } }")
+        );
+        assert!(!html.contains("Links"));
+    }
+
+    #[test]
+    fn html_without_style_blocks_is_left_alone() {
+        let html = "<p style=\"color: red\">Hi</p>";
+
+        assert_eq!(html, inline_css(html).unwrap());
+    }
+
+    #[test]
+    fn specificity_counts_ids_classes_and_types() {
+        assert_eq!((0, 0, 1), specificity("p"));
+        assert_eq!((0, 1, 1), specificity("p.note"));
+        assert_eq!((1, 1, 2), specificity("div#main > a[href]"));
+    }
+}
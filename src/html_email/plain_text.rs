@@ -0,0 +1,441 @@
+use lol_html::{
+    doc_text, element, errors::RewritingError, html_content::Element, rewrite_str,
+    RewriteStrSettings,
+};
+use std::{cell::RefCell, rc::Rc};
+
+/// Derive a readable plain text version of an HTML email. Headings are
+/// underlined, list items are bulleted and links are turned into numbered
+/// footnotes, listed at the end.
+///
+/// # Errors
+///
+/// Will return an error if the HTML couldn't be processed.
+pub fn plain_text(html: &str) -> Result<String, RewritingError> {
+    let builder = Rc::new(RefCell::new(TextBuilder::default()));
+
+    let on_element = {
+        let builder = builder.clone();
+        move |element: &mut Element| {
+            let tag_name = element.tag_name();
+            builder.borrow_mut().start(&tag_name, element);
+
+            if let Some(handlers) = element.end_tag_handlers() {
+                let builder = builder.clone();
+                handlers.push(Box::new(move |_| {
+                    builder.borrow_mut().end(&tag_name);
+                    Ok(())
+                }));
+            }
+
+            Ok(())
+        }
+    };
+    let on_text = {
+        let builder = builder.clone();
+        let mut text_node = String::new();
+        move |chunk: &mut lol_html::html_content::TextChunk| {
+            // Text nodes may arrive in several chunks, which could split an
+            // entity in two.
+            text_node.push_str(chunk.as_str());
+            if chunk.last_in_text_node() {
+                builder.borrow_mut().text(&text_node);
+                text_node.clear();
+            }
+            Ok(())
+        }
+    };
+
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("*", on_element)],
+            document_content_handlers: vec![doc_text!(on_text)],
+            ..RewriteStrSettings::default()
+        },
+    )?;
+
+    let builder = builder.take();
+    Ok(builder.finish())
+}
+
+#[derive(Default)]
+struct TextBuilder {
+    output: String,
+    /// How many line breaks to put before the next piece of text.
+    pending_breaks: usize,
+    /// Text that's being collected for an open heading or link, innermost
+    /// last. It's added to the output once the element is closed.
+    captures: Vec<String>,
+    /// The targets of the open links, innermost last.
+    open_links: Vec<Option<String>>,
+    links: Vec<String>,
+    /// How many elements whose text isn't shown (like `<style>`) are open.
+    hidden_depth: usize,
+    preformatted_depth: usize,
+}
+
+impl TextBuilder {
+    fn start(&mut self, tag_name: &str, element: &Element) {
+        match tag_name {
+            "head" | "script" | "style" | "title" | "template" => {
+                self.hidden_depth += 1;
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.block_break(2);
+                self.captures.push(String::new());
+            }
+            "a" => {
+                let href = element
+                    .get_attribute("href")
+                    .map(|href| decode_entities(href.trim()));
+                self.open_links.push(href);
+                self.captures.push(String::new());
+            }
+            "p" | "ul" | "ol" | "table" | "blockquote" => self.block_break(2),
+            "pre" => {
+                self.block_break(2);
+                self.preformatted_depth += 1;
+            }
+            "div" | "tr" | "section" | "article" | "header" | "footer" => {
+                self.block_break(1);
+            }
+            "li" => {
+                self.block_break(1);
+                self.push("- ");
+            }
+            "br" => self.line_break(),
+            "hr" => {
+                self.block_break(2);
+                self.push("----------");
+                self.block_break(2);
+            }
+            "img" => {
+                if let Some(alt) = element.get_attribute("alt") {
+                    let alt = decode_entities(alt.trim());
+                    if !alt.is_empty() {
+                        self.push(&alt);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag_name: &str) {
+        match tag_name {
+            "head" | "script" | "style" | "title" | "template" => {
+                self.hidden_depth = self.hidden_depth.saturating_sub(1);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let heading = self.captures.pop().unwrap_or_default();
+                let heading = heading.trim();
+                if !heading.is_empty() {
+                    let underline = if tag_name == "h1" { "=" } else { "-" };
+                    self.push(heading);
+                    self.line_break();
+                    self.push(&underline.repeat(heading.chars().count()));
+                }
+                self.block_break(2);
+            }
+            "a" => {
+                let text = self.captures.pop().unwrap_or_default();
+                let text = text.trim();
+                let href = self.open_links.pop().flatten();
+                match href {
+                    // Links within the email and links that show their target
+                    // anyway don't need a footnote.
+                    Some(href)
+                        if !href.is_empty()
+                            && !href.starts_with('#')
+                            && href != text =>
+                    {
+                        self.links.push(href);
+                        let footnote = format!("[{}]", self.links.len());
+                        if text.is_empty() {
+                            self.push(&footnote);
+                        } else {
+                            self.push(&format!("{text} {footnote}"));
+                        }
+                    }
+                    _ => self.push(text),
+                }
+            }
+            "p" | "ul" | "ol" | "table" | "blockquote" => self.block_break(2),
+            "pre" => {
+                self.preformatted_depth =
+                    self.preformatted_depth.saturating_sub(1);
+                self.block_break(2);
+            }
+            "div" | "tr" | "li" | "section" | "article" | "header" | "footer" => {
+                self.block_break(1);
+            }
+            "td" | "th" => self.push(" "),
+            _ => {}
+        }
+    }
+
+    fn text(&mut self, raw: &str) {
+        if self.hidden_depth > 0 {
+            return;
+        }
+
+        let text = decode_entities(raw);
+        if self.preformatted_depth > 0 {
+            self.push_preformatted(&text);
+        } else {
+            self.push(&collapse_whitespace(&text));
+        }
+    }
+
+    fn block_break(&mut self, breaks: usize) {
+        self.pending_breaks = self.pending_breaks.max(breaks);
+    }
+
+    fn line_break(&mut self) {
+        match self.captures.last_mut() {
+            Some(capture) => capture.push(' '),
+            None => {
+                self.output.push('\n');
+                self.pending_breaks = 0;
+            }
+        }
+    }
+
+    /// Add inline text, dropping whitespace at the start of a line and
+    /// whitespace that follows other whitespace.
+    fn push(&mut self, text: &str) {
+        if let Some(capture) = self.captures.last_mut() {
+            push_collapsed(capture, text);
+            return;
+        }
+
+        if self.pending_breaks > 0 && !text.trim().is_empty() {
+            self.start_block();
+        }
+        push_collapsed(&mut self.output, text);
+    }
+
+    fn push_preformatted(&mut self, text: &str) {
+        if let Some(capture) = self.captures.last_mut() {
+            capture.push_str(text);
+            return;
+        }
+
+        if self.pending_breaks > 0 {
+            self.start_block();
+        }
+        self.output.push_str(text);
+    }
+
+    fn start_block(&mut self) {
+        let trimmed_length = self.output.trim_end_matches(' ').len();
+        self.output.truncate(trimmed_length);
+        if !self.output.is_empty() {
+            let existing_breaks =
+                self.output.len() - self.output.trim_end_matches('\n').len();
+            for _ in existing_breaks..self.pending_breaks {
+                self.output.push('\n');
+            }
+        }
+        self.pending_breaks = 0;
+    }
+
+    fn finish(self) -> String {
+        let mut text: String = self
+            .output
+            .lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim()
+            .replace('\u{a0}', " ");
+
+        if !self.links.is_empty() {
+            text.push_str("\n\n");
+            for (index, link) in self.links.iter().enumerate() {
+                text.push_str(&format!("[{}] {link}\n", index + 1));
+            }
+            text.truncate(text.trim_end().len());
+        }
+
+        text
+    }
+}
+
+fn push_collapsed(target: &mut String, text: &str) {
+    let at_whitespace = target.is_empty() || target.ends_with([' ', '\n']);
+    if at_whitespace {
+        target.push_str(text.trim_start_matches(' '));
+    } else {
+        target.push_str(text);
+    }
+}
+
+/// Turn every run of whitespace into a single space, the way browsers do.
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut previous_was_whitespace = false;
+    for character in text.chars() {
+        if character.is_ascii_whitespace() {
+            if !previous_was_whitespace {
+                collapsed.push(' ');
+            }
+            previous_was_whitespace = true;
+        } else {
+            collapsed.push(character);
+            previous_was_whitespace = false;
+        }
+    }
+    collapsed
+}
+
+/// Decode numeric character references and the named ones that are common in
+/// emails. Unknown references are left alone.
+pub(crate) fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let character = rest
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end]).map(|c| (c, end)));
+        match character {
+            Some((character, end)) => {
+                decoded.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    if let Some(number) = entity.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        // Like browsers do, and Postgres doesn't take NUL in text anyway.
+        return Some(
+            char::from_u32(code)
+                .filter(|character| *character != '\0')
+                .unwrap_or(char::REPLACEMENT_CHARACTER),
+        );
+    }
+
+    let character = match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "bull" => '•',
+        "middot" => '·',
+        "euro" => '€',
+        "pound" => '£',
+        _ => return None,
+    };
+    Some(character)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::plain_text;
+
+    #[test]
+    fn paragraphs_are_separated_by_blank_lines() {
+        let text =
+            plain_text("<p>First\n   paragraph.</p><p>Second.</p>").unwrap();
+
+        assert_eq!("First paragraph.\n\nSecond.", text);
+    }
+
+    #[test]
+    fn headings_are_underlined() {
+        let text =
+            plain_text("<h1>Title</h1><h2>Section</h2><p>Body</p>").unwrap();
+
+        assert_eq!("Title\n=====\n\nSection\n-------\n\nBody", text);
+    }
+
+    #[test]
+    fn links_become_footnotes() {
+        let text = plain_text(
+            r#"<p>Read <a href="https://example.com/a?x=1&amp;y=2">this</a> and
+            <a href="https://example.com/b">that</a>.</p>"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "Read this [1] and that [2].\n\n\
+            [1] https://example.com/a?x=1&y=2\n\
+            [2] https://example.com/b",
+            text
+        );
+    }
+
+    #[test]
+    fn links_that_show_their_target_do_not_get_a_footnote() {
+        let text =
+            plain_text(r#"<a href="https://example.com">https://example.com</a>"#)
+                .unwrap();
+
+        assert_eq!("https://example.com", text);
+    }
+
+    #[test]
+    fn list_items_are_bulleted() {
+        let text = plain_text("<ul><li>One</li><li>Two</li></ul>").unwrap();
+
+        assert_eq!("- One\n- Two", text);
+    }
+
+    #[test]
+    fn styles_scripts_and_the_head_are_left_out() {
+        let text = plain_text(
+            "<html><head><title>Title</title><style>p { color: red; }</style>\
+            </head><body><script>alert(1)</script><p>Body</p></body></html>",
+        )
+        .unwrap();
+
+        assert_eq!("Body", text);
+    }
+
+    #[test]
+    fn entities_are_decoded() {
+        let text =
+            plain_text("<p>Tom &amp; Jerry&nbsp;&#8212; &lt;3 &unknown;</p>")
+                .unwrap();
+
+        assert_eq!("Tom & Jerry — <3 &unknown;", text);
+    }
+
+    #[test]
+    fn preformatted_text_keeps_its_whitespace() {
+        let text = plain_text("<pre>fn main() {\n    ok();\n}</pre>").unwrap();
+
+        assert_eq!("fn main() {\n    ok();\n}", text);
+    }
+}
@@ -0,0 +1,259 @@
+//! Saved responses for requests that carry an idempotency key, so that retried
+//! requests replay the original outcome instead of repeating its side effects.
+//!
+//! [`try_processing`] claims the key before the request is handled, and
+//! [`save_response`] stores the outcome in the same transaction as the side
+//! effects, so a key is never saved without them (or the other way around).
+
+pub use persistence::{save_response, try_processing, NextAction};
+pub use purge::{purge_expired_keys, purge_expired_keys_until_stopped};
+
+mod persistence {
+    use crate::domain::IdempotencyKey;
+    use anyhow::Context;
+    use axum::{
+        body::{boxed, Full},
+        http::StatusCode,
+        response::{IntoResponse, Response},
+    };
+    use inspect_error::InspectError;
+    use sqlx::{
+        postgres::{PgHasArrayType, PgTypeInfo},
+        PgPool, Postgres, Transaction,
+    };
+    use tracing::error;
+    use uuid::Uuid;
+
+    #[derive(Debug, sqlx::Type)]
+    #[sqlx(type_name = "header_pair")]
+    struct HeaderPairRecord {
+        name: String,
+        value: Vec<u8>,
+    }
+
+    impl PgHasArrayType for HeaderPairRecord {
+        fn array_type_info() -> PgTypeInfo {
+            PgTypeInfo::with_name("_header_pair")
+        }
+    }
+
+    pub enum NextAction {
+        /// The key hasn't been seen before. Handle the request inside the
+        /// transaction and hand it over to [`save_response`] afterwards.
+        StartProcessing(Box<Transaction<'static, Postgres>>),
+        /// The key has already been used. Return this instead of handling the
+        /// request again.
+        ReturnSavedResponse(Response),
+    }
+
+    /// Claim the idempotency key for the user, or get the response that was
+    /// saved for it.
+    ///
+    /// If a request with the same key is still being handled, this waits until
+    /// its transaction ends.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if something goes wrong talking to the database.
+    #[tracing::instrument(name = "Claiming the idempotency key", skip_all)]
+    pub async fn try_processing(
+        connection_pool: &PgPool,
+        idempotency_key: &IdempotencyKey,
+        user_id: Uuid,
+    ) -> anyhow::Result<NextAction> {
+        let mut transaction = connection_pool
+            .begin()
+            .await
+            .inspect_error(|error| {
+                error!("Failed to begin a transaction: {error}")
+            })
+            .context("failed to begin a transaction")?;
+
+        // The insert blocks on the row lock held by a concurrent request with
+        // the same key, so this only returns once that request is done.
+        let n_inserted_rows = sqlx::query!(
+            r#"
+            INSERT INTO idempotency (user_id, idempotency_key)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+            user_id,
+            idempotency_key.as_ref()
+        )
+        .execute(&mut transaction)
+        .await
+        .inspect_error(|error| error!("Failed to execute query: {error}"))
+        .context("failed to insert the idempotency key")?
+        .rows_affected();
+
+        if n_inserted_rows > 0 {
+            return Ok(NextAction::StartProcessing(Box::new(transaction)));
+        }
+
+        let saved_response =
+            get_saved_response(connection_pool, idempotency_key, user_id)
+                .await?
+                .unwrap_or_else(|| {
+                    // Only possible if the key was purged in the meantime.
+                    (
+                        StatusCode::CONFLICT,
+                        "a request with this idempotency key is being processed",
+                    )
+                        .into_response()
+                });
+
+        Ok(NextAction::ReturnSavedResponse(saved_response))
+    }
+
+    async fn get_saved_response(
+        connection_pool: &PgPool,
+        idempotency_key: &IdempotencyKey,
+        user_id: Uuid,
+    ) -> anyhow::Result<Option<Response>> {
+        let saved_response = sqlx::query!(
+            r#"
+            SELECT
+                response_status_code as "response_status_code!",
+                response_headers as "response_headers!: Vec<HeaderPairRecord>",
+                response_body as "response_body!"
+            FROM idempotency
+            WHERE
+                user_id = $1 AND
+                idempotency_key = $2 AND
+                response_status_code IS NOT NULL
+            "#,
+            user_id,
+            idempotency_key.as_ref()
+        )
+        .fetch_optional(connection_pool)
+        .await
+        .inspect_error(|error| error!("Failed to execute query: {error}"))
+        .context("failed to retrieve the saved response")?;
+
+        let Some(record) = saved_response else {
+            return Ok(None);
+        };
+
+        let status_code = u16::try_from(record.response_status_code)
+            .context("the saved status code is invalid")?;
+        let mut response = Response::builder().status(status_code);
+        for HeaderPairRecord { name, value } in record.response_headers {
+            response = response.header(name, value);
+        }
+        let response = response
+            .body(boxed(Full::from(record.response_body)))
+            .context("the saved response is invalid")?;
+
+        Ok(Some(response))
+    }
+
+    /// Save the response for the idempotency key claimed with
+    /// [`try_processing`] and commit the transaction.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the response body can't be read or something goes
+    /// wrong talking to the database.
+    #[tracing::instrument(name = "Saving the response", skip_all)]
+    pub async fn save_response(
+        mut transaction: Transaction<'static, Postgres>,
+        idempotency_key: &IdempotencyKey,
+        user_id: Uuid,
+        response: Response,
+    ) -> anyhow::Result<Response> {
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body)
+            .await
+            .context("failed to read the response body")?;
+
+        let status_code = i16::try_from(parts.status.as_u16())
+            .context("the status code doesn't fit in a SMALLINT")?;
+        let headers: Vec<_> = parts
+            .headers
+            .iter()
+            .map(|(name, value)| HeaderPairRecord {
+                name: name.as_str().to_owned(),
+                value: value.as_bytes().to_owned(),
+            })
+            .collect();
+
+        // `query_unchecked!` because the macros can't check custom array types.
+        sqlx::query_unchecked!(
+            r#"
+            UPDATE idempotency
+            SET
+                response_status_code = $3,
+                response_headers = $4,
+                response_body = $5
+            WHERE user_id = $1 AND idempotency_key = $2
+            "#,
+            user_id,
+            idempotency_key.as_ref(),
+            status_code,
+            headers,
+            body.as_ref()
+        )
+        .execute(&mut transaction)
+        .await
+        .inspect_error(|error| error!("Failed to execute query: {error}"))
+        .context("failed to save the response")?;
+
+        transaction
+            .commit()
+            .await
+            .inspect_error(|error| {
+                error!("Failed to commit the transaction: {error}");
+            })
+            .context("failed to commit the transaction")?;
+
+        Ok(Response::from_parts(parts, boxed(Full::from(body))))
+    }
+}
+
+mod purge {
+    use crate::configuration::IdempotencySettings;
+    use inspect_error::InspectError;
+    use sqlx::PgPool;
+    use std::time::Duration;
+    use tracing::{error, info};
+
+    /// Purge expired idempotency keys every `settings.purge_interval()`.
+    pub async fn purge_expired_keys_until_stopped(
+        connection_pool: &PgPool,
+        settings: &IdempotencySettings,
+    ) {
+        loop {
+            // Failures are already logged, and the next run will try again.
+            let _ = purge_expired_keys(connection_pool, settings.expiry()).await;
+            tokio::time::sleep(settings.purge_interval()).await;
+        }
+    }
+
+    /// Delete the idempotency keys that are older than `expiry`, so that they
+    /// can be reused and the table doesn't grow forever.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the query fails.
+    #[tracing::instrument(name = "Purging expired idempotency keys", skip_all)]
+    pub async fn purge_expired_keys(
+        connection_pool: &PgPool,
+        expiry: Duration,
+    ) -> sqlx::Result<u64> {
+        let n_deleted_rows = sqlx::query!(
+            r#"
+            DELETE FROM idempotency
+            WHERE created_at < now() - make_interval(secs => $1)
+            "#,
+            expiry.as_secs_f64()
+        )
+        .execute(connection_pool)
+        .await
+        .inspect_error(|error| error!("Failed to execute query: {error}"))?
+        .rows_affected();
+
+        info!("Purged {n_deleted_rows} expired idempotency keys");
+
+        Ok(n_deleted_rows)
+    }
+}
@@ -0,0 +1,613 @@
+use crate::{
+    configuration::{DeliveryWorkerSettings, Settings},
+    domain::SubscriberEmail,
+    email_client::{self, BatchRecipient},
+    templates, tracking, EmailSender, SubscriberLinks,
+};
+use anyhow::Context;
+use inspect_error::InspectError;
+use sqlx::{postgres::PgPoolOptions, PgPool, Postgres, Transaction};
+use std::{collections::BTreeMap, fmt::Display, time::Duration};
+use tracing::{error, info, warn, Span};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionOutcome {
+    TasksCompleted,
+    EmptyQueue,
+}
+
+/// Run the delivery worker with its own connection pool and email client. This
+/// is what the standalone `delivery_worker` binary runs.
+///
+/// # Errors
+///
+/// Doesn't return under normal circumstances: failures to execute individual
+/// tasks are logged and retried.
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+) -> anyhow::Result<()> {
+    let connection_pool = PgPoolOptions::new()
+        .acquire_timeout(Duration::from_secs(10))
+        .connect_lazy_with(configuration.database.connect_options());
+    let email_client = configuration.email_client.client()?;
+    let subscriber_links = SubscriberLinks::new(
+        configuration.application.base_url,
+        configuration.application.hmac_secret,
+    );
+
+    worker_loop(
+        &connection_pool,
+        email_client.as_ref(),
+        &subscriber_links,
+        &configuration.delivery_worker,
+    )
+    .await
+}
+
+/// Keep dequeuing and executing delivery tasks, sleeping for the configured
+/// poll interval whenever the queue is empty. `subscriber_links` are used to
+/// give every subscriber their own unsubscribe and preferences links.
+///
+/// # Errors
+///
+/// Doesn't return under normal circumstances: failures to execute individual
+/// tasks are logged and retried.
+pub async fn worker_loop(
+    connection_pool: &PgPool,
+    email_client: &dyn EmailSender,
+    subscriber_links: &SubscriberLinks,
+    settings: &DeliveryWorkerSettings,
+) -> anyhow::Result<()> {
+    loop {
+        match try_execute_batch(
+            connection_pool,
+            email_client,
+            subscriber_links,
+            settings,
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(settings.poll_interval()).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TasksCompleted) => {}
+        }
+    }
+}
+
+/// Claim up to `settings.batch_size` due delivery tasks, if there are any, and
+/// send each issue to its recipients, one chunk of at most
+/// [`EmailSender::max_chunk_size`] recipients at a time.
+///
+/// Claimed tasks are left alone by other workers for `settings.lease()`. The
+/// outcome of every chunk is saved as soon as it's known, outside of any
+/// transaction spanning the sending, so that a failure later on can't undo it
+/// and have the emails sent again. Tasks of a worker that dies are picked up
+/// again once their lease runs out.
+///
+/// Tasks in a chunk that fails with a transient error are postponed with
+/// exponential backoff until `settings.max_retries` is reached, and so are the
+/// tasks of an issue that can't be loaded, without holding back the other
+/// issues. Tasks that fail for any other reason are dropped, as are the tasks
+/// of subscribers who have unsubscribed or been suppressed since the issue was
+/// published. Deliveries that are given up on are recorded as `failed` in
+/// `delivery_events`, along with the error.
+///
+/// # Errors
+///
+/// Will return an error if something goes wrong talking to the database.
+#[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
+pub async fn try_execute_batch(
+    connection_pool: &PgPool,
+    email_client: &dyn EmailSender,
+    subscriber_links: &SubscriberLinks,
+    settings: &DeliveryWorkerSettings,
+) -> anyhow::Result<ExecutionOutcome> {
+    let tasks = claim_tasks(connection_pool, settings).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+
+    Span::current().record("n_tasks", tasks.len());
+
+    let mut tasks_by_issue: BTreeMap<Uuid, Vec<Task>> = BTreeMap::new();
+    for task in tasks {
+        tasks_by_issue
+            .entry(task.newsletter_issue_id)
+            .or_default()
+            .push(task);
+    }
+
+    for (newsletter_issue_id, tasks) in tasks_by_issue {
+        let issue = match get_issue(connection_pool, newsletter_issue_id).await {
+            Ok(issue) => issue,
+            Err(error) => {
+                // Only hold back this issue: the queue is sorted by issue, so
+                // failing the batch would keep the others from being sent.
+                let error = error.context("failed to load the newsletter issue");
+                let mut transaction = connection_pool.begin().await?;
+                for task in &tasks {
+                    retry_task(
+                        &mut transaction,
+                        settings,
+                        task,
+                        Duration::ZERO,
+                        &error,
+                    )
+                    .await?;
+                }
+                transaction.commit().await?;
+                continue;
+            }
+        };
+        deliver_issue(
+            connection_pool,
+            email_client,
+            subscriber_links,
+            settings,
+            &issue,
+            tasks,
+        )
+        .await?;
+    }
+
+    Ok(ExecutionOutcome::TasksCompleted)
+}
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    /// `None` if the subscriber has since unsubscribed, been deleted or been
+    /// suppressed.
+    subscriber_id: Option<Uuid>,
+    subscriber_name: Option<String>,
+    n_retries: i32,
+}
+
+/// The delay before the next attempt: `base` doubled for every failed attempt
+/// so far, capped at `max`.
+pub(crate) fn backoff(n_retries: i32, base: Duration, max: Duration) -> Duration {
+    let factor = 2_u32.saturating_pow(u32::try_from(n_retries).unwrap_or(0));
+    base.saturating_mul(factor).min(max)
+}
+
+/// Send the issue to the subscribers of the given tasks, personalized for each
+/// of them (see [`templates::substitutions`] and [`tracking::substitutions`]),
+/// and delete or postpone the tasks of each chunk as soon as it's been sent,
+/// depending on how it fared.
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id = %issue.newsletter_issue_id)
+)]
+async fn deliver_issue(
+    connection_pool: &PgPool,
+    email_client: &dyn EmailSender,
+    subscriber_links: &SubscriberLinks,
+    settings: &DeliveryWorkerSettings,
+    issue: &NewsletterIssue,
+    tasks: Vec<Task>,
+) -> sqlx::Result<()> {
+    let mut recipients = Vec::with_capacity(tasks.len());
+    let mut deliverable_tasks = Vec::with_capacity(tasks.len());
+    let mut transaction = connection_pool.begin().await?;
+    for task in tasks {
+        let Some(subscriber_id) = task.subscriber_id else {
+            info!(
+                "{} is no longer subscribed or is suppressed, skipping them",
+                task.subscriber_email
+            );
+            delete_task(&mut transaction, &task).await?;
+            continue;
+        };
+
+        match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(email) => {
+                let unsubscribe_url = subscriber_links.unsubscribe_url(
+                    subscriber_id,
+                    Some(issue.newsletter_issue_id),
+                    issue.list_slug.as_deref(),
+                );
+                let mut substitutions = templates::substitutions(
+                    task.subscriber_name.as_deref().unwrap_or_default(),
+                    &unsubscribe_url,
+                    &subscriber_links.preferences_url(subscriber_id),
+                );
+                if issue.tracking_enabled {
+                    substitutions.extend(tracking::substitutions(
+                        subscriber_links,
+                        issue.newsletter_issue_id,
+                        subscriber_id,
+                        issue.n_tracked_links,
+                    ));
+                }
+                recipients.push(BatchRecipient {
+                    substitutions,
+                    unsubscribe_url: Some(unsubscribe_url),
+                    email,
+                });
+                deliverable_tasks.push(task);
+            }
+            Err(error) => {
+                error!(
+                    "The stored subscriber email `{}` is invalid, giving up: \
+                    {error}",
+                    task.subscriber_email
+                );
+                delete_task(&mut transaction, &task).await?;
+                let error = format!("invalid subscriber email: {error}");
+                record_failed(&mut transaction, [&task], &error).await?;
+            }
+        }
+    }
+    transaction.commit().await?;
+
+    let chunk_size = email_client.max_chunk_size().max(1);
+    for (recipients, tasks) in recipients
+        .chunks(chunk_size)
+        .zip(deliverable_tasks.chunks(chunk_size))
+    {
+        let result = email_client
+            .send_chunk(
+                recipients,
+                &issue.title,
+                &issue.html_content,
+                &issue.text_content,
+            )
+            .await;
+        let mut transaction = connection_pool.begin().await?;
+        save_outcome(&mut transaction, settings, issue, tasks, result).await?;
+        transaction.commit().await?;
+    }
+
+    Ok(())
+}
+
+/// Delete or postpone the tasks of a chunk depending on how sending it fared.
+async fn save_outcome(
+    transaction: &mut Transaction<'static, Postgres>,
+    settings: &DeliveryWorkerSettings,
+    issue: &NewsletterIssue,
+    tasks: &[Task],
+    result: Result<(), email_client::Error>,
+) -> sqlx::Result<()> {
+    match result {
+        Ok(()) => {
+            for task in tasks {
+                delete_task(transaction, task).await?;
+            }
+            record_sent(transaction, issue.newsletter_issue_id, tasks).await?;
+        }
+        Err(error) if error.is_retryable() => {
+            for task in tasks {
+                // Don't come back sooner than the provider asked us to.
+                let retry_after = error.retry_after().unwrap_or_default();
+                retry_task(transaction, settings, task, retry_after, &error)
+                    .await?;
+            }
+        }
+        Err(email_client::Error::PartiallyRejected { rejected, source }) => {
+            error!(
+                "Failed to deliver the issue to {} subscriber(s), giving up: \
+                {source:#}",
+                rejected.len()
+            );
+            for task in tasks {
+                delete_task(transaction, task).await?;
+            }
+            let (failed, sent): (Vec<_>, Vec<_>) = tasks
+                .iter()
+                .enumerate()
+                .partition(|(index, _)| rejected.contains(index));
+            let sent = sent.into_iter().map(|(_, task)| task);
+            record_sent(transaction, issue.newsletter_issue_id, sent).await?;
+            let failed = failed.into_iter().map(|(_, task)| task);
+            record_failed(transaction, failed, &format!("{source:#}")).await?;
+        }
+        Err(error) => {
+            error!(
+                "Failed to deliver the issue to {} subscriber(s), giving up: \
+                {error:#}",
+                tasks.len()
+            );
+            for task in tasks {
+                delete_task(transaction, task).await?;
+            }
+            record_failed(transaction, tasks, &format!("{error:#}")).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Postpone the task with exponential backoff, and no sooner than
+/// `retry_after`, or drop it and record it as failed if it has been retried
+/// `settings.max_retries` times already.
+async fn retry_task(
+    transaction: &mut Transaction<'static, Postgres>,
+    settings: &DeliveryWorkerSettings,
+    task: &Task,
+    retry_after: Duration,
+    error: &(dyn Display + Sync),
+) -> sqlx::Result<()> {
+    if task.n_retries < settings.max_retries {
+        let delay = backoff(
+            task.n_retries,
+            settings.base_backoff(),
+            settings.max_backoff(),
+        )
+        .max(retry_after);
+        warn!(
+            "Failed to deliver the issue to {}, retrying in {}s: {error}",
+            task.subscriber_email,
+            delay.as_secs()
+        );
+        postpone_task(transaction, task, delay).await
+    } else {
+        error!(
+            "Failed to deliver the issue to {}, giving up: {error:#}",
+            task.subscriber_email
+        );
+        delete_task(transaction, task).await?;
+        record_failed(transaction, [task], &format!("{error:#}")).await
+    }
+}
+
+/// Dequeue due tasks and push them back by `settings.lease()`, so that no
+/// other worker picks them up while they're being sent.
+#[tracing::instrument(skip_all)]
+async fn claim_tasks(
+    connection_pool: &PgPool,
+    settings: &DeliveryWorkerSettings,
+) -> sqlx::Result<Vec<Task>> {
+    let mut transaction = connection_pool.begin().await?;
+    // `SKIP LOCKED` lets several workers poll the queue concurrently without
+    // stepping on each other's toes.
+    let tasks = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT
+            queue.newsletter_issue_id,
+            queue.subscriber_email,
+            subscriptions.id AS "subscriber_id?",
+            subscriptions.name AS "subscriber_name?",
+            queue.n_retries
+        FROM issue_delivery_queue AS queue
+        JOIN newsletter_issues AS issue
+            ON issue.newsletter_issue_id = queue.newsletter_issue_id
+        LEFT JOIN subscriptions
+            ON subscriptions.email = queue.subscriber_email
+            AND NOT EXISTS (
+                SELECT 1
+                FROM suppressions
+                WHERE suppressions.email = queue.subscriber_email
+            )
+            AND (
+                (issue.list_slug IS NULL AND subscriptions.status = 'confirmed')
+                OR EXISTS (
+                    SELECT 1
+                    FROM list_subscriptions
+                    WHERE list_subscriptions.subscriber_id = subscriptions.id
+                    AND list_subscriptions.list_slug = issue.list_slug
+                    AND list_subscriptions.status = 'confirmed'
+                )
+            )
+        WHERE queue.execute_after <= now()
+        ORDER BY queue.newsletter_issue_id
+        FOR UPDATE OF queue
+        SKIP LOCKED
+        LIMIT $1
+        "#,
+        settings.batch_size
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    let (newsletter_issue_ids, subscriber_emails): (Vec<_>, Vec<_>) = tasks
+        .iter()
+        .map(|task| (task.newsletter_issue_id, task.subscriber_email.clone()))
+        .unzip();
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue AS queue
+        SET execute_after = now() + make_interval(secs => $3)
+        FROM UNNEST($1::uuid[], $2::text[])
+            AS claimed(newsletter_issue_id, subscriber_email)
+        WHERE queue.newsletter_issue_id = claimed.newsletter_issue_id
+        AND queue.subscriber_email = claimed.subscriber_email
+        "#,
+        &newsletter_issue_ids,
+        &subscriber_emails,
+        settings.lease().as_secs_f64()
+    )
+    .execute(&mut transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+    transaction.commit().await?;
+
+    Ok(tasks)
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut Transaction<'static, Postgres>,
+    task: &Task,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+/// Record that the email provider accepted the issue for the subscribers of
+/// the given tasks.
+#[tracing::instrument(skip_all)]
+async fn record_sent(
+    transaction: &mut Transaction<'static, Postgres>,
+    newsletter_issue_id: Uuid,
+    tasks: impl IntoIterator<Item = &Task> + Send,
+) -> sqlx::Result<()> {
+    let subscriber_emails: Vec<_> = tasks
+        .into_iter()
+        .map(|task| task.subscriber_email.clone())
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO delivery_events (newsletter_issue_id, subscriber_email, kind)
+        SELECT $1, subscriber_email, 'sent'
+        FROM UNNEST($2::text[]) AS subscriber_email
+        "#,
+        newsletter_issue_id,
+        &subscriber_emails
+    )
+    .execute(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+/// Record that the issue won't be delivered to the subscribers of the given
+/// tasks, and why.
+#[tracing::instrument(skip_all)]
+async fn record_failed<'a>(
+    transaction: &mut Transaction<'static, Postgres>,
+    tasks: impl IntoIterator<Item = &'a Task> + Send,
+    error: &str,
+) -> sqlx::Result<()> {
+    let (newsletter_issue_ids, subscriber_emails): (Vec<_>, Vec<_>) = tasks
+        .into_iter()
+        .map(|task| (task.newsletter_issue_id, task.subscriber_email.clone()))
+        .unzip();
+    sqlx::query!(
+        r#"
+        INSERT INTO delivery_events
+            (newsletter_issue_id, subscriber_email, kind, error)
+        SELECT newsletter_issue_id, subscriber_email, 'failed', $3
+        FROM UNNEST($1::uuid[], $2::text[])
+            AS failed(newsletter_issue_id, subscriber_email)
+        "#,
+        &newsletter_issue_ids,
+        &subscriber_emails,
+        error
+    )
+    .execute(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn postpone_task(
+    transaction: &mut Transaction<'static, Postgres>,
+    task: &Task,
+    delay: Duration,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $3)
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        delay.as_secs_f64()
+    )
+    .execute(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+struct NewsletterIssue {
+    newsletter_issue_id: Uuid,
+    list_slug: Option<String>,
+    title: String,
+    text_content: String,
+    html_content: String,
+    tracking_enabled: bool,
+    n_tracked_links: u16,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(
+    connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> anyhow::Result<NewsletterIssue> {
+    let record = sqlx::query!(
+        r#"
+        SELECT
+            newsletter_issue_id,
+            list_slug,
+            title,
+            text_content,
+            html_content,
+            tracking_enabled,
+            (
+                SELECT COUNT(*)
+                FROM tracked_links
+                WHERE tracked_links.newsletter_issue_id =
+                    newsletter_issues.newsletter_issue_id
+            ) AS "n_tracked_links!"
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(connection_pool)
+    .await?;
+
+    Ok(NewsletterIssue {
+        newsletter_issue_id: record.newsletter_issue_id,
+        list_slug: record.list_slug,
+        title: record.title,
+        text_content: record.text_content,
+        html_content: record.html_content,
+        tracking_enabled: record.tracking_enabled,
+        // Publishing tracks at most that many links.
+        n_tracked_links: u16::try_from(record.n_tracked_links)
+            .context("the issue has too many tracked links")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::backoff;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff_doubles_with_every_retry() {
+        let base = Duration::from_secs(1);
+        let max = Duration::from_secs(3600);
+
+        assert_eq!(Duration::from_secs(1), backoff(0, base, max));
+        assert_eq!(Duration::from_secs(2), backoff(1, base, max));
+        assert_eq!(Duration::from_secs(16), backoff(4, base, max));
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let base = Duration::from_secs(1);
+        let max = Duration::from_secs(60);
+
+        assert_eq!(max, backoff(10, base, max));
+        assert_eq!(max, backoff(i32::MAX, base, max));
+    }
+}
@@ -0,0 +1,509 @@
+//! Queueing newsletter issues up for delivery: right away when they're
+//! published, or once they're due if they're scheduled for later.
+//!
+//! Several schedulers can poll at the same time, e.g. one per replica of the
+//! app. Each due issue is locked while it's being queued and marked as queued
+//! in the same transaction, so it's only ever queued once. An issue that fails
+//! to be queued is retried later with exponential backoff, so it doesn't hold
+//! back the issues due after it, and given up on after too many failures.
+
+use crate::{
+    configuration::{SchedulerSettings, Settings},
+    domain::TimeZone,
+    issue_delivery_worker::{backoff, ExecutionOutcome},
+    segment::{self, Filter},
+};
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use inspect_error::InspectError;
+use sqlx::{
+    postgres::PgPoolOptions, Acquire, PgPool, Postgres, QueryBuilder, Transaction,
+};
+use std::{collections::BTreeMap, time::Duration};
+use tracing::{error, warn, Span};
+use uuid::Uuid;
+
+/// Run the scheduler with its own connection pool. This is what the standalone
+/// `issue_scheduler` binary runs.
+///
+/// # Errors
+///
+/// Doesn't return under normal circumstances: failures to queue individual
+/// issues are logged and retried.
+pub async fn run_scheduler_until_stopped(
+    configuration: Settings,
+) -> anyhow::Result<()> {
+    let connection_pool = PgPoolOptions::new()
+        .acquire_timeout(Duration::from_secs(10))
+        .connect_lazy_with(configuration.database.connect_options());
+
+    scheduler_loop(&connection_pool, &configuration.scheduler).await
+}
+
+/// Keep queueing due issues, sleeping for the configured poll interval
+/// whenever there are none.
+///
+/// # Errors
+///
+/// Doesn't return under normal circumstances: failures to queue individual
+/// issues are logged and retried.
+pub async fn scheduler_loop(
+    connection_pool: &PgPool,
+    settings: &SchedulerSettings,
+) -> anyhow::Result<()> {
+    loop {
+        match try_enqueue_due_issue(connection_pool, settings).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(settings.poll_interval()).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TasksCompleted) => {}
+        }
+    }
+}
+
+/// Queue up the deliveries of the scheduled issue that has been due the
+/// longest, if there is one.
+///
+/// If that fails, the failure is recorded on the issue instead, which is
+/// retried after a backoff or given up on after `settings.max_retries`.
+///
+/// # Errors
+///
+/// Will return an error if something goes wrong talking to the database
+/// outside of queueing the issue up.
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id = tracing::field::Empty),
+    err
+)]
+pub async fn try_enqueue_due_issue(
+    connection_pool: &PgPool,
+    settings: &SchedulerSettings,
+) -> anyhow::Result<ExecutionOutcome> {
+    let mut transaction = connection_pool.begin().await?;
+    let Some(newsletter_issue_id) = lock_due_issue(&mut transaction).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+
+    Span::current().record(
+        "newsletter_issue_id",
+        tracing::field::display(newsletter_issue_id),
+    );
+
+    // The savepoint undoes a partial queueing while keeping the issue locked
+    // to record the failure.
+    let mut savepoint = transaction.begin().await?;
+    match enqueue_issue(&mut savepoint, newsletter_issue_id).await {
+        Ok(()) => savepoint.commit().await?,
+        Err(error) => {
+            savepoint.rollback().await?;
+            record_failure(
+                &mut transaction,
+                settings,
+                newsletter_issue_id,
+                &error,
+            )
+            .await?;
+        }
+    }
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TasksCompleted)
+}
+
+#[tracing::instrument(skip_all)]
+async fn lock_due_issue(
+    transaction: &mut Transaction<'static, Postgres>,
+) -> sqlx::Result<Option<Uuid>> {
+    // `SKIP LOCKED` lets other schedulers move on to the next due issue.
+    let record = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE enqueued_at IS NULL
+        AND cancelled_at IS NULL
+        AND enqueue_failed_at IS NULL
+        AND scheduled_at <= now()
+        AND (enqueue_retry_after IS NULL OR enqueue_retry_after <= now())
+        ORDER BY scheduled_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(record.map(|record| record.newsletter_issue_id))
+}
+
+/// Postpone the issue with exponential backoff, or give up on it if it has
+/// been retried `settings.max_retries` times already. Either way, the error is
+/// kept on the issue.
+async fn record_failure(
+    transaction: &mut Transaction<'static, Postgres>,
+    settings: &SchedulerSettings,
+    newsletter_issue_id: Uuid,
+    error: &anyhow::Error,
+) -> sqlx::Result<()> {
+    let n_failures = sqlx::query!(
+        "SELECT n_enqueue_failures FROM newsletter_issues \
+        WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?
+    .n_enqueue_failures;
+
+    let retry_after = if n_failures < settings.max_retries {
+        let delay =
+            backoff(n_failures, settings.base_backoff(), settings.max_backoff());
+        warn!(
+            "Failed to queue the issue up, retrying in {}s: {error:#}",
+            delay.as_secs()
+        );
+        Some(delay)
+    } else {
+        error!("Failed to queue the issue up, giving up: {error:#}");
+        None
+    };
+
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            n_enqueue_failures = n_enqueue_failures + 1,
+            enqueue_error = $2,
+            enqueue_retry_after = now() + make_interval(secs => $3),
+            enqueue_failed_at = CASE WHEN $3 IS NULL THEN now() END
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        format!("{error:#}"),
+        retry_after.map(|delay| delay.as_secs_f64())
+    )
+    .execute(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+/// Who an issue goes to.
+pub struct Audience<'a> {
+    /// The subscribers of `/subscriptions` if there's no list.
+    pub list: Option<&'a str>,
+    /// Subscribers who opted out of the topic are left out.
+    pub topic: Option<&'a str>,
+    /// Only the subscribers matching the filter of a segment are kept.
+    pub filter: Option<&'a Filter>,
+}
+
+impl Audience<'_> {
+    /// Append the condition on `subscriptions` that the audience matches.
+    pub fn push_condition(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        match self.list {
+            Some(list) => {
+                builder.push(
+                    "EXISTS (SELECT 1 FROM list_subscriptions \
+                    WHERE list_subscriptions.subscriber_id = subscriptions.id \
+                    AND list_subscriptions.status = 'confirmed' \
+                    AND list_subscriptions.list_slug = ",
+                );
+                builder.push_bind(list.to_string());
+                builder.push(")");
+            }
+            None => {
+                builder.push("subscriptions.status = 'confirmed'");
+            }
+        }
+        if let Some(topic) = self.topic {
+            builder.push(
+                " AND NOT EXISTS (SELECT 1 FROM topic_opt_outs \
+                WHERE topic_opt_outs.subscriber_id = subscriptions.id \
+                AND topic_opt_outs.topic_slug = ",
+            );
+            builder.push_bind(topic.to_string());
+            builder.push(")");
+        }
+        if let Some(filter) = self.filter {
+            builder.push(" AND ");
+            filter.push_sql(builder);
+        }
+    }
+}
+
+struct IssueAudience {
+    list_slug: Option<String>,
+    topic_slug: Option<String>,
+    segment_filter: Option<String>,
+    local_delivery_time: Option<NaiveDateTime>,
+    default_time_zone: Option<String>,
+}
+
+/// Queue up a delivery task for every subscriber in the issue's audience and
+/// mark the issue as queued.
+///
+/// Issues with a local delivery time are delivered when it's that time in each
+/// subscriber's time zone. Subscribers are put in buckets by the UTC time that
+/// comes out to, so there's one insert per distinct offset rather than one per
+/// subscriber.
+///
+/// # Errors
+///
+/// Will return an error if something goes wrong talking to the database.
+#[tracing::instrument(name = "Queueing the issue for delivery", skip_all)]
+pub async fn enqueue_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> anyhow::Result<()> {
+    let issue = sqlx::query_as!(
+        IssueAudience,
+        r#"
+        SELECT
+            list_slug,
+            topic_slug,
+            segment_filter,
+            local_delivery_time,
+            default_time_zone
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+    let filter = issue
+        .segment_filter
+        .as_deref()
+        .map(segment::parse)
+        .transpose()
+        .context("the segment filter of the issue is invalid")?;
+    let audience = Audience {
+        list: issue.list_slug.as_deref(),
+        topic: issue.topic_slug.as_deref(),
+        filter: filter.as_ref(),
+    };
+
+    match issue.local_delivery_time {
+        None => {
+            insert_delivery_tasks(
+                transaction,
+                newsletter_issue_id,
+                &audience,
+                None,
+            )
+            .await?;
+        }
+        Some(local_delivery_time) => {
+            let default_time_zone = issue
+                .default_time_zone
+                .as_deref()
+                .and_then(TimeZone::parse)
+                .unwrap_or(TimeZone::UTC);
+            let time_zones =
+                get_time_zones(transaction, &audience, default_time_zone).await?;
+            let buckets = bucket_by_delivery_time(
+                &time_zones,
+                default_time_zone,
+                &local_delivery_time,
+            );
+            for (deliver_at, time_zones) in buckets {
+                let bucket = Bucket {
+                    deliver_at,
+                    time_zones,
+                    default_time_zone,
+                };
+                insert_delivery_tasks(
+                    transaction,
+                    newsletter_issue_id,
+                    &audience,
+                    Some(&bucket),
+                )
+                .await?;
+            }
+        }
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET enqueued_at = now()
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+/// The subscribers in these time zones get the issue at the same time.
+struct Bucket {
+    deliver_at: DateTime<Utc>,
+    time_zones: Vec<String>,
+    default_time_zone: TimeZone,
+}
+
+/// Get the time zones of the audience, with the default one standing in for
+/// subscribers who didn't give theirs.
+#[tracing::instrument(name = "Getting the time zones of the audience", skip_all)]
+async fn get_time_zones(
+    transaction: &mut Transaction<'_, Postgres>,
+    audience: &Audience<'_>,
+    default_time_zone: TimeZone,
+) -> sqlx::Result<Vec<String>> {
+    let mut query =
+        QueryBuilder::new("SELECT DISTINCT COALESCE(subscriptions.time_zone, ");
+    query.push_bind(default_time_zone.name());
+    query.push(") FROM subscriptions WHERE ");
+    audience.push_condition(&mut query);
+
+    let rows: Vec<(String,)> = query
+        .build_query_as()
+        .fetch_all(transaction)
+        .await
+        .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(rows.into_iter().map(|(time_zone,)| time_zone).collect())
+}
+
+/// Group the time zones by the UTC time at which clocks in them show
+/// `local_delivery_time`.
+fn bucket_by_delivery_time(
+    time_zones: &[String],
+    default_time_zone: TimeZone,
+    local_delivery_time: &NaiveDateTime,
+) -> BTreeMap<DateTime<Utc>, Vec<String>> {
+    let mut buckets: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for name in time_zones {
+        let time_zone = TimeZone::parse(name).unwrap_or(default_time_zone);
+        buckets
+            .entry(time_zone.to_utc_lenient(local_delivery_time))
+            .or_default()
+            .push(name.clone());
+    }
+    buckets
+}
+
+#[tracing::instrument(name = "Inserting the delivery tasks", skip_all)]
+async fn insert_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    audience: &Audience<'_>,
+    bucket: Option<&Bucket>,
+) -> sqlx::Result<()> {
+    let mut query = QueryBuilder::new(
+        "INSERT INTO issue_delivery_queue \
+        (newsletter_issue_id, subscriber_email, execute_after) \
+        SELECT ",
+    );
+    query.push_bind(newsletter_issue_id);
+    query.push(", email, ");
+    push_execute_after(&mut query, bucket.map(|bucket| bucket.deliver_at));
+    query.push(" FROM subscriptions WHERE ");
+    audience.push_condition(&mut query);
+    if let Some(bucket) = bucket {
+        query.push(" AND COALESCE(subscriptions.time_zone, ");
+        query.push_bind(bucket.default_time_zone.name());
+        query.push(") = ANY(");
+        query.push_bind(bucket.time_zones.clone());
+        query.push(")");
+    }
+
+    query
+        .build()
+        .execute(transaction)
+        .await
+        .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+/// Append when a delivery is due if the issue is sent at `deliver_at` (right
+/// away if `None`): then, or at the start of the following day or week for
+/// subscribers with a daily or weekly delivery window.
+fn push_execute_after(
+    query: &mut QueryBuilder<'_, Postgres>,
+    deliver_at: Option<DateTime<Utc>>,
+) {
+    let push_deliver_at = |query: &mut QueryBuilder<'_, Postgres>| match deliver_at
+    {
+        Some(deliver_at) => {
+            query.push("CAST(");
+            query.push_bind(deliver_at);
+            query.push(" AS timestamptz)");
+        }
+        None => {
+            query.push("now()");
+        }
+    };
+
+    query.push("CASE delivery_window WHEN 'daily' THEN date_trunc('day', ");
+    push_deliver_at(query);
+    query.push(") + interval '1 day' WHEN 'weekly' THEN date_trunc('week', ");
+    push_deliver_at(query);
+    query.push(") + interval '1 week' ELSE ");
+    push_deliver_at(query);
+    query.push(" END");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::bucket_by_delivery_time;
+    use crate::domain::TimeZone;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn time_zones_are_bucketed_by_their_offset_on_the_day() {
+        // The United States had switched to daylight saving time by then, but
+        // Europe hadn't.
+        let local_delivery_time = NaiveDate::from_ymd_opt(2023, 3, 15)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap();
+        let time_zones = [
+            "Europe/Paris",
+            "Europe/London",
+            "Europe/Berlin",
+            "America/New_York",
+        ]
+        .map(String::from);
+
+        let buckets = bucket_by_delivery_time(
+            &time_zones,
+            TimeZone::UTC,
+            &local_delivery_time,
+        );
+
+        let buckets: Vec<_> = buckets
+            .into_iter()
+            .map(|(deliver_at, time_zones)| (deliver_at.to_rfc3339(), time_zones))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    "2023-03-15T08:00:00+00:00".to_string(),
+                    vec!["Europe/Paris".to_string(), "Europe/Berlin".to_string()]
+                ),
+                (
+                    "2023-03-15T09:00:00+00:00".to_string(),
+                    vec!["Europe/London".to_string()]
+                ),
+                (
+                    "2023-03-15T13:00:00+00:00".to_string(),
+                    vec!["America/New_York".to_string()]
+                ),
+            ],
+            buckets
+        );
+    }
+}
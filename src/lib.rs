@@ -1,16 +1,28 @@
 #![allow(clippy::unused_async)]
 
 pub mod configuration;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
 pub mod telemetry;
 
+mod authentication;
 mod domain;
 mod email_client;
 mod extractors;
+mod html_email;
 mod routes;
+mod segment;
+mod session;
 mod startup;
 mod state;
+mod subscriber_links;
+mod templates;
+mod tracking;
+mod webhooks;
 
-pub use email_client::EmailClient;
+pub use email_client::EmailSender;
 pub use startup::build_app;
+pub use subscriber_links::SubscriberLinks;
 
 use state::AppState;
@@ -1,3 +1,5 @@
+use crate::session::{FlashLevel, FlashMessage};
+
 pub mod health_check {
     use axum::{http::StatusCode, response::IntoResponse};
 
@@ -6,4 +8,52 @@ pub mod health_check {
     }
 }
 
+pub mod admin;
+pub mod lists;
+pub mod login;
+pub mod newsletter_schedule;
+pub mod newsletter_stats;
+pub mod newsletters;
+pub mod preferences;
+pub mod segments;
+pub mod subscribers;
+pub mod subscribers_export;
+pub mod subscribers_import;
 pub mod subscription;
+pub mod subscription_confirm;
+pub mod subscription_unsubscribe;
+pub mod suppressions;
+pub mod templates;
+pub mod topics;
+pub mod tracking;
+pub mod webhooks;
+
+/// Escape the characters that have a special meaning in HTML.
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            other => escaped.push(other),
+        }
+    }
+    escaped
+}
+
+/// Render flash messages as HTML paragraphs, errors in bold.
+fn render_flash_messages(messages: &[FlashMessage]) -> String {
+    messages
+        .iter()
+        .map(|message| {
+            let content = escape_html(&message.content);
+            match message.level {
+                FlashLevel::Info => format!("<p><i>{content}</i></p>"),
+                FlashLevel::Error => format!("<p><strong>{content}</strong></p>"),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,211 @@
+use super::{escape_html, render_flash_messages};
+use crate::{
+    authentication::{
+        change_password as store_new_password, get_username, validate_credentials,
+        AuthError, Credentials,
+    },
+    domain::NewPassword,
+    extractors::AuthenticatedUser,
+    session::{Flash, Session},
+};
+use axum::{
+    extract::{FromRequestParts, State},
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Redirect, Response},
+    Form,
+};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::error;
+
+/// Middleware that sends visitors without a logged-in session to the login
+/// form instead of letting them through to the admin pages.
+///
+/// Requests with an `Authorization` header are authenticated with HTTP Basic
+/// auth instead, like the rest of the API, so that scripts can use the admin
+/// API too. They're answered with a `401` if the credentials are wrong.
+pub async fn reject_anonymous_users<B>(
+    State(connection_pool): State<PgPool>,
+    session: Session,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if session.user_id().is_some() {
+        return next.run(request).await;
+    }
+    if !request.headers().contains_key(header::AUTHORIZATION) {
+        return Redirect::to("/login").into_response();
+    }
+
+    let (mut parts, body) = request.into_parts();
+    match AuthenticatedUser::from_request_parts(&mut parts, &connection_pool).await
+    {
+        Ok(user) => {
+            // Saves handlers from checking the credentials again.
+            parts.extensions.insert(user);
+            next.run(Request::from_parts(parts, body)).await
+        }
+        Err(error) => error.into_response(),
+    }
+}
+
+#[tracing::instrument(
+    name = "Showing the admin dashboard",
+    skip_all,
+    fields(user_id = %user.user_id)
+)]
+pub async fn admin_dashboard(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+) -> Result<Html<String>, Error> {
+    let username =
+        escape_html(&get_username(user.user_id, &connection_pool).await?);
+
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Admin dashboard</title>
+</head>
+<body>
+    <p>Welcome {username}!</p>
+    <p>Available actions:</p>
+    <ol>
+        <li><a href="/admin/password">Change password</a></li>
+        <li>
+            <form name="logoutForm" action="/admin/logout" method="post">
+                <input type="submit" value="Logout">
+            </form>
+        </li>
+    </ol>
+</body>
+</html>"#
+    )))
+}
+
+pub async fn change_password_form(
+    _user: AuthenticatedUser,
+    flash: Flash,
+) -> Html<String> {
+    let messages = render_flash_messages(&flash.take_messages());
+
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Change Password</title>
+</head>
+<body>
+    {messages}
+    <form action="/admin/password" method="post">
+        <label>Current password
+            <input type="password" placeholder="Enter current password"
+                name="current_password">
+        </label>
+        <br>
+        <label>New password
+            <input type="password" placeholder="Enter new password"
+                name="new_password">
+        </label>
+        <br>
+        <label>Confirm new password
+            <input type="password" placeholder="Type the new password again"
+                name="new_password_check">
+        </label>
+        <br>
+        <button type="submit">Change password</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct PasswordFormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+/// Change the logged-in user's password. The outcome is reported through a
+/// flash message on the password form, whether it succeeded or not.
+#[tracing::instrument(
+    name = "Changing the password",
+    skip_all,
+    fields(user_id = %user.user_id)
+)]
+pub async fn change_password(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    flash: Flash,
+    Form(form): Form<PasswordFormData>,
+) -> Result<Redirect, Error> {
+    let redirect = Redirect::to("/admin/password");
+
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret()
+    {
+        flash.error(
+            "You entered two different new passwords - \
+            the field values must match.",
+        );
+        return Ok(redirect);
+    }
+
+    let new_password =
+        match NewPassword::parse(form.new_password.expose_secret().clone()) {
+            Ok(new_password) => new_password,
+            Err(_) => {
+                flash.error(
+                    "The new password must be between 12 and 128 characters \
+                    long and mix letters with digits or symbols.",
+                );
+                return Ok(redirect);
+            }
+        };
+
+    let credentials = Credentials {
+        username: get_username(user.user_id, &connection_pool).await?,
+        password: form.current_password,
+    };
+    match validate_credentials(credentials, &connection_pool).await {
+        Ok(_) => {}
+        Err(AuthError::InvalidCredentials(_)) => {
+            flash.error("The current password is incorrect.");
+            return Ok(redirect);
+        }
+        Err(AuthError::UnexpectedError(error)) => return Err(error.into()),
+    }
+
+    store_new_password(user.user_id, new_password.into(), &connection_pool)
+        .await?;
+    flash.info("Your password has been changed.");
+
+    Ok(redirect)
+}
+
+#[tracing::instrument(name = "Logging out", skip_all)]
+pub async fn log_out(session: Session, flash: Flash) -> Redirect {
+    session.purge();
+    flash.info("You have successfully logged out.");
+
+    Redirect::to("/login")
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        error!("Failed to handle the admin request: {self:#}");
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    }
+}
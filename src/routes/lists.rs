@@ -0,0 +1,101 @@
+use crate::{
+    domain::Slug,
+    extractors::{AuthenticatedUser, ValidatedJson},
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use inspect_error::InspectError;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::error;
+use validator::{Validate, ValidationErrors};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct BodyData {
+    /// What subscribers see in the confirmation email.
+    #[validate(length(min = 1, max = 256))]
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct List {
+    slug: String,
+    name: String,
+}
+
+/// Create or rename the list with the given slug. People can then subscribe to
+/// it at `/lists/{slug}/subscriptions`, and issues can be published to it.
+///
+/// Only authenticated users may save lists.
+#[tracing::instrument(
+    name = "Saving a list",
+    skip_all,
+    fields(list_slug = %slug, user_id = %user.user_id)
+)]
+pub async fn save_list(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    Path(slug): Path<String>,
+    ValidatedJson(body): ValidatedJson<BodyData>,
+) -> Result<StatusCode, Error> {
+    let slug = Slug::parse(slug).map_err(Error::InvalidSlug)?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO lists (slug, name)
+        VALUES ($1, $2)
+        ON CONFLICT (slug) DO UPDATE
+        SET name = EXCLUDED.name
+        "#,
+        slug.as_ref(),
+        body.name
+    )
+    .execute(&connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Get every list, in alphabetical order of their names.
+#[tracing::instrument(
+    name = "Getting the lists",
+    skip_all,
+    fields(user_id = %user.user_id)
+)]
+pub async fn get_lists(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+) -> Result<Json<Vec<List>>, Error> {
+    let lists =
+        sqlx::query_as!(List, "SELECT slug, name FROM lists ORDER BY name, slug")
+            .fetch_all(&connection_pool)
+            .await
+            .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(Json(lists))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error("invalid list slug: {0}")]
+    InvalidSlug(ValidationErrors),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidSlug(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use super::render_flash_messages;
+use crate::{
+    authentication::{validate_credentials, AuthError, Credentials},
+    session::{Flash, Session},
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+    Form,
+};
+use secrecy::Secret;
+use serde::Deserialize;
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::error;
+
+pub async fn login_form(flash: Flash) -> Html<String> {
+    let messages = render_flash_messages(&flash.take_messages());
+
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Login</title>
+</head>
+<body>
+    {messages}
+    <form action="/login" method="post">
+        <label>Username
+            <input type="text" placeholder="Enter Username" name="username">
+        </label>
+        <label>Password
+            <input type="password" placeholder="Enter Password" name="password">
+        </label>
+        <button type="submit">Login</button>
+    </form>
+</body>
+</html>"#
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct FormData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Logging in",
+    skip_all,
+    fields(username = %form.username)
+)]
+pub async fn login(
+    State(connection_pool): State<PgPool>,
+    session: Session,
+    flash: Flash,
+    Form(form): Form<FormData>,
+) -> Result<Redirect, Error> {
+    let credentials = Credentials {
+        username: form.username,
+        password: form.password,
+    };
+
+    match validate_credentials(credentials, &connection_pool).await {
+        Ok(user_id) => {
+            // Cycle the session ID to prevent session fixation attacks.
+            session.renew();
+            session.insert_user_id(user_id)?;
+            Ok(Redirect::to("/admin/dashboard"))
+        }
+        Err(AuthError::InvalidCredentials(_)) => {
+            flash.error("Authentication failed.");
+            Ok(Redirect::to("/login"))
+        }
+        Err(AuthError::UnexpectedError(error)) => Err(error.into()),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+    #[error(transparent)]
+    Session(#[from] serde_json::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        error!("Failed to log in: {self:#}");
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    }
+}
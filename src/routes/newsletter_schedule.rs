@@ -0,0 +1,374 @@
+use crate::{
+    domain::TimeZone,
+    extractors::{AuthenticatedUser, ValidatedJson},
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone as _, Utc};
+use inspect_error::InspectError;
+use serde::Deserialize;
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::error;
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct BodyData {
+    /// See [`Schedule::parse`].
+    scheduled_at: Option<String>,
+    send_at_local_time: Option<String>,
+    time_zone: Option<String>,
+}
+
+/// Clocks are at most 14 hours ahead of UTC, in Kiribati.
+const MAX_UTC_OFFSET_IN_HOURS: i64 = 14;
+/// Clocks are at most 12 hours behind UTC, on Baker Island.
+const MIN_UTC_OFFSET_IN_HOURS: i64 = -12;
+
+/// When an issue is sent.
+#[derive(Debug)]
+pub struct Schedule {
+    /// When the scheduler queues the issue up, if not right away.
+    pub queue_at: Option<DateTime<Utc>>,
+    /// Deliver the issue at this local time in every subscriber's time zone.
+    pub local_delivery_time: Option<NaiveDateTime>,
+    /// The time zone of subscribers who didn't give theirs.
+    pub default_time_zone: Option<TimeZone>,
+}
+
+impl Schedule {
+    /// There are two ways to schedule an issue:
+    ///
+    /// - `scheduled_at` sends it at one time for everyone (see
+    ///   [`parse_schedule`]);
+    /// - `send_at_local_time` sends it when it's that local time in each
+    ///   subscriber's time zone, such as `2023-05-09T09:00`. Subscribers who
+    ///   didn't give a time zone are in `time_zone`, or UTC without one. The
+    ///   issue is queued up when the time first comes somewhere in the world.
+    ///
+    /// Returns `None` if the issue should be sent right away.
+    pub fn parse(
+        scheduled_at: Option<&str>,
+        send_at_local_time: Option<&str>,
+        time_zone: Option<&str>,
+    ) -> Result<Option<Self>, ScheduleError> {
+        match (scheduled_at, send_at_local_time) {
+            (None, None) => Ok(None),
+            (Some(_), Some(_)) => Err(ScheduleError::Ambiguous),
+            (Some(scheduled_at), None) => Ok(Some(Self {
+                queue_at: Some(parse_schedule(scheduled_at, time_zone)?),
+                local_delivery_time: None,
+                default_time_zone: None,
+            })),
+            (None, Some(local)) => {
+                let default_time_zone = time_zone
+                    .map(|name| {
+                        TimeZone::parse(name).ok_or_else(|| {
+                            ScheduleError::UnknownTimeZone(name.into())
+                        })
+                    })
+                    .transpose()?;
+                let local = parse_local_time(local)?;
+                let now = Utc::now().naive_utc();
+                if local - Duration::hours(MIN_UTC_OFFSET_IN_HOURS) <= now {
+                    return Err(ScheduleError::Past);
+                }
+                let queue_at = local - Duration::hours(MAX_UTC_OFFSET_IN_HOURS);
+
+                Ok(Some(Self {
+                    queue_at: (queue_at > now)
+                        .then(|| Utc.from_utc_datetime(&queue_at)),
+                    local_delivery_time: Some(local),
+                    default_time_zone,
+                }))
+            }
+        }
+    }
+}
+
+/// Parse the time an issue is scheduled for.
+///
+/// Without a time zone, it must be an RFC 3339 date and time, such as
+/// `2023-05-09T09:00:00+02:00`. With one, it's the local date and time in that
+/// time zone, such as `2023-05-09T09:00`, and daylight saving time is taken into
+/// account. Either way, it must be in the future.
+pub fn parse_schedule(
+    scheduled_at: &str,
+    time_zone: Option<&str>,
+) -> Result<DateTime<Utc>, ScheduleError> {
+    let scheduled_at = match time_zone {
+        Some(time_zone) => {
+            let time_zone = TimeZone::parse(time_zone)
+                .ok_or_else(|| ScheduleError::UnknownTimeZone(time_zone.into()))?;
+            time_zone
+                .to_utc(&parse_local_time(scheduled_at)?)
+                .ok_or(ScheduleError::SkippedTime(time_zone.name()))?
+        }
+        None => DateTime::parse_from_rfc3339(scheduled_at)
+            .map_err(|_| ScheduleError::InvalidTime)?
+            .with_timezone(&Utc),
+    };
+
+    if scheduled_at <= Utc::now() {
+        return Err(ScheduleError::Past);
+    }
+
+    Ok(scheduled_at)
+}
+
+fn parse_local_time(local: &str) -> Result<NaiveDateTime, ScheduleError> {
+    NaiveDateTime::parse_from_str(local, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(local, "%Y-%m-%dT%H:%M:%S"))
+        .map_err(|_| ScheduleError::InvalidLocalTime)
+}
+
+/// Move a scheduled issue to another time, which is given like when publishing
+/// it. This also undoes cancelling it.
+///
+/// Only authenticated users may reschedule issues.
+#[tracing::instrument(
+    name = "Rescheduling a newsletter issue",
+    skip_all,
+    fields(newsletter_issue_id = %newsletter_issue_id, user_id = %user.user_id)
+)]
+pub async fn reschedule_newsletter(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    Path(newsletter_issue_id): Path<Uuid>,
+    ValidatedJson(body): ValidatedJson<BodyData>,
+) -> Result<StatusCode, Error> {
+    let schedule = Schedule::parse(
+        body.scheduled_at.as_deref(),
+        body.send_at_local_time.as_deref(),
+        body.time_zone.as_deref(),
+    )?
+    .ok_or(ScheduleError::Missing)?;
+
+    // The scheduler locks due issues while it queues them, so this either
+    // happens before, or finds the issue queued already.
+    let result = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_at = COALESCE($2, now()),
+            local_delivery_time = $3,
+            default_time_zone = $4,
+            cancelled_at = NULL,
+            n_enqueue_failures = 0,
+            enqueue_error = NULL,
+            enqueue_retry_after = NULL,
+            enqueue_failed_at = NULL
+        WHERE newsletter_issue_id = $1
+        AND enqueued_at IS NULL
+        "#,
+        newsletter_issue_id,
+        schedule.queue_at,
+        schedule.local_delivery_time,
+        schedule.default_time_zone.map(TimeZone::name)
+    )
+    .execute(&connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    if result.rows_affected() == 0 {
+        return Err(not_scheduled(&connection_pool, newsletter_issue_id).await?);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Cancel a scheduled issue, so that it isn't sent unless it's rescheduled.
+///
+/// Only authenticated users may cancel issues.
+#[tracing::instrument(
+    name = "Cancelling a newsletter issue",
+    skip_all,
+    fields(newsletter_issue_id = %newsletter_issue_id, user_id = %user.user_id)
+)]
+pub async fn cancel_newsletter(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    Path(newsletter_issue_id): Path<Uuid>,
+) -> Result<StatusCode, Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET cancelled_at = COALESCE(cancelled_at, now())
+        WHERE newsletter_issue_id = $1
+        AND enqueued_at IS NULL
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    if result.rows_affected() == 0 {
+        return Err(not_scheduled(&connection_pool, newsletter_issue_id).await?);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Tell apart issues that don't exist from issues that were sent already.
+#[tracing::instrument(name = "Checking that the issue exists", skip_all)]
+async fn not_scheduled(
+    connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> sqlx::Result<Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM newsletter_issues WHERE newsletter_issue_id = $1
+        ) AS "exists!"
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(if record.exists {
+        Error::AlreadySent
+    } else {
+        Error::UnknownIssue
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum ScheduleError {
+    #[error("unknown time zone: {0}")]
+    UnknownTimeZone(String),
+    #[error("`scheduled_at` must be an RFC 3339 date and time")]
+    InvalidTime,
+    #[error("local times must be a date and a time, such as `2023-05-09T09:00`")]
+    InvalidLocalTime,
+    #[error("`scheduled_at` doesn't exist in {0}, because clocks skip it")]
+    SkippedTime(&'static str),
+    #[error("the time must be in the future")]
+    Past,
+    #[error("only one of `scheduled_at` and `send_at_local_time` may be given")]
+    Ambiguous,
+    #[error("either `scheduled_at` or `send_at_local_time` must be given")]
+    Missing,
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error(transparent)]
+    InvalidSchedule(#[from] ScheduleError),
+    #[error("there's no newsletter issue with that ID")]
+    UnknownIssue,
+    #[error("the newsletter issue has been sent already")]
+    AlreadySent,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidSchedule(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Self::UnknownIssue => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            Self::AlreadySent => {
+                (StatusCode::CONFLICT, self.to_string()).into_response()
+            }
+            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_schedule, Schedule, ScheduleError};
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn rfc_3339_times_are_converted_to_utc() {
+        let next_year = Utc::now() + Duration::days(366);
+        let scheduled_at = next_year.format("%Y-06-01T09:00:00+02:00").to_string();
+
+        assert_eq!(
+            next_year.format("%Y-06-01T07:00:00+00:00").to_string(),
+            parse_schedule(&scheduled_at, None).unwrap().to_rfc3339()
+        );
+    }
+
+    #[test]
+    fn local_times_are_converted_to_utc() {
+        let next_year = Utc::now() + Duration::days(366);
+        let scheduled_at = next_year.format("%Y-01-10T09:00").to_string();
+
+        assert_eq!(
+            next_year.format("%Y-01-10T08:00:00+00:00").to_string(),
+            parse_schedule(&scheduled_at, Some("Europe/Paris"))
+                .unwrap()
+                .to_rfc3339()
+        );
+    }
+
+    #[test]
+    fn past_times_are_rejected() {
+        assert!(matches!(
+            parse_schedule("2023-01-10T09:00:00Z", None),
+            Err(ScheduleError::Past)
+        ));
+    }
+
+    #[test]
+    fn local_times_need_a_known_time_zone() {
+        assert!(matches!(
+            parse_schedule("2099-01-10T09:00", Some("Europe/Atlantis")),
+            Err(ScheduleError::UnknownTimeZone(_))
+        ));
+        assert!(matches!(
+            parse_schedule("2099-01-10T09:00", None),
+            Err(ScheduleError::InvalidTime)
+        ));
+    }
+
+    #[test]
+    fn local_deliveries_are_queued_up_when_the_time_first_comes() {
+        let schedule =
+            Schedule::parse(None, Some("2099-05-12T09:00"), Some("Europe/Paris"))
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(
+            "2099-05-11T19:00:00+00:00",
+            schedule.queue_at.unwrap().to_rfc3339()
+        );
+        assert_eq!(
+            Some("Europe/Paris"),
+            schedule.default_time_zone.map(|time_zone| time_zone.name())
+        );
+    }
+
+    #[test]
+    fn local_deliveries_are_rejected_once_the_time_has_come_everywhere() {
+        let yesterday = (Utc::now() - Duration::days(1))
+            .format("%Y-%m-%dT%H:%M")
+            .to_string();
+        let in_an_hour = (Utc::now() + Duration::hours(1))
+            .format("%Y-%m-%dT%H:%M")
+            .to_string();
+
+        assert!(matches!(
+            Schedule::parse(None, Some(&yesterday), None),
+            Err(ScheduleError::Past)
+        ));
+        // It's too late to wait, but still time to deliver in some places.
+        assert!(Schedule::parse(None, Some(&in_an_hour), None)
+            .unwrap()
+            .unwrap()
+            .queue_at
+            .is_none());
+    }
+}
@@ -0,0 +1,284 @@
+use crate::extractors::AuthenticatedUser;
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use inspect_error::InspectError;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::error;
+use uuid::Uuid;
+
+/// How many links [`get_newsletter_stats`] reports.
+const N_TOP_LINKS: i64 = 10;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Parameters {
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NewsletterStats {
+    newsletter_issue_id: Uuid,
+    title: String,
+    sent: i64,
+    delivered: i64,
+    bounced: i64,
+    failed: i64,
+    unique_opens: i64,
+    unique_clicks: i64,
+    unsubscribes: i64,
+    top_links: Vec<LinkStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkStats {
+    url: String,
+    unique_clicks: i64,
+    total_clicks: i64,
+}
+
+/// Report how an issue fared, as JSON or, with `?format=csv`, as CSV.
+///
+/// - `sent` counts the subscribers the email provider accepted the issue for,
+///   and `delivered` and `bounced` what the provider reported about them.
+/// - `failed` counts the subscribers the issue was given up on for, because
+///   the provider rejected it or it failed too many times.
+/// - `unique_opens` and `unique_clicks` count subscribers, leaving out the
+///   events that look automated. Clicking implies opening, even if the mail
+///   client didn't load the tracking pixel.
+/// - `unsubscribes` counts the subscribers who unsubscribed through the issue.
+/// - `top_links` are the links with the most unique clicks.
+///
+/// Only authenticated users may see the statistics.
+#[tracing::instrument(
+    name = "Getting the statistics of a newsletter issue",
+    skip_all,
+    fields(newsletter_issue_id = %newsletter_issue_id, user_id = %user.user_id)
+)]
+pub async fn get_newsletter_stats(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    Path(newsletter_issue_id): Path<Uuid>,
+    Query(parameters): Query<Parameters>,
+) -> Result<Response, Error> {
+    let stats = compute_stats(&connection_pool, newsletter_issue_id).await?;
+
+    Ok(match parameters.format {
+        Format::Json => Json(stats).into_response(),
+        Format::Csv => {
+            let disposition = format!(
+                r#"attachment; filename="{newsletter_issue_id}-stats.csv""#
+            );
+            (
+                [
+                    (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_owned()),
+                    (header::CONTENT_DISPOSITION, disposition),
+                ],
+                to_csv(&stats)?,
+            )
+                .into_response()
+        }
+    })
+}
+
+#[tracing::instrument(name = "Computing the statistics", skip_all)]
+async fn compute_stats(
+    connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<NewsletterStats, Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT
+            issue.title,
+            deliveries.sent AS "sent!",
+            deliveries.delivered AS "delivered!",
+            deliveries.bounced AS "bounced!",
+            deliveries.failed AS "failed!",
+            engagement.unique_opens AS "unique_opens!",
+            engagement.unique_clicks AS "unique_clicks!",
+            engagement.unsubscribes AS "unsubscribes!"
+        FROM newsletter_issues AS issue
+        CROSS JOIN LATERAL (
+            SELECT
+                COUNT(DISTINCT subscriber_email) FILTER (WHERE kind = 'sent')
+                    AS sent,
+                COUNT(DISTINCT subscriber_email) FILTER (WHERE kind = 'delivered')
+                    AS delivered,
+                COUNT(DISTINCT subscriber_email) FILTER (WHERE kind = 'bounced')
+                    AS bounced,
+                COUNT(DISTINCT subscriber_email) FILTER (WHERE kind = 'failed')
+                    AS failed
+            FROM delivery_events
+            WHERE newsletter_issue_id = issue.newsletter_issue_id
+        ) AS deliveries
+        CROSS JOIN LATERAL (
+            SELECT
+                COUNT(DISTINCT subscriber_id) FILTER (
+                    WHERE kind IN ('open', 'click') AND bot_reason IS NULL
+                ) AS unique_opens,
+                COUNT(DISTINCT subscriber_id) FILTER (
+                    WHERE kind = 'click' AND bot_reason IS NULL
+                ) AS unique_clicks,
+                COUNT(DISTINCT subscriber_id) FILTER (WHERE kind = 'unsubscribe')
+                    AS unsubscribes
+            FROM engagement_events
+            WHERE newsletter_issue_id = issue.newsletter_issue_id
+        ) AS engagement
+        WHERE issue.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?
+    .ok_or(Error::UnknownIssue)?;
+
+    let top_links = sqlx::query_as!(
+        LinkStats,
+        r#"
+        SELECT
+            links.url,
+            COUNT(DISTINCT events.subscriber_id) AS "unique_clicks!",
+            COUNT(*) AS "total_clicks!"
+        FROM tracked_links AS links
+        JOIN engagement_events AS events
+            ON events.newsletter_issue_id = links.newsletter_issue_id
+            AND events.link_index = links.link_index
+            AND events.kind = 'click'
+            AND events.bot_reason IS NULL
+        WHERE links.newsletter_issue_id = $1
+        GROUP BY links.link_index, links.url
+        ORDER BY 2 DESC, 3 DESC, links.link_index
+        LIMIT $2
+        "#,
+        newsletter_issue_id,
+        N_TOP_LINKS
+    )
+    .fetch_all(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(NewsletterStats {
+        newsletter_issue_id,
+        title: record.title,
+        sent: record.sent,
+        delivered: record.delivered,
+        bounced: record.bounced,
+        failed: record.failed,
+        unique_opens: record.unique_opens,
+        unique_clicks: record.unique_clicks,
+        unsubscribes: record.unsubscribes,
+        top_links,
+    })
+}
+
+/// One `metric,url,value` row per number, so that the links fit in the same
+/// table. Only the rows of links have a URL.
+fn to_csv(stats: &NewsletterStats) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["metric", "url", "value"])?;
+    for (metric, value) in [
+        ("sent", stats.sent),
+        ("delivered", stats.delivered),
+        ("bounced", stats.bounced),
+        ("failed", stats.failed),
+        ("unique_opens", stats.unique_opens),
+        ("unique_clicks", stats.unique_clicks),
+        ("unsubscribes", stats.unsubscribes),
+    ] {
+        writer.write_record([metric, "", &value.to_string()])?;
+    }
+    for link in &stats.top_links {
+        writer.write_record([
+            "link_unique_clicks",
+            &link.url,
+            &link.unique_clicks.to_string(),
+        ])?;
+        writer.write_record([
+            "link_total_clicks",
+            &link.url,
+            &link.total_clicks.to_string(),
+        ])?;
+    }
+    writer
+        .into_inner()
+        .map_err(|error| csv::Error::from(error.into_error()))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error("there's no newsletter issue with that ID")]
+    UnknownIssue,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::UnknownIssue => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            Self::Database(_) | Self::Csv(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_csv, LinkStats, NewsletterStats};
+    use uuid::Uuid;
+
+    #[test]
+    fn every_number_is_a_row() {
+        let stats = NewsletterStats {
+            newsletter_issue_id: Uuid::new_v4(),
+            title: "Issue #1".to_string(),
+            sent: 4,
+            delivered: 3,
+            bounced: 1,
+            failed: 0,
+            unique_opens: 2,
+            unique_clicks: 1,
+            unsubscribes: 0,
+            top_links: vec![LinkStats {
+                url: "https://example.com/?a=1,2".to_string(),
+                unique_clicks: 1,
+                total_clicks: 3,
+            }],
+        };
+
+        let csv = String::from_utf8(to_csv(&stats).unwrap()).unwrap();
+
+        assert_eq!(
+            "metric,url,value\n\
+            sent,,4\n\
+            delivered,,3\n\
+            bounced,,1\n\
+            failed,,0\n\
+            unique_opens,,2\n\
+            unique_clicks,,1\n\
+            unsubscribes,,0\n\
+            link_unique_clicks,\"https://example.com/?a=1,2\",1\n\
+            link_total_clicks,\"https://example.com/?a=1,2\",3\n",
+            csv
+        );
+    }
+}
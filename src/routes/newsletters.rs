@@ -0,0 +1,487 @@
+use crate::{
+    domain::{IdempotencyKey, Slug, TemplateName, TimeZone},
+    extractors::{AuthenticatedUser, ValidatedJson},
+    html_email,
+    idempotency::{save_response, try_processing, NextAction},
+    issue_scheduler::enqueue_issue,
+    routes::{
+        newsletter_schedule::{Schedule, ScheduleError},
+        segments::load_segment_filter,
+        templates::load_template,
+    },
+    state::ApplicationBaseUrl,
+    templates::{self, TemplateEngine},
+    tracking,
+};
+use anyhow::Context;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use inspect_error::InspectError;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::error;
+use uuid::Uuid;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "has_either_content_or_template"))]
+pub struct BodyData {
+    #[validate(length(min = 1))]
+    title: String,
+    #[validate]
+    content: Option<Content>,
+    /// The name of a saved template to render the issue from, instead of
+    /// providing the content directly.
+    template: Option<String>,
+    /// Only the subscribers of the list receive the issue. Issues without a
+    /// list go to the subscribers of `/subscriptions`.
+    list: Option<String>,
+    /// Subscribers who opted out of the topic don't receive the issue. Issues
+    /// without a topic go to everyone.
+    topic: Option<String>,
+    /// The slug of a saved segment. Only the subscribers matching its filter
+    /// receive the issue.
+    segment: Option<String>,
+    /// Send the issue at this time instead of right away (see
+    /// [`Schedule::parse`]).
+    scheduled_at: Option<String>,
+    /// Send the issue at this local time in each subscriber's time zone.
+    send_at_local_time: Option<String>,
+    /// The time zone `scheduled_at` is in, or that of subscribers without one,
+    /// such as `Europe/Paris`.
+    time_zone: Option<String>,
+    /// Whether opens and clicks are tracked (see [`tracking`]). They are by
+    /// default.
+    tracking: Option<bool>,
+    /// For clients that can't set the `Idempotency-Key` header.
+    idempotency_key: Option<String>,
+}
+
+fn has_either_content_or_template(body: &BodyData) -> Result<(), ValidationError> {
+    if body.content.is_some() == body.template.is_some() {
+        Err(ValidationError::new(
+            "exactly one of `content` and `template` must be provided",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct Content {
+    #[validate(length(min = 1))]
+    html: String,
+    /// Derived from the HTML if missing.
+    #[validate(length(min = 1))]
+    text: Option<String>,
+}
+
+/// Store the issue and queue up a delivery task for every confirmed subscriber,
+/// of the issue's list if it has one.
+/// The emails themselves are sent by the delivery worker (see
+/// [`crate::issue_delivery_worker`]), which is why this responds with `202`.
+///
+/// Scheduled issues are queued up by the scheduler (see
+/// [`crate::issue_scheduler`]) once they're due instead. The response body then
+/// has the ID of the issue, to reschedule or cancel it, and the UTC time it's
+/// queued up at. Issues sent at a local time are delivered to each subscriber
+/// when it's that time for them.
+///
+/// Subscribers who opted out of the issue's topic, or who don't match its
+/// segment, are skipped. Those with a daily or weekly delivery window get it at
+/// the start of the next day or week.
+///
+/// The HTML is prepared for mail clients first (see [`html_email::prepare`]),
+/// then links and a pixel are added to track opens and clicks, unless tracking
+/// is turned off for the issue. If the resulting HTML is large enough to be
+/// clipped by Gmail, the response body says so as `{ "warnings": [...] }`.
+///
+/// Only authenticated users may publish.
+///
+/// Requests with an idempotency key (in the `Idempotency-Key` header or the
+/// `idempotency_key` field) are only handled once per user and key. Retries get
+/// the saved response instead of publishing the issue again.
+#[tracing::instrument(
+    name = "Publishing a newsletter issue",
+    skip_all,
+    fields(title = %body.title, user_id = %user.user_id)
+)]
+pub async fn publish_newsletter(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    State(template_engine): State<Arc<TemplateEngine>>,
+    State(base_url): State<ApplicationBaseUrl>,
+    headers: HeaderMap,
+    ValidatedJson(body): ValidatedJson<BodyData>,
+) -> Result<Response, Error> {
+    let idempotency_key = get_idempotency_key(&headers, &body)?;
+    let list = body
+        .list
+        .clone()
+        .map(Slug::parse)
+        .transpose()
+        .map_err(Error::InvalidList)?;
+    let topic = body
+        .topic
+        .clone()
+        .map(Slug::parse)
+        .transpose()
+        .map_err(Error::InvalidTopic)?;
+    let schedule = Schedule::parse(
+        body.scheduled_at.as_deref(),
+        body.send_at_local_time.as_deref(),
+        body.time_zone.as_deref(),
+    )?;
+    let scheduled_at = schedule.as_ref().and_then(|schedule| schedule.queue_at);
+    // The filter is copied into the issue, so that editing the segment doesn't
+    // change who scheduled issues go to.
+    let segment_filter = match body.segment.clone() {
+        Some(slug) => {
+            let slug = Slug::parse(slug).map_err(Error::InvalidSegment)?;
+            Some(
+                load_segment_filter(&connection_pool, &slug)
+                    .await?
+                    .ok_or(Error::UnknownSegment)?,
+            )
+        }
+        None => None,
+    };
+
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => {
+            match try_processing(&connection_pool, idempotency_key, user.user_id)
+                .await?
+            {
+                NextAction::StartProcessing(transaction) => *transaction,
+                NextAction::ReturnSavedResponse(saved_response) => {
+                    return Ok(saved_response);
+                }
+            }
+        }
+        None => connection_pool.begin().await.inspect_error(|error| {
+            error!("Failed to begin a transaction: {error}");
+        })?,
+    };
+
+    if let Some(list) = &list {
+        if !list_exists(&mut transaction, list).await? {
+            return Err(Error::UnknownList);
+        }
+    }
+    if let Some(topic) = &topic {
+        if !topic_exists(&mut transaction, topic).await? {
+            return Err(Error::UnknownTopic);
+        }
+    }
+    let list = list.as_ref().map(AsRef::as_ref);
+    let topic = topic.as_ref().map(AsRef::as_ref);
+
+    let tracking_enabled = body.tracking.unwrap_or(true);
+    let mut content =
+        get_content(&connection_pool, &template_engine, body).await?;
+    let tracked_links = if tracking_enabled {
+        let tracked = tracking::add_tracking(&content.html, &base_url.0)
+            .context("failed to add tracking to the HTML")?;
+        content.html = tracked.html;
+        tracked.links
+    } else {
+        Vec::new()
+    };
+    let warnings: Vec<_> = html_email::clipping_warning(&content.html)
+        .into_iter()
+        .collect();
+    let newsletter_issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &IssueData {
+            title: &content.title,
+            text_content: &content.text,
+            html_content: &content.html,
+            list,
+            topic,
+            segment_filter: segment_filter.as_deref(),
+            scheduled_at,
+            local_delivery_time: schedule
+                .as_ref()
+                .and_then(|schedule| schedule.local_delivery_time),
+            default_time_zone: schedule
+                .as_ref()
+                .and_then(|schedule| schedule.default_time_zone)
+                .map(TimeZone::name),
+            tracking_enabled,
+        },
+    )
+    .await?;
+    insert_tracked_links(&mut transaction, newsletter_issue_id, &tracked_links)
+        .await?;
+
+    let response = if let Some(scheduled_at) = scheduled_at {
+        let mut body = json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "scheduled_at": scheduled_at.to_rfc3339(),
+        });
+        if !warnings.is_empty() {
+            body["warnings"] = json!(warnings);
+        }
+        (StatusCode::ACCEPTED, Json(body)).into_response()
+    } else {
+        enqueue_issue(&mut transaction, newsletter_issue_id).await?;
+        if warnings.is_empty() {
+            StatusCode::ACCEPTED.into_response()
+        } else {
+            let body = json!({ "warnings": warnings });
+            (StatusCode::ACCEPTED, Json(body)).into_response()
+        }
+    };
+    let response = match &idempotency_key {
+        Some(idempotency_key) => {
+            save_response(transaction, idempotency_key, user.user_id, response)
+                .await?
+        }
+        None => {
+            transaction.commit().await.inspect_error(|error| {
+                error!("Failed to commit the transaction: {error}");
+            })?;
+            response
+        }
+    };
+
+    Ok(response)
+}
+
+/// The header takes precedence over the body field.
+fn get_idempotency_key(
+    headers: &HeaderMap,
+    body: &BodyData,
+) -> Result<Option<IdempotencyKey>, Error> {
+    let idempotency_key = match headers.get("Idempotency-Key") {
+        Some(value) => Some(
+            value
+                .to_str()
+                .map_err(|_| Error::NonTextIdempotencyKey)?
+                .to_owned(),
+        ),
+        None => body.idempotency_key.clone(),
+    };
+
+    idempotency_key
+        .map(IdempotencyKey::parse)
+        .transpose()
+        .map_err(Error::InvalidIdempotencyKey)
+}
+
+struct IssueContent {
+    title: String,
+    html: String,
+    text: String,
+}
+
+/// Take the content from the body or render it from the named template, then
+/// prepare it for sending.
+async fn get_content(
+    connection_pool: &PgPool,
+    template_engine: &TemplateEngine,
+    body: BodyData,
+) -> Result<IssueContent, Error> {
+    let (html, text) = if let Some(content) = body.content {
+        (content.html, content.text)
+    } else {
+        // Validation guarantees that there's a template if there's no content.
+        let name = body.template.unwrap_or_default();
+        let name =
+            TemplateName::parse(name).map_err(Error::InvalidTemplateName)?;
+        let template = load_template(connection_pool, &name)
+            .await?
+            .ok_or(Error::UnknownTemplate)?;
+        let rendered = template_engine
+            .render(&template, &body.title)
+            .inspect_error(|error| {
+                error!("Failed to render the template: {error}");
+            })?;
+        (rendered.html, Some(rendered.text))
+    };
+
+    let prepared = html_email::prepare(&html, text.as_deref())
+        .context("failed to prepare the HTML for sending")?;
+
+    Ok(IssueContent {
+        title: body.title,
+        html: prepared.html,
+        text: prepared.text,
+    })
+}
+
+#[tracing::instrument(name = "Checking that the list exists", skip_all)]
+async fn list_exists(
+    transaction: &mut Transaction<'_, Postgres>,
+    list: &Slug,
+) -> sqlx::Result<bool> {
+    let record = sqlx::query!(
+        r#"SELECT EXISTS(SELECT 1 FROM lists WHERE slug = $1) AS "exists!""#,
+        list.as_ref()
+    )
+    .fetch_one(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(record.exists)
+}
+
+#[tracing::instrument(name = "Checking that the topic exists", skip_all)]
+async fn topic_exists(
+    transaction: &mut Transaction<'_, Postgres>,
+    topic: &Slug,
+) -> sqlx::Result<bool> {
+    let record = sqlx::query!(
+        r#"SELECT EXISTS(SELECT 1 FROM topics WHERE slug = $1) AS "exists!""#,
+        topic.as_ref()
+    )
+    .fetch_one(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(record.exists)
+}
+
+struct IssueData<'a> {
+    title: &'a str,
+    text_content: &'a str,
+    html_content: &'a str,
+    list: Option<&'a str>,
+    topic: Option<&'a str>,
+    segment_filter: Option<&'a str>,
+    scheduled_at: Option<DateTime<Utc>>,
+    local_delivery_time: Option<NaiveDateTime>,
+    default_time_zone: Option<&'a str>,
+    tracking_enabled: bool,
+}
+
+#[tracing::instrument(name = "Saving the newsletter issue", skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue: &IssueData<'_>,
+) -> sqlx::Result<Uuid> {
+    let newsletter_issue_id = Uuid::new_v4();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            list_slug,
+            topic_slug,
+            segment_filter,
+            scheduled_at,
+            local_delivery_time,
+            default_time_zone,
+            tracking_enabled
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        "#,
+        newsletter_issue_id,
+        issue.title,
+        issue.text_content,
+        issue.html_content,
+        issue.list,
+        issue.topic,
+        issue.segment_filter,
+        issue.scheduled_at,
+        issue.local_delivery_time,
+        issue.default_time_zone,
+        issue.tracking_enabled
+    )
+    .execute(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(newsletter_issue_id)
+}
+
+/// Save the original URLs of the issue's links, which are indexed by their
+/// position.
+#[tracing::instrument(name = "Saving the tracked links", skip_all)]
+async fn insert_tracked_links(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    links: &[String],
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO tracked_links (newsletter_issue_id, link_index, url)
+        SELECT $1, link.index - 1, link.url
+        FROM UNNEST($2::text[]) WITH ORDINALITY AS link(url, index)
+        "#,
+        newsletter_issue_id,
+        links
+    )
+    .execute(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error("the idempotency key must be valid UTF-8")]
+    NonTextIdempotencyKey,
+    #[error("invalid idempotency key: {0}")]
+    InvalidIdempotencyKey(ValidationErrors),
+    #[error("invalid template name: {0}")]
+    InvalidTemplateName(ValidationErrors),
+    #[error("there's no template with that name")]
+    UnknownTemplate,
+    #[error(transparent)]
+    Template(#[from] templates::Error),
+    #[error("invalid list: {0}")]
+    InvalidList(ValidationErrors),
+    #[error("there's no list with that slug")]
+    UnknownList,
+    #[error("invalid segment: {0}")]
+    InvalidSegment(ValidationErrors),
+    #[error("there's no segment with that slug")]
+    UnknownSegment,
+    #[error("invalid topic: {0}")]
+    InvalidTopic(ValidationErrors),
+    #[error("there's no topic with that slug")]
+    UnknownTopic,
+    #[error(transparent)]
+    InvalidSchedule(#[from] ScheduleError),
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::NonTextIdempotencyKey
+            | Self::InvalidIdempotencyKey(_)
+            | Self::InvalidTemplateName(_)
+            | Self::UnknownTemplate
+            | Self::Template(_)
+            | Self::InvalidList(_)
+            | Self::UnknownList
+            | Self::InvalidSegment(_)
+            | Self::UnknownSegment
+            | Self::InvalidTopic(_)
+            | Self::UnknownTopic
+            | Self::InvalidSchedule(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Self::Database(_) | Self::Unexpected(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
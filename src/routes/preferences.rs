@@ -0,0 +1,320 @@
+use super::{escape_html, render_flash_messages, topics::load_topics};
+use crate::{
+    domain::{DeliveryWindow, SubscriberName},
+    session::Flash,
+    SubscriberLinks,
+};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+    Form,
+};
+use inspect_error::InspectError;
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::{collections::HashSet, fmt::Write};
+use thiserror::Error;
+use tracing::{error, Span};
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+struct Subscriber {
+    email: String,
+    name: String,
+    delivery_window: String,
+}
+
+/// Show the subscriber's preferences: their name, the topics they receive and
+/// when they want to get them.
+#[tracing::instrument(
+    name = "Showing the preferences page",
+    skip_all,
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn preferences_form(
+    State(connection_pool): State<PgPool>,
+    State(subscriber_links): State<SubscriberLinks>,
+    Query(parameters): Query<Parameters>,
+    flash: Flash,
+) -> Result<Html<String>, Error> {
+    let subscriber_id = subscriber_links
+        .verify(&parameters.token)
+        .ok_or(Error::InvalidToken)?;
+    Span::current()
+        .record("subscriber_id", tracing::field::display(subscriber_id));
+
+    let subscriber = get_subscriber(&connection_pool, subscriber_id)
+        .await?
+        .ok_or(Error::UnknownSubscriber)?;
+    let opted_out = get_opted_out_topics(&connection_pool, subscriber_id).await?;
+    let topics = load_topics(&connection_pool).await?;
+
+    let messages = render_flash_messages(&flash.take_messages());
+    let token = escape_html(&parameters.token);
+    let email = escape_html(&subscriber.email);
+    let name = escape_html(&subscriber.name);
+    let topic_checkboxes = topics.iter().fold(String::new(), |mut html, topic| {
+        let checked = if opted_out.contains(&topic.slug) {
+            ""
+        } else {
+            " checked"
+        };
+        let _ = write!(
+            html,
+            r#"
+        <label>
+            <input type="checkbox" name="topic" value="{}"{checked}>
+            {}
+        </label>
+        <br>"#,
+            escape_html(&topic.slug),
+            escape_html(&topic.name)
+        );
+        html
+    });
+    let window_options =
+        DeliveryWindow::ALL
+            .iter()
+            .fold(String::new(), |mut html, window| {
+                let value = window.as_str();
+                let selected = if value == subscriber.delivery_window {
+                    " selected"
+                } else {
+                    ""
+                };
+                let _ = write!(
+                    html,
+                    r#"
+            <option value="{value}"{selected}>{}</option>"#,
+                    window_label(*window)
+                );
+                html
+            });
+
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Preferences</title>
+</head>
+<body>
+    {messages}
+    <p>Preferences for {email}</p>
+    <form action="/preferences?token={token}" method="post">
+        <label>Name
+            <input type="text" name="name" value="{name}">
+        </label>
+        <br>
+        <fieldset>
+            <legend>Topics</legend>{topic_checkboxes}
+        </fieldset>
+        <label>When to get issues
+            <select name="delivery_window">{window_options}
+            </select>
+        </label>
+        <br>
+        <button type="submit">Save preferences</button>
+    </form>
+</body>
+</html>"#
+    )))
+}
+
+const fn window_label(window: DeliveryWindow) -> &'static str {
+    match window {
+        DeliveryWindow::Immediately => "As soon as an issue is published",
+        DeliveryWindow::Daily => "At the start of the next day",
+        DeliveryWindow::Weekly => "At the start of the next week",
+    }
+}
+
+/// Save the subscriber's preferences. The form has a `name`, a
+/// `delivery_window` and a `topic` field for every topic they want to receive,
+/// so topics that aren't in the form are opted out of.
+///
+/// The outcome is reported through a flash message on the preferences page,
+/// whether it succeeded or not.
+#[tracing::instrument(
+    name = "Saving the subscriber's preferences",
+    skip_all,
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn save_preferences(
+    State(connection_pool): State<PgPool>,
+    State(subscriber_links): State<SubscriberLinks>,
+    Query(parameters): Query<Parameters>,
+    flash: Flash,
+    Form(form): Form<Vec<(String, String)>>,
+) -> Result<Redirect, Error> {
+    let subscriber_id = subscriber_links
+        .verify(&parameters.token)
+        .ok_or(Error::InvalidToken)?;
+    Span::current()
+        .record("subscriber_id", tracing::field::display(subscriber_id));
+    let redirect =
+        Redirect::to(&format!("/preferences?token={}", parameters.token));
+
+    let field = |name: &str| {
+        form.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+    };
+    let Ok(name) = SubscriberName::parse(field("name").unwrap_or_default()) else {
+        flash.error("Please enter a valid name.");
+        return Ok(redirect);
+    };
+    let Some(window) =
+        DeliveryWindow::parse(&field("delivery_window").unwrap_or_default())
+    else {
+        flash.error("Please pick when you want to get issues.");
+        return Ok(redirect);
+    };
+    let topics: Vec<_> = form
+        .iter()
+        .filter(|(key, _)| key == "topic")
+        .map(|(_, value)| value.clone())
+        .collect();
+
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .inspect_error(|error| error!("Failed to begin a transaction: {error}"))?;
+    let updated =
+        update_subscriber(&mut transaction, subscriber_id, &name, window).await?;
+    if !updated {
+        return Err(Error::UnknownSubscriber);
+    }
+    replace_topic_opt_outs(&mut transaction, subscriber_id, &topics).await?;
+    transaction.commit().await.inspect_error(|error| {
+        error!("Failed to commit the transaction: {error}");
+    })?;
+
+    flash.info("Your preferences have been saved.");
+    Ok(redirect)
+}
+
+#[tracing::instrument(name = "Getting the subscriber", skip_all)]
+async fn get_subscriber(
+    connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> sqlx::Result<Option<Subscriber>> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT email, name, delivery_window
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))
+}
+
+#[tracing::instrument(name = "Getting the topics opted out of", skip_all)]
+async fn get_opted_out_topics(
+    connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> sqlx::Result<HashSet<String>> {
+    let records = sqlx::query!(
+        "SELECT topic_slug FROM topic_opt_outs WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .fetch_all(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| record.topic_slug)
+        .collect())
+}
+
+/// Returns whether the subscriber exists.
+#[tracing::instrument(name = "Updating the subscriber", skip_all)]
+async fn update_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    name: &SubscriberName,
+    window: DeliveryWindow,
+) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET name = $2, delivery_window = $3
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        name.as_ref(),
+        window.as_str()
+    )
+    .execute(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Opt the subscriber out of every topic but the given ones. Unknown topics are
+/// ignored.
+#[tracing::instrument(name = "Replacing the topic opt-outs", skip_all)]
+async fn replace_topic_opt_outs(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    topics: &[String],
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "DELETE FROM topic_opt_outs WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO topic_opt_outs (subscriber_id, topic_slug)
+        SELECT $1, slug
+        FROM topics
+        WHERE slug <> ALL($2)
+        "#,
+        subscriber_id,
+        topics
+    )
+    .execute(&mut *transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("the subscriber token is invalid")]
+    InvalidToken,
+    #[error("the subscriber doesn't exist anymore")]
+    UnknownSubscriber,
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidToken => (StatusCode::UNAUTHORIZED, self.to_string()),
+            Self::UnknownSubscriber => (StatusCode::NOT_FOUND, self.to_string()),
+            Self::Database(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::INTERNAL_SERVER_ERROR.to_string(),
+            ),
+        }
+        .into_response()
+    }
+}
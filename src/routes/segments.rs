@@ -0,0 +1,167 @@
+use crate::{
+    domain::Slug,
+    extractors::{AuthenticatedUser, ValidatedJson},
+    issue_scheduler::Audience,
+    segment,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use inspect_error::InspectError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgPool, Postgres, QueryBuilder, Row};
+use thiserror::Error;
+use tracing::error;
+use validator::{Validate, ValidationErrors};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct BodyData {
+    /// See [`segment`] for the syntax.
+    #[validate(length(min = 1, max = 4096))]
+    filter: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Segment {
+    slug: String,
+    filter: String,
+}
+
+/// Save the segment with the given slug, replacing it if it exists. Issues
+/// published with it only go to the subscribers matching its filter.
+///
+/// Only authenticated users may save segments.
+#[tracing::instrument(
+    name = "Saving a segment",
+    skip_all,
+    fields(segment_slug = %slug, user_id = %user.user_id)
+)]
+pub async fn save_segment(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    Path(slug): Path<String>,
+    ValidatedJson(body): ValidatedJson<BodyData>,
+) -> Result<StatusCode, Error> {
+    let slug = Slug::parse(slug).map_err(Error::InvalidSlug)?;
+    segment::parse(&body.filter)?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO segments (slug, filter)
+        VALUES ($1, $2)
+        ON CONFLICT (slug) DO UPDATE
+        SET filter = EXCLUDED.filter
+        "#,
+        slug.as_ref(),
+        body.filter
+    )
+    .execute(&connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Get every segment, in alphabetical order of their slugs.
+#[tracing::instrument(
+    name = "Getting the segments",
+    skip_all,
+    fields(user_id = %user.user_id)
+)]
+pub async fn get_segments(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+) -> Result<Json<Vec<Segment>>, Error> {
+    let segments = sqlx::query_as!(
+        Segment,
+        "SELECT slug, filter FROM segments ORDER BY slug"
+    )
+    .fetch_all(&connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(Json(segments))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct PreviewBodyData {
+    #[validate(length(min = 1, max = 4096))]
+    filter: String,
+    /// Count the subscribers of this list, instead of those of issues published
+    /// without a list.
+    list: Option<String>,
+    /// Leave out the subscribers who opted out of this topic.
+    topic: Option<String>,
+}
+
+/// Count the subscribers an issue published with the filter (and the list and
+/// topic, if any) would go to, as `{ "count": ... }`.
+#[tracing::instrument(
+    name = "Previewing a segment",
+    skip_all,
+    fields(user_id = %user.user_id)
+)]
+pub async fn preview_segment(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    ValidatedJson(body): ValidatedJson<PreviewBodyData>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let filter = segment::parse(&body.filter)?;
+    let audience = Audience {
+        list: body.list.as_deref(),
+        topic: body.topic.as_deref(),
+        filter: Some(&filter),
+    };
+
+    let mut query =
+        QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM subscriptions WHERE ");
+    audience.push_condition(&mut query);
+    let count: i64 = query
+        .build()
+        .fetch_one(&connection_pool)
+        .await
+        .and_then(|row| row.try_get(0))
+        .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(Json(json!({ "count": count })))
+}
+
+/// Load the filter of the segment with the given slug.
+#[tracing::instrument(name = "Loading the segment", skip_all)]
+pub async fn load_segment_filter(
+    connection_pool: &PgPool,
+    slug: &Slug,
+) -> sqlx::Result<Option<String>> {
+    let record =
+        sqlx::query!("SELECT filter FROM segments WHERE slug = $1", slug.as_ref())
+            .fetch_optional(connection_pool)
+            .await
+            .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(record.map(|record| record.filter))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error("invalid segment slug: {0}")]
+    InvalidSlug(ValidationErrors),
+    #[error("invalid filter: {0}")]
+    InvalidFilter(#[from] segment::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidSlug(_) | Self::InvalidFilter(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
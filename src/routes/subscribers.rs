@@ -0,0 +1,402 @@
+use crate::{
+    domain::{SubscriberEmail, SubscriberName, SubscriptionStatus, Tags},
+    extractors::AuthenticatedUser,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, SecondsFormat, Utc};
+use inspect_error::InspectError;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use thiserror::Error;
+use tracing::error;
+use uuid::Uuid;
+use validator::ValidationErrors;
+
+const DEFAULT_LIMIT: i64 = 50;
+
+const MAX_LIMIT: i64 = 500;
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Subscriber {
+    pub(super) id: Uuid,
+    pub(super) email: String,
+    pub(super) name: String,
+    pub(super) status: String,
+    pub(super) time_zone: Option<String>,
+    pub(super) delivery_window: String,
+    pub(super) tags: Vec<String>,
+    pub(super) created_at: DateTime<Utc>,
+    pub(super) confirmed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Parameters {
+    status: Option<String>,
+    /// Inclusive.
+    created_after: Option<DateTime<Utc>>,
+    /// Exclusive.
+    created_before: Option<DateTime<Utc>>,
+    /// Part of the address or the name, in any case.
+    search: Option<String>,
+    limit: Option<i64>,
+    /// The `next_cursor` of the previous page.
+    after: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubscribersPage {
+    subscribers: Vec<Subscriber>,
+    /// `None` on the last page.
+    next_cursor: Option<String>,
+}
+
+/// Get a page of subscribers, oldest first, optionally filtered by status,
+/// creation time and a search of their address and name.
+///
+/// Pages are fetched after the last subscriber of the previous one rather than
+/// at an offset, so they stay fast however deep they are, and subscribers
+/// added or deleted meanwhile don't shift them. `limit` is capped at
+/// [`MAX_LIMIT`].
+#[tracing::instrument(
+    name = "Getting the subscribers",
+    skip_all,
+    fields(user_id = %user.user_id)
+)]
+pub async fn get_subscribers(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    Query(parameters): Query<Parameters>,
+) -> Result<Json<SubscribersPage>, Error> {
+    let status = parameters
+        .status
+        .as_deref()
+        .map(|status| {
+            SubscriptionStatus::parse(status).ok_or(Error::UnknownStatus)
+        })
+        .transpose()?;
+    let after = parameters
+        .after
+        .as_deref()
+        .map(|cursor| decode_cursor(cursor).ok_or(Error::InvalidCursor))
+        .transpose()?;
+    let limit = parameters
+        .limit
+        .unwrap_or(DEFAULT_LIMIT)
+        .clamp(1, MAX_LIMIT);
+
+    let mut query = QueryBuilder::<Postgres>::new(
+        r#"
+        SELECT
+            id,
+            email,
+            name,
+            status,
+            time_zone,
+            delivery_window,
+            tags,
+            created_at,
+            confirmed_at
+        FROM subscriptions
+        WHERE true
+        "#,
+    );
+    if let Some(status) = status {
+        query.push(" AND status = ").push_bind(status.as_str());
+    }
+    if let Some(created_after) = parameters.created_after {
+        query.push(" AND created_at >= ").push_bind(created_after);
+    }
+    if let Some(created_before) = parameters.created_before {
+        query.push(" AND created_at < ").push_bind(created_before);
+    }
+    if let Some(search) = parameters.search.as_deref().map(str::trim) {
+        let pattern = format!("%{}%", escape_like_pattern(search));
+        // Nondeterministic collations like `case_insensitive` don't support
+        // `LIKE`.
+        query
+            .push(r#" AND (email COLLATE "default" ILIKE "#)
+            .push_bind(pattern.clone())
+            .push(r#" OR name COLLATE "default" ILIKE "#)
+            .push_bind(pattern)
+            .push(")");
+    }
+    if let Some((created_at, id)) = after {
+        query
+            .push(" AND (created_at, id) > (")
+            .push_bind(created_at)
+            .push(", ")
+            .push_bind(id)
+            .push(")");
+    }
+    // One more than the page, to tell whether there's another one.
+    query
+        .push(" ORDER BY created_at, id LIMIT ")
+        .push_bind(limit + 1);
+
+    let mut subscribers = query
+        .build_query_as::<Subscriber>()
+        .fetch_all(&connection_pool)
+        .await
+        .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    let next_cursor = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(encode_cursor)
+    } else {
+        None
+    };
+    Ok(Json(SubscribersPage {
+        subscribers,
+        next_cursor,
+    }))
+}
+
+fn encode_cursor(subscriber: &Subscriber) -> String {
+    URL_SAFE_NO_PAD.encode(format!(
+        "{}|{}",
+        // Postgres keeps microseconds, so the cursor matches the row exactly.
+        subscriber
+            .created_at
+            .to_rfc3339_opts(SecondsFormat::Micros, true),
+        subscriber.id
+    ))
+}
+
+fn decode_cursor(cursor: &str) -> Option<(DateTime<Utc>, Uuid)> {
+    let cursor = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+    let (created_at, id) = cursor.split_once('|')?;
+    let created_at = DateTime::parse_from_rfc3339(created_at).ok()?;
+    Some((created_at.with_timezone(&Utc), Uuid::parse_str(id).ok()?))
+}
+
+/// Match `value` literally in a `LIKE` pattern.
+fn escape_like_pattern(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        if matches!(character, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
+
+#[tracing::instrument(
+    name = "Getting a subscriber",
+    skip_all,
+    fields(subscriber_id = %id, user_id = %user.user_id)
+)]
+pub async fn get_subscriber(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Subscriber>, Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT
+            id,
+            email,
+            name,
+            status,
+            time_zone,
+            delivery_window,
+            tags,
+            created_at,
+            confirmed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?
+    .ok_or(Error::UnknownSubscriber)?;
+
+    Ok(Json(subscriber))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubscriberUpdate {
+    name: Option<String>,
+    email: Option<String>,
+    status: Option<String>,
+    tags: Option<Vec<String>>,
+}
+
+/// Change the fields of the subscriber that are in the body, validated like the
+/// subscription form, and get the subscriber back. Confirming a subscriber
+/// doesn't send them anything.
+#[tracing::instrument(
+    name = "Updating a subscriber",
+    skip_all,
+    fields(subscriber_id = %id, user_id = %user.user_id)
+)]
+pub async fn update_subscriber(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    Path(id): Path<Uuid>,
+    Json(update): Json<SubscriberUpdate>,
+) -> Result<Json<Subscriber>, Error> {
+    let name = update.name.map(SubscriberName::parse).transpose()?;
+    let email = update.email.map(SubscriberEmail::parse).transpose()?;
+    let status = update
+        .status
+        .as_deref()
+        .map(|status| {
+            SubscriptionStatus::parse(status).ok_or(Error::UnknownStatus)
+        })
+        .transpose()?;
+    let tags = update.tags.map(Tags::parse).transpose()?;
+
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        UPDATE subscriptions
+        SET
+            name = COALESCE($2, name),
+            email = COALESCE($3, email),
+            status = COALESCE($4::text, status),
+            confirmed_at = CASE
+                WHEN $4::text = 'confirmed' THEN COALESCE(confirmed_at, now())
+                ELSE confirmed_at
+            END,
+            tags = COALESCE($5, tags)
+        WHERE id = $1
+        RETURNING
+            id,
+            email,
+            name,
+            status,
+            time_zone,
+            delivery_window,
+            tags,
+            created_at,
+            confirmed_at
+        "#,
+        id,
+        name.as_ref().map(AsRef::as_ref),
+        email.as_ref().map(AsRef::as_ref),
+        status.map(SubscriptionStatus::as_str),
+        tags.as_ref().map(AsRef::as_ref)
+    )
+    .fetch_optional(&connection_pool)
+    .await
+    .map_err(|error| match error {
+        sqlx::Error::Database(error)
+            if error.constraint() == Some("subscriptions_email_key") =>
+        {
+            Error::EmailTaken
+        }
+        error => {
+            error!("Failed to execute query: {error}");
+            Error::Database(error)
+        }
+    })?
+    .ok_or(Error::UnknownSubscriber)?;
+
+    Ok(Json(subscriber))
+}
+
+/// Delete the subscriber along with their subscriptions to lists, topic
+/// preferences and engagement. Their address isn't suppressed, so they can
+/// subscribe again.
+#[tracing::instrument(
+    name = "Deleting a subscriber",
+    skip_all,
+    fields(subscriber_id = %id, user_id = %user.user_id)
+)]
+pub async fn delete_subscriber(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, Error> {
+    let result = sqlx::query!("DELETE FROM subscriptions WHERE id = $1", id)
+        .execute(&connection_pool)
+        .await
+        .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    if result.rows_affected() == 0 {
+        return Err(Error::UnknownSubscriber);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error(transparent)]
+    InvalidInput(#[from] ValidationErrors),
+    #[error("unknown subscription status")]
+    UnknownStatus,
+    #[error("invalid cursor")]
+    InvalidCursor,
+    #[error("unknown subscriber")]
+    UnknownSubscriber,
+    #[error("another subscriber has this address")]
+    EmailTaken,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidInput(_) => {
+                let message = format!("Input validation error: [{self}]")
+                    .replace('\n', ", ");
+                (StatusCode::UNPROCESSABLE_ENTITY, message).into_response()
+            }
+            Self::UnknownStatus | Self::InvalidCursor => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Self::UnknownSubscriber => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            Self::EmailTaken => {
+                (StatusCode::CONFLICT, self.to_string()).into_response()
+            }
+            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_cursor, encode_cursor, escape_like_pattern, Subscriber};
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    #[test]
+    fn cursors_point_at_the_subscriber_they_were_made_from() {
+        let subscriber = Subscriber {
+            id: Uuid::new_v4(),
+            email: "ursula@example.com".to_owned(),
+            name: "Ursula".to_owned(),
+            status: "confirmed".to_owned(),
+            time_zone: None,
+            delivery_window: "immediately".to_owned(),
+            tags: Vec::new(),
+            created_at: Utc.timestamp_micros(1_684_315_800_123_456).unwrap(),
+            confirmed_at: None,
+        };
+
+        assert_eq!(
+            Some((subscriber.created_at, subscriber.id)),
+            decode_cursor(&encode_cursor(&subscriber))
+        );
+        assert_eq!(None, decode_cursor("not a cursor"));
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(r"100\%\_sure\\", escape_like_pattern(r"100%_sure\"));
+        assert_eq!("ursula", escape_like_pattern("ursula"));
+    }
+}
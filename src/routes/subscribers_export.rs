@@ -0,0 +1,299 @@
+use super::subscribers::Subscriber;
+use crate::{domain::SubscriptionStatus, extractors::AuthenticatedUser};
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures_util::{stream, TryStreamExt};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::io;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tracing::{error, Instrument, Span};
+
+/// How much of the export is buffered before it's sent.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How many chunks may wait for a slow client before the export pauses.
+const N_BUFFERED_CHUNKS: usize = 4;
+
+const CSV_HEADERS: [&str; 9] = [
+    "id",
+    "email",
+    "name",
+    "status",
+    "time_zone",
+    "delivery_window",
+    "tags",
+    "created_at",
+    "confirmed_at",
+];
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Parameters {
+    #[serde(default)]
+    format: Format,
+    /// Only export the subscribers with this status.
+    status: Option<String>,
+}
+
+/// Export every subscriber, or those with the given `status`, oldest first, as
+/// CSV or, with `?format=ndjson`, as one JSON object per line.
+///
+/// The subscribers are sent as they're read from the database, so exports of
+/// any size take little memory. If reading fails halfway through, the response
+/// is cut short, so that it can't be mistaken for a complete export.
+#[tracing::instrument(
+    name = "Exporting subscribers",
+    skip_all,
+    fields(user_id = %user.user_id)
+)]
+pub async fn export_subscribers(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    Query(parameters): Query<Parameters>,
+) -> Result<Response, Error> {
+    let status = parameters
+        .status
+        .as_deref()
+        .map(|status| {
+            SubscriptionStatus::parse(status).ok_or(Error::UnknownStatus)
+        })
+        .transpose()?;
+
+    let (sender, receiver) = mpsc::channel(N_BUFFERED_CHUNKS);
+    tokio::spawn(
+        send_subscribers(connection_pool, status, parameters.format, sender)
+            .instrument(Span::current()),
+    );
+    let body = StreamBody::new(stream::unfold(receiver, |mut receiver| async {
+        let chunk = receiver.recv().await?;
+        Some((chunk, receiver))
+    }));
+
+    let (content_type, extension) = match parameters.format {
+        Format::Csv => ("text/csv; charset=utf-8", "csv"),
+        Format::Ndjson => ("application/x-ndjson", "ndjson"),
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(r#"attachment; filename="subscribers.{extension}""#),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Read the subscribers with a cursor and send them to the response body in
+/// chunks, until they've all been sent or the client goes away.
+async fn send_subscribers(
+    connection_pool: PgPool,
+    status: Option<SubscriptionStatus>,
+    format: Format,
+    sender: mpsc::Sender<io::Result<Bytes>>,
+) {
+    if let Err(error) =
+        try_send_subscribers(&connection_pool, status, format, &sender).await
+    {
+        error!("Failed to export the subscribers: {error:#}");
+        // An error in the body aborts the response.
+        let _ = sender.send(Err(io::Error::other(error))).await;
+    }
+}
+
+async fn try_send_subscribers(
+    connection_pool: &PgPool,
+    status: Option<SubscriptionStatus>,
+    format: Format,
+    sender: &mpsc::Sender<io::Result<Bytes>>,
+) -> anyhow::Result<()> {
+    let mut encoder = Encoder::new(format)?;
+    let mut subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT
+            id,
+            email,
+            name,
+            status,
+            time_zone,
+            delivery_window,
+            tags,
+            created_at,
+            confirmed_at
+        FROM subscriptions
+        WHERE $1::text IS NULL OR status = $1
+        ORDER BY created_at, id
+        "#,
+        status.map(SubscriptionStatus::as_str)
+    )
+    .fetch(connection_pool);
+
+    while let Some(subscriber) = subscribers.try_next().await? {
+        encoder.encode(&subscriber)?;
+        if let Some(chunk) = encoder.take_chunk(CHUNK_SIZE) {
+            if sender.send(Ok(chunk)).await.is_err() {
+                // The client went away.
+                return Ok(());
+            }
+        }
+    }
+    if let Some(chunk) = encoder.take_chunk(0) {
+        let _ = sender.send(Ok(chunk)).await;
+    }
+
+    Ok(())
+}
+
+struct Encoder {
+    format: Format,
+    buffer: Vec<u8>,
+}
+
+impl Encoder {
+    fn new(format: Format) -> csv::Result<Self> {
+        let mut encoder = Self {
+            format,
+            buffer: Vec::new(),
+        };
+        if let Format::Csv = format {
+            encoder.write_csv_record(CSV_HEADERS)?;
+        }
+        Ok(encoder)
+    }
+
+    fn encode(&mut self, subscriber: &Subscriber) -> anyhow::Result<()> {
+        match self.format {
+            Format::Csv => {
+                let format_time = |time: &DateTime<Utc>| {
+                    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+                };
+                self.write_csv_record([
+                    subscriber.id.to_string().as_str(),
+                    &subscriber.email,
+                    &subscriber.name,
+                    &subscriber.status,
+                    subscriber.time_zone.as_deref().unwrap_or_default(),
+                    &subscriber.delivery_window,
+                    // Like imports expect them.
+                    &subscriber.tags.join(","),
+                    &format_time(&subscriber.created_at),
+                    &subscriber
+                        .confirmed_at
+                        .as_ref()
+                        .map(format_time)
+                        .unwrap_or_default(),
+                ])?;
+            }
+            Format::Ndjson => {
+                serde_json::to_writer(&mut self.buffer, subscriber)?;
+                self.buffer.push(b'\n');
+            }
+        }
+        Ok(())
+    }
+
+    fn write_csv_record<'a>(
+        &mut self,
+        record: impl IntoIterator<Item = &'a str>,
+    ) -> csv::Result<()> {
+        let mut writer = csv::Writer::from_writer(&mut self.buffer);
+        writer.write_record(record)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Take what's been encoded so far, if it's at least `min_len` bytes long
+    /// and not empty.
+    fn take_chunk(&mut self, min_len: usize) -> Option<Bytes> {
+        if self.buffer.is_empty() || self.buffer.len() < min_len {
+            return None;
+        }
+        Some(Bytes::from(std::mem::take(&mut self.buffer)))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("unknown subscription status")]
+    UnknownStatus,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::UnknownStatus => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Encoder, Format, Subscriber};
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    fn subscriber() -> Subscriber {
+        Subscriber {
+            id: Uuid::nil(),
+            email: "ursula@example.com".to_owned(),
+            name: "Le Guin, Ursula".to_owned(),
+            status: "confirmed".to_owned(),
+            time_zone: None,
+            delivery_window: "immediately".to_owned(),
+            tags: vec!["sci-fi".to_owned(), "2023".to_owned()],
+            created_at: Utc.with_ymd_and_hms(2023, 5, 17, 9, 30, 0).unwrap(),
+            confirmed_at: None,
+        }
+    }
+
+    #[test]
+    fn subscribers_are_encoded_as_csv() {
+        let mut encoder = Encoder::new(Format::Csv).unwrap();
+
+        encoder.encode(&subscriber()).unwrap();
+        let chunk = encoder.take_chunk(0).unwrap();
+
+        assert_eq!(
+            "id,email,name,status,time_zone,delivery_window,tags,created_at,\
+            confirmed_at\n\
+            00000000-0000-0000-0000-000000000000,ursula@example.com,\
+            \"Le Guin, Ursula\",confirmed,,immediately,\"sci-fi,2023\",\
+            2023-05-17T09:30:00.000000Z,\n",
+            chunk
+        );
+        assert_eq!(None, encoder.take_chunk(0));
+    }
+
+    #[test]
+    fn chunks_are_held_back_until_they_are_big_enough() {
+        let mut encoder = Encoder::new(Format::Ndjson).unwrap();
+
+        encoder.encode(&subscriber()).unwrap();
+
+        assert_eq!(None, encoder.take_chunk(1024));
+        let line = encoder.take_chunk(0).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_slice(line.strip_suffix(b"\n").unwrap()).unwrap();
+        assert_eq!("ursula@example.com", json["email"]);
+        assert_eq!(serde_json::json!(["sci-fi", "2023"]), json["tags"]);
+    }
+}
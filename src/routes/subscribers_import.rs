@@ -0,0 +1,327 @@
+use crate::{
+    authentication::spawn_blocking_with_tracing,
+    domain::{SubscriberEmail, SubscriberName, SubscriptionStatus, Tags},
+    extractors::AuthenticatedUser,
+};
+use anyhow::Context;
+use axum::{
+    extract::{BodyStream, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures_util::TryStreamExt;
+use inspect_error::InspectError;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io,
+};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio_util::io::{StreamReader, SyncIoBridge};
+use tracing::error;
+use uuid::Uuid;
+
+/// How many subscribers are inserted at once.
+const BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+struct Row {
+    name: String,
+    email: String,
+    #[serde(default)]
+    status: Option<String>,
+    /// Separated by commas, each one optionally in double quotes, the way
+    /// Mailchimp exports them.
+    #[serde(default)]
+    tags: Option<String>,
+}
+
+#[derive(Debug)]
+struct ImportedSubscriber {
+    name: SubscriberName,
+    email: SubscriberEmail,
+    status: SubscriptionStatus,
+    tags: Tags,
+}
+
+/// A row of the file and the line it's on.
+type ParsedRow = (u64, Result<ImportedSubscriber, String>);
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    imported: u64,
+    /// Addresses that were subscribed already.
+    skipped: u64,
+    errors: Vec<RowError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowError {
+    line: u64,
+    error: String,
+}
+
+/// Add every subscriber in a CSV file with `name` and `email` columns, and
+/// optional `status` and `tags` columns. Addresses that are subscribed already
+/// are left as they are, and nobody is sent a confirmation email.
+///
+/// The file is read as it's uploaded, and the subscribers are inserted in
+/// batches of [`BATCH_SIZE`], so that large files don't have to fit in memory.
+/// Rows that can't be imported, including malformed ones and those that repeat
+/// an address of the same batch, don't stop the others from being imported:
+/// they're reported by line instead. An address repeated in a later batch is
+/// subscribed already by then, so it's counted as skipped.
+#[tracing::instrument(
+    name = "Importing subscribers",
+    skip_all,
+    fields(user_id = %user.user_id)
+)]
+pub async fn import_subscribers(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    body: BodyStream,
+) -> Result<Json<ImportReport>, Error> {
+    let body =
+        SyncIoBridge::new(StreamReader::new(body.map_err(io::Error::other)));
+    let (sender, mut rows) = mpsc::channel(BATCH_SIZE);
+    let reader = spawn_blocking_with_tracing(move || read_rows(body, &sender));
+
+    let mut report = ImportReport::default();
+    // The lines of the addresses in the batch, keyed by lowercase address like
+    // the `case_insensitive` collation of `subscriptions.email` compares them.
+    let mut lines_by_email = HashMap::with_capacity(BATCH_SIZE);
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    while let Some((line, row)) = rows.recv().await {
+        let subscriber = match row {
+            Ok(subscriber) => subscriber,
+            Err(error) => {
+                report.errors.push(RowError { line, error });
+                continue;
+            }
+        };
+        match lines_by_email.entry(subscriber.email.as_ref().to_lowercase()) {
+            Entry::Occupied(first_line) => report.errors.push(RowError {
+                line,
+                error: format!(
+                    "the address is already on line {}",
+                    first_line.get()
+                ),
+            }),
+            Entry::Vacant(entry) => {
+                entry.insert(line);
+                batch.push(subscriber);
+            }
+        }
+
+        if batch.len() == BATCH_SIZE {
+            insert_batch(&connection_pool, &batch, &mut report).await?;
+            batch.clear();
+            lines_by_email.clear();
+        }
+    }
+    // What was read before the upload failed is kept, so that running the
+    // import again picks up where it stopped.
+    let read = reader.await.context("failed to spawn a blocking task")?;
+    insert_batch(&connection_pool, &batch, &mut report).await?;
+    read?;
+
+    Ok(Json(report))
+}
+
+/// Read the rows of the file, sending each one on as soon as it's read. This
+/// blocks on the upload, so it has to run on its own thread.
+fn read_rows(
+    reader: impl io::Read,
+    rows: &mpsc::Sender<ParsedRow>,
+) -> Result<(), Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let headers: csv::StringRecord =
+        reader.headers()?.iter().map(str::to_lowercase).collect();
+    for column in ["name", "email"] {
+        if !headers.iter().any(|header| header == column) {
+            return Err(Error::MissingColumn(column));
+        }
+    }
+
+    for record in reader.records() {
+        let (line, row) = match record {
+            Ok(record) => (
+                record.position().map_or(0, csv::Position::line),
+                record
+                    .deserialize::<Row>(Some(&headers))
+                    .map_err(|error| error.to_string())
+                    .and_then(parse_row),
+            ),
+            // The rest of the upload can't be read.
+            Err(error) if error.is_io_error() => return Err(error.into()),
+            Err(error) => (
+                error.position().map_or(0, csv::Position::line),
+                Err(format!("malformed row: {error}")),
+            ),
+        };
+        // The request handler stops receiving if it fails.
+        if rows.blocking_send((line, row)).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_row(row: Row) -> Result<ImportedSubscriber, String> {
+    let name = SubscriberName::parse(row.name)
+        .map_err(|error| format!("invalid name: {error}"))?;
+    let email = SubscriberEmail::parse(row.email)
+        .map_err(|error| format!("invalid email address: {error}"))?;
+    // Without a status, subscribers are confirmed.
+    let status = match row.status.as_deref().filter(|status| !status.is_empty()) {
+        None => SubscriptionStatus::Confirmed,
+        Some(status) => SubscriptionStatus::parse(status)
+            .ok_or_else(|| format!("unknown status `{status}`"))?,
+    };
+    let tags = Tags::parse(
+        row.tags
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(|tag| tag.trim().trim_matches('"')),
+    )
+    .map_err(|error| format!("invalid tags: {error}"))?;
+
+    Ok(ImportedSubscriber {
+        name,
+        email,
+        status,
+        tags,
+    })
+}
+
+/// Insert the subscribers whose address isn't subscribed already. The batch is
+/// inserted in one statement, so it's imported entirely or not at all.
+#[tracing::instrument(
+    name = "Importing a batch of subscribers",
+    skip_all,
+    fields(n_subscribers = batch.len())
+)]
+async fn insert_batch(
+    connection_pool: &PgPool,
+    batch: &[ImportedSubscriber],
+    report: &mut ImportReport,
+) -> sqlx::Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+
+    let mut query = QueryBuilder::<Postgres>::new(
+        "INSERT INTO subscriptions (id, email, name, status, confirmed_at, tags) ",
+    );
+    query.push_values(batch, |mut row, subscriber| {
+        row.push_bind(Uuid::new_v4())
+            .push_bind(subscriber.email.as_ref())
+            .push_bind(subscriber.name.as_ref())
+            .push_bind(subscriber.status.as_str())
+            .push(if subscriber.status == SubscriptionStatus::Confirmed {
+                "now()"
+            } else {
+                "NULL"
+            })
+            .push_bind(subscriber.tags.as_ref());
+    });
+    query.push(" ON CONFLICT (email) DO NOTHING");
+    let result = query
+        .build()
+        .execute(connection_pool)
+        .await
+        .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    report.imported += result.rows_affected();
+    report.skipped += batch.len() as u64 - result.rows_affected();
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error("the file isn't valid CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("the file doesn't have a `{0}` column")]
+    MissingColumn(&'static str),
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Csv(_) | Self::MissingColumn(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Self::Database(_) | Self::Unexpected(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_row, Row};
+    use crate::domain::SubscriptionStatus;
+
+    #[test]
+    fn mailchimp_tags_are_unquoted() {
+        let tags = |tags: &str| {
+            parse_row(Row {
+                name: "Ursula".to_owned(),
+                email: "ursula@example.com".to_owned(),
+                status: None,
+                tags: Some(tags.to_owned()),
+            })
+            .map(|subscriber| subscriber.tags.as_ref().to_vec())
+        };
+
+        assert_eq!(
+            vec!["rust", "events"],
+            tags(r#""rust","events","rust""#).unwrap()
+        );
+        assert_eq!(vec!["rust", "events"], tags("rust, events,").unwrap());
+        assert!(tags("").unwrap().is_empty());
+        assert!(tags(&"a".repeat(101)).is_err());
+    }
+
+    #[test]
+    fn rows_are_validated() {
+        let row = |name: &str, email: &str, status: Option<&str>| Row {
+            name: name.to_owned(),
+            email: email.to_owned(),
+            status: status.map(str::to_owned),
+            tags: None,
+        };
+
+        assert_eq!(
+            SubscriptionStatus::Confirmed,
+            parse_row(row("Ursula", "ursula@example.com", None))
+                .unwrap()
+                .status
+        );
+        assert_eq!(
+            SubscriptionStatus::Unsubscribed,
+            parse_row(row("Ursula", "ursula@example.com", Some("unsubscribed")))
+                .unwrap()
+                .status
+        );
+        assert!(
+            parse_row(row("Ursula", "ursula@example.com", Some("cleaned")))
+                .is_err()
+        );
+        assert!(parse_row(row("Ursula", "not-an-email", None)).is_err());
+        assert!(parse_row(row("<Ursula>", "ursula@example.com", None)).is_err());
+    }
+}
@@ -1,10 +1,24 @@
 #![allow(clippy::module_name_repetitions)]
 
-use crate::{domain::NewSubscriber, extractors::ValidatedForm};
-use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use super::escape_html;
+use crate::{
+    domain::{NewSubscriber, Slug, SubscriberEmail, TimeZone},
+    email_client::{self, EmailSender},
+    extractors::ValidatedForm,
+    session::Session,
+    state::ApplicationBaseUrl,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
 use inspect_error::InspectError;
-use sqlx::PgPool;
-use tracing::error;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::{error, info};
 use uuid::Uuid;
 
 #[tracing::instrument(
@@ -17,13 +31,230 @@ use uuid::Uuid;
 )]
 pub async fn subscribe(
     State(connection_pool): State<PgPool>,
+    State(email_client): State<Arc<dyn EmailSender>>,
+    State(base_url): State<ApplicationBaseUrl>,
+    session: Session,
     ValidatedForm(subscription_data): ValidatedForm<NewSubscriber>,
-) -> impl IntoResponse {
-    if (insert_subscriber(&connection_pool, &subscription_data).await).is_ok() {
-        StatusCode::OK
-    } else {
-        StatusCode::INTERNAL_SERVER_ERROR
+) -> Result<StatusCode, Error> {
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .inspect_error(|error| error!("Failed to begin a transaction: {error}"))?;
+
+    if !check_suppression(&mut transaction, &session, &subscription_data).await? {
+        return Ok(StatusCode::OK);
     }
+
+    // Subscribing twice with the same email shouldn't fail. If the subscriber
+    // hasn't confirmed yet, they probably lost the first email, so send them a
+    // fresh one. If they have, there's nothing left to do.
+    let subscriber_id =
+        match find_subscriber(&mut transaction, &subscription_data.email).await? {
+            Some(subscriber) if subscriber.status == "confirmed" => {
+                return Ok(StatusCode::OK);
+            }
+            Some(subscriber) => subscriber.id,
+            None => {
+                insert_subscriber(&mut transaction, &subscription_data).await?
+            }
+        };
+
+    let subscription_token = generate_subscription_token();
+    store_token(&mut transaction, subscriber_id, &subscription_token).await?;
+
+    transaction.commit().await.inspect_error(|error| {
+        error!("Failed to commit the transaction: {error}")
+    })?;
+
+    send_confirmation_email(
+        email_client.as_ref(),
+        &subscription_data.email,
+        "our newsletter",
+        &base_url.0,
+        &subscription_token,
+    )
+    .await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Subscribe to the list with the given slug. This works like
+/// [`subscribe`], except that the confirmation is for that list only: being
+/// subscribed to other lists, or to issues published without a list, doesn't
+/// count.
+#[tracing::instrument(
+    name = "Adding a new subscriber to a list",
+    skip_all,
+    fields(
+        list_slug = %slug,
+        subscriber_email = %subscription_data.email.as_ref(),
+        subscriber_name = %subscription_data.name.as_ref(),
+    )
+)]
+pub async fn subscribe_to_list(
+    State(connection_pool): State<PgPool>,
+    State(email_client): State<Arc<dyn EmailSender>>,
+    State(base_url): State<ApplicationBaseUrl>,
+    Path(slug): Path<String>,
+    session: Session,
+    ValidatedForm(subscription_data): ValidatedForm<NewSubscriber>,
+) -> Result<StatusCode, Error> {
+    let slug = Slug::parse(slug).map_err(|_| Error::UnknownList)?;
+
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .inspect_error(|error| error!("Failed to begin a transaction: {error}"))?;
+
+    let list_name = get_list_name(&mut transaction, &slug)
+        .await?
+        .ok_or(Error::UnknownList)?;
+    if !check_suppression(&mut transaction, &session, &subscription_data).await? {
+        return Ok(StatusCode::OK);
+    }
+    let subscriber_id =
+        match find_subscriber(&mut transaction, &subscription_data.email).await? {
+            Some(subscriber) => subscriber.id,
+            None => {
+                insert_subscriber(&mut transaction, &subscription_data).await?
+            }
+        };
+    // Same as in `subscribe`: there's nothing to do for confirmed subscribers,
+    // and everyone else gets a fresh confirmation email.
+    if !upsert_list_subscription(&mut transaction, &slug, subscriber_id).await? {
+        return Ok(StatusCode::OK);
+    }
+
+    let subscription_token = generate_subscription_token();
+    store_list_token(&mut transaction, subscriber_id, &slug, &subscription_token)
+        .await?;
+
+    transaction.commit().await.inspect_error(|error| {
+        error!("Failed to commit the transaction: {error}")
+    })?;
+
+    send_confirmation_email(
+        email_client.as_ref(),
+        &subscription_data.email,
+        &list_name,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Nothing is sent to suppressed addresses, so they can't be subscribed, unless
+/// a logged-in admin explicitly overrides the suppression. That lifts it.
+/// Returns whether the address can be subscribed.
+///
+/// Only logged-in admins are told that an address is suppressed. Anyone else
+/// gets the same response as for a new subscription, without the confirmation
+/// email, so that the form doesn't reveal who is on the suppression list.
+#[tracing::instrument(name = "Checking the suppression list", skip_all)]
+async fn check_suppression(
+    transaction: &mut Transaction<'_, Postgres>,
+    session: &Session,
+    subscription_data: &NewSubscriber,
+) -> Result<bool, Error> {
+    let logged_in = session.user_id().is_some();
+    if subscription_data.override_suppression && logged_in {
+        let result = sqlx::query!(
+            "DELETE FROM suppressions WHERE email = $1",
+            subscription_data.email.as_ref()
+        )
+        .execute(transaction)
+        .await
+        .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+        if result.rows_affected() > 0 {
+            info!("Lifted the suppression of the address");
+        }
+        return Ok(true);
+    }
+
+    let suppressed = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM suppressions WHERE email = $1
+        ) AS "suppressed!"
+        "#,
+        subscription_data.email.as_ref()
+    )
+    .fetch_one(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?
+    .suppressed;
+    if suppressed && logged_in {
+        return Err(Error::Suppressed);
+    }
+    if suppressed {
+        info!("The address is suppressed, not subscribing it");
+    }
+
+    Ok(!suppressed)
+}
+
+struct ExistingSubscriber {
+    id: Uuid,
+    status: String,
+}
+
+#[tracing::instrument(name = "Looking up an existing subscriber", skip_all)]
+async fn find_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> sqlx::Result<Option<ExistingSubscriber>> {
+    let subscriber = sqlx::query_as!(
+        ExistingSubscriber,
+        "SELECT id, status FROM subscriptions WHERE email = $1",
+        email.as_ref()
+    )
+    .fetch_optional(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(subscriber)
+}
+
+#[tracing::instrument(name = "Getting the name of the list", skip_all)]
+async fn get_list_name(
+    transaction: &mut Transaction<'_, Postgres>,
+    slug: &Slug,
+) -> sqlx::Result<Option<String>> {
+    let record =
+        sqlx::query!("SELECT name FROM lists WHERE slug = $1", slug.as_ref())
+            .fetch_optional(transaction)
+            .await
+            .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(record.map(|record| record.name))
+}
+
+/// Add the subscriber to the list, pending confirmation, unless they're
+/// already confirmed. Returns whether they need to confirm.
+#[tracing::instrument(name = "Saving the list subscription", skip_all)]
+async fn upsert_list_subscription(
+    transaction: &mut Transaction<'_, Postgres>,
+    slug: &Slug,
+    subscriber_id: Uuid,
+) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_slug, subscriber_id, status)
+        VALUES ($1, $2, 'pending_confirmation')
+        ON CONFLICT (list_slug, subscriber_id) DO UPDATE
+        SET status = 'pending_confirmation'
+        WHERE list_subscriptions.status <> 'confirmed'
+        "#,
+        slug.as_ref(),
+        subscriber_id
+    )
+    .execute(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(result.rows_affected() > 0)
 }
 
 #[tracing::instrument(
@@ -31,21 +262,143 @@ pub async fn subscribe(
     skip_all
 )]
 async fn insert_subscriber(
-    connection_pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
     subscription_data: &NewSubscriber,
-) -> sqlx::Result<()> {
+) -> sqlx::Result<Uuid> {
+    let subscriber_id = Uuid::new_v4();
+
     sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, status)
-        VALUES ($1, $2, $3, 'confirmed')
+        INSERT INTO subscriptions (id, email, name, status, time_zone)
+        VALUES ($1, $2, $3, 'pending_confirmation', $4)
         "#,
-        Uuid::new_v4(),
+        subscriber_id,
         subscription_data.email.as_ref(),
-        subscription_data.name.as_ref()
+        subscription_data.name.as_ref(),
+        subscription_data.time_zone().map(TimeZone::name)
     )
-    .execute(connection_pool)
+    .execute(transaction)
     .await
     .inspect_error(|error| error!("Faild to execute query: {error}"))?;
 
+    Ok(subscriber_id)
+}
+
+#[tracing::instrument(
+    name = "Storing the subscription token in the database",
+    skip_all
+)]
+async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
+        VALUES ($1, $2)
+        "#,
+        subscription_token,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
     Ok(())
 }
+
+#[tracing::instrument(
+    name = "Storing the list subscription token in the database",
+    skip_all
+)]
+async fn store_list_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    slug: &Slug,
+    subscription_token: &str,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (
+            subscription_token, subscriber_id, list_slug
+        )
+        VALUES ($1, $2, $3)
+        "#,
+        subscription_token,
+        subscriber_id,
+        slug.as_ref()
+    )
+    .execute(transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+/// `newsletter` is what the subscriber is welcomed to.
+#[tracing::instrument(name = "Sending a confirmation email", skip_all)]
+async fn send_confirmation_email(
+    email_client: &dyn EmailSender,
+    recipient: &SubscriberEmail,
+    newsletter: &str,
+    base_url: &str,
+    subscription_token: &str,
+) -> Result<(), email_client::Error> {
+    let confirmation_link = format!(
+        "{base_url}/subscriptions/confirm?subscription_token={subscription_token}"
+    );
+    let html_content = format!(
+        "Welcome to {}!<br />\
+        Click <a href=\"{confirmation_link}\">here</a> to confirm your subscription.",
+        escape_html(newsletter)
+    );
+    let text_content = format!(
+        "Welcome to {newsletter}!\n\
+        Visit {confirmation_link} to confirm your subscription."
+    );
+
+    email_client
+        .send_email(recipient, "Welcome!", &html_content, &text_content, None)
+        .await
+        .inspect_error(|error| {
+            error!("Failed to send the confirmation email: {error}");
+        })
+}
+
+/// Generate a random, 25-character-long, case-sensitive subscription token.
+fn generate_subscription_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error(transparent)]
+    SendEmail(#[from] email_client::Error),
+    #[error("there's no list with that slug")]
+    UnknownList,
+    #[error("this address is on the suppression list")]
+    Suppressed,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::UnknownList => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            Self::Suppressed => {
+                (StatusCode::CONFLICT, self.to_string()).into_response()
+            }
+            Self::Database(_) | Self::SendEmail(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+#![allow(clippy::module_name_repetitions)]
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use inspect_error::InspectError;
+use serde::Deserialize;
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::error;
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct Parameters {
+    subscription_token: String,
+}
+
+/// Confirm the subscription the token was sent for: to a list if it was sent
+/// by [`super::subscription::subscribe_to_list`], to issues published without
+/// a list otherwise.
+#[tracing::instrument(name = "Confirming a pending subscriber", skip_all)]
+pub async fn confirm(
+    State(connection_pool): State<PgPool>,
+    Query(parameters): Query<Parameters>,
+) -> Result<StatusCode, Error> {
+    let token = get_token(&connection_pool, &parameters.subscription_token)
+        .await?
+        .ok_or(Error::UnknownToken)?;
+
+    match token.list_slug {
+        Some(list_slug) => {
+            confirm_list_subscription(
+                &connection_pool,
+                token.subscriber_id,
+                &list_slug,
+            )
+            .await?;
+        }
+        None => confirm_subscriber(&connection_pool, token.subscriber_id).await?,
+    }
+
+    Ok(StatusCode::OK)
+}
+
+struct Token {
+    subscriber_id: Uuid,
+    list_slug: Option<String>,
+}
+
+#[tracing::instrument(name = "Getting the subscriber ID from the token", skip_all)]
+async fn get_token(
+    connection_pool: &PgPool,
+    subscription_token: &str,
+) -> sqlx::Result<Option<Token>> {
+    sqlx::query_as!(
+        Token,
+        r#"
+        SELECT subscriber_id, list_slug FROM subscription_tokens
+        WHERE subscription_token = $1
+        "#,
+        subscription_token
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))
+}
+
+#[tracing::instrument(name = "Marking the subscriber as confirmed", skip_all)]
+async fn confirm_subscriber(
+    connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'confirmed', confirmed_at = COALESCE(confirmed_at, now())
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .execute(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Marking the list subscription as confirmed",
+    skip_all
+)]
+async fn confirm_list_subscription(
+    connection_pool: &PgPool,
+    subscriber_id: Uuid,
+    list_slug: &str,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        WITH confirmed AS (
+            UPDATE list_subscriptions SET status = 'confirmed'
+            WHERE subscriber_id = $1 AND list_slug = $2
+            RETURNING subscriber_id
+        )
+        UPDATE subscriptions
+        SET confirmed_at = COALESCE(confirmed_at, now())
+        WHERE id IN (SELECT subscriber_id FROM confirmed)
+        "#,
+        subscriber_id,
+        list_slug
+    )
+    .execute(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("the subscription token is not associated with any subscriber")]
+    UnknownToken,
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::UnknownToken => (StatusCode::UNAUTHORIZED, self.to_string()),
+            Self::Database(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::INTERNAL_SERVER_ERROR.to_string(),
+            ),
+        }
+        .into_response()
+    }
+}
@@ -0,0 +1,202 @@
+#![allow(clippy::module_name_repetitions)]
+
+use super::{
+    escape_html,
+    tracking::{record_event, user_agent, Event},
+};
+use crate::SubscriberLinks;
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use inspect_error::InspectError;
+use serde::Deserialize;
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::{error, Span};
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct Parameters {
+    token: String,
+    /// Only unsubscribe from this list.
+    list: Option<String>,
+    /// The issue the link was in. A malformed ID doesn't stop anyone from
+    /// unsubscribing, it's just ignored.
+    issue: Option<String>,
+}
+
+impl Parameters {
+    fn newsletter_issue_id(&self) -> Option<Uuid> {
+        self.issue.as_deref().and_then(|issue| issue.parse().ok())
+    }
+}
+
+/// Ask the subscriber to confirm that they want to leave. Unsubscribing right
+/// away on `GET` would let link scanners and prefetching unsubscribe people by
+/// accident.
+#[tracing::instrument(name = "Showing the unsubscribe page", skip_all)]
+pub async fn unsubscribe_form(
+    State(subscriber_links): State<SubscriberLinks>,
+    Query(parameters): Query<Parameters>,
+) -> Result<Html<String>, Error> {
+    subscriber_links
+        .verify(&parameters.token)
+        .ok_or(Error::InvalidToken)?;
+    let mut action =
+        format!("/subscriptions/unsubscribe?token={}", parameters.token);
+    if let Some(newsletter_issue_id) = parameters.newsletter_issue_id() {
+        action.push_str("&issue=");
+        action.push_str(&newsletter_issue_id.to_string());
+    }
+    if let Some(list) = &parameters.list {
+        action.push_str("&list=");
+        action.push_str(list);
+    }
+    let action = escape_html(&action);
+
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving the newsletter?</p>
+    <form action="{action}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#
+    )))
+}
+
+/// Unsubscribe the subscriber the token was made for, from the given list if
+/// there's one or from issues published without a list. This is also where mail
+/// clients send one-click unsubscribe requests (RFC 8058), whose
+/// `List-Unsubscribe=One-Click` body is ignored.
+///
+/// Unsubscribing twice, or after the subscriber has been deleted, isn't an
+/// error. Unsubscribing through the link in an issue is recorded for its
+/// statistics.
+#[tracing::instrument(
+    name = "Unsubscribing a subscriber",
+    skip_all,
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn unsubscribe(
+    State(connection_pool): State<PgPool>,
+    State(subscriber_links): State<SubscriberLinks>,
+    Query(parameters): Query<Parameters>,
+    headers: HeaderMap,
+) -> Result<Html<&'static str>, Error> {
+    let subscriber_id = subscriber_links
+        .verify(&parameters.token)
+        .ok_or(Error::InvalidToken)?;
+    Span::current()
+        .record("subscriber_id", tracing::field::display(subscriber_id));
+
+    match &parameters.list {
+        Some(list) => {
+            mark_list_subscription_as_unsubscribed(
+                &connection_pool,
+                subscriber_id,
+                list,
+            )
+            .await?;
+        }
+        None => {
+            mark_subscriber_as_unsubscribed(&connection_pool, subscriber_id)
+                .await?;
+        }
+    }
+
+    if let Some(newsletter_issue_id) = parameters.newsletter_issue_id() {
+        let event = Event {
+            newsletter_issue_id,
+            subscriber_id,
+            kind: "unsubscribe",
+            link_index: None,
+            user_agent: user_agent(&headers),
+            bot_reason: None,
+        };
+        // The subscriber is unsubscribed either way.
+        let _ = record_event(&connection_pool, &event).await;
+    }
+
+    Ok(Html(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You've been unsubscribed and won't receive the newsletter anymore.</p>
+</body>
+</html>"#,
+    ))
+}
+
+#[tracing::instrument(name = "Marking the subscriber as unsubscribed", skip_all)]
+async fn mark_subscriber_as_unsubscribed(
+    connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1",
+        subscriber_id
+    )
+    .execute(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Marking the list subscription as unsubscribed",
+    skip_all
+)]
+async fn mark_list_subscription_as_unsubscribed(
+    connection_pool: &PgPool,
+    subscriber_id: Uuid,
+    list_slug: &str,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions SET status = 'unsubscribed'
+        WHERE subscriber_id = $1 AND list_slug = $2
+        "#,
+        subscriber_id,
+        list_slug
+    )
+    .execute(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("the subscriber token is invalid")]
+    InvalidToken,
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidToken => (StatusCode::UNAUTHORIZED, self.to_string()),
+            Self::Database(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::INTERNAL_SERVER_ERROR.to_string(),
+            ),
+        }
+        .into_response()
+    }
+}
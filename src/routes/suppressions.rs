@@ -0,0 +1,240 @@
+use crate::{
+    domain::SubscriberEmail,
+    extractors::{AuthenticatedUser, ValidatedJson},
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use inspect_error::InspectError;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::error;
+use validator::{Validate, ValidationErrors};
+
+/// The reason of imported suppressions that don't have one.
+const DEFAULT_REASON: &str = "manual";
+
+#[derive(Debug, Serialize)]
+pub struct Suppression {
+    email: String,
+    reason: String,
+    /// `admin`, `import` or the email provider that reported the address.
+    source: String,
+    created_at: DateTime<Utc>,
+}
+
+/// Get every suppressed address, the latest first.
+#[tracing::instrument(
+    name = "Getting the suppressions",
+    skip_all,
+    fields(user_id = %user.user_id)
+)]
+pub async fn get_suppressions(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+) -> Result<Json<Vec<Suppression>>, Error> {
+    let suppressions = sqlx::query_as!(
+        Suppression,
+        r#"
+        SELECT email, reason, source, created_at
+        FROM suppressions
+        ORDER BY created_at DESC, email
+        "#
+    )
+    .fetch_all(&connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(Json(suppressions))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct BodyData {
+    #[validate(length(min = 1, max = 1024))]
+    reason: String,
+}
+
+/// Suppress the address, so that nothing is sent to it anymore, not even the
+/// confirmation emails of new subscriptions. Suppressing an address again
+/// replaces its reason.
+#[tracing::instrument(
+    name = "Suppressing an address",
+    skip_all,
+    fields(subscriber_email = %email, user_id = %user.user_id)
+)]
+pub async fn save_suppression(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    Path(email): Path<String>,
+    ValidatedJson(body): ValidatedJson<BodyData>,
+) -> Result<StatusCode, Error> {
+    let email = SubscriberEmail::parse(email).map_err(Error::InvalidEmail)?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email, reason, source)
+        VALUES ($1, $2, 'admin')
+        ON CONFLICT (email) DO UPDATE
+        SET reason = EXCLUDED.reason, source = EXCLUDED.source
+        "#,
+        email.as_ref(),
+        body.reason
+    )
+    .execute(&connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Lift the suppression of the address. Its subscriptions, if any, are left as
+/// they are.
+#[tracing::instrument(
+    name = "Lifting a suppression",
+    skip_all,
+    fields(subscriber_email = %email, user_id = %user.user_id)
+)]
+pub async fn delete_suppression(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    Path(email): Path<String>,
+) -> Result<StatusCode, Error> {
+    let result = sqlx::query!("DELETE FROM suppressions WHERE email = $1", email)
+        .execute(&connection_pool)
+        .await
+        .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    if result.rows_affected() == 0 {
+        return Err(Error::UnknownSuppression);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportedRow {
+    email: String,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    imported: u64,
+    /// Addresses that were suppressed already, or that the file repeats.
+    skipped: u64,
+    errors: Vec<RowError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowError {
+    line: u64,
+    error: String,
+}
+
+/// Suppress every address in a CSV file with an `email` column and an optional
+/// `reason` column. Addresses that are suppressed already keep their reason.
+///
+/// Rows that can't be imported don't stop the others from being imported:
+/// they're reported by line instead.
+#[tracing::instrument(
+    name = "Importing suppressions",
+    skip_all,
+    fields(user_id = %user.user_id)
+)]
+pub async fn import_suppressions(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    body: String,
+) -> Result<Json<ImportReport>, Error> {
+    let mut report = ImportReport::default();
+    let mut emails = Vec::new();
+    let mut reasons = Vec::new();
+
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(body.as_bytes());
+    let headers = reader.headers()?.clone();
+    if !headers.iter().any(|header| header == "email") {
+        return Err(Error::MissingEmailColumn);
+    }
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, csv::Position::line);
+        let row = match record.deserialize::<ImportedRow>(Some(&headers)) {
+            Ok(row) => row,
+            Err(error) => {
+                report.errors.push(RowError {
+                    line,
+                    error: error.to_string(),
+                });
+                continue;
+            }
+        };
+        match SubscriberEmail::parse(row.email) {
+            Ok(email) => {
+                emails.push(email.as_ref().to_owned());
+                reasons.push(
+                    row.reason
+                        .filter(|reason| !reason.is_empty())
+                        .unwrap_or_else(|| DEFAULT_REASON.to_owned()),
+                );
+            }
+            Err(error) => report.errors.push(RowError {
+                line,
+                error: format!("invalid email address: {error}"),
+            }),
+        }
+    }
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email, reason, source)
+        SELECT email, reason, 'import'
+        FROM UNNEST($1::text[], $2::text[]) AS imported(email, reason)
+        ON CONFLICT (email) DO NOTHING
+        "#,
+        &emails,
+        &reasons
+    )
+    .execute(&connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+    report.imported = result.rows_affected();
+    report.skipped = emails.len() as u64 - report.imported;
+
+    Ok(Json(report))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error("the file isn't valid CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("invalid email address: {0}")]
+    InvalidEmail(ValidationErrors),
+    #[error("the file doesn't have an `email` column")]
+    MissingEmailColumn,
+    #[error("that address isn't suppressed")]
+    UnknownSuppression,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Csv(_) | Self::InvalidEmail(_) | Self::MissingEmailColumn => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Self::UnknownSuppression => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{
+    domain::TemplateName,
+    extractors::{AuthenticatedUser, ValidatedJson},
+    templates::{self, TemplateEngine, TemplateSource},
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use inspect_error::InspectError;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::error;
+use validator::{Validate, ValidationErrors};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct BodyData {
+    #[validate(length(min = 1))]
+    html: String,
+    /// Derived from the HTML if missing.
+    #[validate(length(min = 1))]
+    text: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Template {
+    name: String,
+    html: String,
+    text: Option<String>,
+}
+
+/// Create or replace the template with the given name. Templates are rendered
+/// before they're saved, so ones that wouldn't render are rejected with `400`.
+///
+/// Only authenticated users may save templates.
+#[tracing::instrument(
+    name = "Saving an email template",
+    skip_all,
+    fields(template_name = %name, user_id = %user.user_id)
+)]
+pub async fn save_template(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    State(template_engine): State<Arc<TemplateEngine>>,
+    Path(name): Path<String>,
+    ValidatedJson(body): ValidatedJson<BodyData>,
+) -> Result<StatusCode, Error> {
+    let name = TemplateName::parse(name).map_err(Error::InvalidName)?;
+    let source = TemplateSource {
+        html: body.html,
+        text: body.text,
+    };
+    template_engine
+        .validate(&source)
+        .inspect_error(|error| error!("The template is invalid: {error}"))?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO email_templates (name, html_source, text_source)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (name) DO UPDATE
+        SET
+            html_source = EXCLUDED.html_source,
+            text_source = EXCLUDED.text_source
+        "#,
+        name.as_ref(),
+        source.html,
+        source.text
+    )
+    .execute(&connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Return the sources of the template with the given name.
+#[tracing::instrument(
+    name = "Fetching an email template",
+    skip_all,
+    fields(template_name = %name, user_id = %user.user_id)
+)]
+pub async fn get_template(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    Path(name): Path<String>,
+) -> Result<Json<Template>, Error> {
+    let name = TemplateName::parse(name).map_err(Error::InvalidName)?;
+    let source = load_template(&connection_pool, &name)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    Ok(Json(Template {
+        name: name.as_ref().to_owned(),
+        html: source.html,
+        text: source.text,
+    }))
+}
+
+/// Look up a stored template by name.
+#[tracing::instrument(name = "Loading an email template", skip_all)]
+pub async fn load_template(
+    connection_pool: &PgPool,
+    name: &TemplateName,
+) -> sqlx::Result<Option<TemplateSource>> {
+    sqlx::query_as!(
+        TemplateSource,
+        r#"
+        SELECT html_source AS html, text_source AS text
+        FROM email_templates
+        WHERE name = $1
+        "#,
+        name.as_ref()
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error("invalid template name: {0}")]
+    InvalidName(ValidationErrors),
+    #[error(transparent)]
+    InvalidTemplate(#[from] templates::Error),
+    #[error("there's no template with that name")]
+    NotFound,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidName(_) | Self::InvalidTemplate(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Self::NotFound => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{
+    domain::Slug,
+    extractors::{AuthenticatedUser, ValidatedJson},
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use inspect_error::InspectError;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::error;
+use validator::{Validate, ValidationErrors};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct BodyData {
+    /// What subscribers see on their preferences page.
+    #[validate(length(min = 1, max = 256))]
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Topic {
+    pub slug: String,
+    pub name: String,
+}
+
+/// Create or rename the topic with the given slug. Issues can then be published
+/// under it, and subscribers can opt out of it on their preferences page.
+///
+/// Only authenticated users may save topics.
+#[tracing::instrument(
+    name = "Saving a topic",
+    skip_all,
+    fields(topic_slug = %slug, user_id = %user.user_id)
+)]
+pub async fn save_topic(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+    Path(slug): Path<String>,
+    ValidatedJson(body): ValidatedJson<BodyData>,
+) -> Result<StatusCode, Error> {
+    let slug = Slug::parse(slug).map_err(Error::InvalidSlug)?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO topics (slug, name)
+        VALUES ($1, $2)
+        ON CONFLICT (slug) DO UPDATE
+        SET name = EXCLUDED.name
+        "#,
+        slug.as_ref(),
+        body.name
+    )
+    .execute(&connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// List every topic, in alphabetical order of their names.
+#[tracing::instrument(
+    name = "Listing the topics",
+    skip_all,
+    fields(user_id = %user.user_id)
+)]
+pub async fn list_topics(
+    user: AuthenticatedUser,
+    State(connection_pool): State<PgPool>,
+) -> Result<Json<Vec<Topic>>, Error> {
+    Ok(Json(load_topics(&connection_pool).await?))
+}
+
+/// Load every topic, in alphabetical order of their names.
+#[tracing::instrument(name = "Loading the topics", skip_all)]
+pub async fn load_topics(connection_pool: &PgPool) -> sqlx::Result<Vec<Topic>> {
+    sqlx::query_as!(Topic, "SELECT slug, name FROM topics ORDER BY name, slug")
+        .fetch_all(connection_pool)
+        .await
+        .inspect_error(|error| error!("Failed to execute query: {error}"))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error("invalid topic slug: {0}")]
+    InvalidSlug(ValidationErrors),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidSlug(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::{tracking, SubscriberLinks};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use inspect_error::InspectError;
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::{error, Span};
+use uuid::Uuid;
+
+/// Record that the subscriber opened the issue, and respond with the tracking
+/// pixel. Invalid tokens get the pixel too, so that mail clients don't show a
+/// broken image.
+#[tracing::instrument(
+    name = "Tracking an open",
+    skip_all,
+    fields(
+        newsletter_issue_id = tracing::field::Empty,
+        subscriber_id = tracing::field::Empty,
+    )
+)]
+pub async fn track_open(
+    State(connection_pool): State<PgPool>,
+    State(subscriber_links): State<SubscriberLinks>,
+    Path(token): Path<String>,
+    method: Method,
+    headers: HeaderMap,
+) -> Response {
+    let ids = token
+        .strip_suffix(".gif")
+        .and_then(|token| subscriber_links.verify_open_token(token));
+    if let Some((newsletter_issue_id, subscriber_id)) = ids {
+        record_ids(newsletter_issue_id, subscriber_id);
+        let event = Event {
+            newsletter_issue_id,
+            subscriber_id,
+            kind: "open",
+            link_index: None,
+            user_agent: user_agent(&headers),
+            bot_reason: tracking::bot_reason(
+                user_agent(&headers),
+                method == Method::HEAD,
+            ),
+        };
+        // The pixel is served either way.
+        let _ = record_event(&connection_pool, &event).await;
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, "image/gif"),
+            (header::CACHE_CONTROL, "no-store, max-age=0"),
+        ],
+        tracking::PIXEL,
+    )
+        .into_response()
+}
+
+/// Record that the subscriber clicked the link, and redirect them to its URL.
+#[tracing::instrument(
+    name = "Tracking a click",
+    skip_all,
+    fields(
+        newsletter_issue_id = tracing::field::Empty,
+        subscriber_id = tracing::field::Empty,
+    )
+)]
+pub async fn track_click(
+    State(connection_pool): State<PgPool>,
+    State(subscriber_links): State<SubscriberLinks>,
+    Path(token): Path<String>,
+    method: Method,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    let (newsletter_issue_id, subscriber_id, link_index) = subscriber_links
+        .verify_click_token(&token)
+        .ok_or(Error::UnknownLink)?;
+    record_ids(newsletter_issue_id, subscriber_id);
+    let link_index = i32::from(link_index);
+
+    let url = sqlx::query!(
+        r#"
+        SELECT url
+        FROM tracked_links
+        WHERE newsletter_issue_id = $1 AND link_index = $2
+        "#,
+        newsletter_issue_id,
+        link_index
+    )
+    .fetch_optional(&connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?
+    .ok_or(Error::UnknownLink)?
+    .url;
+
+    let event = Event {
+        newsletter_issue_id,
+        subscriber_id,
+        kind: "click",
+        link_index: Some(link_index),
+        user_agent: user_agent(&headers),
+        bot_reason: tracking::bot_reason(
+            user_agent(&headers),
+            method == Method::HEAD,
+        ),
+    };
+    // Readers should get where they're going even if this fails.
+    let _ = record_event(&connection_pool, &event).await;
+
+    // URLs are normalized when they're tracked, but links tracked before that
+    // may not fit in a header.
+    let location = HeaderValue::try_from(url)
+        .inspect_error(|error| error!("The link's URL is invalid: {error}"))
+        .map_err(|_| Error::UnknownLink)?;
+    Ok((StatusCode::SEE_OTHER, [(header::LOCATION, location)]).into_response())
+}
+
+fn record_ids(newsletter_issue_id: Uuid, subscriber_id: Uuid) {
+    Span::current()
+        .record(
+            "newsletter_issue_id",
+            tracing::field::display(newsletter_issue_id),
+        )
+        .record("subscriber_id", tracing::field::display(subscriber_id));
+}
+
+pub(super) fn user_agent(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+}
+
+pub(super) struct Event<'a> {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub kind: &'static str,
+    pub link_index: Option<i32>,
+    pub user_agent: Option<&'a str>,
+    pub bot_reason: Option<&'static str>,
+}
+
+/// Store the event, unless the subscriber or the issue has been deleted since.
+#[tracing::instrument(name = "Recording the event", skip_all)]
+pub(super) async fn record_event(
+    connection_pool: &PgPool,
+    event: &Event<'_>,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO engagement_events (
+            newsletter_issue_id,
+            subscriber_id,
+            kind,
+            link_index,
+            user_agent,
+            bot_reason
+        )
+        SELECT $1::uuid, $2::uuid, $3::text, $4::int, $5::text, $6::text
+        WHERE EXISTS (SELECT 1 FROM subscriptions WHERE id = $2)
+        AND EXISTS (
+            SELECT 1 FROM newsletter_issues WHERE newsletter_issue_id = $1
+        )
+        "#,
+        event.newsletter_issue_id,
+        event.subscriber_id,
+        event.kind,
+        event.link_index,
+        event.user_agent,
+        event.bot_reason
+    )
+    .execute(connection_pool)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error("this link doesn't exist")]
+    UnknownLink,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::UnknownLink => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
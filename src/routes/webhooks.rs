@@ -0,0 +1,204 @@
+use crate::webhooks::{
+    Provider, ProviderEvent, VerificationError, WebhookVerifier,
+};
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use inspect_error::InspectError;
+use sqlx::{PgPool, Postgres, Transaction};
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+/// Record the events an email provider reports about the emails it sent.
+/// Addresses that hard bounced or complained about spam are suppressed, so
+/// they aren't emailed anymore, and deliveries and bounces count towards the
+/// statistics of the last issue sent to the address.
+///
+/// Events the provider already reported are ignored, since providers retry
+/// requests that they aren't sure went through. Requests signed with a token
+/// that was used already are rejected, since the token is all that's signed.
+#[tracing::instrument(
+    name = "Receiving a webhook",
+    skip_all,
+    fields(provider = %provider, n_events = tracing::field::Empty)
+)]
+pub async fn receive_webhook(
+    State(connection_pool): State<PgPool>,
+    State(verifier): State<WebhookVerifier>,
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, Error> {
+    let provider = Provider::parse(&provider).ok_or(Error::UnknownProvider)?;
+    let token = verifier
+        .verify(provider, &headers, &body)
+        .inspect_error(|error| warn!("Rejected the webhook: {error:#}"))?;
+    let events = provider
+        .parse_events(&body)
+        .inspect_error(|error| error!("Failed to parse the events: {error}"))?;
+    tracing::Span::current().record("n_events", events.len());
+
+    let mut transaction = connection_pool.begin().await?;
+    if let Some(token) = token {
+        use_token(&mut transaction, provider, &token).await?;
+    }
+    for event in &events {
+        record_event(&mut transaction, provider, event).await?;
+    }
+    transaction.commit().await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Fail with [`Error::TokenUsed`] if the token was used already, and forget
+/// tokens whose signature is too old to be accepted anyway.
+#[tracing::instrument(name = "Using the token", skip_all)]
+async fn use_token(
+    transaction: &mut Transaction<'static, Postgres>,
+    provider: Provider,
+    token: &str,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "DELETE FROM used_webhook_tokens WHERE used_at < now() - interval '1 hour'"
+    )
+    .execute(&mut *transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO used_webhook_tokens (provider, token)
+        VALUES ($1, $2)
+        ON CONFLICT (provider, token) DO NOTHING
+        "#,
+        provider.as_str(),
+        token
+    )
+    .execute(&mut *transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+    if result.rows_affected() == 0 {
+        warn!("Rejected the webhook: its token was used already");
+        return Err(Error::TokenUsed);
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Recording the event",
+    skip_all,
+    fields(kind = event.kind.as_str())
+)]
+async fn record_event(
+    transaction: &mut Transaction<'static, Postgres>,
+    provider: Provider,
+    event: &ProviderEvent,
+) -> sqlx::Result<()> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO email_events (
+            provider,
+            provider_event_id,
+            subscriber_email,
+            kind,
+            reason,
+            occurred_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (provider, provider_event_id) DO NOTHING
+        "#,
+        provider.as_str(),
+        event.id,
+        event.email,
+        event.kind.as_str(),
+        event.reason,
+        event.occurred_at
+    )
+    .execute(&mut *transaction)
+    .await
+    .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+    if result.rows_affected() == 0 {
+        info!("The event was recorded already, skipping it");
+        return Ok(());
+    }
+
+    if let Some(kind) = event.kind.delivery_event_kind() {
+        sqlx::query!(
+            r#"
+            INSERT INTO delivery_events (
+                newsletter_issue_id,
+                subscriber_email,
+                kind,
+                occurred_at
+            )
+            SELECT newsletter_issue_id, subscriber_email, $2, $3
+            FROM delivery_events
+            WHERE lower(subscriber_email) = lower($1) AND kind = 'sent'
+            ORDER BY occurred_at DESC, id DESC
+            LIMIT 1
+            "#,
+            event.email,
+            kind,
+            event.occurred_at
+        )
+        .execute(&mut *transaction)
+        .await
+        .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+    }
+
+    if event.kind.suppresses() {
+        sqlx::query!(
+            r#"
+            INSERT INTO suppressions (email, reason, source, created_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (email) DO NOTHING
+            "#,
+            event.email,
+            event.kind.as_str(),
+            provider.as_str(),
+            event.occurred_at
+        )
+        .execute(&mut *transaction)
+        .await
+        .inspect_error(|error| error!("Failed to execute query: {error}"))?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error("there's no webhook for that provider")]
+    UnknownProvider,
+    #[error(transparent)]
+    Unverified(#[from] VerificationError),
+    #[error("the request was received already")]
+    TokenUsed,
+    #[error("the events couldn't be read")]
+    InvalidBody(#[from] serde_json::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::UnknownProvider
+            | Self::Unverified(VerificationError::NotConfigured) => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            Self::Unverified(VerificationError::InvalidSignature(_))
+            | Self::TokenUsed => {
+                (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+            }
+            Self::InvalidBody(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
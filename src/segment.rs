@@ -0,0 +1,270 @@
+//! Segments narrow an issue down to the subscribers matching a filter, such as
+//! `confirmed_at < 30 days ago and not opted_out("events")`.
+//!
+//! A filter is a combination of conditions with `and`, `or`, `not` and
+//! parentheses. The conditions are:
+//!
+//! - `email`, `name`, `status` or `delivery_window`, followed by `=`, `!=` or
+//!   `contains` (which ignores case) and a double-quoted string;
+//! - `subscribed_at` or `confirmed_at`, followed by `<` (earlier than) or `>`
+//!   (later than) and an age such as `30 days ago`;
+//! - `in_list("slug")`, for confirmed subscribers of the list;
+//! - `opted_out("slug")`, for subscribers who opted out of the topic;
+//! - `opened(last: 3)`, for subscribers who opened or clicked at least one of
+//!   the last 3 tracked issues sent to them, leaving out opens by bots.
+//!
+//! Filters are compiled to SQL over `subscriptions` by [`Filter::push_sql`],
+//! with every value bound as a parameter.
+
+pub use parser::{parse, Error};
+
+mod parser;
+
+use sqlx::{Postgres, QueryBuilder};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    And(Box<Self>, Box<Self>),
+    Or(Box<Self>, Box<Self>),
+    Not(Box<Self>),
+    Text {
+        field: TextField,
+        operator: TextOperator,
+        value: String,
+    },
+    Time {
+        field: TimeField,
+        operator: TimeOperator,
+        age: Duration,
+    },
+    InList(String),
+    OptedOut(String),
+    Opened {
+        last: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextField {
+    Email,
+    Name,
+    Status,
+    DeliveryWindow,
+}
+
+impl TextField {
+    const fn column(self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Name => "name",
+            Self::Status => "status",
+            Self::DeliveryWindow => "delivery_window",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextOperator {
+    Equal,
+    NotEqual,
+    Contains,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeField {
+    SubscribedAt,
+    ConfirmedAt,
+}
+
+impl TimeField {
+    const fn column(self) -> &'static str {
+        match self {
+            Self::SubscribedAt => "created_at",
+            Self::ConfirmedAt => "confirmed_at",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeOperator {
+    /// Longer ago than the age.
+    Before,
+    /// More recently than the age.
+    After,
+}
+
+impl Filter {
+    /// Append the filter to `builder` as a boolean SQL expression over the
+    /// `subscriptions` table.
+    ///
+    /// Conditions on missing values, like the confirmation time of subscribers
+    /// who haven't confirmed, are false rather than `NULL`, so that `not`
+    /// matches them.
+    pub fn push_sql(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        builder.push("(");
+        match self {
+            Self::And(left, right) => {
+                left.push_sql(builder);
+                builder.push(" AND ");
+                right.push_sql(builder);
+            }
+            Self::Or(left, right) => {
+                left.push_sql(builder);
+                builder.push(" OR ");
+                right.push_sql(builder);
+            }
+            Self::Not(filter) => {
+                builder.push("NOT ");
+                filter.push_sql(builder);
+            }
+            Self::Text {
+                field,
+                operator,
+                value,
+            } => {
+                let column = field.column();
+                match operator {
+                    TextOperator::Equal => {
+                        builder.push(format!("subscriptions.{column} = "));
+                        builder.push_bind(value.clone());
+                    }
+                    TextOperator::NotEqual => {
+                        builder.push(format!("subscriptions.{column} <> "));
+                        builder.push_bind(value.clone());
+                    }
+                    // Substring searches don't work with the case-insensitive
+                    // collation of some columns, hence the lower-casing.
+                    TextOperator::Contains => {
+                        builder.push(format!(
+                            "strpos(lower(subscriptions.{column} \
+                            COLLATE \"default\"), lower("
+                        ));
+                        builder.push_bind(value.clone());
+                        builder.push(")) > 0");
+                    }
+                }
+            }
+            Self::Time {
+                field,
+                operator,
+                age,
+            } => {
+                let operator = match operator {
+                    TimeOperator::Before => "<",
+                    TimeOperator::After => ">",
+                };
+                builder.push(format!(
+                    "COALESCE(subscriptions.{} {operator} \
+                    now() - make_interval(secs => ",
+                    field.column()
+                ));
+                builder.push_bind(age.as_secs_f64());
+                builder.push("), false)");
+            }
+            Self::InList(slug) => {
+                builder.push(
+                    "EXISTS (SELECT 1 FROM list_subscriptions \
+                    WHERE list_subscriptions.subscriber_id = subscriptions.id \
+                    AND list_subscriptions.status = 'confirmed' \
+                    AND list_subscriptions.list_slug = ",
+                );
+                builder.push_bind(slug.clone());
+                builder.push(")");
+            }
+            Self::OptedOut(slug) => {
+                builder.push(
+                    "EXISTS (SELECT 1 FROM topic_opt_outs \
+                    WHERE topic_opt_outs.subscriber_id = subscriptions.id \
+                    AND topic_opt_outs.topic_slug = ",
+                );
+                builder.push_bind(slug.clone());
+                builder.push(")");
+            }
+            // Delivery events are matched on the address as it was stored, so
+            // the index on them can be used.
+            Self::Opened { last } => {
+                builder.push(
+                    "EXISTS (SELECT 1 FROM (\
+                        SELECT sent.newsletter_issue_id \
+                        FROM delivery_events AS sent \
+                        JOIN newsletter_issues AS issues \
+                        ON issues.newsletter_issue_id = sent.newsletter_issue_id \
+                        WHERE sent.subscriber_email = \
+                        subscriptions.email COLLATE \"default\" \
+                        AND sent.kind = 'sent' AND issues.tracking_enabled \
+                        ORDER BY sent.occurred_at DESC LIMIT ",
+                );
+                builder.push_bind(i64::from(*last));
+                builder.push(
+                    ") AS recent \
+                    JOIN engagement_events AS events \
+                    ON events.newsletter_issue_id = recent.newsletter_issue_id \
+                    WHERE events.subscriber_id = subscriptions.id \
+                    AND events.kind IN ('open', 'click') \
+                    AND events.bot_reason IS NULL)",
+                );
+            }
+        }
+        builder.push(")");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use sqlx::{Postgres, QueryBuilder};
+
+    fn compile(filter: &str) -> String {
+        let mut builder = QueryBuilder::<Postgres>::new("");
+        parse(filter).unwrap().push_sql(&mut builder);
+        builder.sql().to_string()
+    }
+
+    #[test]
+    fn values_are_bound_as_parameters() {
+        assert_eq!(
+            "(subscriptions.email = $1)",
+            compile(r#"email = "x' OR 1 = 1 --""#)
+        );
+    }
+
+    #[test]
+    fn filters_are_compiled_to_sql() {
+        assert_eq!(
+            "(((COALESCE(subscriptions.confirmed_at < \
+            now() - make_interval(secs => $1), false)) \
+            AND (NOT (strpos(lower(subscriptions.name COLLATE \"default\"), \
+            lower($2)) > 0))) \
+            OR (EXISTS (SELECT 1 FROM list_subscriptions \
+            WHERE list_subscriptions.subscriber_id = subscriptions.id \
+            AND list_subscriptions.status = 'confirmed' \
+            AND list_subscriptions.list_slug = $3)))",
+            compile(
+                r#"confirmed_at < 30 days ago and not name contains "bot"
+                or in_list("rust")"#
+            )
+        );
+    }
+
+    #[test]
+    fn opens_are_compiled_to_sql() {
+        assert_eq!(
+            "(EXISTS (SELECT 1 FROM (\
+            SELECT sent.newsletter_issue_id \
+            FROM delivery_events AS sent \
+            JOIN newsletter_issues AS issues \
+            ON issues.newsletter_issue_id = sent.newsletter_issue_id \
+            WHERE sent.subscriber_email = \
+            subscriptions.email COLLATE \"default\" \
+            AND sent.kind = 'sent' AND issues.tracking_enabled \
+            ORDER BY sent.occurred_at DESC LIMIT $1) AS recent \
+            JOIN engagement_events AS events \
+            ON events.newsletter_issue_id = recent.newsletter_issue_id \
+            WHERE events.subscriber_id = subscriptions.id \
+            AND events.kind IN ('open', 'click') \
+            AND events.bot_reason IS NULL))",
+            compile("opened(last: 3)")
+        );
+    }
+}
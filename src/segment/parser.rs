@@ -0,0 +1,512 @@
+use super::{Filter, TextField, TextOperator, TimeField, TimeOperator};
+use std::time::Duration;
+
+/// How deeply parentheses and `not` can be nested.
+const MAX_DEPTH: usize = 32;
+
+/// Filters are compiled and dropped recursively, and every `and` or `or` adds a
+/// level, so together with [`MAX_DEPTH`] this keeps filters from overflowing
+/// the stack.
+const MAX_CONDITIONS: usize = 100;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("{message} at character {position}")]
+pub struct Error {
+    message: String,
+    /// Counted from 1. One past the end if the filter ended too soon.
+    position: usize,
+}
+
+/// Parse a filter (see the [module documentation](super) for the syntax).
+///
+/// # Errors
+///
+/// Will return an error pointing at the first thing that doesn't fit the
+/// syntax.
+pub fn parse(input: &str) -> Result<Filter, Error> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        index: 0,
+        depth: 0,
+        n_conditions: 0,
+        end: input.chars().count() + 1,
+    };
+    let filter = parser.expression()?;
+    match parser.tokens.get(parser.index) {
+        Some((position, _)) => Err(error(*position, "expected `and` or `or`")),
+        None => Ok(filter),
+    }
+}
+
+fn error(position: usize, message: impl Into<String>) -> Error {
+    Error {
+        message: message.into(),
+        position,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    String(String),
+    Number(u64),
+    LeftParenthesis,
+    RightParenthesis,
+    Colon,
+    Comparison(&'static str),
+}
+
+/// Split the input into tokens, each with the position of its first character.
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, Error> {
+    let characters: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while let Some(&character) = characters.get(index) {
+        let position = index + 1;
+        let token = match character {
+            _ if character.is_whitespace() => {
+                index += 1;
+                continue;
+            }
+            '(' => {
+                index += 1;
+                Token::LeftParenthesis
+            }
+            ')' => {
+                index += 1;
+                Token::RightParenthesis
+            }
+            ':' => {
+                index += 1;
+                Token::Colon
+            }
+            '=' | '<' | '>' => {
+                index += 1;
+                Token::Comparison(match character {
+                    '=' => "=",
+                    '<' => "<",
+                    _ => ">",
+                })
+            }
+            '!' if characters.get(index + 1) == Some(&'=') => {
+                index += 2;
+                Token::Comparison("!=")
+            }
+            '"' => {
+                let mut value = String::new();
+                index += 1;
+                loop {
+                    match characters.get(index) {
+                        Some('"') => break,
+                        Some('\\') => match characters.get(index + 1) {
+                            Some(escaped @ ('"' | '\\')) => {
+                                value.push(*escaped);
+                                index += 2;
+                            }
+                            _ => {
+                                return Err(error(
+                                    index + 1,
+                                    "only `\\\"` and `\\\\` can be escaped",
+                                ))
+                            }
+                        },
+                        Some(character) => {
+                            value.push(*character);
+                            index += 1;
+                        }
+                        None => {
+                            return Err(error(position, "unterminated string"))
+                        }
+                    }
+                }
+                index += 1;
+                Token::String(value)
+            }
+            _ if character.is_ascii_digit() => {
+                let start = index;
+                while characters.get(index).is_some_and(char::is_ascii_digit) {
+                    index += 1;
+                }
+                let digits: String = characters[start..index].iter().collect();
+                Token::Number(
+                    digits
+                        .parse()
+                        .map_err(|_| error(position, "number too large"))?,
+                )
+            }
+            _ if character.is_ascii_alphabetic() || character == '_' => {
+                let start = index;
+                while characters.get(index).is_some_and(|character| {
+                    character.is_ascii_alphanumeric() || *character == '_'
+                }) {
+                    index += 1;
+                }
+                Token::Word(characters[start..index].iter().collect())
+            }
+            _ => return Err(error(position, format!("unexpected `{character}`"))),
+        };
+        tokens.push((position, token));
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+    depth: usize,
+    n_conditions: usize,
+    /// The position reported when the filter ends too soon.
+    end: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> (usize, Option<Token>) {
+        match self.tokens.get(self.index) {
+            Some((position, token)) => {
+                self.index += 1;
+                (*position, Some(token.clone()))
+            }
+            None => (self.end, None),
+        }
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        let matches = matches!(
+            self.tokens.get(self.index),
+            Some((_, Token::Word(next))) if next == word
+        );
+        if matches {
+            self.index += 1;
+        }
+        matches
+    }
+
+    fn expect(
+        &mut self,
+        expected: &Token,
+        description: &str,
+    ) -> Result<(), Error> {
+        match self.next() {
+            (_, Some(token)) if token == *expected => Ok(()),
+            (position, _) => {
+                Err(error(position, format!("expected {description}")))
+            }
+        }
+    }
+
+    fn expect_string(&mut self) -> Result<String, Error> {
+        match self.next() {
+            (_, Some(Token::String(value))) => Ok(value),
+            (position, _) => {
+                Err(error(position, "expected a double-quoted string"))
+            }
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), Error> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            let position = self
+                .tokens
+                .get(self.index)
+                .map_or(self.end, |(position, _)| *position);
+            return Err(error(position, "the filter is nested too deeply"));
+        }
+        Ok(())
+    }
+
+    /// `or` binds looser than `and`.
+    fn expression(&mut self) -> Result<Filter, Error> {
+        self.enter()?;
+        let mut filter = self.conjunction()?;
+        while self.eat_word("or") {
+            filter = Filter::Or(Box::new(filter), Box::new(self.conjunction()?));
+        }
+        self.depth -= 1;
+        Ok(filter)
+    }
+
+    fn conjunction(&mut self) -> Result<Filter, Error> {
+        let mut filter = self.negation()?;
+        while self.eat_word("and") {
+            filter = Filter::And(Box::new(filter), Box::new(self.negation()?));
+        }
+        Ok(filter)
+    }
+
+    fn negation(&mut self) -> Result<Filter, Error> {
+        if self.eat_word("not") {
+            self.enter()?;
+            let filter = Filter::Not(Box::new(self.negation()?));
+            self.depth -= 1;
+            Ok(filter)
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Filter, Error> {
+        match self.next() {
+            (_, Some(Token::LeftParenthesis)) => {
+                let filter = self.expression()?;
+                self.expect(&Token::RightParenthesis, "`)`")?;
+                Ok(filter)
+            }
+            (position, Some(Token::Word(word))) => self.condition(position, &word),
+            (position, _) => Err(error(position, "expected a condition")),
+        }
+    }
+
+    fn condition(&mut self, position: usize, word: &str) -> Result<Filter, Error> {
+        self.n_conditions += 1;
+        if self.n_conditions > MAX_CONDITIONS {
+            return Err(error(
+                position,
+                format!("the filter has more than {MAX_CONDITIONS} conditions"),
+            ));
+        }
+
+        let text_field = match word {
+            "email" => Some(TextField::Email),
+            "name" => Some(TextField::Name),
+            "status" => Some(TextField::Status),
+            "delivery_window" => Some(TextField::DeliveryWindow),
+            _ => None,
+        };
+        if let Some(field) = text_field {
+            return self.text_condition(field);
+        }
+
+        match word {
+            "subscribed_at" => self.time_condition(TimeField::SubscribedAt),
+            "confirmed_at" => self.time_condition(TimeField::ConfirmedAt),
+            "in_list" => Ok(Filter::InList(self.argument()?)),
+            "opted_out" => Ok(Filter::OptedOut(self.argument()?)),
+            "opened" => self.opened_condition(),
+            _ => Err(error(position, format!("unknown field `{word}`"))),
+        }
+    }
+
+    fn text_condition(&mut self, field: TextField) -> Result<Filter, Error> {
+        let operator = match self.next() {
+            (_, Some(Token::Comparison("="))) => TextOperator::Equal,
+            (_, Some(Token::Comparison("!="))) => TextOperator::NotEqual,
+            (_, Some(Token::Word(word))) if word == "contains" => {
+                TextOperator::Contains
+            }
+            (position, _) => {
+                return Err(error(position, "expected `=`, `!=` or `contains`"))
+            }
+        };
+
+        Ok(Filter::Text {
+            field,
+            operator,
+            value: self.expect_string()?,
+        })
+    }
+
+    fn time_condition(&mut self, field: TimeField) -> Result<Filter, Error> {
+        let operator = match self.next() {
+            (_, Some(Token::Comparison("<"))) => TimeOperator::Before,
+            (_, Some(Token::Comparison(">"))) => TimeOperator::After,
+            (position, _) => return Err(error(position, "expected `<` or `>`")),
+        };
+        let (position, amount) = match self.next() {
+            (position, Some(Token::Number(amount))) => (position, amount),
+            (position, _) => return Err(error(position, "expected a number")),
+        };
+        let unit = match self.next() {
+            (_, Some(Token::Word(word))) if word.starts_with("minute") => 60,
+            (_, Some(Token::Word(word))) if word.starts_with("hour") => 60 * 60,
+            (_, Some(Token::Word(word))) if word.starts_with("day") => {
+                24 * 60 * 60
+            }
+            (_, Some(Token::Word(word))) if word.starts_with("week") => {
+                7 * 24 * 60 * 60
+            }
+            (position, _) => {
+                return Err(error(
+                    position,
+                    "expected `minutes`, `hours`, `days` or `weeks`",
+                ))
+            }
+        };
+        let age = amount
+            .checked_mul(unit)
+            .map(Duration::from_secs)
+            .ok_or_else(|| error(position, "the age is too large"))?;
+        if !self.eat_word("ago") {
+            let (position, _) = self.next();
+            return Err(error(position, "expected `ago`"));
+        }
+
+        Ok(Filter::Time {
+            field,
+            operator,
+            age,
+        })
+    }
+
+    /// `opened(last: 3)`.
+    fn opened_condition(&mut self) -> Result<Filter, Error> {
+        self.expect(&Token::LeftParenthesis, "`(`")?;
+        if !self.eat_word("last") {
+            let (position, _) = self.next();
+            return Err(error(position, "expected `last`"));
+        }
+        self.expect(&Token::Colon, "`:`")?;
+        let last = match self.next() {
+            (position, Some(Token::Number(last))) => u32::try_from(last)
+                .ok()
+                .filter(|last| *last > 0)
+                .ok_or_else(|| {
+                    error(position, "expected a positive number of issues")
+                })?,
+            (position, _) => return Err(error(position, "expected a number")),
+        };
+        self.expect(&Token::RightParenthesis, "`)`")?;
+
+        Ok(Filter::Opened { last })
+    }
+
+    /// The argument of a function-like condition, such as `in_list("rust")`.
+    fn argument(&mut self) -> Result<String, Error> {
+        self.expect(&Token::LeftParenthesis, "`(`")?;
+        let argument = self.expect_string()?;
+        self.expect(&Token::RightParenthesis, "`)`")?;
+        Ok(argument)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{error, parse};
+    use crate::segment::{
+        Filter, TextField, TextOperator, TimeField, TimeOperator,
+    };
+    use std::time::Duration;
+
+    fn text(field: TextField, operator: TextOperator, value: &str) -> Filter {
+        Filter::Text {
+            field,
+            operator,
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let status = text(TextField::Status, TextOperator::Equal, "confirmed");
+        let list = Filter::InList("rust".to_string());
+        let topic = Filter::OptedOut("events".to_string());
+
+        assert_eq!(
+            Ok(Filter::Or(
+                Box::new(Filter::And(
+                    Box::new(status.clone()),
+                    Box::new(list.clone())
+                )),
+                Box::new(topic.clone())
+            )),
+            parse(
+                r#"status = "confirmed" and in_list("rust") or opted_out("events")"#
+            )
+        );
+        assert_eq!(
+            Ok(Filter::And(
+                Box::new(status),
+                Box::new(Filter::Or(Box::new(list), Box::new(topic)))
+            )),
+            parse(
+                r#"status = "confirmed" and (in_list("rust") or opted_out("events"))"#
+            )
+        );
+    }
+
+    #[test]
+    fn ages_are_parsed() {
+        assert_eq!(
+            Ok(Filter::Not(Box::new(Filter::Time {
+                field: TimeField::ConfirmedAt,
+                operator: TimeOperator::Before,
+                age: Duration::from_secs(30 * 24 * 60 * 60),
+            }))),
+            parse("not confirmed_at < 30 days ago")
+        );
+        assert_eq!(
+            Ok(Filter::Time {
+                field: TimeField::SubscribedAt,
+                operator: TimeOperator::After,
+                age: Duration::from_secs(60 * 60),
+            }),
+            parse("subscribed_at > 1 hour ago")
+        );
+    }
+
+    #[test]
+    fn opens_are_parsed() {
+        assert_eq!(
+            Ok(Filter::Not(Box::new(Filter::Opened { last: 3 }))),
+            parse("not opened(last: 3)")
+        );
+        assert_eq!(
+            Err(error(14, "expected a positive number of issues")),
+            parse("opened(last: 0)")
+        );
+        assert_eq!(Err(error(8, "expected `last`")), parse("opened(3)"));
+    }
+
+    #[test]
+    fn strings_can_contain_escaped_quotes() {
+        assert_eq!(
+            Ok(text(
+                TextField::Name,
+                TextOperator::Contains,
+                r#"the "best""#
+            )),
+            parse(r#"name contains "the \"best\"""#)
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(
+            Err(error(1, "unknown field `emial`")),
+            parse(r#"emial = "x""#)
+        );
+        assert_eq!(Err(error(9, "unterminated string")), parse(r#"email = "x"#));
+        assert_eq!(
+            Err(error(22, "expected `ago`")),
+            parse("confirmed_at < 3 days")
+        );
+        assert_eq!(
+            Err(error(13, "expected `and` or `or`")),
+            parse(r#"email = "x" "y""#)
+        );
+        assert_eq!(Err(error(1, "expected a condition")), parse(""));
+    }
+
+    #[test]
+    fn deeply_nested_filters_are_rejected() {
+        let filter =
+            format!("{}in_list(\"rust\"){}", "(".repeat(100), ")".repeat(100));
+
+        assert!(parse(&filter).is_err());
+        assert!(parse(&"not ".repeat(100)).is_err());
+    }
+
+    #[test]
+    fn long_chains_of_conditions_are_rejected() {
+        let condition = r#"email = "a""#;
+        let filter = |n: usize| vec![condition; n].join(" and ");
+
+        assert!(parse(&filter(100)).is_ok());
+        assert_eq!(
+            Err(error(1601, "the filter has more than 100 conditions")),
+            parse(&filter(101))
+        );
+    }
+}
@@ -0,0 +1,632 @@
+//! Server-side sessions, identified by a signed cookie.
+//!
+//! [`layer`] loads the session (and any flash messages) before the request is
+//! handled and persists whatever the handler changed afterwards. Handlers use the
+//! [`Session`] and [`Flash`] extractors to interact with them.
+
+pub use flash::{Flash, FlashLevel, FlashMessage};
+pub use handle::Session;
+pub use manager::{layer, SessionManager};
+pub use memory_store::MemorySessionStore;
+pub use postgres_store::{
+    purge_expired_sessions_until_stopped, PostgresSessionStore,
+};
+pub use store::{SessionData, SessionStore};
+
+mod store {
+    use async_trait::async_trait;
+    use std::{collections::HashMap, time::Duration};
+
+    pub type SessionData = HashMap<String, serde_json::Value>;
+
+    /// A place to keep session data between requests.
+    #[async_trait]
+    pub trait SessionStore: Send + Sync {
+        /// Load the data of the session, unless it doesn't exist or has expired.
+        async fn load(
+            &self,
+            session_id: &str,
+        ) -> anyhow::Result<Option<SessionData>>;
+
+        /// Create or overwrite the session, resetting its expiry.
+        async fn store(
+            &self,
+            session_id: &str,
+            data: &SessionData,
+            ttl: Duration,
+        ) -> anyhow::Result<()>;
+
+        async fn destroy(&self, session_id: &str) -> anyhow::Result<()>;
+    }
+}
+
+mod postgres_store {
+    use super::{SessionData, SessionStore};
+    use anyhow::Context;
+    use async_trait::async_trait;
+    use inspect_error::InspectError;
+    use sqlx::PgPool;
+    use std::time::Duration;
+    use tracing::{error, info};
+
+    pub struct PostgresSessionStore {
+        connection_pool: PgPool,
+    }
+
+    impl PostgresSessionStore {
+        #[must_use]
+        pub const fn new(connection_pool: PgPool) -> Self {
+            Self { connection_pool }
+        }
+    }
+
+    #[async_trait]
+    impl SessionStore for PostgresSessionStore {
+        async fn load(
+            &self,
+            session_id: &str,
+        ) -> anyhow::Result<Option<SessionData>> {
+            let row = sqlx::query!(
+                r#"
+                SELECT data FROM sessions
+                WHERE session_id = $1 AND expires_at > now()
+                "#,
+                session_id
+            )
+            .fetch_optional(&self.connection_pool)
+            .await
+            .context("failed to load the session")?;
+
+            row.map(|row| serde_json::from_value(row.data))
+                .transpose()
+                .context("failed to deserialize the session data")
+        }
+
+        async fn store(
+            &self,
+            session_id: &str,
+            data: &SessionData,
+            ttl: Duration,
+        ) -> anyhow::Result<()> {
+            let data = serde_json::to_value(data)
+                .context("failed to serialize the session data")?;
+
+            sqlx::query!(
+                r#"
+                INSERT INTO sessions (session_id, data, expires_at)
+                VALUES ($1, $2, now() + make_interval(secs => $3))
+                ON CONFLICT (session_id) DO UPDATE
+                SET data = EXCLUDED.data, expires_at = EXCLUDED.expires_at
+                "#,
+                session_id,
+                data,
+                ttl.as_secs_f64()
+            )
+            .execute(&self.connection_pool)
+            .await
+            .context("failed to store the session")?;
+
+            Ok(())
+        }
+
+        async fn destroy(&self, session_id: &str) -> anyhow::Result<()> {
+            sqlx::query!("DELETE FROM sessions WHERE session_id = $1", session_id)
+                .execute(&self.connection_pool)
+                .await
+                .context("failed to destroy the session")?;
+
+            Ok(())
+        }
+    }
+
+    /// Purge expired sessions every `purge_interval`.
+    pub async fn purge_expired_sessions_until_stopped(
+        connection_pool: &PgPool,
+        purge_interval: Duration,
+    ) {
+        loop {
+            // Failures are already logged, and the next run will try again.
+            let _ = purge_expired_sessions(connection_pool).await;
+            tokio::time::sleep(purge_interval).await;
+        }
+    }
+
+    /// Delete the sessions that have expired. They're never loaded again, but
+    /// the table would otherwise grow forever.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the query fails.
+    #[tracing::instrument(name = "Purging expired sessions", skip_all)]
+    async fn purge_expired_sessions(
+        connection_pool: &PgPool,
+    ) -> sqlx::Result<u64> {
+        let n_deleted_rows =
+            sqlx::query!("DELETE FROM sessions WHERE expires_at <= now()")
+                .execute(connection_pool)
+                .await
+                .inspect_error(|error| error!("Failed to execute query: {error}"))?
+                .rows_affected();
+
+        info!("Purged {n_deleted_rows} expired sessions");
+
+        Ok(n_deleted_rows)
+    }
+}
+
+mod memory_store {
+    use super::{SessionData, SessionStore};
+    use async_trait::async_trait;
+    use std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    };
+    use tokio::sync::RwLock;
+
+    /// Keeps sessions in the process's memory. They're lost on restart and
+    /// aren't shared between replicas.
+    #[derive(Default)]
+    pub struct MemorySessionStore {
+        sessions: RwLock<HashMap<String, (SessionData, Instant)>>,
+    }
+
+    impl MemorySessionStore {
+        #[must_use]
+        pub fn new() -> Self {
+            Self::default()
+        }
+    }
+
+    #[async_trait]
+    impl SessionStore for MemorySessionStore {
+        async fn load(
+            &self,
+            session_id: &str,
+        ) -> anyhow::Result<Option<SessionData>> {
+            let sessions = self.sessions.read().await;
+            let data = sessions
+                .get(session_id)
+                .filter(|(_, expires_at)| *expires_at > Instant::now())
+                .map(|(data, _)| data.clone());
+
+            Ok(data)
+        }
+
+        async fn store(
+            &self,
+            session_id: &str,
+            data: &SessionData,
+            ttl: Duration,
+        ) -> anyhow::Result<()> {
+            let mut sessions = self.sessions.write().await;
+            // Piggyback on writes to evict expired sessions, so memory usage
+            // doesn't grow without bound.
+            let now = Instant::now();
+            sessions.retain(|_, (_, expires_at)| *expires_at > now);
+            sessions.insert(session_id.to_string(), (data.clone(), now + ttl));
+
+            Ok(())
+        }
+
+        async fn destroy(&self, session_id: &str) -> anyhow::Result<()> {
+            self.sessions.write().await.remove(session_id);
+            Ok(())
+        }
+    }
+}
+
+mod handle {
+    use super::SessionData;
+    use async_trait::async_trait;
+    use axum::{
+        extract::FromRequestParts,
+        http::{request::Parts, StatusCode},
+    };
+    use serde::{de::DeserializeOwned, Serialize};
+    use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+    use uuid::Uuid;
+
+    const USER_ID_KEY: &str = "user_id";
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(super) enum Status {
+        Unchanged,
+        Changed,
+        /// The session should get a new ID, e.g. to prevent session fixation
+        /// when logging in.
+        Renewed,
+        Purged,
+    }
+
+    #[derive(Debug)]
+    pub(super) struct Inner {
+        pub(super) id: Option<String>,
+        pub(super) data: SessionData,
+        pub(super) status: Status,
+    }
+
+    /// The current request's session. Changes are persisted by
+    /// [`super::layer`] once the handler returns.
+    #[derive(Debug, Clone)]
+    pub struct Session(Arc<Mutex<Inner>>);
+
+    impl Session {
+        pub(super) fn new(id: Option<String>, data: SessionData) -> Self {
+            Self(Arc::new(Mutex::new(Inner {
+                id,
+                data,
+                status: Status::Unchanged,
+            })))
+        }
+
+        pub(super) fn lock(&self) -> MutexGuard<'_, Inner> {
+            self.0.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        #[must_use]
+        pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+            let inner = self.lock();
+            let value = inner.data.get(key)?.clone();
+            serde_json::from_value(value).ok()
+        }
+
+        /// Insert a value into the session.
+        ///
+        /// # Errors
+        ///
+        /// Will return an error if the value can't be serialized to JSON.
+        pub fn insert<T: Serialize>(
+            &self,
+            key: &str,
+            value: T,
+        ) -> serde_json::Result<()> {
+            let value = serde_json::to_value(value)?;
+            let mut inner = self.lock();
+            inner.data.insert(key.to_string(), value);
+            if inner.status == Status::Unchanged {
+                inner.status = Status::Changed;
+            }
+            Ok(())
+        }
+
+        /// Give the session a new ID while keeping its data.
+        pub fn renew(&self) {
+            let mut inner = self.lock();
+            if inner.status != Status::Purged {
+                inner.status = Status::Renewed;
+            }
+        }
+
+        /// Destroy the session and its data.
+        pub fn purge(&self) {
+            let mut inner = self.lock();
+            inner.data.clear();
+            inner.status = Status::Purged;
+        }
+
+        #[must_use]
+        pub fn user_id(&self) -> Option<Uuid> {
+            self.get(USER_ID_KEY)
+        }
+
+        /// Associate the session with a user.
+        ///
+        /// # Errors
+        ///
+        /// Will return an error if the ID can't be serialized to JSON.
+        pub fn insert_user_id(&self, user_id: Uuid) -> serde_json::Result<()> {
+            self.insert(USER_ID_KEY, user_id)
+        }
+    }
+
+    #[async_trait]
+    impl<S> FromRequestParts<S> for Session
+    where
+        S: Send + Sync,
+    {
+        type Rejection = (StatusCode, &'static str);
+
+        async fn from_request_parts(
+            parts: &mut Parts,
+            _state: &S,
+        ) -> Result<Self, Self::Rejection> {
+            parts.extensions.get::<Self>().cloned().ok_or((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "the session layer is missing",
+            ))
+        }
+    }
+}
+
+mod flash {
+    use async_trait::async_trait;
+    use axum::{
+        extract::FromRequestParts,
+        http::{request::Parts, StatusCode},
+    };
+    use serde::{Deserialize, Serialize};
+    use std::sync::{Arc, Mutex, PoisonError};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum FlashLevel {
+        Info,
+        Error,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct FlashMessage {
+        pub level: FlashLevel,
+        pub content: String,
+    }
+
+    #[derive(Debug, Default)]
+    pub(super) struct Inner {
+        /// Messages set by the previous request. `None` once they've been read.
+        pub(super) incoming: Option<Vec<FlashMessage>>,
+        pub(super) had_incoming: bool,
+        pub(super) outgoing: Vec<FlashMessage>,
+    }
+
+    /// One-off messages that survive a single redirect, such as "Your password
+    /// has been changed." They're kept in a signed cookie rather than in the
+    /// session, so anonymous visitors don't need one.
+    #[derive(Debug, Clone, Default)]
+    pub struct Flash(pub(super) Arc<Mutex<Inner>>);
+
+    impl Flash {
+        pub(super) fn new(incoming: Option<Vec<FlashMessage>>) -> Self {
+            Self(Arc::new(Mutex::new(Inner {
+                had_incoming: incoming.is_some(),
+                incoming,
+                outgoing: Vec::new(),
+            })))
+        }
+
+        /// Take the messages set by the previous request. They won't be shown
+        /// again.
+        #[must_use]
+        pub fn take_messages(&self) -> Vec<FlashMessage> {
+            let mut inner = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+            inner.incoming.take().unwrap_or_default()
+        }
+
+        /// Set a message to be shown by the next request.
+        pub fn info(&self, content: impl Into<String>) {
+            self.push(FlashLevel::Info, content.into());
+        }
+
+        /// Set an error message to be shown by the next request.
+        pub fn error(&self, content: impl Into<String>) {
+            self.push(FlashLevel::Error, content.into());
+        }
+
+        fn push(&self, level: FlashLevel, content: String) {
+            let mut inner = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+            inner.outgoing.push(FlashMessage { level, content });
+        }
+    }
+
+    #[async_trait]
+    impl<S> FromRequestParts<S> for Flash
+    where
+        S: Send + Sync,
+    {
+        type Rejection = (StatusCode, &'static str);
+
+        async fn from_request_parts(
+            parts: &mut Parts,
+            _state: &S,
+        ) -> Result<Self, Self::Rejection> {
+            parts.extensions.get::<Self>().cloned().ok_or((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "the session layer is missing",
+            ))
+        }
+    }
+}
+
+mod manager {
+    use super::{
+        flash::Flash, handle::Status, FlashMessage, Session, SessionStore,
+    };
+    use axum::{
+        extract::State,
+        http::{header, HeaderValue, Request},
+        middleware::Next,
+        response::{IntoResponse, Response},
+    };
+    use cookie::{time, Cookie, CookieJar, Key, SameSite};
+    use rand::{distributions::Alphanumeric, thread_rng, Rng};
+    use std::{sync::Arc, time::Duration};
+    use tracing::error;
+
+    const SESSION_COOKIE: &str = "id";
+    const FLASH_COOKIE: &str = "_flash";
+
+    #[derive(Clone)]
+    pub struct SessionManager {
+        store: Arc<dyn SessionStore>,
+        key: Key,
+        ttl: Duration,
+        secure: bool,
+    }
+
+    impl SessionManager {
+        /// Create a new `SessionManager`. Cookies are only marked as `Secure`
+        /// when `secure` is `true`, i.e. when the app is served over HTTPS.
+        #[must_use]
+        pub fn new(
+            store: Arc<dyn SessionStore>,
+            key: Key,
+            ttl: Duration,
+            secure: bool,
+        ) -> Self {
+            Self {
+                store,
+                key,
+                ttl,
+                secure,
+            }
+        }
+
+        fn cookie(&self, name: &'static str, value: String) -> Cookie<'static> {
+            Cookie::build(name, value)
+                .path("/")
+                .http_only(true)
+                .secure(self.secure)
+                .same_site(SameSite::Lax)
+                .finish()
+        }
+
+        fn removal_cookie(&self, name: &'static str) -> Cookie<'static> {
+            let mut cookie = self.cookie(name, String::new());
+            cookie.make_removal();
+            cookie
+        }
+    }
+
+    /// Middleware that loads the session and flash messages into the request's
+    /// extensions and persists any changes made to them after the handler runs.
+    pub async fn layer<B>(
+        State(manager): State<SessionManager>,
+        mut request: Request<B>,
+        next: Next<B>,
+    ) -> Response {
+        let mut jar = CookieJar::new();
+        for value in request.headers().get_all(header::COOKIE) {
+            let Ok(value) = value.to_str() else { continue };
+            for cookie in Cookie::split_parse_encoded(value.to_string()).flatten()
+            {
+                jar.add_original(cookie);
+            }
+        }
+
+        let session_id = jar
+            .signed(&manager.key)
+            .get(SESSION_COOKIE)
+            .map(|cookie| cookie.value().to_string());
+        let session = match load_session(&manager, session_id).await {
+            Ok(session) => session,
+            Err(error) => {
+                error!("Failed to load the session: {error:#}");
+                return axum::http::StatusCode::INTERNAL_SERVER_ERROR
+                    .into_response();
+            }
+        };
+        let incoming_flashes = jar
+            .signed(&manager.key)
+            .get(FLASH_COOKIE)
+            .and_then(|cookie| serde_json::from_str(cookie.value()).ok());
+        let flash = Flash::new(incoming_flashes);
+
+        request.extensions_mut().insert(session.clone());
+        request.extensions_mut().insert(flash.clone());
+
+        let mut response = next.run(request).await;
+
+        if let Err(error) = save_session(&manager, &session, &mut jar).await {
+            error!("Failed to save the session: {error:#}");
+            return axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        save_flashes(&manager, &flash, &mut jar);
+
+        for cookie in jar.delta() {
+            match HeaderValue::from_str(&cookie.encoded().to_string()) {
+                Ok(value) => {
+                    response.headers_mut().append(header::SET_COOKIE, value);
+                }
+                Err(error) => error!("Failed to encode a cookie: {error}"),
+            }
+        }
+
+        response
+    }
+
+    async fn load_session(
+        manager: &SessionManager,
+        session_id: Option<String>,
+    ) -> anyhow::Result<Session> {
+        if let Some(session_id) = session_id {
+            if let Some(data) = manager.store.load(&session_id).await? {
+                return Ok(Session::new(Some(session_id), data));
+            }
+        }
+
+        Ok(Session::new(None, super::SessionData::new()))
+    }
+
+    async fn save_session(
+        manager: &SessionManager,
+        session: &Session,
+        jar: &mut CookieJar,
+    ) -> anyhow::Result<()> {
+        let (id, data, status) = {
+            let inner = session.lock();
+            (inner.id.clone(), inner.data.clone(), inner.status)
+        };
+
+        match status {
+            Status::Unchanged => return Ok(()),
+            Status::Purged => {
+                if let Some(id) = id {
+                    manager.store.destroy(&id).await?;
+                }
+                jar.add(manager.removal_cookie(SESSION_COOKIE));
+                return Ok(());
+            }
+            Status::Renewed => {
+                if let Some(id) = id {
+                    manager.store.destroy(&id).await?;
+                }
+            }
+            Status::Changed => {
+                if let Some(id) = id {
+                    manager.store.store(&id, &data, manager.ttl).await?;
+                    return Ok(());
+                }
+            }
+        }
+
+        // The session is either new or renewed, so it needs a new ID.
+        let id = generate_session_id();
+        manager.store.store(&id, &data, manager.ttl).await?;
+
+        let mut cookie = manager.cookie(SESSION_COOKIE, id);
+        cookie.set_max_age(time::Duration::try_from(manager.ttl).ok());
+        jar.signed_mut(&manager.key).add(cookie);
+
+        Ok(())
+    }
+
+    fn save_flashes(manager: &SessionManager, flash: &Flash, jar: &mut CookieJar) {
+        let inner = flash
+            .0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        if !inner.outgoing.is_empty() {
+            match serde_json::to_string::<Vec<FlashMessage>>(&inner.outgoing) {
+                Ok(value) => {
+                    let cookie = manager.cookie(FLASH_COOKIE, value);
+                    jar.signed_mut(&manager.key).add(cookie);
+                }
+                Err(error) => {
+                    error!("Failed to serialize flash messages: {error}")
+                }
+            }
+        } else if inner.had_incoming && inner.incoming.is_none() {
+            // The messages have been shown, so they shouldn't be shown again.
+            jar.add(manager.removal_cookie(FLASH_COOKIE));
+        }
+    }
+
+    /// Generate a random, 64-character-long session ID.
+    fn generate_session_id() -> String {
+        let mut rng = thread_rng();
+        std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(64)
+            .collect()
+    }
+}
@@ -6,27 +6,82 @@ use std::{
 
 use anyhow::Context;
 use axum::{
-    routing::{get, post},
+    middleware,
+    routing::{get, post, put},
     Router, Server,
 };
+use cookie::Key;
 use inspect_error::InspectError;
+use secrecy::ExposeSecret;
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use tower_http::trace::TraceLayer;
 use tracing::{error, info};
 
 use crate::{
-    configuration::Settings,
-    email_client::EmailClient,
-    routes::{health_check::health, subscription::subscribe},
+    authentication::create_first_user,
+    configuration::{
+        DeliveryWorkerSettings, IdempotencySettings, SchedulerSettings,
+        SessionSettings, SessionStoreKind, Settings,
+    },
+    email_client::EmailSender,
+    idempotency::purge_expired_keys_until_stopped,
+    issue_delivery_worker::worker_loop,
+    issue_scheduler::scheduler_loop,
+    routes::{
+        admin::{
+            admin_dashboard, change_password, change_password_form, log_out,
+            reject_anonymous_users,
+        },
+        health_check::health,
+        lists::{get_lists, save_list},
+        login::{login, login_form},
+        newsletter_schedule::{cancel_newsletter, reschedule_newsletter},
+        newsletter_stats::get_newsletter_stats,
+        newsletters::publish_newsletter,
+        preferences::{preferences_form, save_preferences},
+        segments::{get_segments, preview_segment, save_segment},
+        subscribers::{
+            delete_subscriber, get_subscriber, get_subscribers, update_subscriber,
+        },
+        subscribers_export::export_subscribers,
+        subscribers_import::import_subscribers,
+        subscription::{subscribe, subscribe_to_list},
+        subscription_confirm::confirm,
+        subscription_unsubscribe::{unsubscribe, unsubscribe_form},
+        suppressions::{
+            delete_suppression, get_suppressions, import_suppressions,
+            save_suppression,
+        },
+        templates::{get_template, save_template},
+        topics::{list_topics, save_topic},
+        tracking::{track_click, track_open},
+        webhooks::receive_webhook,
+    },
+    session::{
+        self, purge_expired_sessions_until_stopped, MemorySessionStore,
+        PostgresSessionStore, SessionManager,
+    },
+    state::ApplicationBaseUrl,
     telemetry::RequestIdMakeSpan,
-    AppState,
+    templates::TemplateEngine,
+    webhooks::WebhookVerifier,
+    AppState, SubscriberLinks,
 };
 
 pub struct App {
     port: u16,
     address: SocketAddr,
     connection_pool: PgPool,
-    email_client: EmailClient,
+    email_client: Arc<dyn EmailSender>,
+    base_url: String,
+    template_engine: Arc<TemplateEngine>,
+    subscriber_links: SubscriberLinks,
+    webhook_verifier: WebhookVerifier,
+    delivery_worker: DeliveryWorkerSettings,
+    scheduler: SchedulerSettings,
+    session: SessionSettings,
+    idempotency: IdempotencySettings,
+    cookie_key: Key,
     listener: TcpListener,
 }
 
@@ -42,7 +97,9 @@ impl App {
         self.connection_pool = pool;
     }
 
-    /// Run the contained hyper server.
+    /// Run the contained hyper server, along with the tasks that purge expired
+    /// idempotency keys and sessions, and the newsletter delivery worker and
+    /// the issue scheduler if they're configured to run in-process.
     ///
     /// # Errors
     ///
@@ -54,11 +111,90 @@ impl App {
         let Self {
             connection_pool,
             email_client,
+            base_url,
+            template_engine,
+            subscriber_links,
+            webhook_verifier,
+            delivery_worker,
+            scheduler,
+            session,
+            idempotency,
+            cookie_key,
             listener,
             address,
             ..
         } = self;
-        let router = build_router(connection_pool, email_client);
+
+        if delivery_worker.run_in_process {
+            let connection_pool = connection_pool.clone();
+            let email_client = email_client.clone();
+            let subscriber_links = subscriber_links.clone();
+
+            tokio::spawn(async move {
+                if let Err(error) = worker_loop(
+                    &connection_pool,
+                    email_client.as_ref(),
+                    &subscriber_links,
+                    &delivery_worker,
+                )
+                .await
+                {
+                    error!("The delivery worker stopped unexpectedly: {error:#}");
+                }
+            });
+        }
+
+        if scheduler.run_in_process {
+            let connection_pool = connection_pool.clone();
+
+            tokio::spawn(async move {
+                if let Err(error) =
+                    scheduler_loop(&connection_pool, &scheduler).await
+                {
+                    error!("The issue scheduler stopped unexpectedly: {error:#}");
+                }
+            });
+        }
+
+        {
+            let connection_pool = connection_pool.clone();
+            tokio::spawn(async move {
+                purge_expired_keys_until_stopped(&connection_pool, &idempotency)
+                    .await;
+            });
+        }
+
+        // The store is created here rather than in `build_app()` so that it uses
+        // the final connection pool (see `App::set_custom_connection_pool`).
+        let session_store: Arc<dyn session::SessionStore> = match session.store {
+            SessionStoreKind::Postgres => {
+                let purge_interval = session.purge_interval();
+                let pool = connection_pool.clone();
+                tokio::spawn(async move {
+                    purge_expired_sessions_until_stopped(&pool, purge_interval)
+                        .await;
+                });
+
+                Arc::new(PostgresSessionStore::new(connection_pool.clone()))
+            }
+            SessionStoreKind::Memory => Arc::new(MemorySessionStore::new()),
+        };
+        let session_manager = SessionManager::new(
+            session_store,
+            cookie_key,
+            session.ttl(),
+            base_url.starts_with("https://"),
+        );
+
+        let router = build_router(
+            connection_pool,
+            email_client,
+            base_url,
+            template_engine,
+            subscriber_links,
+            webhook_verifier,
+            session_manager,
+        );
 
         info!("Listening on {address}");
 
@@ -78,6 +214,7 @@ impl App {
 ///
 /// - cannot bind the `TcpListener` used to start the server;
 /// - cannot run the migrations in the `migrations/` directory;
+/// - cannot create the admin user from the configuration;
 /// - cannot get the local address of the `TcpListener` (for logging purposes);
 /// - something goes wrong running the server.
 pub async fn build_app(configuration: Settings) -> anyhow::Result<App> {
@@ -104,8 +241,47 @@ pub async fn build_app(configuration: Settings) -> anyhow::Result<App> {
         .await
         .context("something went wrong running the migrations")?;
 
+    // Create the first user, if there are none yet.
+    if let Some(admin) = &configuration.admin {
+        if create_first_user(&admin.username, &admin.password, &connection_pool)
+            .await
+            .context("couldn't create the admin user")?
+        {
+            info!("Created the admin user `{}`", admin.username);
+        }
+    }
+
     // Create the email client.
-    let email_client = create_email_client(&configuration);
+    let email_client = configuration
+        .email_client
+        .client()
+        .context("couldn't build the email client")?;
+
+    // Load the email layouts and partials.
+    let template_engine = TemplateEngine::from_directory(
+        &configuration.application.templates_directory,
+    )
+    .context("couldn't load the email templates")?;
+
+    // Derive the key used to sign cookies.
+    let cookie_key = Key::try_from(
+        configuration
+            .application
+            .hmac_secret
+            .expose_secret()
+            .as_bytes(),
+    )
+    .context("the HMAC secret must be at least 64 bytes long")?;
+
+    // Links for subscribers are signed with the same secret as cookies.
+    let subscriber_links = SubscriberLinks::new(
+        configuration.application.base_url.clone(),
+        configuration.application.hmac_secret.clone(),
+    );
+
+    // Parse the keys the providers' webhooks are verified with.
+    let webhook_verifier = WebhookVerifier::new(&configuration.webhooks)
+        .context("couldn't load the webhook settings")?;
 
     // Extract the address.
     let address = listener
@@ -117,6 +293,15 @@ pub async fn build_app(configuration: Settings) -> anyhow::Result<App> {
         address,
         connection_pool,
         email_client,
+        base_url: configuration.application.base_url,
+        template_engine: Arc::new(template_engine),
+        subscriber_links,
+        webhook_verifier,
+        delivery_worker: configuration.delivery_worker,
+        scheduler: configuration.scheduler,
+        session: configuration.session,
+        idempotency: configuration.idempotency,
+        cookie_key,
         listener,
     })
 }
@@ -131,27 +316,81 @@ async fn run_migrations(connection_pool: &PgPool) -> sqlx::Result<()> {
     Ok(())
 }
 
-fn create_email_client(configuration: &Settings) -> EmailClient {
-    let sender_email = configuration
-        .email_client
-        .sender()
-        .expect("the sender email should be valid");
-    let base_url = configuration.email_client.base_url.clone();
-    let auth_token = configuration.email_client.authorization_token.clone();
-    let timeout = configuration.email_client.timeout();
-
-    EmailClient::new(sender_email, base_url, auth_token, timeout)
-}
+fn build_router(
+    connection_pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    base_url: String,
+    template_engine: Arc<TemplateEngine>,
+    subscriber_links: SubscriberLinks,
+    webhook_verifier: WebhookVerifier,
+    session_manager: SessionManager,
+) -> Router {
+    let base_url = ApplicationBaseUrl(base_url);
 
-fn build_router(connection_pool: PgPool, email_client: EmailClient) -> Router {
-    let email_client = Arc::new(email_client);
+    let admin_routes = Router::new()
+        .route("/dashboard", get(admin_dashboard))
+        .route("/password", get(change_password_form).post(change_password))
+        .route("/logout", post(log_out))
+        .route("/newsletters/:id/stats", get(get_newsletter_stats))
+        .route("/subscribers", get(get_subscribers))
+        .route(
+            "/subscribers/:id",
+            get(get_subscriber)
+                .patch(update_subscriber)
+                .delete(delete_subscriber),
+        )
+        .route("/subscribers/export", get(export_subscribers))
+        .route("/subscribers/import", post(import_subscribers))
+        .route("/suppressions", get(get_suppressions))
+        .route("/suppressions/import", post(import_suppressions))
+        .route(
+            "/suppressions/:email",
+            put(save_suppression).delete(delete_suppression),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            connection_pool.clone(),
+            reject_anonymous_users,
+        ));
 
     Router::new()
         .route("/health", get(health))
         .route("/subscriptions", post(subscribe))
+        .route("/subscriptions/confirm", get(confirm))
+        .route(
+            "/subscriptions/unsubscribe",
+            get(unsubscribe_form).post(unsubscribe),
+        )
+        .route("/lists", get(get_lists))
+        .route("/lists/:slug", put(save_list))
+        .route("/lists/:slug/subscriptions", post(subscribe_to_list))
+        .route("/preferences", get(preferences_form).post(save_preferences))
+        .route("/newsletters", post(publish_newsletter))
+        .route(
+            "/newsletters/:id/schedule",
+            put(reschedule_newsletter).delete(cancel_newsletter),
+        )
+        .route("/templates/:name", put(save_template).get(get_template))
+        .route("/segments", get(get_segments))
+        .route("/segments/preview", post(preview_segment))
+        .route("/segments/:slug", put(save_segment))
+        .route("/topics", get(list_topics))
+        .route("/topics/:slug", put(save_topic))
+        .route("/t/o/:token", get(track_open))
+        .route("/t/c/:token", get(track_click))
+        .route("/webhooks/:provider", post(receive_webhook))
+        .route("/login", get(login_form).post(login))
+        .nest("/admin", admin_routes)
+        .layer(middleware::from_fn_with_state(
+            session_manager,
+            session::layer,
+        ))
         .layer(TraceLayer::new_for_http().make_span_with(RequestIdMakeSpan::new()))
         .with_state(AppState {
             connection_pool,
             email_client,
+            base_url,
+            template_engine,
+            subscriber_links,
+            webhook_verifier,
         })
 }
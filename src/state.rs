@@ -1,4 +1,7 @@
-use crate::EmailClient;
+use crate::{
+    templates::TemplateEngine, webhooks::WebhookVerifier, EmailSender,
+    SubscriberLinks,
+};
 use axum::extract::FromRef;
 use sqlx::PgPool;
 use std::sync::Arc;
@@ -7,17 +10,50 @@ use std::sync::Arc;
 #[derive(Clone)]
 pub struct AppState {
     pub connection_pool: PgPool,
-    pub email_client: Arc<EmailClient>,
+    pub email_client: Arc<dyn EmailSender>,
+    pub base_url: ApplicationBaseUrl,
+    pub template_engine: Arc<TemplateEngine>,
+    pub subscriber_links: SubscriberLinks,
+    pub webhook_verifier: WebhookVerifier,
 }
 
+/// The URL the app is publicly reachable at, used to build links that are sent
+/// out in emails.
+#[derive(Clone, Debug)]
+pub struct ApplicationBaseUrl(pub String);
+
 impl FromRef<AppState> for PgPool {
     fn from_ref(input: &AppState) -> Self {
         input.connection_pool.clone()
     }
 }
 
-impl FromRef<AppState> for Arc<EmailClient> {
+impl FromRef<AppState> for Arc<dyn EmailSender> {
     fn from_ref(input: &AppState) -> Self {
         input.email_client.clone()
     }
 }
+
+impl FromRef<AppState> for Arc<TemplateEngine> {
+    fn from_ref(input: &AppState) -> Self {
+        input.template_engine.clone()
+    }
+}
+
+impl FromRef<AppState> for ApplicationBaseUrl {
+    fn from_ref(input: &AppState) -> Self {
+        input.base_url.clone()
+    }
+}
+
+impl FromRef<AppState> for SubscriberLinks {
+    fn from_ref(input: &AppState) -> Self {
+        input.subscriber_links.clone()
+    }
+}
+
+impl FromRef<AppState> for WebhookVerifier {
+    fn from_ref(input: &AppState) -> Self {
+        input.webhook_verifier.clone()
+    }
+}
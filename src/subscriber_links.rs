@@ -0,0 +1,290 @@
+//! Signed links that let subscribers leave or manage their preferences without
+//! logging in or looking up a stored token. The token is the subscriber's ID
+//! followed by an HMAC of it, so it can't be forged for anyone else.
+//!
+//! Tracking tokens (see [`crate::tracking`]) are made the same way, from the
+//! issue and subscriber IDs, and the index of the link for clicks.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Keeps subscriber tokens from being valid as any other kind of signature made
+/// with the same secret. Changing it would break the links in sent emails.
+const CONTEXT: &[u8] = b"unsubscribe:";
+const OPEN_CONTEXT: &[u8] = b"open:";
+const CLICK_CONTEXT: &[u8] = b"click:";
+
+#[derive(Clone, Debug)]
+pub struct SubscriberLinks {
+    base_url: String,
+    key: Secret<String>,
+}
+
+impl SubscriberLinks {
+    /// Create links to the app at `base_url`, signed with `key`.
+    #[must_use]
+    pub const fn new(base_url: String, key: Secret<String>) -> Self {
+        Self { base_url, key }
+    }
+
+    /// Where the subscriber goes to stop receiving the newsletter, or only the
+    /// given list. It's also the target of one-click unsubscribe requests
+    /// (RFC 8058). Unsubscribing is attributed to the issue the link is in, if
+    /// any.
+    ///
+    /// The issue and the list aren't signed: the token already limits the link
+    /// to the subscriber's own subscriptions.
+    #[must_use]
+    pub fn unsubscribe_url(
+        &self,
+        subscriber_id: Uuid,
+        newsletter_issue_id: Option<Uuid>,
+        list_slug: Option<&str>,
+    ) -> String {
+        let mut url = format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            self.base_url,
+            self.token(subscriber_id)
+        );
+        if let Some(newsletter_issue_id) = newsletter_issue_id {
+            url.push_str("&issue=");
+            url.push_str(&newsletter_issue_id.to_string());
+        }
+        // Slugs are URL-safe as they are.
+        if let Some(list_slug) = list_slug {
+            url.push_str("&list=");
+            url.push_str(list_slug);
+        }
+        url
+    }
+
+    /// Where the subscriber manages their name, topics and delivery window.
+    #[must_use]
+    pub fn preferences_url(&self, subscriber_id: Uuid) -> String {
+        format!(
+            "{}/preferences?token={}",
+            self.base_url,
+            self.token(subscriber_id)
+        )
+    }
+
+    /// The subscriber's token, which is URL-safe as is.
+    #[must_use]
+    pub fn token(&self, subscriber_id: Uuid) -> String {
+        self.sign(CONTEXT, subscriber_id.as_bytes())
+    }
+
+    /// Return the ID of the subscriber the token was made for, if its signature
+    /// is valid.
+    #[must_use]
+    pub fn verify(&self, token: &str) -> Option<Uuid> {
+        let payload = self.verify_signed(CONTEXT, token)?;
+        Uuid::from_slice(&payload).ok()
+    }
+
+    /// The token of the tracking pixel in the subscriber's copy of the issue.
+    #[must_use]
+    pub fn open_token(
+        &self,
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+    ) -> String {
+        self.sign(OPEN_CONTEXT, &ids(newsletter_issue_id, subscriber_id))
+    }
+
+    /// Return the issue and subscriber IDs of a valid open token.
+    #[must_use]
+    pub fn verify_open_token(&self, token: &str) -> Option<(Uuid, Uuid)> {
+        let payload = self.verify_signed(OPEN_CONTEXT, token)?;
+        split_ids(&payload)
+    }
+
+    /// The token of the link with the given index in the subscriber's copy of
+    /// the issue.
+    #[must_use]
+    pub fn click_token(
+        &self,
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+        link_index: u16,
+    ) -> String {
+        let mut payload = ids(newsletter_issue_id, subscriber_id);
+        payload.extend(link_index.to_be_bytes());
+        self.sign(CLICK_CONTEXT, &payload)
+    }
+
+    /// Return the issue and subscriber IDs and the link index of a valid click
+    /// token.
+    #[must_use]
+    pub fn verify_click_token(&self, token: &str) -> Option<(Uuid, Uuid, u16)> {
+        let payload = self.verify_signed(CLICK_CONTEXT, token)?;
+        if payload.len() != 34 {
+            return None;
+        }
+        let (ids, link_index) = payload.split_at(32);
+        let (newsletter_issue_id, subscriber_id) = split_ids(ids)?;
+        let link_index = u16::from_be_bytes(link_index.try_into().ok()?);
+        Some((newsletter_issue_id, subscriber_id, link_index))
+    }
+
+    /// The payload followed by its HMAC, URL-safe.
+    fn sign(&self, context: &[u8], payload: &[u8]) -> String {
+        let mut token = payload.to_vec();
+        token.extend(self.mac(context, payload).finalize().into_bytes());
+        URL_SAFE_NO_PAD.encode(token)
+    }
+
+    /// Return the payload of the token, if its signature is valid.
+    fn verify_signed(&self, context: &[u8], token: &str) -> Option<Vec<u8>> {
+        const SIGNATURE_LENGTH: usize = 32;
+
+        let token = URL_SAFE_NO_PAD.decode(token).ok()?;
+        let payload_length = token.len().checked_sub(SIGNATURE_LENGTH)?;
+        let (payload, signature) = token.split_at(payload_length);
+
+        self.mac(context, payload)
+            .verify_slice(signature)
+            .ok()
+            .map(|()| payload.to_vec())
+    }
+
+    fn mac(&self, context: &[u8], payload: &[u8]) -> Hmac<Sha256> {
+        #[allow(clippy::expect_used)]
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.key.expose_secret().as_bytes())
+                .expect("HMAC should accept keys of any length");
+        mac.update(context);
+        mac.update(payload);
+        mac
+    }
+}
+
+fn ids(newsletter_issue_id: Uuid, subscriber_id: Uuid) -> Vec<u8> {
+    [
+        newsletter_issue_id.as_bytes().as_slice(),
+        subscriber_id.as_bytes(),
+    ]
+    .concat()
+}
+
+fn split_ids(payload: &[u8]) -> Option<(Uuid, Uuid)> {
+    if payload.len() != 32 {
+        return None;
+    }
+    let (newsletter_issue_id, subscriber_id) = payload.split_at(16);
+    Some((
+        Uuid::from_slice(newsletter_issue_id).ok()?,
+        Uuid::from_slice(subscriber_id).ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberLinks;
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn links(key: &str) -> SubscriberLinks {
+        SubscriberLinks::new(
+            "https://example.com".to_string(),
+            Secret::new(key.to_string()),
+        )
+    }
+
+    #[test]
+    fn tokens_are_verified() {
+        let links = links("key");
+        let subscriber_id = Uuid::new_v4();
+
+        assert_eq!(
+            Some(subscriber_id),
+            links.verify(&links.token(subscriber_id))
+        );
+    }
+
+    #[test]
+    fn tokens_signed_with_another_key_are_rejected() {
+        let subscriber_id = Uuid::new_v4();
+        let token = links("another key").token(subscriber_id);
+
+        assert_eq!(None, links("key").verify(&token));
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let links = links("key");
+        let token = links.token(Uuid::new_v4());
+        let other_token = links.token(Uuid::new_v4());
+        // Another subscriber's ID with this subscriber's signature.
+        let forged = format!("{}{}", &other_token[..21], &token[21..]);
+
+        assert_eq!(None, links.verify(&forged));
+        assert_eq!(None, links.verify("garbage"));
+        assert_eq!(None, links.verify(""));
+    }
+
+    #[test]
+    fn the_urls_carry_the_token() {
+        let links = links("key");
+        let subscriber_id = Uuid::new_v4();
+        let newsletter_issue_id = Uuid::new_v4();
+        let token = links.token(subscriber_id);
+
+        assert_eq!(
+            format!("https://example.com/subscriptions/unsubscribe?token={token}"),
+            links.unsubscribe_url(subscriber_id, None, None)
+        );
+        assert_eq!(
+            format!(
+                "https://example.com/subscriptions/unsubscribe?token={token}\
+                &issue={newsletter_issue_id}&list=rust"
+            ),
+            links.unsubscribe_url(
+                subscriber_id,
+                Some(newsletter_issue_id),
+                Some("rust")
+            )
+        );
+        assert_eq!(
+            format!("https://example.com/preferences?token={token}"),
+            links.preferences_url(subscriber_id)
+        );
+    }
+
+    #[test]
+    fn tracking_tokens_are_verified() {
+        let links = links("key");
+        let newsletter_issue_id = Uuid::new_v4();
+        let subscriber_id = Uuid::new_v4();
+
+        assert_eq!(
+            Some((newsletter_issue_id, subscriber_id)),
+            links.verify_open_token(
+                &links.open_token(newsletter_issue_id, subscriber_id)
+            )
+        );
+        assert_eq!(
+            Some((newsletter_issue_id, subscriber_id, 3)),
+            links.verify_click_token(&links.click_token(
+                newsletter_issue_id,
+                subscriber_id,
+                3
+            ))
+        );
+    }
+
+    #[test]
+    fn tokens_are_only_valid_for_their_purpose() {
+        let links = links("key");
+        let newsletter_issue_id = Uuid::new_v4();
+        let subscriber_id = Uuid::new_v4();
+        let open_token = links.open_token(newsletter_issue_id, subscriber_id);
+
+        assert_eq!(None, links.verify_click_token(&open_token));
+        assert_eq!(None, links.verify(&open_token));
+        assert_eq!(None, links.verify_open_token(&links.token(subscriber_id)));
+    }
+}
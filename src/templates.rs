@@ -0,0 +1,454 @@
+//! Email templates, written in Handlebars. Every email is rendered twice, once
+//! for the `text/html` part and once for the `text/plain` part (unless the
+//! template only has HTML, in which case the plain text is derived from it),
+//! and wrapped in the shared layout from the `templates/` directory.
+//!
+//! The directory holds `layout.html.hbs` and `layout.txt.hbs`, which get the
+//! rendered email as `content`, and any number of partials in `partials/`
+//! (`partials/footer.html.hbs` is available as `{{> footer}}` in HTML
+//! templates). Newsletter templates themselves are stored in the database.
+//!
+//! Emails are rendered once per issue, not once per subscriber, so that they
+//! can be sent in batches. Per-subscriber variables like `{{name}}` render as
+//! placeholders, which [`substitutions`] fills in for every recipient.
+
+use crate::html_email;
+use handlebars::{
+    template::TemplateElement, Context, Handlebars, Helper, HelperResult, Output,
+    RenderContext, RenderError, Template,
+};
+use serde_json::json;
+use std::{collections::HashMap, fs, path::Path};
+
+const NAME_PLACEHOLDER: &str = "[[name]]";
+const HTML_NAME_PLACEHOLDER: &str = "[[name:html]]";
+const UNSUBSCRIBE_URL_PLACEHOLDER: &str = "[[unsubscribe_url]]";
+const PREFERENCES_URL_PLACEHOLDER: &str = "[[preferences_url]]";
+
+/// The HTML and plain text sources of a template. Without a plain text source,
+/// the plain text part is derived from the rendered HTML.
+#[derive(Debug, Clone)]
+pub struct TemplateSource {
+    pub html: String,
+    pub text: Option<String>,
+}
+
+/// An email rendered from a template, with placeholders for the
+/// per-subscriber variables.
+#[derive(Debug)]
+pub struct RenderedEmail {
+    pub html: String,
+    pub text: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("the HTML template is invalid: {0}")]
+    Html(#[source] RenderError),
+    #[error("the plain text template is invalid: {0}")]
+    Text(#[source] RenderError),
+    #[error("the plain text couldn't be derived from the HTML")]
+    PlainText(#[source] lol_html::errors::RewritingError),
+}
+
+pub struct TemplateEngine {
+    html: Handlebars<'static>,
+    text: Handlebars<'static>,
+}
+
+impl TemplateEngine {
+    /// Load the layouts and partials from the given directory.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if a layout is missing or a file in the directory
+    /// can't be read or compiled.
+    pub fn from_directory(directory: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let directory = directory.as_ref();
+        let mut html = Handlebars::new();
+        let mut text = Handlebars::new();
+
+        // Plain text must come out as is.
+        text.register_escape_fn(handlebars::no_escape);
+        for registry in [&mut html, &mut text] {
+            // Fail on typos like `{{nmae}}` instead of rendering nothing.
+            registry.set_strict_mode(true);
+        }
+        html.register_helper("unsubscribe_link", Box::new(html_unsubscribe_link));
+        text.register_helper("unsubscribe_link", Box::new(text_unsubscribe_link));
+        html.register_helper("preferences_link", Box::new(html_preferences_link));
+        text.register_helper("preferences_link", Box::new(text_preferences_link));
+
+        html.register_template_file("layout", directory.join("layout.html.hbs"))?;
+        text.register_template_file("layout", directory.join("layout.txt.hbs"))?;
+
+        let partials = directory.join("partials");
+        if partials.is_dir() {
+            for entry in fs::read_dir(partials)? {
+                let path = entry?.path();
+                let Some(file_name) =
+                    path.file_name().and_then(|name| name.to_str())
+                else {
+                    continue;
+                };
+
+                if let Some(name) = file_name.strip_suffix(".html.hbs") {
+                    html.register_partial(name, fs::read_to_string(&path)?)?;
+                } else if let Some(name) = file_name.strip_suffix(".txt.hbs") {
+                    text.register_partial(name, fs::read_to_string(&path)?)?;
+                }
+            }
+        }
+
+        Ok(Self { html, text })
+    }
+
+    /// Render both parts of the template inside the layout.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if either part doesn't compile or refers to a
+    /// variable, partial or helper that doesn't exist.
+    pub fn render(
+        &self,
+        template: &TemplateSource,
+        title: &str,
+    ) -> Result<RenderedEmail, Error> {
+        let html = render_in_layout(
+            &self.html,
+            &template.html,
+            title,
+            HTML_NAME_PLACEHOLDER,
+        )
+        .map_err(Error::Html)?;
+        let text = match &template.text {
+            Some(source) => {
+                render_in_layout(&self.text, source, title, NAME_PLACEHOLDER)
+                    .map_err(Error::Text)?
+            }
+            // The derived text has no escaping, so it gets the raw name.
+            None => html_email::plain_text(&html)
+                .map_err(Error::PlainText)?
+                .replace(HTML_NAME_PLACEHOLDER, NAME_PLACEHOLDER),
+        };
+
+        Ok(RenderedEmail { html, text })
+    }
+
+    /// Check that the template renders, so that broken templates are caught
+    /// when they're saved rather than when an issue is published.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::render`].
+    pub fn validate(&self, template: &TemplateSource) -> Result<(), Error> {
+        self.render(template, "Title").map(|_| ())
+    }
+}
+
+fn render_in_layout(
+    registry: &Handlebars<'static>,
+    source: &str,
+    title: &str,
+    name: &str,
+) -> Result<String, RenderError> {
+    let template = Template::compile(source)?;
+    if let Some(partial) = find_missing_partial(registry, &template) {
+        return Err(RenderError::new(format!(
+            "the partial `{partial}` doesn't exist"
+        )));
+    }
+
+    let content = registry
+        .render_template(source, &json!({ "title": title, "name": name }))?;
+
+    registry.render(
+        "layout",
+        &json!({ "title": title, "name": name, "content": content }),
+    )
+}
+
+/// Handlebars renders partials that don't exist as nothing, even in strict mode,
+/// so they're looked for separately. Partial blocks (`{{#> name}}...{{/name}}`)
+/// are skipped, because their content is the fallback for a missing partial.
+fn find_missing_partial(
+    registry: &Handlebars<'static>,
+    template: &Template,
+) -> Option<String> {
+    template.elements.iter().find_map(|element| match element {
+        TemplateElement::PartialExpression(partial) => partial
+            .name
+            .as_name()
+            .filter(|name| !registry.has_template(name))
+            .map(ToString::to_string),
+        TemplateElement::HelperBlock(helper) => {
+            [&helper.template, &helper.inverse]
+                .into_iter()
+                .flatten()
+                .find_map(|template| find_missing_partial(registry, template))
+        }
+        TemplateElement::PartialBlock(decorator)
+        | TemplateElement::DecoratorBlock(decorator) => decorator
+            .template
+            .as_ref()
+            .and_then(|template| find_missing_partial(registry, template)),
+        _ => None,
+    })
+}
+
+/// `{{unsubscribe_link}}`, or `{{unsubscribe_link "Link text"}}`.
+fn html_unsubscribe_link(
+    helper: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    write_html_link(helper, out, UNSUBSCRIBE_URL_PLACEHOLDER, "Unsubscribe")
+}
+
+fn text_unsubscribe_link(
+    helper: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    write_text_link(helper, out, UNSUBSCRIBE_URL_PLACEHOLDER, "Unsubscribe")
+}
+
+/// `{{preferences_link}}`, or `{{preferences_link "Link text"}}`.
+fn html_preferences_link(
+    helper: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    write_html_link(
+        helper,
+        out,
+        PREFERENCES_URL_PLACEHOLDER,
+        "Manage preferences",
+    )
+}
+
+fn text_preferences_link(
+    helper: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    write_text_link(
+        helper,
+        out,
+        PREFERENCES_URL_PLACEHOLDER,
+        "Manage preferences",
+    )
+}
+
+/// The link text is the helper's first parameter, if it has one.
+fn link_text<'a>(helper: &'a Helper, default: &'a str) -> &'a str {
+    helper
+        .param(0)
+        .and_then(|param| param.value().as_str())
+        .unwrap_or(default)
+}
+
+fn write_html_link(
+    helper: &Helper,
+    out: &mut dyn Output,
+    url_placeholder: &str,
+    default_text: &str,
+) -> HelperResult {
+    out.write(&format!(
+        r#"<a href="{url_placeholder}">{}</a>"#,
+        handlebars::html_escape(link_text(helper, default_text))
+    ))?;
+    Ok(())
+}
+
+fn write_text_link(
+    helper: &Helper,
+    out: &mut dyn Output,
+    url_placeholder: &str,
+    default_text: &str,
+) -> HelperResult {
+    out.write(&format!(
+        "{}: {url_placeholder}",
+        link_text(helper, default_text)
+    ))?;
+    Ok(())
+}
+
+/// The values of the per-subscriber variables for one recipient, to be used as
+/// their [`crate::email_client::BatchRecipient::substitutions`].
+#[must_use]
+pub fn substitutions(
+    name: &str,
+    unsubscribe_url: &str,
+    preferences_url: &str,
+) -> HashMap<String, String> {
+    HashMap::from([
+        (NAME_PLACEHOLDER.to_string(), name.to_string()),
+        (
+            HTML_NAME_PLACEHOLDER.to_string(),
+            handlebars::html_escape(name),
+        ),
+        // The URLs only have URL-safe characters, so they're safe in both
+        // parts.
+        (
+            UNSUBSCRIBE_URL_PLACEHOLDER.to_string(),
+            unsubscribe_url.to_string(),
+        ),
+        (
+            PREFERENCES_URL_PLACEHOLDER.to_string(),
+            preferences_url.to_string(),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{substitutions, TemplateEngine, TemplateSource};
+    use crate::email_client::BatchRecipient;
+
+    fn engine() -> TemplateEngine {
+        TemplateEngine::from_directory("templates").unwrap()
+    }
+
+    fn template(html: &str, text: &str) -> TemplateSource {
+        TemplateSource {
+            html: html.to_string(),
+            text: Some(text.to_string()),
+        }
+    }
+
+    fn personalize(text: &str, name: &str) -> String {
+        let recipient = BatchRecipient {
+            email: crate::domain::SubscriberEmail::parse(
+                "ursula@example.com".to_string(),
+            )
+            .unwrap(),
+            substitutions: substitutions(
+                name,
+                "https://example.com/unsubscribe",
+                "https://example.com/preferences",
+            ),
+            unsubscribe_url: None,
+        };
+        recipient.substitute(text)
+    }
+
+    #[test]
+    fn both_parts_are_rendered_inside_the_layout() {
+        let rendered = engine()
+            .render(
+                &template("<p>Hi, {{name}}!</p>", "Hi, {{name}}!"),
+                "Issue #1",
+            )
+            .unwrap();
+
+        assert!(rendered.html.contains("<title>Issue #1</title>"));
+        assert!(rendered.html.contains("<p>Hi, [[name:html]]!</p>"));
+        assert!(rendered.text.starts_with("Issue #1"));
+        assert!(rendered.text.contains("Hi, [[name]]!"));
+    }
+
+    #[test]
+    fn names_are_escaped_in_html_only() {
+        let rendered = engine()
+            .render(&template("{{name}}", "{{name}}"), "Title")
+            .unwrap();
+
+        let html = personalize(&rendered.html, "Tom & Jerry");
+        let text = personalize(&rendered.text, "Tom & Jerry");
+
+        assert!(html.contains("Tom &amp; Jerry"));
+        assert!(text.contains("Tom & Jerry"));
+    }
+
+    #[test]
+    fn placeholders_in_names_are_left_alone() {
+        let rendered = engine()
+            .render(&template("{{name}}", "{{name}}"), "Title")
+            .unwrap();
+
+        let text = personalize(&rendered.text, "[[unsubscribe_url]]");
+
+        assert!(text.contains("[[unsubscribe_url]]"));
+    }
+
+    #[test]
+    fn the_footer_links_to_the_unsubscribe_and_preferences_urls() {
+        let rendered = engine()
+            .render(&template("<p>Body</p>", "Body"), "Title")
+            .unwrap();
+
+        let html = personalize(&rendered.html, "Ursula");
+        let text = personalize(&rendered.text, "Ursula");
+
+        assert!(html.contains(
+            r#"<a href="https://example.com/unsubscribe">Unsubscribe</a>"#
+        ));
+        assert!(html.contains(
+            r#"<a href="https://example.com/preferences">Manage preferences</a>"#
+        ));
+        assert!(text.contains("Unsubscribe: https://example.com/unsubscribe"));
+        assert!(
+            text.contains("Manage preferences: https://example.com/preferences")
+        );
+    }
+
+    #[test]
+    fn the_plain_text_is_derived_from_the_html_if_missing() {
+        let source = TemplateSource {
+            html: "<p>Hi, {{name}}!</p>".to_string(),
+            text: None,
+        };
+
+        let rendered = engine().render(&source, "Issue #1").unwrap();
+        let text = personalize(&rendered.text, "Tom & Jerry");
+
+        assert!(text.starts_with("Issue #1\n========"));
+        assert!(text.contains("Hi, Tom & Jerry!"));
+        // The links from the footer become footnotes.
+        assert!(text.contains("Unsubscribe [1] or Manage preferences [2]."));
+        assert!(text.ends_with(
+            "[1] https://example.com/unsubscribe\n\
+            [2] https://example.com/preferences"
+        ));
+    }
+
+    #[test]
+    fn the_unsubscribe_link_text_can_be_changed() {
+        let rendered = engine()
+            .render(
+                &template(r#"{{unsubscribe_link "Stop <these>"}}"#, "Body"),
+                "Title",
+            )
+            .unwrap();
+
+        assert!(rendered.html.contains(">Stop &lt;these&gt;</a>"));
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected() {
+        assert!(engine().validate(&template("{{nmae}}", "Body")).is_err());
+    }
+
+    #[test]
+    fn unknown_partials_are_rejected() {
+        assert!(engine()
+            .validate(&template("<p>Body</p>", "{{> missing}}"))
+            .is_err());
+    }
+
+    #[test]
+    fn syntax_errors_are_rejected() {
+        assert!(engine()
+            .validate(&template("{{#if title}}unclosed", "Body"))
+            .is_err());
+    }
+}
@@ -0,0 +1,304 @@
+//! Open and click tracking. The HTML of tracked issues has its links pointed at
+//! `/t/c/{token}`, which records the click and redirects to the original URL,
+//! and a pixel from `/t/o/{token}.gif` appended, which records the open when
+//! the mail client loads images.
+//!
+//! Like the other per-subscriber variables (see [`crate::templates`]), the
+//! tokens are placeholders in the stored HTML, filled in for every recipient by
+//! [`substitutions`].
+//!
+//! Not every request comes from a reader: mail clients and security scanners
+//! fetch images and follow links on their own. Events that look automated are
+//! still stored, but with the reason from [`bot_reason`], so that they can be
+//! left out of the statistics.
+
+use crate::{html_email::decode_entities, SubscriberLinks};
+use lol_html::{
+    element, errors::RewritingError, html_content::ContentType, rewrite_str,
+    RewriteStrSettings,
+};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use url::Url;
+use uuid::Uuid;
+
+const OPEN_TOKEN_PLACEHOLDER: &str = "[[open_token]]";
+
+/// A transparent 1x1 GIF.
+pub const PIXEL: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff\
+    !\xf9\x04\x01\x00\x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02D\x01\x00;";
+
+/// Substrings of the user agents of crawlers, link previewers and the scanners
+/// of email security gateways, in lower case.
+const BOT_USER_AGENTS: &[&str] = &[
+    "bot",
+    "spider",
+    "crawl",
+    "preview",
+    "scanner",
+    "headlesschrome",
+    "python-requests",
+    "curl/",
+    "wget/",
+    "go-http-client",
+    "barracuda",
+    "mimecast",
+    "proofpoint",
+    "facebookexternalhit",
+];
+
+fn click_token_placeholder(link_index: usize) -> String {
+    format!("[[click_token:{link_index}]]")
+}
+
+#[derive(Debug)]
+pub struct TrackedHtml {
+    pub html: String,
+    /// The original URLs, normalized, in the order of their link indices.
+    pub links: Vec<String>,
+}
+
+/// Point the web links of `html` at the click tracker and append the tracking
+/// pixel, for the app at `base_url`.
+///
+/// Only valid `http` and `https` URLs are tracked. Other links, like `mailto:`
+/// links and the subscriber's own unsubscribe and preferences links, are left
+/// alone.
+///
+/// # Errors
+///
+/// Will return an error if the HTML couldn't be processed.
+pub fn add_tracking(
+    html: &str,
+    base_url: &str,
+) -> Result<TrackedHtml, RewritingError> {
+    let links = Rc::new(RefCell::new(Vec::new()));
+    let pixel_placed = Rc::new(RefCell::new(false));
+    let pixel = format!(
+        r#"<img src="{base_url}/t/o/{OPEN_TOKEN_PLACEHOLDER}.gif" width="1" height="1" alt="" style="display: block; border: 0;">"#
+    );
+
+    let mut html = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("a[href]", |element| {
+                    let Some(href) = element.get_attribute("href") else {
+                        return Ok(());
+                    };
+                    let mut links = links.borrow_mut();
+                    // Indices must fit in the tokens.
+                    if let Some(url) = web_url(&href)
+                        .filter(|_| links.len() < usize::from(u16::MAX))
+                    {
+                        element.set_attribute(
+                            "href",
+                            &format!(
+                                "{base_url}/t/c/{}",
+                                click_token_placeholder(links.len())
+                            ),
+                        )?;
+                        links.push(url.into());
+                    }
+                    Ok(())
+                }),
+                element!("body", |element| {
+                    element.append(&pixel, ContentType::Html);
+                    *pixel_placed.borrow_mut() = true;
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::default()
+        },
+    )?;
+    if !*pixel_placed.borrow() {
+        html.push_str(&pixel);
+    }
+
+    let links = links.take();
+    Ok(TrackedHtml { html, links })
+}
+
+/// The URL of a web link. Its serialization is safe to redirect to: control
+/// characters and the like are percent-encoded.
+fn web_url(href: &str) -> Option<Url> {
+    Url::parse(&decode_entities(href.trim()))
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// The values of the tracking placeholders for one recipient of an issue with
+/// `n_links` tracked links.
+#[must_use]
+pub fn substitutions(
+    subscriber_links: &SubscriberLinks,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    n_links: u16,
+) -> HashMap<String, String> {
+    let mut substitutions = HashMap::from([(
+        OPEN_TOKEN_PLACEHOLDER.to_string(),
+        subscriber_links.open_token(newsletter_issue_id, subscriber_id),
+    )]);
+    for link_index in 0..n_links {
+        substitutions.insert(
+            click_token_placeholder(usize::from(link_index)),
+            subscriber_links.click_token(
+                newsletter_issue_id,
+                subscriber_id,
+                link_index,
+            ),
+        );
+    }
+    substitutions
+}
+
+/// Why a request to the trackers looks automated, if it does.
+///
+/// - Apple Mail Privacy Protection loads every image of every email through a
+///   proxy as soon as it's received, with a bare `Mozilla/5.0` user agent.
+/// - Crawlers, link previewers and security scanners mostly say who they are in
+///   their user agents, or don't send one at all.
+/// - Readers never send `HEAD` requests, but link checkers do.
+#[must_use]
+pub fn bot_reason(
+    user_agent: Option<&str>,
+    is_head_request: bool,
+) -> Option<&'static str> {
+    if is_head_request {
+        return Some("head_request");
+    }
+    let user_agent = user_agent.map(str::trim).unwrap_or_default();
+    if user_agent.is_empty() {
+        return Some("missing_user_agent");
+    }
+    if user_agent == "Mozilla/5.0" {
+        return Some("apple_mail_privacy_protection");
+    }
+    let user_agent = user_agent.to_ascii_lowercase();
+    BOT_USER_AGENTS
+        .iter()
+        .any(|bot| user_agent.contains(bot))
+        .then_some("bot_user_agent")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_tracking, bot_reason, substitutions, PIXEL};
+    use crate::SubscriberLinks;
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    #[test]
+    fn web_links_are_rewritten_and_the_pixel_is_appended() {
+        let html = r#"<html><body><p><a href="https://example.com/a">A</a>
+            <a href="mailto:ursula@example.com">Mail</a>
+            <a href="[[unsubscribe_url]]">Unsubscribe</a>
+            <a href=" HTTP://example.com/b?x=1&amp;y=2 ">B</a></p></body></html>"#;
+
+        let tracked = add_tracking(html, "https://app.example.com").unwrap();
+
+        assert_eq!(
+            vec!["https://example.com/a", "http://example.com/b?x=1&y=2"],
+            tracked.links
+        );
+        assert!(tracked.html.contains(
+            r#"<a href="https://app.example.com/t/c/[[click_token:0]]">A</a>"#
+        ));
+        assert!(tracked.html.contains(
+            r#"<a href="https://app.example.com/t/c/[[click_token:1]]">B</a>"#
+        ));
+        assert!(tracked.html.contains(r#"href="mailto:ursula@example.com""#));
+        assert!(tracked.html.contains(r#"href="[[unsubscribe_url]]""#));
+        assert!(tracked.html.contains(
+            r#"src="https://app.example.com/t/o/[[open_token]].gif" width="1" height="1" alt="" style="display: block; border: 0;"></body>"#
+        ));
+    }
+
+    #[test]
+    fn urls_are_normalized_and_invalid_ones_left_alone() {
+        let html = r#"<a href="https://example.com/a&#10;b&#0;c d">A</a>
+            <a href="https://exa mple.com/">B</a>"#;
+
+        let tracked = add_tracking(html, "https://app.example.com").unwrap();
+
+        assert_eq!(vec!["https://example.com/ab%EF%BF%BDc%20d"], tracked.links);
+        assert!(tracked
+            .html
+            .contains(r#"<a href="https://exa mple.com/">B</a>"#));
+    }
+
+    #[test]
+    fn the_pixel_is_appended_to_fragments_too() {
+        let tracked =
+            add_tracking("<p>Hi</p>", "https://app.example.com").unwrap();
+
+        assert!(tracked.html.starts_with("<p>Hi</p><img "));
+        assert!(tracked.links.is_empty());
+    }
+
+    #[test]
+    fn links_past_the_last_index_are_left_alone() {
+        let html = r#"<a href="https://example.com">A</a>"#.repeat(65_536);
+
+        let tracked = add_tracking(&html, "https://app.example.com").unwrap();
+
+        assert_eq!(usize::from(u16::MAX), tracked.links.len());
+        assert!(tracked
+            .html
+            .contains(r#"<a href="https://example.com">A</a><img "#));
+    }
+
+    #[test]
+    fn every_placeholder_gets_a_token() {
+        let links = SubscriberLinks::new(
+            "https://app.example.com".to_string(),
+            Secret::new("key".to_string()),
+        );
+        let newsletter_issue_id = Uuid::new_v4();
+        let subscriber_id = Uuid::new_v4();
+
+        let substitutions =
+            substitutions(&links, newsletter_issue_id, subscriber_id, 2);
+
+        assert_eq!(3, substitutions.len());
+        assert_eq!(
+            Some((newsletter_issue_id, subscriber_id, 1)),
+            links.verify_click_token(&substitutions["[[click_token:1]]"])
+        );
+        assert_eq!(
+            Some((newsletter_issue_id, subscriber_id)),
+            links.verify_open_token(&substitutions["[[open_token]]"])
+        );
+    }
+
+    #[test]
+    fn automated_requests_are_recognized() {
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 \
+            Firefox/112.0";
+        let gmail = "Mozilla/5.0 (Windows NT 5.1; rv:11.0) Gecko Firefox/11.0 \
+            (via ggpht.com GoogleImageProxy)";
+
+        assert_eq!(None, bot_reason(Some(firefox), false));
+        assert_eq!(None, bot_reason(Some(gmail), false));
+        assert_eq!(Some("head_request"), bot_reason(Some(firefox), true));
+        assert_eq!(Some("missing_user_agent"), bot_reason(None, false));
+        assert_eq!(
+            Some("apple_mail_privacy_protection"),
+            bot_reason(Some("Mozilla/5.0"), false)
+        );
+        assert_eq!(
+            Some("bot_user_agent"),
+            bot_reason(Some("Slackbot-LinkExpanding 1.0"), false)
+        );
+        assert_eq!(
+            Some("bot_user_agent"),
+            bot_reason(Some("python-requests/2.28.2"), false)
+        );
+    }
+
+    #[test]
+    fn the_pixel_is_a_gif() {
+        assert!(PIXEL.starts_with(b"GIF89a"));
+        assert_eq!(43, PIXEL.len());
+    }
+}
@@ -0,0 +1,204 @@
+//! Events that email providers report through their webhooks: deliveries,
+//! bounces, spam complaints and emails they dropped without trying. Every
+//! provider signs its requests and describes its events in its own way, so
+//! requests are verified with [`WebhookVerifier`] and their events normalized
+//! into [`ProviderEvent`]s here.
+
+mod mailgun;
+mod postmark;
+mod sendgrid;
+
+use crate::configuration::WebhookSettings;
+use axum::http::HeaderMap;
+use chrono::{DateTime, TimeZone, Utc};
+use p256::ecdsa::VerifyingKey;
+use secrecy::Secret;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    SendGrid,
+    Postmark,
+    Mailgun,
+}
+
+impl Provider {
+    /// The provider named `name` in the webhook's URL, if there's one.
+    #[must_use]
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "sendgrid" => Some(Self::SendGrid),
+            "postmark" => Some(Self::Postmark),
+            "mailgun" => Some(Self::Mailgun),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::SendGrid => "sendgrid",
+            Self::Postmark => "postmark",
+            Self::Mailgun => "mailgun",
+        }
+    }
+
+    /// Read the events in the body of a request to the provider's webhook.
+    /// Those the app has no use for, like the provider's own open tracking,
+    /// are left out.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the body isn't what the provider sends.
+    pub fn parse_events(
+        self,
+        body: &[u8],
+    ) -> Result<Vec<ProviderEvent>, serde_json::Error> {
+        match self {
+            Self::SendGrid => sendgrid::parse(body),
+            Self::Postmark => postmark::parse(body),
+            Self::Mailgun => mailgun::parse(body),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderEvent {
+    /// The provider's ID for the event, if it gives one.
+    pub id: Option<String>,
+    pub email: String,
+    pub kind: EventKind,
+    /// The provider's explanation, such as the receiving server's reply.
+    pub reason: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Delivered,
+    /// The address doesn't exist or can't receive email.
+    HardBounce,
+    /// The receiving server refused the email for now, like when the mailbox
+    /// is full.
+    SoftBounce,
+    /// The recipient marked the email as spam.
+    Complaint,
+    /// The provider didn't try to send the email, usually because the address
+    /// is on its own suppression list.
+    Dropped,
+}
+
+impl EventKind {
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Delivered => "delivered",
+            Self::HardBounce => "hard_bounce",
+            Self::SoftBounce => "soft_bounce",
+            Self::Complaint => "complaint",
+            Self::Dropped => "dropped",
+        }
+    }
+
+    /// Whether the address shouldn't be emailed anymore.
+    #[must_use]
+    pub const fn suppresses(self) -> bool {
+        matches!(self, Self::HardBounce | Self::Complaint)
+    }
+
+    /// The kind of the `delivery_events` the event counts as in the statistics
+    /// of issues, if any.
+    #[must_use]
+    pub const fn delivery_event_kind(self) -> Option<&'static str> {
+        match self {
+            Self::Delivered => Some("delivered"),
+            Self::HardBounce | Self::SoftBounce => Some("bounced"),
+            Self::Complaint | Self::Dropped => None,
+        }
+    }
+}
+
+/// Checks that requests to the webhooks come from the providers, with the
+/// secrets from [`WebhookSettings`].
+#[derive(Debug, Clone)]
+pub struct WebhookVerifier {
+    sendgrid_key: Option<VerifyingKey>,
+    postmark_credentials: Option<(String, Secret<String>)>,
+    mailgun_signing_key: Option<Secret<String>>,
+}
+
+impl WebhookVerifier {
+    /// # Errors
+    ///
+    /// Will return an error if the SendGrid verification key is invalid.
+    pub fn new(settings: &WebhookSettings) -> anyhow::Result<Self> {
+        let sendgrid_key = settings
+            .sendgrid_verification_key
+            .as_deref()
+            .map(sendgrid::verifying_key)
+            .transpose()?;
+
+        Ok(Self {
+            sendgrid_key,
+            postmark_credentials: settings
+                .postmark_username
+                .clone()
+                .zip(settings.postmark_password.clone()),
+            mailgun_signing_key: settings.mailgun_signing_key.clone(),
+        })
+    }
+
+    /// Check the signature or the credentials of a request to the provider's
+    /// webhook. Returns the token the request is signed with, if the provider
+    /// signs one, which mustn't be accepted more than once.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the provider's webhook isn't configured or the
+    /// request doesn't come from the provider.
+    pub fn verify(
+        &self,
+        provider: Provider,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Option<String>, VerificationError> {
+        let result = match provider {
+            Provider::SendGrid => {
+                let key = self
+                    .sendgrid_key
+                    .as_ref()
+                    .ok_or(VerificationError::NotConfigured)?;
+                sendgrid::verify(key, headers, body).map(|()| None)
+            }
+            Provider::Postmark => {
+                let (username, password) = self
+                    .postmark_credentials
+                    .as_ref()
+                    .ok_or(VerificationError::NotConfigured)?;
+                postmark::verify(username, password, headers).map(|()| None)
+            }
+            Provider::Mailgun => {
+                let signing_key = self
+                    .mailgun_signing_key
+                    .as_ref()
+                    .ok_or(VerificationError::NotConfigured)?;
+                mailgun::verify(signing_key, body, Utc::now()).map(Some)
+            }
+        };
+        result.map_err(VerificationError::InvalidSignature)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum VerificationError {
+    #[error("the provider's webhook isn't configured")]
+    NotConfigured,
+    #[error("the request doesn't come from the provider")]
+    InvalidSignature(#[source] anyhow::Error),
+}
+
+/// The time of a Unix timestamp in milliseconds, or now if it's out of range.
+fn from_unix_millis(milliseconds: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(milliseconds)
+        .single()
+        .unwrap_or_else(Utc::now)
+}
@@ -0,0 +1,227 @@
+use super::{from_unix_millis, EventKind, ProviderEvent};
+use anyhow::{ensure, Context};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use sha2::Sha256;
+
+#[derive(Debug, Deserialize)]
+struct Signed {
+    signature: Signature,
+}
+
+#[derive(Debug, Deserialize)]
+struct Signature {
+    timestamp: String,
+    token: String,
+    /// Hex-encoded.
+    signature: String,
+}
+
+/// How far the signature's timestamp may be from `now`.
+const MAX_AGE: Duration = Duration::minutes(5);
+
+/// Mailgun signs the timestamp and the random token in the body with
+/// HMAC-SHA256, using the webhook signing key. The event itself isn't signed,
+/// so signatures are only accepted for a few minutes, and the token is returned
+/// for the caller to make sure it's only used once.
+pub(super) fn verify(
+    signing_key: &Secret<String>,
+    body: &[u8],
+    now: DateTime<Utc>,
+) -> anyhow::Result<String> {
+    let Signed { signature } = serde_json::from_slice(body)
+        .context("the body doesn't have a signature")?;
+
+    #[allow(clippy::expect_used)]
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(signing_key.expose_secret().as_bytes())
+            .expect("HMAC should accept keys of any length");
+    mac.update(signature.timestamp.as_bytes());
+    mac.update(signature.token.as_bytes());
+    mac.verify_slice(
+        &hex::decode(&signature.signature)
+            .context("the signature isn't valid hex")?,
+    )
+    .context("the signature doesn't match")?;
+
+    let signed_at = signature
+        .timestamp
+        .parse()
+        .ok()
+        .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single())
+        .context("the timestamp isn't valid")?;
+    ensure!(
+        (now - signed_at).abs() <= MAX_AGE,
+        "the signature is too old"
+    );
+
+    Ok(signature.token)
+}
+
+#[derive(Debug, Deserialize)]
+struct Body {
+    #[serde(rename = "event-data")]
+    event_data: EventData,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventData {
+    id: Option<String>,
+    event: String,
+    recipient: String,
+    /// In seconds, with a fractional part.
+    timestamp: f64,
+    /// Either `permanent` or `temporary`, for failures.
+    severity: Option<String>,
+    /// Why an email failed, such as `bounce` or `suppress-bounce`.
+    reason: Option<String>,
+    #[serde(rename = "delivery-status", default)]
+    delivery_status: DeliveryStatus,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DeliveryStatus {
+    message: Option<String>,
+    description: Option<String>,
+}
+
+/// Mailgun posts one event at a time, next to its signature.
+pub(super) fn parse(body: &[u8]) -> Result<Vec<ProviderEvent>, serde_json::Error> {
+    let Body { event_data: event } = serde_json::from_slice(body)?;
+
+    let kind = match (event.event.as_str(), event.severity.as_deref()) {
+        ("delivered", _) => EventKind::Delivered,
+        // Mailgun doesn't try to send to addresses on its suppression lists.
+        ("failed", Some("permanent"))
+            if event
+                .reason
+                .as_deref()
+                .is_some_and(|reason| reason.starts_with("suppress-")) =>
+        {
+            EventKind::Dropped
+        }
+        ("failed", Some("permanent")) => EventKind::HardBounce,
+        ("failed", _) => EventKind::SoftBounce,
+        ("complained", _) => EventKind::Complaint,
+        _ => return Ok(Vec::new()),
+    };
+    let reason = [
+        event.delivery_status.description,
+        event.delivery_status.message,
+    ]
+    .into_iter()
+    .flatten()
+    .find(|reason| !reason.is_empty())
+    .or(event.reason);
+
+    #[allow(clippy::cast_possible_truncation)]
+    let milliseconds = (event.timestamp * 1000.0) as i64;
+    Ok(vec![ProviderEvent {
+        id: event.id,
+        email: event.recipient,
+        kind,
+        reason,
+        occurred_at: from_unix_millis(milliseconds),
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, verify};
+    use crate::webhooks::EventKind;
+    use chrono::{Duration, TimeZone, Utc};
+    use secrecy::Secret;
+
+    fn body(signature: &str, event_data: &str) -> Vec<u8> {
+        format!(
+            r#"{{
+                "signature": {{
+                    "timestamp": "1683970000",
+                    "token": "a8ce0edb2dd8301dee6c2405235584e45aa91d1e9f979f3de0",
+                    "signature": "{signature}"
+                }},
+                "event-data": {event_data}
+            }}"#
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn signatures_are_verified() {
+        let signing_key = Secret::new("key-1234".to_string());
+        // HMAC-SHA256("key-1234", timestamp + token).
+        let signature =
+            "db1c32ae7d07aa9e36d41d936906679ee7e9a627b8acd0f0813e7dd8b244d8d4";
+        let forged =
+            "db1c32ae7d07aa9e36d41d936906679ee7e9a627b8acd0f0813e7dd8b244d8d5";
+
+        let now = Utc.timestamp_opt(1_683_970_000, 0).unwrap();
+
+        assert_eq!(
+            "a8ce0edb2dd8301dee6c2405235584e45aa91d1e9f979f3de0",
+            verify(&signing_key, &body(signature, "{}"), now).unwrap()
+        );
+        assert!(verify(&signing_key, &body(forged, "{}"), now).is_err());
+        assert!(verify(&signing_key, &body("not hex", "{}"), now).is_err());
+        assert!(verify(&signing_key, b"{}", now).is_err());
+    }
+
+    #[test]
+    fn old_signatures_are_rejected() {
+        let signing_key = Secret::new("key-1234".to_string());
+        let signature =
+            "db1c32ae7d07aa9e36d41d936906679ee7e9a627b8acd0f0813e7dd8b244d8d4";
+        let signed_at = Utc.timestamp_opt(1_683_970_000, 0).unwrap();
+        let body = body(signature, "{}");
+
+        assert!(
+            verify(&signing_key, &body, signed_at + Duration::minutes(5)).is_ok()
+        );
+        assert!(
+            verify(&signing_key, &body, signed_at + Duration::minutes(6)).is_err()
+        );
+        assert!(
+            verify(&signing_key, &body, signed_at - Duration::minutes(6)).is_err()
+        );
+    }
+
+    #[test]
+    fn events_are_normalized() {
+        let hard_bounce = body(
+            "",
+            r#"{
+                "id": "G9Bn5sl1TC6nu79C8C0bwg", "event": "failed",
+                "severity": "permanent", "reason": "bounce",
+                "recipient": "ursula@example.com", "timestamp": 1683970000.5,
+                "delivery-status": {
+                    "message": "", "description": "No such mailbox"
+                }
+            }"#,
+        );
+        let suppressed = body(
+            "",
+            r#"{
+                "event": "failed", "severity": "permanent",
+                "reason": "suppress-bounce", "recipient": "ursula@example.com",
+                "timestamp": 1683970000
+            }"#,
+        );
+        let opened = body(
+            "",
+            r#"{"event": "opened", "recipient": "ursula@example.com", "timestamp": 1}"#,
+        );
+
+        let hard_bounce = parse(&hard_bounce).unwrap();
+
+        assert_eq!(EventKind::HardBounce, hard_bounce[0].kind);
+        assert_eq!(Some("No such mailbox"), hard_bounce[0].reason.as_deref());
+        assert_eq!(
+            "2023-05-13T09:26:40.500+00:00",
+            hard_bounce[0].occurred_at.to_rfc3339()
+        );
+        assert_eq!(EventKind::Dropped, parse(&suppressed).unwrap()[0].kind);
+        assert!(parse(&opened).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+use super::{EventKind, ProviderEvent};
+use crate::extractors::basic_authentication;
+use anyhow::ensure;
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use subtle::ConstantTimeEq;
+
+/// Postmark doesn't sign its requests, but it can send the credentials that
+/// are in the webhook's URL with HTTP Basic auth.
+pub(super) fn verify(
+    username: &str,
+    password: &Secret<String>,
+    headers: &HeaderMap,
+) -> anyhow::Result<()> {
+    let credentials = basic_authentication(headers)?;
+    let matches = credentials.username.as_bytes().ct_eq(username.as_bytes())
+        & credentials
+            .password
+            .expose_secret()
+            .as_bytes()
+            .ct_eq(password.expose_secret().as_bytes());
+    ensure!(bool::from(matches), "the credentials don't match");
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Event {
+    record_type: String,
+    #[serde(rename = "ID")]
+    id: Option<i64>,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    /// The kind of bounce, such as `HardBounce`.
+    #[serde(rename = "Type")]
+    bounce_type: Option<String>,
+    /// Of bounces and spam complaints.
+    email: Option<String>,
+    /// Of deliveries.
+    recipient: Option<String>,
+    description: Option<String>,
+    details: Option<String>,
+    bounced_at: Option<String>,
+    delivered_at: Option<String>,
+}
+
+/// Postmark posts one event at a time, as a JSON object.
+pub(super) fn parse(body: &[u8]) -> Result<Vec<ProviderEvent>, serde_json::Error> {
+    let event: Event = serde_json::from_slice(body)?;
+
+    let kind = match (event.record_type.as_str(), event.bounce_type.as_deref()) {
+        ("Delivery", _) => EventKind::Delivered,
+        ("Bounce", Some("HardBounce" | "BadEmailAddress")) => {
+            EventKind::HardBounce
+        }
+        ("Bounce", Some("ManuallyDeactivated")) => EventKind::Dropped,
+        ("Bounce", _) => EventKind::SoftBounce,
+        ("SpamComplaint", _) => EventKind::Complaint,
+        _ => return Ok(Vec::new()),
+    };
+    let (Some(email), Some(occurred_at)) = (
+        event.email.or(event.recipient),
+        event.bounced_at.or(event.delivered_at),
+    ) else {
+        return Ok(Vec::new());
+    };
+    // Deliveries don't have an ID of their own, but there's only one per
+    // message.
+    let id = match (kind, event.id, event.message_id) {
+        (EventKind::Delivered, _, Some(message_id)) => {
+            Some(format!("delivery:{message_id}"))
+        }
+        (_, id, _) => id.map(|id| id.to_string()),
+    };
+
+    Ok(vec![ProviderEvent {
+        id,
+        email,
+        kind,
+        reason: event
+            .details
+            .filter(|details| !details.is_empty())
+            .or(event.description),
+        occurred_at: DateTime::parse_from_rfc3339(&occurred_at)
+            .map_or_else(|_| Utc::now(), |time| time.with_timezone(&Utc)),
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, verify};
+    use crate::webhooks::EventKind;
+    use axum::http::{header, HeaderMap};
+    use secrecy::Secret;
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, authorization.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn credentials_are_verified() {
+        let password = Secret::new("hunter2".to_string());
+
+        // "postmark:hunter2" and "postmark:hunter3".
+        assert!(verify(
+            "postmark",
+            &password,
+            &headers("Basic cG9zdG1hcms6aHVudGVyMg==")
+        )
+        .is_ok());
+        assert!(verify(
+            "postmark",
+            &password,
+            &headers("Basic cG9zdG1hcms6aHVudGVyMw==")
+        )
+        .is_err());
+        assert!(verify("postmark", &password, &HeaderMap::new()).is_err());
+    }
+
+    #[test]
+    fn events_are_normalized() {
+        let hard_bounce = br#"{
+            "RecordType": "Bounce", "ID": 4323372036854775807,
+            "Type": "HardBounce", "TypeCode": 1, "MessageID": "883953f4",
+            "Email": "ursula@example.com", "BouncedAt": "2023-05-13T16:33:54.9070259Z",
+            "Description": "The server was unable to deliver your message.",
+            "Details": "smtp;550 5.1.1 The email account does not exist."
+        }"#;
+        let delivery = br#"{
+            "RecordType": "Delivery", "MessageID": "883953f4",
+            "Recipient": "ursula@example.com", "DeliveredAt": "2023-05-13T16:33:54Z"
+        }"#;
+        let open = br#"{"RecordType": "Open", "MessageID": "883953f4"}"#;
+
+        let hard_bounce = parse(hard_bounce).unwrap();
+        let delivery = parse(delivery).unwrap();
+
+        assert_eq!(EventKind::HardBounce, hard_bounce[0].kind);
+        assert_eq!(Some("4323372036854775807"), hard_bounce[0].id.as_deref());
+        assert_eq!(
+            Some("smtp;550 5.1.1 The email account does not exist."),
+            hard_bounce[0].reason.as_deref()
+        );
+        assert_eq!(EventKind::Delivered, delivery[0].kind);
+        assert_eq!(Some("delivery:883953f4"), delivery[0].id.as_deref());
+        assert_eq!("ursula@example.com", delivery[0].email);
+        assert!(parse(open).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+use super::{from_unix_millis, EventKind, ProviderEvent};
+use anyhow::Context;
+use axum::http::HeaderMap;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use p256::{
+    ecdsa::{signature::Verifier, Signature, VerifyingKey},
+    pkcs8::DecodePublicKey,
+};
+use serde::Deserialize;
+
+const SIGNATURE_HEADER: &str = "X-Twilio-Email-Event-Webhook-Signature";
+const TIMESTAMP_HEADER: &str = "X-Twilio-Email-Event-Webhook-Timestamp";
+
+/// Read the verification key SendGrid shows in the settings of the signed
+/// Event Webhook.
+pub(super) fn verifying_key(key: &str) -> anyhow::Result<VerifyingKey> {
+    let der = STANDARD
+        .decode(key.trim())
+        .context("the SendGrid verification key isn't valid base64")?;
+    VerifyingKey::from_public_key_der(&der)
+        .context("the SendGrid verification key isn't a P-256 public key")
+}
+
+/// SendGrid signs the timestamp header followed by the body with ECDSA, and
+/// sends the base64-encoded signature in another header.
+pub(super) fn verify(
+    key: &VerifyingKey,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> anyhow::Result<()> {
+    let signature = header(headers, SIGNATURE_HEADER)?;
+    let timestamp = header(headers, TIMESTAMP_HEADER)?;
+
+    let signature = Signature::from_der(
+        &STANDARD
+            .decode(signature)
+            .context("the signature isn't valid base64")?,
+    )
+    .context("the signature isn't an ECDSA signature")?;
+    // Both `s` and its negation are valid, but only the lower one verifies.
+    let signature = signature.normalize_s().unwrap_or(signature);
+
+    key.verify(&[timestamp.as_bytes(), body].concat(), &signature)
+        .context("the signature doesn't match")
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> anyhow::Result<&'a str> {
+    headers
+        .get(name)
+        .with_context(|| format!("the '{name}' header was missing"))?
+        .to_str()
+        .with_context(|| format!("the '{name}' header was not a valid string"))
+}
+
+#[derive(Debug, Deserialize)]
+struct Event {
+    email: String,
+    /// In seconds.
+    timestamp: i64,
+    event: String,
+    sg_event_id: Option<String>,
+    /// Either `bounce` or `blocked`, for bounces.
+    #[serde(rename = "type")]
+    bounce_type: Option<String>,
+    reason: Option<String>,
+}
+
+/// SendGrid posts a JSON array of events.
+pub(super) fn parse(body: &[u8]) -> Result<Vec<ProviderEvent>, serde_json::Error> {
+    let events: Vec<Event> = serde_json::from_slice(body)?;
+
+    Ok(events
+        .into_iter()
+        .filter_map(|event| {
+            let kind = match (event.event.as_str(), event.bounce_type.as_deref()) {
+                ("delivered", _) => EventKind::Delivered,
+                // Blocked emails were refused by the receiving server, but the
+                // address may well exist.
+                ("bounce", Some("blocked")) => EventKind::SoftBounce,
+                ("bounce", _) => EventKind::HardBounce,
+                ("spamreport", _) => EventKind::Complaint,
+                ("dropped", _) => EventKind::Dropped,
+                _ => return None,
+            };
+            Some(ProviderEvent {
+                id: event.sg_event_id,
+                email: event.email,
+                kind,
+                reason: event.reason,
+                occurred_at: from_unix_millis(
+                    event.timestamp.saturating_mul(1000),
+                ),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse, verify, verifying_key, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+    };
+    use crate::webhooks::EventKind;
+    use axum::http::HeaderMap;
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use p256::{
+        ecdsa::{signature::Signer, Signature, SigningKey},
+        pkcs8::EncodePublicKey,
+    };
+
+    fn signed_headers(signing_key: &SigningKey, body: &[u8]) -> HeaderMap {
+        let timestamp = "1683970000";
+        let signature: Signature =
+            signing_key.sign(&[timestamp.as_bytes(), body].concat());
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            SIGNATURE_HEADER,
+            STANDARD.encode(signature.to_der()).parse().unwrap(),
+        );
+        headers.insert(TIMESTAMP_HEADER, timestamp.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn signatures_are_verified() {
+        let signing_key = SigningKey::from_bytes(&[7; 32].into()).unwrap();
+        let public_key = signing_key
+            .verifying_key()
+            .to_public_key_der()
+            .unwrap()
+            .into_vec();
+        let key = verifying_key(&STANDARD.encode(public_key)).unwrap();
+        let body = br#"[{"email":"ursula@example.com"}]"#;
+        let headers = signed_headers(&signing_key, body);
+
+        assert!(verify(&key, &headers, body).is_ok());
+        assert!(verify(&key, &headers, b"[]").is_err());
+        assert!(verify(&key, &HeaderMap::new(), body).is_err());
+    }
+
+    #[test]
+    fn signatures_by_another_key_are_rejected() {
+        let signing_key = SigningKey::from_bytes(&[7; 32].into()).unwrap();
+        let other_key = SigningKey::from_bytes(&[8; 32].into()).unwrap();
+        let body = b"[]";
+
+        assert!(verify(
+            signing_key.verifying_key(),
+            &signed_headers(&other_key, body),
+            body
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn events_are_normalized() {
+        let body = br#"[
+            {"email": "a@example.com", "timestamp": 1683970000, "event": "bounce",
+             "type": "bounce", "sg_event_id": "1", "reason": "550 No such user"},
+            {"email": "b@example.com", "timestamp": 1683970000, "event": "bounce",
+             "type": "blocked", "sg_event_id": "2"},
+            {"email": "c@example.com", "timestamp": 1683970000, "event": "spamreport",
+             "sg_event_id": "3"},
+            {"email": "d@example.com", "timestamp": 1683970000, "event": "open",
+             "sg_event_id": "4"}
+        ]"#;
+
+        let events = parse(body).unwrap();
+
+        assert_eq!(3, events.len());
+        assert_eq!(EventKind::HardBounce, events[0].kind);
+        assert_eq!(Some("550 No such user"), events[0].reason.as_deref());
+        assert_eq!(
+            "2023-05-13T09:26:40+00:00",
+            events[0].occurred_at.to_rfc3339()
+        );
+        assert_eq!(EventKind::SoftBounce, events[1].kind);
+        assert_eq!(EventKind::Complaint, events[2].kind);
+        assert_eq!(Some("3"), events[2].id.as_deref());
+    }
+}
@@ -0,0 +1,73 @@
+use sqlx::PgPool;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_access_the_admin_dashboard(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app.get_admin_dashboard().await;
+
+    // Assert.
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn logout_clears_session_state(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act - Part 1 - Log in.
+    app.login().await;
+
+    // Act - Part 2 - Follow the redirect.
+    let html_page = app.get_admin_dashboard_html().await;
+
+    // Assert - Part 2.
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+    // Act - Part 3 - Log out.
+    let response = app.post_logout().await;
+
+    // Assert - Part 3.
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 4 - Follow the redirect.
+    let html_page = app.get_login_html().await;
+
+    // Assert - Part 4.
+    assert!(html_page.contains("<p><i>You have successfully logged out.</i></p>"));
+
+    // Act - Part 5 - Attempt to load the admin dashboard.
+    let response = app.get_admin_dashboard().await;
+
+    // Assert - Part 5.
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn logged_in_users_can_publish_without_basic_auth(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+
+    // Act.
+    let response = app
+        .api_client
+        .post(format!("{}/newsletters", app.address))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "html": "<p>Newsletter body as HTML</p>",
+                "text": "Newsletter body as plain text",
+            }
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(202, response.status().as_u16());
+}
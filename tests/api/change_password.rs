@@ -0,0 +1,165 @@
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_see_the_change_password_form(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app.get_change_password().await;
+
+    // Assert.
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_change_your_password(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act.
+    let response = app
+        .post_change_password(&json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert.
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn new_password_fields_must_match(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+
+    // Act - Part 1 - Try to change the password.
+    let response = app
+        .post_change_password(&json!({
+            "current_password": &app.test_user.password,
+            "new_password": Uuid::new_v4().to_string(),
+            "new_password_check": Uuid::new_v4().to_string(),
+        }))
+        .await;
+
+    // Assert - Part 1.
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Act - Part 2 - Follow the redirect.
+    let html_page = app.get_change_password_html().await;
+
+    // Assert - Part 2.
+    assert!(html_page.contains(
+        "<p><strong>You entered two different new passwords - \
+        the field values must match.</strong></p>"
+    ));
+}
+
+#[sqlx::test]
+async fn current_password_must_be_valid(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Try to change the password.
+    let response = app
+        .post_change_password(&json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert - Part 1.
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Act - Part 2 - Follow the redirect.
+    let html_page = app.get_change_password_html().await;
+
+    // Assert - Part 2.
+    assert!(html_page
+        .contains("<p><strong>The current password is incorrect.</strong></p>"));
+}
+
+#[sqlx::test]
+async fn weak_new_passwords_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    let test_cases = ["short1!", "onlylettershere", "1234567890123"];
+
+    for new_password in test_cases {
+        // Act - Part 1 - Try to change the password.
+        let response = app
+            .post_change_password(&json!({
+                "current_password": &app.test_user.password,
+                "new_password": new_password,
+                "new_password_check": new_password,
+            }))
+            .await;
+
+        // Assert - Part 1.
+        assert_is_redirect_to(&response, "/admin/password");
+
+        // Act - Part 2 - Follow the redirect.
+        let html_page = app.get_change_password_html().await;
+
+        // Assert - Part 2.
+        assert!(
+            html_page.contains("The new password must be between 12 and 128"),
+            "The password `{new_password}` should have been rejected."
+        );
+    }
+}
+
+#[sqlx::test]
+async fn changing_password_works(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Log in.
+    app.login().await;
+
+    // Act - Part 2 - Change the password.
+    let response = app
+        .post_change_password(&json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert - Part 2.
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Act - Part 3 - Follow the redirect.
+    let html_page = app.get_change_password_html().await;
+
+    // Assert - Part 3.
+    assert!(html_page.contains("<p><i>Your password has been changed.</i></p>"));
+
+    // Act - Part 4 - Log out.
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 5 - Log in using the new password.
+    let response = app
+        .post_login(&json!({
+            "username": &app.test_user.username,
+            "password": &new_password,
+        }))
+        .await;
+
+    // Assert - Part 5.
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
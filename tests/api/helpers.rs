@@ -1,6 +1,30 @@
-use newsletter::{build_app, configuration, telemetry::init_subscriber};
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use newsletter::{
+    build_app,
+    configuration::{self, DeliveryWorkerSettings, SchedulerSettings},
+    issue_delivery_worker::{try_execute_batch, ExecutionOutcome},
+    issue_scheduler::try_enqueue_due_issue,
+    telemetry::init_subscriber,
+    EmailSender, SubscriberLinks,
+};
 use once_cell::sync::Lazy;
+use p256::{
+    ecdsa::{signature::Signer, Signature, SigningKey},
+    pkcs8::EncodePublicKey,
+};
+use secrecy::Secret;
+use sha2::Sha256;
 use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     if std::env::var("TEST_LOG").is_ok() {
@@ -10,8 +34,72 @@ static TRACING: Lazy<()> = Lazy::new(|| {
     }
 });
 
+/// The secrets the providers' webhooks are verified with in tests.
+const SENDGRID_SIGNING_KEY: [u8; 32] = [7; 32];
+const POSTMARK_USERNAME: &str = "postmark";
+const POSTMARK_PASSWORD: &str = "postmark-webhook-password";
+const MAILGUN_SIGNING_KEY: &str = "mailgun-webhook-signing-key";
+
 pub struct TestApp {
     pub address: String,
+    pub port: u16,
+    pub connection_pool: PgPool,
+    pub email_server: MockServer,
+    pub email_client: Arc<dyn EmailSender>,
+    pub delivery_worker: DeliveryWorkerSettings,
+    pub scheduler: SchedulerSettings,
+    /// Signed with the app's secret, but pointing at the test server.
+    pub subscriber_links: SubscriberLinks,
+    pub test_user: TestUser,
+    /// Keeps cookies between requests and doesn't follow redirects, like a
+    /// browser session whose redirects the tests inspect one by one.
+    pub api_client: reqwest::Client,
+}
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, connection_pool: &PgPool) {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        // Match the parameters used by the app.
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash)
+            VALUES ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash,
+        )
+        .execute(connection_pool)
+        .await
+        .expect("failed to store the test user");
+    }
+}
+
+/// Confirmation links embedded in the request to the email API.
+pub struct ConfirmationLinks {
+    pub html: reqwest::Url,
+    pub plain_text: reqwest::Url,
 }
 
 impl TestApp {
@@ -26,23 +114,685 @@ impl TestApp {
             .await
             .expect("sending the request should not fail")
     }
+
+    pub async fn post_list_subscriptions(
+        &self,
+        slug: &str,
+        body: String,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/lists/{slug}/subscriptions", self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn post_newsletters(
+        &self,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        let endpoint = format!("{}/newsletters", self.address);
+
+        reqwest::Client::new()
+            .post(endpoint)
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn put_template(
+        &self,
+        name: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/templates/{name}", self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn get_template(&self, name: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/templates/{name}", self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn put_list(
+        &self,
+        slug: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/lists/{slug}", self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn put_segment(
+        &self,
+        slug: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/segments/{slug}", self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn post_segment_preview(
+        &self,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/segments/preview", self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn put_topic(
+        &self,
+        slug: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/topics/{slug}", self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    /// The link to the preferences page of the subscriber with the given email.
+    pub async fn preferences_url(&self, email: &str) -> String {
+        let subscriber_id =
+            sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+                .fetch_one(&self.connection_pool)
+                .await
+                .unwrap()
+                .id;
+        self.subscriber_links.preferences_url(subscriber_id)
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.get_html("/login").await
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.get("/admin/dashboard").await
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_html("/admin/dashboard").await
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.get("/admin/password").await
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_html("/admin/password").await
+    }
+
+    pub async fn post_change_password<Body>(
+        &self,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", self.address))
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    /// Log in as the test user.
+    pub async fn login(&self) {
+        let response = self
+            .post_login(&serde_json::json!({
+                "username": &self.test_user.username,
+                "password": &self.test_user.password,
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+    }
+
+    pub async fn get_newsletter_stats(
+        &self,
+        newsletter_issue_id: &str,
+        format: &str,
+    ) -> reqwest::Response {
+        self.get(&format!(
+            "/admin/newsletters/{newsletter_issue_id}/stats?format={format}"
+        ))
+        .await
+    }
+
+    pub async fn post_subscribers_import(
+        &self,
+        csv: impl Into<reqwest::Body>,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/subscribers/import", self.address))
+            .header("Content-Type", "text/csv")
+            .body(csv)
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn get_subscribers(&self, query: &str) -> reqwest::Response {
+        self.get(&format!("/admin/subscribers?{query}")).await
+    }
+
+    pub async fn get_subscriber(&self, id: &str) -> reqwest::Response {
+        self.get(&format!("/admin/subscribers/{id}")).await
+    }
+
+    pub async fn patch_subscriber(
+        &self,
+        id: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .patch(format!("{}/admin/subscribers/{id}", self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn delete_subscriber(&self, id: &str) -> reqwest::Response {
+        self.api_client
+            .delete(format!("{}/admin/subscribers/{id}", self.address))
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn get_subscribers_export(&self, query: &str) -> reqwest::Response {
+        self.get(&format!("/admin/subscribers/export?{query}"))
+            .await
+    }
+
+    pub async fn get_suppressions(&self) -> reqwest::Response {
+        self.get("/admin/suppressions").await
+    }
+
+    pub async fn put_suppression(
+        &self,
+        email: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .put(format!("{}/admin/suppressions/{email}", self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn delete_suppression(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .delete(format!("{}/admin/suppressions/{email}", self.address))
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn post_suppressions_import(&self, csv: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/suppressions/import", self.address))
+            .header("Content-Type", "text/csv")
+            .body(csv.to_owned())
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    /// Post the body to the provider's webhook, signed like the provider would.
+    pub async fn post_webhook(
+        &self,
+        provider: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        let request = self
+            .api_client
+            .post(format!("{}/webhooks/{provider}", self.address));
+        let request = match provider {
+            "sendgrid" => {
+                let body = body.to_string();
+                let timestamp = "1683970000";
+                let signing_key =
+                    SigningKey::from_bytes(&SENDGRID_SIGNING_KEY.into()).unwrap();
+                let signature: Signature =
+                    signing_key.sign(format!("{timestamp}{body}").as_bytes());
+                request
+                    .header(
+                        "X-Twilio-Email-Event-Webhook-Signature",
+                        STANDARD.encode(signature.to_der()),
+                    )
+                    .header("X-Twilio-Email-Event-Webhook-Timestamp", timestamp)
+                    .header("Content-Type", "application/json")
+                    .body(body)
+            }
+            "postmark" => request
+                .basic_auth(POSTMARK_USERNAME, Some(POSTMARK_PASSWORD))
+                .json(body),
+            "mailgun" => request.json(&serde_json::json!({
+                "signature": mailgun_signature(
+                    chrono::Utc::now().timestamp(),
+                    &Uuid::new_v4().to_string()
+                ),
+                "event-data": body,
+            })),
+            _ => request.json(body),
+        };
+        request
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    /// Start a request authenticated with HTTP Basic auth instead of a session,
+    /// like a script would send it. Redirects aren't followed.
+    pub fn basic_auth_request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+    ) -> reqwest::RequestBuilder {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .request(method, format!("{}{path}", self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+    }
+
+    async fn get(&self, path: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}{path}", self.address))
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    async fn get_html(&self, path: &str) -> String {
+        self.get(path).await.text().await.unwrap()
+    }
+
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: &serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        let endpoint = format!("{}/newsletters", self.address);
+
+        reqwest::Client::new()
+            .post(endpoint)
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(body)
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    /// Execute delivery tasks until the queue has no due tasks left. The
+    /// in-process worker is disabled in tests so they're deterministic.
+    pub async fn put_newsletter_schedule(
+        &self,
+        newsletter_issue_id: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!(
+                "{}/newsletters/{newsletter_issue_id}/schedule",
+                self.address
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    pub async fn delete_newsletter_schedule(
+        &self,
+        newsletter_issue_id: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!(
+                "{}/newsletters/{newsletter_issue_id}/schedule",
+                self.address
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("sending the request should not fail")
+    }
+
+    /// Run the scheduler until no scheduled issue is due.
+    pub async fn enqueue_due_issues(&self) {
+        while let ExecutionOutcome::TasksCompleted =
+            try_enqueue_due_issue(&self.connection_pool, &self.scheduler)
+                .await
+                .unwrap()
+        {}
+    }
+
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_batch(
+                &self.connection_pool,
+                self.email_client.as_ref(),
+                &self.subscriber_links,
+                &self.delivery_worker,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Publish the issue and deliver it right away. Returns the ID of the issue
+    /// and the body of the last request made to the email server, which has
+    /// the SendGrid personalizations of the subscribers.
+    pub async fn publish_and_dispatch(
+        &self,
+        body: &serde_json::Value,
+    ) -> (String, serde_json::Value) {
+        self.post_newsletters(body)
+            .await
+            .error_for_status()
+            .unwrap();
+        self.dispatch_all_pending_emails().await;
+
+        let newsletter_issue_id = sqlx::query!(
+            r#"
+            SELECT newsletter_issue_id::text AS "id!"
+            FROM newsletter_issues
+            ORDER BY published_at DESC
+            LIMIT 1
+            "#
+        )
+        .fetch_one(&self.connection_pool)
+        .await
+        .unwrap()
+        .id;
+
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .expect("the issue should have been sent");
+        (
+            newsletter_issue_id,
+            serde_json::from_slice(&email_request.body).unwrap(),
+        )
+    }
+
+    /// Subscribe with the given email and return the confirmation links from the
+    /// email that was sent out.
+    pub async fn create_unconfirmed_subscriber(
+        &self,
+        email: &str,
+    ) -> ConfirmationLinks {
+        let body = serde_json::json!({ "name": "le guin", "email": email });
+        let body = serde_urlencoded::to_string(&body).unwrap();
+
+        let _mock_guard = Mock::given(path("/v3/mail/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Create unconfirmed subscriber")
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+
+        self.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+
+        self.get_confirmation_links(&email_request)
+    }
+
+    /// Subscribe with the given email and follow the confirmation link.
+    pub async fn create_confirmed_subscriber(&self, email: &str) {
+        let confirmation_links = self.create_unconfirmed_subscriber(email).await;
+
+        reqwest::get(confirmation_links.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    /// Subscribe to the list with the given email and follow the confirmation
+    /// link.
+    pub async fn create_confirmed_list_subscriber(&self, slug: &str, email: &str) {
+        let body = serde_json::json!({ "name": "le guin", "email": email });
+        let body = serde_urlencoded::to_string(&body).unwrap();
+
+        let _mock_guard = Mock::given(path("/v3/mail/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Create confirmed list subscriber")
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+
+        self.post_list_subscriptions(slug, body)
+            .await
+            .error_for_status()
+            .unwrap();
+
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        let confirmation_links = self.get_confirmation_links(&email_request);
+
+        reqwest::get(confirmation_links.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    /// Extract the confirmation links from a request intercepted by the mock
+    /// email server.
+    pub fn get_confirmation_links(
+        &self,
+        email_request: &wiremock::Request,
+    ) -> ConfirmationLinks {
+        let body: serde_json::Value =
+            serde_json::from_slice(&email_request.body).unwrap();
+
+        // The first element is the HTML body, and the second is the plain text.
+        let get_link = |index: usize| {
+            let content = body["content"][index]["value"].as_str().unwrap();
+            let links: Vec<_> = linkify::LinkFinder::new()
+                .links(content)
+                .filter(|link| *link.kind() == linkify::LinkKind::Url)
+                .collect();
+            assert_eq!(1, links.len());
+
+            let mut link = reqwest::Url::parse(links[0].as_str()).unwrap();
+            // Make sure we don't call random APIs on the web.
+            assert_eq!("127.0.0.1", link.host_str().unwrap());
+            link.set_port(Some(self.port)).unwrap();
+            link
+        };
+
+        ConfirmationLinks {
+            html: get_link(0),
+            plain_text: get_link(1),
+        }
+    }
 }
 
 pub async fn spawn_app(connection_pool: PgPool) -> TestApp {
     Lazy::force(&TRACING);
 
+    let email_server = MockServer::start().await;
+
     let mut configuration = configuration::build()
         .expect("app configuration should be present and valid");
     configuration.application.port = 0;
+    configuration.email_client.base_url = email_server.uri();
+    configuration.delivery_worker.run_in_process = false;
+    configuration.scheduler.run_in_process = false;
+    // The mocks count requests, so every failure should be reported right away.
+    // Retrying is covered by the email client's own tests.
+    configuration.email_client.retry.max_attempts = 1;
+    configuration.webhooks.sendgrid_verification_key = Some(
+        STANDARD.encode(
+            SigningKey::from_bytes(&SENDGRID_SIGNING_KEY.into())
+                .unwrap()
+                .verifying_key()
+                .to_public_key_der()
+                .unwrap(),
+        ),
+    );
+    configuration.webhooks.postmark_username = Some(POSTMARK_USERNAME.to_string());
+    configuration.webhooks.postmark_password =
+        Some(Secret::new(POSTMARK_PASSWORD.to_string()));
+    configuration.webhooks.mailgun_signing_key =
+        Some(Secret::new(MAILGUN_SIGNING_KEY.to_string()));
+
+    let email_client = configuration.email_client.client().unwrap();
+    let delivery_worker = configuration.delivery_worker.clone();
+    let scheduler = configuration.scheduler.clone();
+    let hmac_secret = configuration.application.hmac_secret.clone();
 
     let mut app = build_app(configuration).await.unwrap();
-    app.set_custom_connection_pool(connection_pool);
+    app.set_custom_connection_pool(connection_pool.clone());
 
     let port = app.port();
 
     tokio::spawn(async { app.run().await });
 
+    let test_user = TestUser::generate();
+    test_user.store(&connection_pool).await;
+
+    let api_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+
+    let address = format!("http://127.0.0.1:{port}");
+    let subscriber_links = SubscriberLinks::new(address.clone(), hmac_secret);
+
     TestApp {
-        address: format!("http://127.0.0.1:{port}"),
+        address,
+        port,
+        connection_pool,
+        email_server,
+        email_client,
+        delivery_worker,
+        scheduler,
+        subscriber_links,
+        test_user,
+        api_client,
     }
 }
+
+/// The body of a request publishing an issue with a title and both parts of
+/// its content.
+pub fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "Newsletter body as plain text",
+        }
+    })
+}
+
+/// [`newsletter_request_body`] with the given fields, such as `"list"` or
+/// `"topic"`, added or replaced.
+pub fn newsletter_request_body_with(
+    fields: serde_json::Value,
+) -> serde_json::Value {
+    let mut body = newsletter_request_body();
+    for (field, value) in fields.as_object().expect("fields should be an object") {
+        body[field] = value.clone();
+    }
+    body
+}
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(303, response.status().as_u16());
+    assert_eq!(location, response.headers()["Location"]);
+}
+
+/// The `signature` of a request to the Mailgun webhook.
+pub fn mailgun_signature(timestamp: i64, token: &str) -> serde_json::Value {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(MAILGUN_SIGNING_KEY.as_bytes()).unwrap();
+    mac.update(format!("{timestamp}{token}").as_bytes());
+    serde_json::json!({
+        "timestamp": timestamp.to_string(),
+        "token": token,
+        "signature": hex::encode(mac.finalize().into_bytes()),
+    })
+}
@@ -0,0 +1,251 @@
+use serde_json::json;
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{newsletter_request_body_with, spawn_app, TestApp};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+async fn create_lists(app: &TestApp) {
+    app.put_list("rust", &json!({ "name": "Rust Weekly" }))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.put_list("events", &json!({ "name": "Events" }))
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+fn subscription_body() -> String {
+    serde_urlencoded::to_string(json!({ "name": "le guin", "email": EMAIL }))
+        .unwrap()
+}
+
+/// Publish an issue and return the requests it made to the email server.
+async fn publish(app: &TestApp, list: Option<&str>) -> Vec<wiremock::Request> {
+    let n_requests_before =
+        app.email_server.received_requests().await.unwrap().len();
+
+    app.post_newsletters(&newsletter_request_body_with(json!({ "list": list })))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    app.email_server.received_requests().await.unwrap()[n_requests_before..]
+        .to_vec()
+}
+
+async fn list_subscription_status(app: &TestApp, list: &str) -> String {
+    sqlx::query!(
+        r#"
+        SELECT list_subscriptions.status
+        FROM list_subscriptions
+        JOIN subscriptions ON subscriptions.id = list_subscriptions.subscriber_id
+        WHERE subscriptions.email = $1 AND list_subscriptions.list_slug = $2
+        "#,
+        EMAIL,
+        list
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+#[sqlx::test]
+async fn saved_lists_are_listed_by_name(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    create_lists(&app).await;
+
+    // Assert.
+    let lists: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/lists", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        json!([
+            { "slug": "events", "name": "Events" },
+            { "slug": "rust", "name": "Rust Weekly" },
+        ]),
+        lists
+    );
+}
+
+#[sqlx::test]
+async fn subscribing_to_a_list_sends_a_confirmation_for_it(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    create_lists(&app).await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let response = app
+        .post_list_subscriptions("rust", subscription_body())
+        .await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body = String::from_utf8_lossy(&email_request.body);
+    assert!(body.contains("Welcome to Rust Weekly!"));
+    assert_eq!(
+        "pending_confirmation",
+        list_subscription_status(&app, "rust").await
+    );
+
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!("confirmed", list_subscription_status(&app, "rust").await);
+}
+
+#[sqlx::test]
+async fn one_email_can_subscribe_to_several_lists(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    create_lists(&app).await;
+
+    // Act.
+    app.create_confirmed_list_subscriber("rust", EMAIL).await;
+    app.create_confirmed_list_subscriber("events", EMAIL).await;
+
+    // Assert.
+    assert_eq!("confirmed", list_subscription_status(&app, "rust").await);
+    assert_eq!("confirmed", list_subscription_status(&app, "events").await);
+}
+
+#[sqlx::test]
+async fn subscribing_to_an_unknown_list_is_rejected_with_404(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app
+        .post_list_subscriptions("no-such-list", subscription_body())
+        .await;
+
+    // Assert.
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn issues_published_to_a_list_only_reach_its_subscribers(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    create_lists(&app).await;
+    app.create_confirmed_list_subscriber("rust", EMAIL).await;
+    app.create_confirmed_list_subscriber("events", "octavia_butler@gmail.com")
+        .await;
+    app.create_confirmed_subscriber("n_k_jemisin@gmail.com")
+        .await;
+
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(202))
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let list_requests = publish(&app, Some("rust")).await;
+    let unlisted_requests = publish(&app, None).await;
+
+    // Assert.
+    let recipients = |requests: &[wiremock::Request]| {
+        requests
+            .iter()
+            .flat_map(|request| {
+                let body: serde_json::Value =
+                    serde_json::from_slice(&request.body).unwrap();
+                body["personalizations"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|personalization| {
+                        personalization["to"][0]["email"]
+                            .as_str()
+                            .unwrap()
+                            .to_string()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vec![EMAIL.to_string()], recipients(&list_requests));
+    assert_eq!(
+        vec!["n_k_jemisin@gmail.com".to_string()],
+        recipients(&unlisted_requests)
+    );
+}
+
+#[sqlx::test]
+async fn unsubscribing_from_a_list_issue_only_leaves_that_list(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    create_lists(&app).await;
+    app.create_confirmed_list_subscriber("rust", EMAIL).await;
+    app.create_confirmed_list_subscriber("events", EMAIL).await;
+
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(202))
+        .mount(&app.email_server)
+        .await;
+    let requests = publish(&app, Some("rust")).await;
+    let body: serde_json::Value =
+        serde_json::from_slice(&requests[0].body).unwrap();
+    let unsubscribe_url = body["personalizations"][0]["headers"]
+        ["List-Unsubscribe"]
+        .as_str()
+        .unwrap()
+        .trim_matches(['<', '>'])
+        .to_string();
+
+    // Act.
+    let response = reqwest::Client::new()
+        .post(&unsubscribe_url)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    assert!(unsubscribe_url.ends_with("&list=rust"));
+    assert_eq!("unsubscribed", list_subscription_status(&app, "rust").await);
+    assert_eq!("confirmed", list_subscription_status(&app, "events").await);
+}
+
+#[sqlx::test]
+async fn publishing_to_an_unknown_list_is_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app
+        .post_newsletters(&newsletter_request_body_with(
+            json!({ "list": "no-such-list" }),
+        ))
+        .await;
+
+    // Assert.
+    assert_eq!(400, response.status().as_u16());
+}
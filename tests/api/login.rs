@@ -0,0 +1,122 @@
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[sqlx::test]
+async fn an_error_flash_message_is_set_on_failure(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act - Part 1 - Try to log in.
+    let login_body = json!({
+        "username": "random-username",
+        "password": "random-password",
+    });
+    let response = app.post_login(&login_body).await;
+
+    // Assert - Part 1.
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Follow the redirect.
+    let html_page = app.get_login_html().await;
+
+    // Assert - Part 2.
+    assert!(html_page.contains("<p><strong>Authentication failed.</strong></p>"));
+
+    // Act - Part 3 - Reload the login page.
+    let html_page = app.get_login_html().await;
+
+    // Assert - Part 3.
+    assert!(!html_page.contains("Authentication failed."));
+}
+
+#[sqlx::test]
+async fn redirect_to_admin_dashboard_after_login_success(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act - Part 1 - Log in.
+    let login_body = json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    });
+    let response = app.post_login(&login_body).await;
+
+    // Assert - Part 1.
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 2 - Follow the redirect.
+    let html_page = app.get_admin_dashboard_html().await;
+
+    // Assert - Part 2.
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[sqlx::test]
+async fn session_cookies_are_signed_and_http_only(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let login_body = json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    });
+    let response = app.post_login(&login_body).await;
+
+    // Assert.
+    let session_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == "id")
+        .expect("a session cookie should be set");
+    assert!(session_cookie.http_only());
+    // A signed cookie carries its signature in front of the session ID, so the
+    // ID alone is never accepted back.
+    let value = session_cookie.value();
+    let forged_cookie = format!("id={}", &value[value.len() - 64..]);
+    let response = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("{}/admin/dashboard", app.address))
+        .header("Cookie", forged_cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn expired_sessions_are_purged(pool: PgPool) {
+    // Arrange.
+    sqlx::query!(
+        r#"
+        INSERT INTO sessions (session_id, data, expires_at)
+        VALUES
+            ('expired', '{}', now() - interval '1 minute'),
+            ('live', '{}', now() + interval '1 day')
+        "#
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    // Act.
+    // The app purges expired sessions as soon as it starts.
+    let app = spawn_app(pool).await;
+
+    // Assert.
+    let mut session_ids = Vec::new();
+    for _ in 0..50 {
+        session_ids = sqlx::query_scalar!("SELECT session_id FROM sessions")
+            .fetch_all(&app.connection_pool)
+            .await
+            .unwrap();
+        if session_ids.len() == 1 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(vec!["live"], session_ids);
+}
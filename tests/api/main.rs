@@ -1,5 +1,24 @@
 #![allow(clippy::unwrap_used)]
 
+mod admin_dashboard;
+mod change_password;
 mod health_check;
 mod helpers;
+mod lists;
+mod login;
+mod newsletter_stats;
+mod newsletters;
+mod preferences;
+mod scheduling;
+mod segments;
+mod subscribers;
+mod subscribers_export;
+mod subscribers_import;
 mod subscriptions;
+mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod suppressions;
+mod templates;
+mod topics;
+mod tracking;
+mod webhooks;
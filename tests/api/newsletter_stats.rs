@@ -0,0 +1,213 @@
+use reqwest::Method;
+use serde_json::json;
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{
+    assert_is_redirect_to, newsletter_request_body_with, spawn_app, TestApp,
+};
+
+const FIREFOX: &str =
+    "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/112.0";
+
+/// Send an issue with one link to the given confirmed subscribers, and return
+/// its ID and the personalizations of the request made to SendGrid.
+async fn send_issue(
+    app: &TestApp,
+    emails: &[&str],
+) -> (String, Vec<serde_json::Value>) {
+    for email in emails {
+        app.create_confirmed_subscriber(email).await;
+    }
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(202))
+        .mount(&app.email_server)
+        .await;
+
+    let (newsletter_issue_id, mut body) = app
+        .publish_and_dispatch(&newsletter_request_body_with(json!({
+            "content": {
+                "html": r#"<p>Read <a href="https://example.com/post">the post</a>.</p>"#,
+            },
+        })))
+        .await;
+    let personalizations =
+        serde_json::from_value(body["personalizations"].take()).unwrap();
+
+    (newsletter_issue_id, personalizations)
+}
+
+/// The personalization of the email sent to `email`.
+fn personalization<'a>(
+    personalizations: &'a [serde_json::Value],
+    email: &str,
+) -> &'a serde_json::Value {
+    personalizations
+        .iter()
+        .find(|personalization| personalization["to"][0]["email"] == email)
+        .unwrap()
+}
+
+async fn get(url: &str, user_agent: &str) {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(url)
+        .header("User-Agent", user_agent)
+        .send()
+        .await
+        .unwrap();
+}
+
+#[sqlx::test]
+async fn stats_count_subscribers_who_engaged(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let (newsletter_issue_id, personalizations) = send_issue(
+        &app,
+        &["ursula_le_guin@gmail.com", "octavia_butler@gmail.com"],
+    )
+    .await;
+    let ursula = personalization(&personalizations, "ursula_le_guin@gmail.com");
+    let octavia = personalization(&personalizations, "octavia_butler@gmail.com");
+    let click_url = format!(
+        "{}/t/c/{}",
+        app.address,
+        ursula["substitutions"]["[[click_token:0]]"]
+            .as_str()
+            .unwrap()
+    );
+    let open_url = |personalization: &serde_json::Value| {
+        format!(
+            "{}/t/o/{}.gif",
+            app.address,
+            personalization["substitutions"]["[[open_token]]"]
+                .as_str()
+                .unwrap()
+        )
+    };
+
+    // Ursula clicks twice without loading images, and Apple Mail opens the
+    // issue for her.
+    get(&click_url, FIREFOX).await;
+    get(&click_url, FIREFOX).await;
+    get(&open_url(ursula), "Mozilla/5.0").await;
+    // Octavia opens the issue, then unsubscribes.
+    get(&open_url(octavia), FIREFOX).await;
+    let unsubscribe_url = octavia["headers"]["List-Unsubscribe"]
+        .as_str()
+        .unwrap()
+        .trim_matches(['<', '>']);
+    reqwest::Client::new()
+        .post(unsubscribe_url)
+        .send()
+        .await
+        .unwrap();
+
+    app.login().await;
+
+    // Act.
+    let response = app.get_newsletter_stats(&newsletter_issue_id, "json").await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let stats: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "title": "Newsletter title",
+            "sent": 2,
+            "delivered": 0,
+            "bounced": 0,
+            "failed": 0,
+            "unique_opens": 2,
+            "unique_clicks": 1,
+            "unsubscribes": 1,
+            "top_links": [{
+                "url": "https://example.com/post",
+                "unique_clicks": 1,
+                "total_clicks": 2,
+            }],
+        }),
+        stats
+    );
+}
+
+#[sqlx::test]
+async fn stats_can_be_downloaded_as_csv(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let (newsletter_issue_id, _) =
+        send_issue(&app, &["ursula_le_guin@gmail.com"]).await;
+    app.login().await;
+
+    // Act.
+    let response = app.get_newsletter_stats(&newsletter_issue_id, "csv").await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "text/csv; charset=utf-8",
+        response.headers()["Content-Type"]
+    );
+    let csv = response.text().await.unwrap();
+    assert!(csv.starts_with("metric,url,value\nsent,,1\n"));
+}
+
+#[sqlx::test]
+async fn stats_of_unknown_issues_are_not_found(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+
+    // Act.
+    let response = app
+        .get_newsletter_stats("6f1c8e5e-1b8a-4c1e-9d0b-2a7e4f3c9b10", "json")
+        .await;
+
+    // Assert.
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_see_stats(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app
+        .get_newsletter_stats("6f1c8e5e-1b8a-4c1e-9d0b-2a7e4f3c9b10", "json")
+        .await;
+
+    // Assert.
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn stats_can_be_read_with_basic_auth(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let (newsletter_issue_id, _) =
+        send_issue(&app, &["ursula_le_guin@gmail.com"]).await;
+
+    // Act.
+    let response = app
+        .basic_auth_request(
+            Method::GET,
+            &format!("/admin/newsletters/{newsletter_issue_id}/stats?format=json"),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let stats: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(1, stats["sent"]);
+}
@@ -0,0 +1,863 @@
+use newsletter::{
+    configuration::{self, EmailProvider},
+    idempotency::purge_expired_keys,
+};
+use serde_json::json;
+use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, body_string_contains, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{
+    newsletter_request_body, newsletter_request_body_with, spawn_app,
+};
+
+#[sqlx::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_unconfirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let response = app.post_newsletters(&newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    assert_eq!(202, response.status().as_u16());
+    // Mock verifies on drop that we haven't sent the newsletter email.
+}
+
+#[sqlx::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let response = app.post_newsletters(&newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    assert_eq!(202, response.status().as_u16());
+    // Mock verifies on drop that we've sent the newsletter email.
+}
+
+#[sqlx::test]
+async fn subscribers_are_sent_the_issue_in_a_single_batch(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool.clone()).await;
+    let subscribers = [
+        "ursula_le_guin@gmail.com",
+        "terry_pratchett@gmail.com",
+        "octavia_butler@gmail.com",
+    ];
+    for subscriber in subscribers {
+        app.create_confirmed_subscriber(subscriber).await;
+    }
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .and(body_string_contains(subscribers[0]))
+        .and(body_string_contains(subscribers[1]))
+        .and(body_string_contains(subscribers[2]))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    app.post_newsletters(&newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    let remaining_tasks =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(0, remaining_tasks);
+    // Mock verifies on drop that all subscribers were sent a single request.
+}
+
+#[sqlx::test]
+async fn failing_to_deliver_to_one_subscriber_does_not_abort_the_batch(
+    pool: PgPool,
+) {
+    // Arrange.
+    let mut app = spawn_app(pool).await;
+    // Send one email per batch, so that each subscriber gets their own request.
+    app.delivery_worker.batch_size = 1;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    app.create_confirmed_subscriber("terry_pratchett@gmail.com")
+        .await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(body_string_contains("ursula_le_guin@gmail.com"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/v3/mail/send"))
+        .and(body_string_contains("terry_pratchett@gmail.com"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let response = app.post_newsletters(&newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    assert_eq!(202, response.status().as_u16());
+    // Mocks verify on drop that both emails were attempted.
+}
+
+#[sqlx::test]
+async fn transient_failures_are_retried_later(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool.clone()).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    app.post_newsletters(&newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    let task = sqlx::query!(
+        r#"
+        SELECT n_retries, execute_after > now() AS "postponed!"
+        FROM issue_delivery_queue
+        "#
+    )
+    .fetch_one(&pool)
+    .await
+    .expect("the task should still be queued");
+
+    assert_eq!(1, task.n_retries);
+    assert!(task.postponed);
+}
+
+#[sqlx::test]
+async fn an_issue_that_fails_to_load_does_not_hold_back_the_others(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool.clone()).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(body_string_contains("Working issue"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    for title in ["Broken issue", "Working issue"] {
+        let mut body = newsletter_request_body();
+        body["title"] = json!(title);
+        app.post_newsletters(&body)
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    // More tracked links than the worker can load.
+    sqlx::query!(
+        r#"
+        INSERT INTO tracked_links (newsletter_issue_id, link_index, url)
+        SELECT newsletter_issue_id, link_index, 'https://example.com'
+        FROM newsletter_issues, generate_series(0, 65535) AS link_index
+        WHERE title = 'Broken issue'
+        "#
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    // Act.
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    let task = sqlx::query!(
+        r#"
+        SELECT n_retries, execute_after > now() AS "postponed!"
+        FROM issue_delivery_queue
+        "#
+    )
+    .fetch_one(&pool)
+    .await
+    .expect("the task of the broken issue should still be queued");
+
+    assert_eq!(1, task.n_retries);
+    assert!(task.postponed);
+    // Mock verifies on drop that we've sent the working issue.
+}
+
+#[sqlx::test]
+async fn permanent_failures_are_not_retried(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool.clone()).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(400))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    app.post_newsletters(&newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    let remaining =
+        sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    let failure = sqlx::query!(
+        "SELECT subscriber_email, error FROM delivery_events WHERE kind = 'failed'"
+    )
+    .fetch_one(&pool)
+    .await
+    .expect("the failure should be recorded");
+
+    assert_eq!(0, remaining.count);
+    assert_eq!("ursula_le_guin@gmail.com", failure.subscriber_email);
+    assert!(failure.error.is_some());
+}
+
+#[sqlx::test]
+async fn deliveries_are_given_up_on_after_too_many_retries(pool: PgPool) {
+    // Arrange.
+    let mut app = spawn_app(pool.clone()).await;
+    app.delivery_worker.max_retries = 0;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    app.post_newsletters(&newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    let remaining =
+        sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    let n_failed = sqlx::query!(
+        r#"SELECT count(*) AS "count!" FROM delivery_events WHERE kind = 'failed'"#
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap()
+    .count;
+
+    assert_eq!(0, remaining.count);
+    assert_eq!(1, n_failed);
+}
+
+#[sqlx::test]
+async fn subscribers_whose_message_was_accepted_are_counted_as_sent(pool: PgPool) {
+    // Arrange.
+    let mut app = spawn_app(pool.clone()).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    app.create_confirmed_subscriber("octavia_butler@gmail.com")
+        .await;
+    // Postmark reports the messages of a batch one by one.
+    let mut settings = configuration::build().unwrap().email_client;
+    settings.provider = EmailProvider::Postmark;
+    settings.base_url = app.email_server.uri();
+    settings.retry.max_attempts = 1;
+    app.email_client = settings.client().unwrap();
+
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            { "ErrorCode": 0, "Message": "OK" },
+            { "ErrorCode": 406, "Message": "Inactive recipient" }
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    app.post_newsletters(&newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    let n_sent = sqlx::query!(
+        r#"SELECT count(*) AS "count!" FROM delivery_events WHERE kind = 'sent'"#
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap()
+    .count;
+    let failed = sqlx::query!(
+        "SELECT subscriber_email FROM delivery_events WHERE kind = 'failed'"
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    let remaining =
+        sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .count;
+
+    assert_eq!(1, n_sent);
+    assert_eq!(1, failed.len());
+    assert_eq!("octavia_butler@gmail.com", failed[0].subscriber_email);
+    assert_eq!(0, remaining);
+}
+
+#[sqlx::test]
+async fn newsletters_returns_422_for_invalid_data(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let test_cases = vec![
+        (
+            json!({
+                "content": {
+                    "html": "<p>Newsletter body as HTML</p>",
+                    "text": "Newsletter body as plain text",
+                }
+            }),
+            "missing title",
+        ),
+        (json!({ "title": "Newsletter!" }), "missing content"),
+        (
+            json!({
+                "title": "",
+                "content": {
+                    "html": "<p>Newsletter body as HTML</p>",
+                    "text": "Newsletter body as plain text",
+                }
+            }),
+            "empty title",
+        ),
+        (
+            json!({
+                "title": "Newsletter!",
+                "content": { "html": "<p>Newsletter body as HTML</p>", "text": "" }
+            }),
+            "empty text body",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        // Act.
+        let response = app.post_newsletters(&invalid_body).await;
+
+        // Assert.
+        assert_eq!(
+            422,
+            response.status().as_u16(),
+            "The API did not fail with `422: Unprocessable Entity` when the \
+            payload was: {error_message}"
+        );
+    }
+}
+
+#[sqlx::test]
+async fn requests_missing_authorization_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[sqlx::test]
+async fn non_existing_user_is_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(Uuid::new_v4().to_string(), Some(Uuid::new_v4().to_string()))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[sqlx::test]
+async fn invalid_password_is_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let username = &app.test_user.username;
+    // Random password.
+    let password = Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
+
+    // Act.
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[sqlx::test]
+async fn newsletter_creation_is_idempotent(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Publish the newsletter.
+    let response = app
+        .post_newsletters_with_idempotency_key(
+            &newsletter_request_body(),
+            &idempotency_key,
+        )
+        .await;
+
+    // Assert - Part 1.
+    assert_eq!(202, response.status().as_u16());
+
+    // Act - Part 2 - Publish it again.
+    let response = app
+        .post_newsletters_with_idempotency_key(
+            &newsletter_request_body(),
+            &idempotency_key,
+        )
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - Part 2.
+    assert_eq!(202, response.status().as_u16());
+    // Mock verifies on drop that we have sent the newsletter email once.
+}
+
+#[sqlx::test]
+async fn concurrent_duplicate_requests_are_handled_gracefully(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let body = newsletter_request_body();
+    let (response1, response2) = tokio::join!(
+        app.post_newsletters_with_idempotency_key(&body, &idempotency_key),
+        app.post_newsletters_with_idempotency_key(&body, &idempotency_key),
+    );
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    assert_eq!(202, response1.status().as_u16());
+    assert_eq!(202, response2.status().as_u16());
+    // Mock verifies on drop that we have sent the newsletter email once.
+}
+
+#[sqlx::test]
+async fn the_idempotency_key_can_be_sent_in_the_body(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    let mut body = newsletter_request_body();
+    body["idempotency_key"] = json!(Uuid::new_v4().to_string());
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let response1 = app.post_newsletters(&body).await;
+    let response2 = app.post_newsletters(&body).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    assert_eq!(202, response1.status().as_u16());
+    assert_eq!(202, response2.status().as_u16());
+    // Mock verifies on drop that we have sent the newsletter email once.
+}
+
+#[sqlx::test]
+async fn invalid_idempotency_keys_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let test_cases = [("", "empty key"), (&*"a".repeat(51), "51-character key")];
+
+    for (idempotency_key, description) in test_cases {
+        // Act.
+        let response = app
+            .post_newsletters_with_idempotency_key(
+                &newsletter_request_body(),
+                idempotency_key,
+            )
+            .await;
+
+        // Assert.
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had an \
+            {description}."
+        );
+    }
+}
+
+#[sqlx::test]
+async fn expired_idempotency_keys_can_be_reused(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    app.post_newsletters_with_idempotency_key(
+        &newsletter_request_body(),
+        &idempotency_key,
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    purge_expired_keys(&app.connection_pool, Duration::ZERO)
+        .await
+        .unwrap();
+    app.post_newsletters_with_idempotency_key(
+        &newsletter_request_body(),
+        &idempotency_key,
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    // Mock verifies on drop that we have sent the newsletter email twice.
+}
+
+#[sqlx::test]
+async fn newsletters_can_be_rendered_from_a_template(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    app.put_template(
+        "weekly",
+        &json!({ "html": "<p>Hi, {{name}}!</p>", "text": "Hi, {{name}}!" }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let response = app
+        .post_newsletters(&json!({ "title": "Issue #1", "template": "weekly" }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    assert_eq!(202, response.status().as_u16());
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    let html = body["content"][0]["value"].as_str().unwrap();
+    let text = body["content"][1]["value"].as_str().unwrap();
+    assert!(html.contains("<title>Issue #1</title>"));
+    assert!(html.contains("<p>Hi, [[name:html]]!</p>"));
+    assert!(text.contains("Hi, [[name]]!"));
+    assert!(text.contains("Unsubscribe: [[unsubscribe_url]]"));
+
+    // SendGrid fills in the placeholders for every recipient.
+    let substitutions = &body["personalizations"][0]["substitutions"];
+    assert_eq!("le guin", substitutions["[[name]]"]);
+    assert_eq!("le guin", substitutions["[[name:html]]"]);
+    assert!(substitutions["[[unsubscribe_url]]"]
+        .as_str()
+        .unwrap()
+        .starts_with(&format!(
+            "{}/subscriptions/unsubscribe?token=",
+            app.address
+        )));
+}
+
+#[sqlx::test]
+async fn publishing_with_an_unknown_template_is_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app
+        .post_newsletters(&json!({ "title": "Issue #1", "template": "missing" }))
+        .await;
+
+    // Assert.
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn publishing_with_both_content_and_a_template_is_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let mut body = newsletter_request_body();
+    body["template"] = json!("weekly");
+
+    // Act.
+    let response = app.post_newsletters(&body).await;
+
+    // Assert.
+    assert_eq!(422, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn html_newsletters_are_prepared_for_mail_clients(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": {
+                "html": "<style>h1 { color: red }</style>\
+                    <h1>News</h1><p>Read <a href=\"https://example.com\">this</a>.</p>",
+            },
+            "tracking": false,
+        }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    assert_eq!(202, response.status().as_u16());
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    let html = body["content"][0]["value"].as_str().unwrap();
+    let text = body["content"][1]["value"].as_str().unwrap();
+    assert_eq!(
+        "<h1 style=\"color: red\">News</h1>\
+        <p>Read <a href=\"https://example.com\">this</a>.</p>",
+        html
+    );
+    assert_eq!(
+        "News\n====\n\nRead this [1].\n\n[1] https://example.com",
+        text
+    );
+}
+
+#[sqlx::test]
+async fn newsletters_that_would_be_clipped_by_gmail_get_a_warning(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let paragraph = format!("<p>{}</p>", "a".repeat(1024));
+
+    // Act.
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": {
+                "html": paragraph.repeat(110),
+                "text": "Newsletter body as plain text",
+            }
+        }))
+        .await;
+
+    // Assert.
+    assert_eq!(202, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let warnings = body["warnings"].as_array().unwrap();
+    assert_eq!(1, warnings.len());
+    assert!(warnings[0].as_str().unwrap().contains("clip"));
+}
+
+#[sqlx::test]
+async fn the_size_warning_accounts_for_tracking(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    // Tracking makes every link longer, pushing the HTML over the limit.
+    let links: String = (0..1000)
+        .map(|i| format!(r#"<a href="https://example.com/{i}">{i}</a>"#))
+        .collect();
+    let padding = format!("<p>{}</p>", "a".repeat(100 * 1024 - links.len()));
+
+    // Act.
+    let response = app
+        .post_newsletters(&newsletter_request_body_with(json!({
+            "content": { "html": links + &padding },
+        })))
+        .await;
+
+    // Assert.
+    assert_eq!(202, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["warnings"][0].as_str().unwrap().contains("clip"));
+}
+
+#[sqlx::test]
+async fn tasks_being_sent_are_not_picked_up_by_another_worker(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(202).set_delay(Duration::from_millis(500)),
+        )
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(&newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act.
+    tokio::join!(
+        app.dispatch_all_pending_emails(),
+        app.dispatch_all_pending_emails()
+    );
+
+    // Assert.
+    // Mock verifies on Drop that the issue was sent once.
+    let n_tasks =
+        sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM issue_delivery_queue"#)
+            .fetch_one(&app.connection_pool)
+            .await
+            .unwrap()
+            .n;
+    assert_eq!(0, n_tasks);
+}
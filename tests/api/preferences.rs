@@ -0,0 +1,248 @@
+use serde_json::json;
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{
+    assert_is_redirect_to, newsletter_request_body_with, spawn_app, TestApp,
+};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+/// Create a confirmed subscriber, the `rust` and `events` topics, and return
+/// the subscriber's preferences URL.
+async fn set_up(app: &TestApp) -> String {
+    app.create_confirmed_subscriber(EMAIL).await;
+    app.put_topic("rust", &json!({ "name": "Rust" }))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.put_topic("events", &json!({ "name": "Events" }))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.preferences_url(EMAIL).await
+}
+
+async fn post_preferences(
+    app: &TestApp,
+    url: &str,
+    form: &[(&str, &str)],
+) -> reqwest::Response {
+    app.api_client.post(url).form(form).send().await.unwrap()
+}
+
+async fn get_preferences_html(app: &TestApp, url: &str) -> String {
+    app.api_client
+        .get(url)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+#[sqlx::test]
+async fn the_preferences_page_shows_the_current_preferences(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let url = set_up(&app).await;
+
+    // Act.
+    let html = get_preferences_html(&app, &url).await;
+
+    // Assert.
+    assert!(html.contains(EMAIL));
+    assert!(html.contains(r#"<input type="text" name="name" value="le guin">"#));
+    assert!(html
+        .contains(r#"<input type="checkbox" name="topic" value="rust" checked>"#));
+    assert!(html.contains(
+        r#"<input type="checkbox" name="topic" value="events" checked>"#
+    ));
+    assert!(html.contains(r#"<option value="immediately" selected>"#));
+}
+
+#[sqlx::test]
+async fn saving_preferences_updates_them(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let url = set_up(&app).await;
+    let path = url.trim_start_matches(&app.address);
+
+    // Act.
+    let response = post_preferences(
+        &app,
+        &url,
+        &[
+            ("name", "Ursula"),
+            ("topic", "rust"),
+            ("delivery_window", "weekly"),
+        ],
+    )
+    .await;
+
+    // Assert.
+    assert_is_redirect_to(&response, path);
+    let html = get_preferences_html(&app, &url).await;
+    assert!(html.contains("<p><i>Your preferences have been saved.</i></p>"));
+    assert!(html.contains(r#"<input type="text" name="name" value="Ursula">"#));
+    assert!(html
+        .contains(r#"<input type="checkbox" name="topic" value="rust" checked>"#));
+    assert!(
+        html.contains(r#"<input type="checkbox" name="topic" value="events">"#)
+    );
+    assert!(html.contains(r#"<option value="weekly" selected>"#));
+}
+
+#[sqlx::test]
+async fn invalid_preferences_are_not_saved(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let url = set_up(&app).await;
+    let test_cases = [
+        (
+            [("name", "<script>"), ("delivery_window", "daily")],
+            "Please enter a valid name.",
+        ),
+        (
+            [("name", "Ursula"), ("delivery_window", "hourly")],
+            "Please pick when you want to get issues.",
+        ),
+    ];
+
+    for (form, message) in test_cases {
+        // Act.
+        post_preferences(&app, &url, &form).await;
+
+        // Assert.
+        let html = get_preferences_html(&app, &url).await;
+        assert!(html.contains(&format!("<p><strong>{message}</strong></p>")));
+        assert!(html.contains(r#"value="le guin""#));
+        assert!(html.contains(r#"<option value="immediately" selected>"#));
+    }
+}
+
+#[sqlx::test]
+async fn invalid_tokens_are_rejected_with_401(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let url = format!("{}/preferences?token=forged", app.address);
+
+    // Act.
+    let get_response = reqwest::get(&url).await.unwrap();
+    let post_response = reqwest::Client::new()
+        .post(&url)
+        .form(&[("name", "Ursula"), ("delivery_window", "daily")])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(401, get_response.status().as_u16());
+    assert_eq!(401, post_response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn subscribers_who_opted_out_of_a_topic_do_not_receive_it(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let url = set_up(&app).await;
+    post_preferences(
+        &app,
+        &url,
+        &[
+            ("name", "le guin"),
+            ("topic", "rust"),
+            ("delivery_window", "immediately"),
+        ],
+    )
+    .await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    app.post_newsletters(&newsletter_request_body_with(
+        json!({ "topic": "events" }),
+    ))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.post_newsletters(&newsletter_request_body_with(
+        json!({ "topic": "rust" }),
+    ))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    // Mock verifies on Drop that only the `rust` issue was sent.
+}
+
+#[sqlx::test]
+async fn issues_are_held_back_until_the_delivery_window(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let url = set_up(&app).await;
+    post_preferences(
+        &app,
+        &url,
+        &[
+            ("name", "le guin"),
+            ("topic", "rust"),
+            ("delivery_window", "daily"),
+        ],
+    )
+    .await;
+
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    app.post_newsletters(&newsletter_request_body_with(
+        json!({ "topic": "rust" }),
+    ))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    let record = sqlx::query!(
+        r#"
+        SELECT execute_after > now() AS "deferred!"
+        FROM issue_delivery_queue
+        "#
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap();
+    assert!(record.deferred);
+}
+
+#[sqlx::test]
+async fn publishing_to_an_unknown_topic_is_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app
+        .post_newsletters(&newsletter_request_body_with(
+            json!({ "topic": "no-such-topic" }),
+        ))
+        .await;
+
+    // Assert.
+    assert_eq!(400, response.status().as_u16());
+}
@@ -0,0 +1,376 @@
+use serde_json::json;
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{newsletter_request_body_with, spawn_app, TestApp};
+
+fn scheduled_newsletter_request_body() -> serde_json::Value {
+    newsletter_request_body_with(json!({
+        "scheduled_at": "2099-05-12T09:00:00+02:00",
+    }))
+}
+
+/// Schedule the issue and return its ID.
+async fn schedule_newsletter(app: &TestApp, body: &serde_json::Value) -> String {
+    let response = app.post_newsletters(body).await;
+    assert_eq!(202, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+/// Pretend that the scheduled time has come.
+async fn make_due(app: &TestApp, newsletter_issue_id: &str) {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_at = now() - interval '1 minute'
+        WHERE newsletter_issue_id = $1::text::uuid
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+}
+
+#[sqlx::test]
+async fn scheduled_issues_are_not_sent_before_they_are_due(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let response = app
+        .post_newsletters(&scheduled_newsletter_request_body())
+        .await;
+    app.enqueue_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    assert_eq!(202, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("2099-05-12T07:00:00+00:00", body["scheduled_at"]);
+    // Mock verifies on drop that we haven't sent the newsletter email.
+}
+
+#[sqlx::test]
+async fn due_issues_are_sent_exactly_once(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    let newsletter_issue_id =
+        schedule_newsletter(&app, &scheduled_newsletter_request_body()).await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    make_due(&app, &newsletter_issue_id).await;
+    app.enqueue_due_issues().await;
+    app.enqueue_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    // Mock verifies on drop that we've sent the newsletter email once.
+}
+
+#[sqlx::test]
+async fn issues_that_fail_to_be_queued_do_not_hold_back_the_others(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    let broken_issue_id =
+        schedule_newsletter(&app, &scheduled_newsletter_request_body()).await;
+    let newsletter_issue_id =
+        schedule_newsletter(&app, &scheduled_newsletter_request_body()).await;
+    make_due(&app, &newsletter_issue_id).await;
+    // Due before the other issue, and impossible to queue up.
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_at = now() - interval '1 hour', segment_filter = '(('
+        WHERE newsletter_issue_id = $1::text::uuid
+        "#,
+        broken_issue_id
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    app.enqueue_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    let record = sqlx::query!(
+        r#"
+        SELECT
+            enqueued_at,
+            n_enqueue_failures,
+            enqueue_error,
+            enqueue_retry_after AS "enqueue_retry_after!"
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1::text::uuid
+        "#,
+        broken_issue_id
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(None, record.enqueued_at);
+    assert_eq!(1, record.n_enqueue_failures);
+    assert!(record.enqueue_error.unwrap().contains("segment filter"));
+    assert!(record.enqueue_retry_after > chrono::Utc::now());
+    // Mock verifies on drop that we've sent the other issue.
+}
+
+#[sqlx::test]
+async fn cancelled_issues_are_not_sent(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    let newsletter_issue_id =
+        schedule_newsletter(&app, &scheduled_newsletter_request_body()).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let response = app.delete_newsletter_schedule(&newsletter_issue_id).await;
+    make_due(&app, &newsletter_issue_id).await;
+    app.enqueue_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    assert_eq!(204, response.status().as_u16());
+    // Mock verifies on drop that we haven't sent the newsletter email.
+}
+
+#[sqlx::test]
+async fn rescheduling_moves_the_issue_and_undoes_cancelling_it(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let newsletter_issue_id =
+        schedule_newsletter(&app, &scheduled_newsletter_request_body()).await;
+    app.delete_newsletter_schedule(&newsletter_issue_id).await;
+
+    // Act.
+    let response = app
+        .put_newsletter_schedule(
+            &newsletter_issue_id,
+            &json!({
+                "scheduled_at": "2099-01-10T09:00",
+                "time_zone": "America/New_York",
+            }),
+        )
+        .await;
+
+    // Assert.
+    assert_eq!(204, response.status().as_u16());
+    let record = sqlx::query!(
+        r#"
+        SELECT scheduled_at AS "scheduled_at!", cancelled_at
+        FROM newsletter_issues
+        "#
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        "2099-01-10T14:00:00+00:00",
+        record.scheduled_at.to_rfc3339()
+    );
+    assert_eq!(None, record.cancelled_at);
+}
+
+#[sqlx::test]
+async fn local_times_are_scheduled_in_the_given_time_zone(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let mut body = scheduled_newsletter_request_body();
+    body["scheduled_at"] = json!("2099-07-14T09:00");
+    body["time_zone"] = json!("Europe/Paris");
+
+    // Act.
+    let response = app.post_newsletters(&body).await;
+
+    // Assert.
+    assert_eq!(202, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("2099-07-14T07:00:00+00:00", body["scheduled_at"]);
+}
+
+#[sqlx::test]
+async fn invalid_schedules_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let test_cases = [
+        (json!("next tuesday"), None, "a time that isn't RFC 3339"),
+        (json!("2020-05-12T09:00:00Z"), None, "a time in the past"),
+        (
+            json!("2099-05-12T09:00"),
+            Some("Europe/Atlantis"),
+            "an unknown time zone",
+        ),
+        (
+            json!("2099-03-29T02:30"),
+            Some("Europe/Paris"),
+            "a local time that clocks skip",
+        ),
+    ];
+
+    for (scheduled_at, time_zone, description) in test_cases {
+        let mut body = scheduled_newsletter_request_body();
+        body["scheduled_at"] = scheduled_at;
+        body["time_zone"] = json!(time_zone);
+
+        // Act.
+        let response = app.post_newsletters(&body).await;
+
+        // Assert.
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when given {description}."
+        );
+    }
+}
+
+#[sqlx::test]
+async fn issues_that_were_sent_cannot_be_rescheduled_or_cancelled(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let newsletter_issue_id =
+        schedule_newsletter(&app, &scheduled_newsletter_request_body()).await;
+    make_due(&app, &newsletter_issue_id).await;
+    app.enqueue_due_issues().await;
+
+    // Act.
+    let reschedule_response = app
+        .put_newsletter_schedule(
+            &newsletter_issue_id,
+            &json!({ "scheduled_at": "2099-05-12T09:00:00Z" }),
+        )
+        .await;
+    let cancel_response =
+        app.delete_newsletter_schedule(&newsletter_issue_id).await;
+
+    // Assert.
+    assert_eq!(409, reschedule_response.status().as_u16());
+    assert_eq!(409, cancel_response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn unknown_issues_cannot_be_cancelled(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app
+        .delete_newsletter_schedule("6f1c8e5e-1b8a-4c1e-9d0b-2a7e4f3c9b10")
+        .await;
+
+    // Assert.
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn local_time_deliveries_are_due_at_that_time_for_each_subscriber(
+    pool: PgPool,
+) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    app.create_confirmed_subscriber("octavia_butler@gmail.com")
+        .await;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET time_zone = 'America/New_York'
+        WHERE email = 'octavia_butler@gmail.com'
+        "#
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+    let mut body = scheduled_newsletter_request_body();
+    body["scheduled_at"] = json!(null);
+    body["send_at_local_time"] = json!("2099-01-10T09:00");
+    // For subscribers who didn't give theirs.
+    body["time_zone"] = json!("Europe/Paris");
+
+    // Act.
+    let response = app.post_newsletters(&body).await;
+    let response_body: serde_json::Value = response.json().await.unwrap();
+    let newsletter_issue_id =
+        response_body["newsletter_issue_id"].as_str().unwrap();
+    make_due(&app, newsletter_issue_id).await;
+    app.enqueue_due_issues().await;
+
+    // Assert.
+    // It's first 09:00 in Kiribati, 14 hours ahead of UTC.
+    assert_eq!("2099-01-09T19:00:00+00:00", response_body["scheduled_at"]);
+    let tasks = sqlx::query!(
+        r#"
+        SELECT subscriber_email, execute_after
+        FROM issue_delivery_queue
+        ORDER BY subscriber_email
+        "#
+    )
+    .fetch_all(&app.connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(2, tasks.len());
+    assert_eq!("octavia_butler@gmail.com", tasks[0].subscriber_email);
+    assert_eq!(
+        "2099-01-10T14:00:00+00:00",
+        tasks[0].execute_after.to_rfc3339()
+    );
+    assert_eq!("ursula_le_guin@gmail.com", tasks[1].subscriber_email);
+    assert_eq!(
+        "2099-01-10T08:00:00+00:00",
+        tasks[1].execute_after.to_rfc3339()
+    );
+}
+
+#[sqlx::test]
+async fn issues_cannot_be_scheduled_both_ways_at_once(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let mut body = scheduled_newsletter_request_body();
+    body["send_at_local_time"] = json!("2099-01-10T09:00");
+
+    // Act.
+    let response = app.post_newsletters(&body).await;
+
+    // Assert.
+    assert_eq!(400, response.status().as_u16());
+}
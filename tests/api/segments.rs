@@ -0,0 +1,233 @@
+use serde_json::json;
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{newsletter_request_body, spawn_app, TestApp};
+
+async fn create_subscribers(app: &TestApp) {
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    app.create_confirmed_subscriber("octavia_butler@example.com")
+        .await;
+    app.create_unconfirmed_subscriber("n_k_jemisin@gmail.com")
+        .await;
+}
+
+async fn preview(app: &TestApp, filter: &str) -> i64 {
+    let response = app.post_segment_preview(&json!({ "filter": filter })).await;
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["count"].as_i64().unwrap()
+}
+
+#[sqlx::test]
+async fn the_preview_counts_the_confirmed_subscribers_matching_the_filter(
+    pool: PgPool,
+) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    create_subscribers(&app).await;
+
+    // Act.
+    let test_cases = [
+        (r#"email contains "GMAIL""#, 1),
+        (r#"not email contains "gmail""#, 1),
+        (r#"name = "le guin" or email = "nobody@example.com""#, 2),
+        ("confirmed_at > 1 hour ago", 2),
+        ("confirmed_at < 30 days ago", 0),
+    ];
+
+    // Assert.
+    for (filter, expected_count) in test_cases {
+        assert_eq!(
+            expected_count,
+            preview(&app, filter).await,
+            "The preview of `{filter}` was off."
+        );
+    }
+}
+
+#[sqlx::test]
+async fn subscribers_confirmed_long_ago_can_be_targeted(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    create_subscribers(&app).await;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET confirmed_at = now() - interval '45 days'
+        WHERE email = 'ursula_le_guin@gmail.com'
+        "#
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+
+    // Act.
+    let count = preview(&app, "confirmed_at < 30 days ago").await;
+
+    // Assert.
+    assert_eq!(1, count);
+}
+
+#[sqlx::test]
+async fn invalid_filters_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let save_response = app
+        .put_segment("gmail", &json!({ "filter": r#"emial contains "gmail""# }))
+        .await;
+    let preview_response = app
+        .post_segment_preview(&json!({ "filter": "confirmed_at < 30 days" }))
+        .await;
+
+    // Assert.
+    assert_eq!(400, save_response.status().as_u16());
+    assert_eq!(
+        "invalid filter: unknown field `emial` at character 1",
+        save_response.text().await.unwrap()
+    );
+    assert_eq!(400, preview_response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn issues_published_to_a_segment_only_reach_matching_subscribers(
+    pool: PgPool,
+) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    create_subscribers(&app).await;
+    app.put_segment("gmail", &json!({ "filter": r#"email contains "@gmail""# }))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    app.post_newsletters(&json!({
+        "title": "Newsletter title",
+        "segment": "gmail",
+        "content": {
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "Newsletter body as plain text",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    let email_request = app.email_server.received_requests().await.unwrap().pop();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.unwrap().body).unwrap();
+    let personalizations = body["personalizations"].as_array().unwrap();
+    assert_eq!(1, personalizations.len());
+    assert_eq!(
+        "ursula_le_guin@gmail.com",
+        personalizations[0]["to"][0]["email"]
+    );
+}
+
+#[sqlx::test]
+async fn publishing_to_an_unknown_segment_is_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "segment": "no-such-segment",
+            "content": { "html": "<p>Hi!</p>", "text": "Hi!" }
+        }))
+        .await;
+
+    // Assert.
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn filters_with_too_many_conditions_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    // Short enough to be accepted, but long enough to chain 200 conditions.
+    let filter = vec![r#"email = "a""#; 200].join(" and ");
+
+    // Act.
+    let response = app.post_segment_preview(&json!({ "filter": filter })).await;
+
+    // Assert.
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        "invalid filter: the filter has more than 100 conditions at character \
+        1601",
+        response.text().await.unwrap()
+    );
+}
+
+/// Publish an issue to every confirmed subscriber and send it, and return the
+/// URL of the pixel that tracks opens in the email sent to `email`.
+async fn send_issue(app: &TestApp, email: &str) -> String {
+    let (_, body) = app.publish_and_dispatch(&newsletter_request_body()).await;
+    let personalization = body["personalizations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|personalization| personalization["to"][0]["email"] == email)
+        .unwrap();
+    format!(
+        "{}/t/o/{}.gif",
+        app.address,
+        personalization["substitutions"]["[[open_token]]"]
+            .as_str()
+            .unwrap()
+    )
+}
+
+#[sqlx::test]
+async fn subscribers_who_opened_recent_issues_can_be_targeted(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    create_subscribers(&app).await;
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(202))
+        .mount(&app.email_server)
+        .await;
+    let open_url = send_issue(&app, "ursula_le_guin@gmail.com").await;
+    reqwest::Client::new()
+        .get(open_url)
+        .header(
+            "User-Agent",
+            "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 \
+            Firefox/112.0",
+        )
+        .send()
+        .await
+        .unwrap();
+
+    // Act.
+    let opened_the_issue = preview(&app, "opened(last: 1)").await;
+    let did_not_open_the_issue = preview(&app, "not opened(last: 1)").await;
+    send_issue(&app, "ursula_le_guin@gmail.com").await;
+    let opened_the_latest_issue = preview(&app, "opened(last: 1)").await;
+    let opened_one_of_two_issues = preview(&app, "opened(last: 2)").await;
+
+    // Assert.
+    assert_eq!(1, opened_the_issue);
+    assert_eq!(1, did_not_open_the_issue);
+    assert_eq!(0, opened_the_latest_issue);
+    assert_eq!(1, opened_one_of_two_issues);
+}
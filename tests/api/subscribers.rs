@@ -0,0 +1,398 @@
+use reqwest::Method;
+use sqlx::PgPool;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn import(app: &TestApp, csv: &str) {
+    let response = app.post_subscribers_import(csv.to_owned()).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+/// The ID of the subscriber with the given address.
+async fn subscriber_id(app: &TestApp, email: &str) -> String {
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .id
+        .to_string()
+}
+
+async fn emails(app: &TestApp, query: &str) -> Vec<String> {
+    let response = app.get_subscribers(query).await;
+    assert_eq!(200, response.status().as_u16());
+    let page: serde_json::Value = response.json().await.unwrap();
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|subscriber| subscriber["email"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[sqlx::test]
+async fn subscribers_are_listed_a_page_at_a_time(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    // Imported in the same transaction, so they're all created at once.
+    let mut csv = String::from("name,email\n");
+    for i in 0..5 {
+        csv.push_str(&format!("Subscriber {i},subscriber_{i}@example.com\n"));
+    }
+    import(&app, &csv).await;
+
+    // Act.
+    let mut pages = Vec::new();
+    let mut query = "limit=2".to_owned();
+    loop {
+        let response = app.get_subscribers(&query).await;
+        assert_eq!(200, response.status().as_u16());
+        let page: serde_json::Value = response.json().await.unwrap();
+        pages.push(page["subscribers"].as_array().unwrap().len());
+        match page["next_cursor"].as_str() {
+            Some(cursor) => query = format!("limit=2&after={cursor}"),
+            None => break,
+        }
+    }
+
+    // Assert.
+    assert_eq!(vec![2, 2, 1], pages);
+}
+
+#[sqlx::test]
+async fn pages_do_not_repeat_or_skip_subscribers(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    let mut csv = String::from("name,email\n");
+    for i in 0..5 {
+        csv.push_str(&format!("Subscriber {i},subscriber_{i}@example.com\n"));
+    }
+    import(&app, &csv).await;
+    let everyone = emails(&app, "").await;
+
+    // Act.
+    let response = app.get_subscribers("limit=3").await;
+    let page: serde_json::Value = response.json().await.unwrap();
+    let cursor = page["next_cursor"].as_str().unwrap();
+    let rest = emails(&app, &format!("after={cursor}")).await;
+
+    // Assert.
+    assert_eq!(5, everyone.len());
+    assert_eq!(everyone[3..], rest[..]);
+}
+
+#[sqlx::test]
+async fn subscribers_can_be_filtered_and_searched(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    import(
+        &app,
+        "name,email,status\n\
+        Ursula Le Guin,ursula_le_guin@gmail.com,confirmed\n\
+        Octavia Butler,octavia_butler@gmail.com,unsubscribed\n\
+        Ted Chiang,tedXchiang@gmail.com,confirmed\n",
+    )
+    .await;
+
+    // Act and assert.
+    assert_eq!(
+        vec!["octavia_butler@gmail.com"],
+        emails(&app, "status=unsubscribed").await
+    );
+    // Case doesn't matter, and `_` isn't a wildcard.
+    assert_eq!(
+        vec!["ursula_le_guin@gmail.com"],
+        emails(&app, "search=LE_GUIN").await
+    );
+    assert_eq!(
+        vec!["octavia_butler@gmail.com"],
+        emails(&app, "search=butler").await
+    );
+    assert_eq!(
+        vec!["ursula_le_guin@gmail.com"],
+        emails(&app, "status=confirmed&search=ursula").await
+    );
+    assert!(emails(&app, "created_before=2023-01-01T00:00:00Z")
+        .await
+        .is_empty());
+    assert_eq!(
+        3,
+        emails(&app, "created_after=2023-01-01T00:00:00Z")
+            .await
+            .len()
+    );
+}
+
+#[sqlx::test]
+async fn invalid_parameters_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+
+    for query in ["status=cleaned", "after=not-a-cursor"] {
+        // Act.
+        let response = app.get_subscribers(query).await;
+
+        // Assert.
+        assert_eq!(400, response.status().as_u16(), "{query}");
+    }
+}
+
+#[sqlx::test]
+async fn a_subscriber_can_be_fetched(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    import(
+        &app,
+        "name,email,tags\nUrsula Le Guin,ursula_le_guin@gmail.com,sci-fi\n",
+    )
+    .await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+
+    // Act.
+    let response = app.get_subscriber(&id).await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(id, subscriber["id"]);
+    assert_eq!("Ursula Le Guin", subscriber["name"]);
+    assert_eq!("confirmed", subscriber["status"]);
+    assert_eq!(serde_json::json!(["sci-fi"]), subscriber["tags"]);
+}
+
+#[sqlx::test]
+async fn unknown_subscribers_are_not_found(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    let id = uuid::Uuid::new_v4().to_string();
+
+    // Act.
+    let responses = [
+        app.get_subscriber(&id).await,
+        app.patch_subscriber(&id, &serde_json::json!({ "name": "Ursula" }))
+            .await,
+        app.delete_subscriber(&id).await,
+    ];
+
+    // Assert.
+    for response in responses {
+        assert_eq!(404, response.status().as_u16());
+    }
+}
+
+#[sqlx::test]
+async fn subscribers_are_updated(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    import(
+        &app,
+        "name,email,status\n\
+        Ursula,ursula_le_guin@gmail.com,pending_confirmation\n",
+    )
+    .await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+
+    // Act.
+    let response = app
+        .patch_subscriber(
+            &id,
+            &serde_json::json!({
+                "name": "Ursula K. Le Guin",
+                "status": "confirmed",
+                "tags": [" sci-fi ", "fantasy", "sci-fi"],
+            }),
+        )
+        .await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("Ursula K. Le Guin", subscriber["name"]);
+    assert_eq!("ursula_le_guin@gmail.com", subscriber["email"]);
+    assert_eq!("confirmed", subscriber["status"]);
+    assert!(subscriber["confirmed_at"].is_string());
+    assert_eq!(serde_json::json!(["sci-fi", "fantasy"]), subscriber["tags"]);
+    let saved: serde_json::Value =
+        app.get_subscriber(&id).await.json().await.unwrap();
+    assert_eq!(subscriber, saved);
+}
+
+#[sqlx::test]
+async fn invalid_updates_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    import(&app, "name,email\nUrsula,ursula_le_guin@gmail.com\n").await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    let test_cases = [
+        (
+            serde_json::json!({ "email": "not-an-email" }),
+            422,
+            "invalid email",
+        ),
+        (
+            serde_json::json!({ "name": "<Ursula>" }),
+            422,
+            "invalid name",
+        ),
+        (
+            serde_json::json!({ "tags": ["a".repeat(101)] }),
+            422,
+            "long tag",
+        ),
+        (
+            serde_json::json!({ "status": "cleaned" }),
+            400,
+            "unknown status",
+        ),
+    ];
+
+    for (body, status, description) in test_cases {
+        // Act.
+        let response = app.patch_subscriber(&id, &body).await;
+
+        // Assert.
+        assert_eq!(
+            status,
+            response.status().as_u16(),
+            "The API did not reject an update with an {description}."
+        );
+    }
+    let subscriber: serde_json::Value =
+        app.get_subscriber(&id).await.json().await.unwrap();
+    assert_eq!("Ursula", subscriber["name"]);
+}
+
+#[sqlx::test]
+async fn taking_the_address_of_another_subscriber_is_a_conflict(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    import(
+        &app,
+        "name,email\n\
+        Ursula,ursula_le_guin@gmail.com\n\
+        Octavia,octavia_butler@gmail.com\n",
+    )
+    .await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+
+    // Act.
+    let response = app
+        .patch_subscriber(
+            &id,
+            &serde_json::json!({ "email": "Octavia_Butler@gmail.com" }),
+        )
+        .await;
+
+    // Assert.
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn subscribers_are_deleted(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    // Unconfirmed subscribers have a confirmation token.
+    app.create_unconfirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    app.login().await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+
+    // Act.
+    let response = app.delete_subscriber(&id).await;
+
+    // Assert.
+    assert_eq!(204, response.status().as_u16());
+    assert_eq!(404, app.get_subscriber(&id).await.status().as_u16());
+    let n_tokens =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscription_tokens"#)
+            .fetch_one(&app.connection_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(0, n_tokens);
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_manage_subscribers(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let id = uuid::Uuid::new_v4().to_string();
+
+    // Act.
+    let responses = [
+        app.get_subscribers("").await,
+        app.get_subscriber(&id).await,
+        app.patch_subscriber(&id, &serde_json::json!({ "name": "Ursula" }))
+            .await,
+        app.delete_subscriber(&id).await,
+    ];
+
+    // Assert.
+    for response in responses {
+        assert_is_redirect_to(&response, "/login");
+    }
+}
+
+#[sqlx::test]
+async fn subscribers_can_be_managed_with_basic_auth(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+
+    // Act.
+    let list_response = app
+        .basic_auth_request(Method::GET, "/admin/subscribers")
+        .send()
+        .await
+        .unwrap();
+    let update_response = app
+        .basic_auth_request(Method::PATCH, &format!("/admin/subscribers/{id}"))
+        .json(&serde_json::json!({ "name": "Ursula K. Le Guin" }))
+        .send()
+        .await
+        .unwrap();
+    let delete_response = app
+        .basic_auth_request(Method::DELETE, &format!("/admin/subscribers/{id}"))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(200, list_response.status().as_u16());
+    let page: serde_json::Value = list_response.json().await.unwrap();
+    assert_eq!(id, page["subscribers"][0]["id"]);
+    assert_eq!(200, update_response.status().as_u16());
+    assert_eq!(204, delete_response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn wrong_basic_auth_credentials_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/subscribers", app.address))
+        .basic_auth(&app.test_user.username, Some("not the password"))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
@@ -0,0 +1,154 @@
+use reqwest::Method;
+use sqlx::PgPool;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn import(app: &TestApp, csv: String) {
+    let response = app.post_subscribers_import(csv).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn subscribers_are_exported_as_csv(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    import(
+        &app,
+        "name,email,status,tags\n\
+        \"Le Guin, Ursula\",ursula_le_guin@gmail.com,,\"sci-fi,fantasy\"\n\
+        Octavia Butler,octavia_butler@gmail.com,unsubscribed,\n"
+            .to_owned(),
+    )
+    .await;
+
+    // Act.
+    let response = app.get_subscribers_export("format=csv").await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "text/csv; charset=utf-8",
+        response.headers()["Content-Type"]
+    );
+    assert_eq!(
+        r#"attachment; filename="subscribers.csv""#,
+        response.headers()["Content-Disposition"]
+    );
+    let csv = response.text().await.unwrap();
+    let mut reader = csv::Reader::from_reader(csv.as_bytes());
+    let headers = reader.headers().unwrap().clone();
+    let rows: Vec<csv::StringRecord> =
+        reader.records().map(Result::unwrap).collect();
+    assert_eq!(2, rows.len());
+    let column = |row: &csv::StringRecord, name: &str| {
+        let index = headers.iter().position(|header| header == name).unwrap();
+        row[index].to_owned()
+    };
+    let ursula = rows
+        .iter()
+        .find(|row| column(row, "email") == "ursula_le_guin@gmail.com")
+        .unwrap();
+    assert_eq!("Le Guin, Ursula", column(ursula, "name"));
+    assert_eq!("confirmed", column(ursula, "status"));
+    assert_eq!("sci-fi,fantasy", column(ursula, "tags"));
+    assert!(!column(ursula, "confirmed_at").is_empty());
+}
+
+#[sqlx::test]
+async fn exports_can_be_filtered_by_status(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    import(
+        &app,
+        "name,email,status\n\
+        Ursula,ursula_le_guin@gmail.com,confirmed\n\
+        Octavia,octavia_butler@gmail.com,unsubscribed\n"
+            .to_owned(),
+    )
+    .await;
+
+    // Act.
+    let response = app
+        .get_subscribers_export("format=ndjson&status=unsubscribed")
+        .await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("application/x-ndjson", response.headers()["Content-Type"]);
+    let ndjson = response.text().await.unwrap();
+    let subscribers: Vec<serde_json::Value> = ndjson
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(1, subscribers.len());
+    assert_eq!("octavia_butler@gmail.com", subscribers[0]["email"]);
+    assert_eq!("unsubscribed", subscribers[0]["status"]);
+}
+
+#[sqlx::test]
+async fn large_exports_are_sent_in_full(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    let mut csv = String::from("name,email\n");
+    for i in 0..2500 {
+        csv.push_str(&format!("Subscriber {i},subscriber_{i}@example.com\n"));
+    }
+    import(&app, csv).await;
+
+    // Act.
+    let response = app.get_subscribers_export("format=ndjson").await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let ndjson = response.text().await.unwrap();
+    assert_eq!(2500, ndjson.lines().count());
+    assert!(ndjson.ends_with('\n'));
+}
+
+#[sqlx::test]
+async fn unknown_statuses_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+
+    // Act.
+    let response = app.get_subscribers_export("status=cleaned").await;
+
+    // Assert.
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_export_subscribers(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app.get_subscribers_export("format=csv").await;
+
+    // Assert.
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn subscribers_can_be_exported_with_basic_auth(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    // Act.
+    let response = app
+        .basic_auth_request(Method::GET, "/admin/subscribers/export?format=ndjson")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let ndjson = response.text().await.unwrap();
+    assert!(ndjson.contains("ursula_le_guin@gmail.com"));
+}
@@ -0,0 +1,178 @@
+use reqwest::Method;
+use sqlx::PgPool;
+use wiremock::{matchers::any, Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[sqlx::test]
+async fn subscribers_are_imported_from_csv(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    app.login().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Headers are matched regardless of case, like Mailchimp capitalizes them.
+    let csv = "Name,Email,Status,Tags\n\
+        Octavia Butler,octavia_butler@gmail.com,,\"\"\"sci-fi\"\",\"\"2023\"\"\"\n\
+        N. K. Jemisin,n_k_jemisin@gmail.com,pending_confirmation,\n\
+        Nobody,not-an-email,,\n\
+        <script>,ted_chiang@gmail.com,,\n\
+        Octavia again,Octavia_Butler@gmail.com,,\n\
+        Ursula K. Le Guin,ursula_le_guin@gmail.com,,\n\
+        Iain Banks,iain_banks@gmail.com,cleaned,\n";
+
+    // Act.
+    let response = app.post_subscribers_import(csv.to_owned()).await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(2, report["imported"]);
+    assert_eq!(1, report["skipped"]);
+    let error_lines: Vec<_> = report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["line"].as_u64().unwrap())
+        .collect();
+    assert_eq!(vec![4, 5, 6, 8], error_lines);
+    assert_eq!(
+        "the address is already on line 2",
+        report["errors"][2]["error"]
+    );
+
+    let imported = sqlx::query!(
+        r#"
+        SELECT email, name, status, confirmed_at IS NOT NULL AS "confirmed!", tags
+        FROM subscriptions
+        WHERE email <> 'ursula_le_guin@gmail.com'
+        ORDER BY email
+        "#
+    )
+    .fetch_all(&app.connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(2, imported.len());
+    assert_eq!("n_k_jemisin@gmail.com", imported[0].email);
+    assert_eq!("pending_confirmation", imported[0].status);
+    assert!(!imported[0].confirmed);
+    assert_eq!("Octavia Butler", imported[1].name);
+    assert_eq!("confirmed", imported[1].status);
+    assert!(imported[1].confirmed);
+    assert_eq!(vec!["sci-fi", "2023"], imported[1].tags);
+    // Mock verifies on drop that we haven't sent any confirmation emails.
+}
+
+#[sqlx::test]
+async fn large_files_are_imported_in_batches(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    let mut csv = String::from("name,email\n");
+    for i in 0..2500 {
+        csv.push_str(&format!("Subscriber {i},subscriber_{i}@example.com\n"));
+    }
+
+    // Act.
+    let response = app.post_subscribers_import(csv).await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(2500, report["imported"]);
+    let n_subscribers =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+            .fetch_one(&app.connection_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(2500, n_subscribers);
+}
+
+#[sqlx::test]
+async fn malformed_rows_do_not_stop_the_import(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    let mut csv = b"name,email\n".to_vec();
+    for i in 0..1500 {
+        csv.extend(format!("Subscriber {i},subscriber_{i}@example.com\n").bytes());
+    }
+    csv.extend(b"Not \xff UTF-8,octavia_butler@gmail.com\n");
+    csv.extend(b"Ursula K. Le Guin,ursula_le_guin@gmail.com\n");
+
+    // Act.
+    let response = app.post_subscribers_import(csv).await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(1501, report["imported"]);
+    assert_eq!(1502, report["errors"][0]["line"]);
+    assert!(report["errors"][0]["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("malformed row"));
+}
+
+#[sqlx::test]
+async fn files_without_the_required_columns_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+
+    // Act.
+    let response = app
+        .post_subscribers_import("email\nursula_le_guin@gmail.com\n".to_owned())
+        .await;
+
+    // Assert.
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        "the file doesn't have a `name` column",
+        response.text().await.unwrap()
+    );
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_import_subscribers(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app
+        .post_subscribers_import(
+            "name,email\nUrsula,ursula_le_guin@gmail.com\n".to_owned(),
+        )
+        .await;
+
+    // Assert.
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn subscribers_can_be_imported_with_basic_auth(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app
+        .basic_auth_request(Method::POST, "/admin/subscribers/import")
+        .header("Content-Type", "text/csv")
+        .body("name,email\nUrsula,ursula_le_guin@gmail.com\n")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(1, report["imported"]);
+}
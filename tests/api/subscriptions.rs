@@ -1,4 +1,8 @@
 use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
 
 use crate::helpers::spawn_app;
 
@@ -7,20 +11,149 @@ async fn subscribe_returns_200_for_valid_form_data(pool: PgPool) {
     // Arrange.
     let app = spawn_app(pool.clone()).await;
 
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
     // Act.
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
     let response = app.post_subscriptions(body.into()).await;
 
     // Assert.
     assert_eq!(200, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn subscribe_persists_the_new_subscriber_as_pending(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool.clone()).await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
 
-    let saved = sqlx::query!("SELECT email, name FROM subscriptions")
+    // Assert.
+    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
         .fetch_one(&pool)
         .await
         .expect("the entry should've been inserted");
 
     assert_eq!("ursula_le_guin@gmail.com", saved.email);
     assert_eq!("le guin", saved.name);
+    assert_eq!("pending_confirmation", saved.status);
+}
+
+#[sqlx::test]
+async fn subscribe_persists_the_time_zone_if_there_is_one(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool.clone()).await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let bodies = [
+        "name=le%20guin&email=ursula_le_guin%40gmail.com\
+        &time_zone=America%2FLos_Angeles",
+        "name=butler&email=octavia_butler%40gmail.com&time_zone=",
+    ];
+    for body in bodies {
+        let response = app.post_subscriptions(body.into()).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    // Assert.
+    let saved = sqlx::query!("SELECT time_zone FROM subscriptions ORDER BY email")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(None, saved[0].time_zone);
+    assert_eq!(Some("America/Los_Angeles"), saved[1].time_zone.as_deref());
+}
+
+#[sqlx::test]
+async fn subscribe_sends_a_confirmation_email_with_a_link(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+
+    // Assert.
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // The two links should be identical.
+    assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+}
+
+#[sqlx::test]
+async fn subscribing_twice_before_confirming_sends_another_email(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool.clone()).await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // Act.
+    let first_response = app.post_subscriptions(body.into()).await;
+    let second_response = app.post_subscriptions(body.into()).await;
+
+    // Assert.
+    assert_eq!(200, first_response.status().as_u16());
+    assert_eq!(200, second_response.status().as_u16());
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    let second_links = app.get_confirmation_links(&email_requests[1]);
+    assert_ne!(first_links.html, second_links.html);
+}
+
+#[sqlx::test]
+async fn subscribe_returns_500_if_the_confirmation_email_cannot_be_sent(
+    pool: PgPool,
+) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert.
+    assert_eq!(500, response.status().as_u16());
 }
 
 #[sqlx::test]
@@ -61,6 +194,10 @@ async fn subscribe_returns_422_when_fields_are_present_but_invalid(pool: PgPool)
             "name=le%20guin&email=definitely-not-an-email",
             "the email is invalid",
         ),
+        (
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&time_zone=Mars",
+            "the time zone is unknown",
+        ),
     ];
 
     for (invalid_body, error_message) in test_cases {
@@ -0,0 +1,95 @@
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::spawn_app;
+
+#[sqlx::test]
+async fn confirmations_without_token_are_rejected_with_400(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = reqwest::get(&format!("{}/subscriptions/confirm", app.address))
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn confirmations_with_an_unknown_token_are_rejected_with_401(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=not-a-real-token",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    // Assert.
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn the_link_returned_by_subscribe_returns_200_if_called(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // Act.
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn clicking_on_the_confirmation_link_confirms_a_subscriber(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool.clone()).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // Act.
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert.
+    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
+        .fetch_one(&pool)
+        .await
+        .expect("the entry should've been inserted");
+
+    assert_eq!("ursula_le_guin@gmail.com", saved.email);
+    assert_eq!("le guin", saved.name);
+    assert_eq!("confirmed", saved.status);
+}
@@ -0,0 +1,210 @@
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{newsletter_request_body, spawn_app, TestApp};
+
+/// Send an issue to the subscribers and return the headers of the
+/// personalization of the first one.
+async fn send_issue(app: &TestApp) -> serde_json::Value {
+    let _mock_guard = Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let (_, body) = app.publish_and_dispatch(&newsletter_request_body()).await;
+    body["personalizations"][0]["headers"].clone()
+}
+
+async fn subscription_status(app: &TestApp, email: &str) -> String {
+    sqlx::query!("SELECT status FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[sqlx::test]
+async fn newsletters_have_one_click_unsubscribe_headers(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    // Act.
+    let headers = send_issue(&app).await;
+
+    // Assert.
+    let list_unsubscribe = headers["List-Unsubscribe"].as_str().unwrap();
+    assert!(list_unsubscribe.starts_with(&format!(
+        "<{}/subscriptions/unsubscribe?token=",
+        app.address
+    )));
+    assert!(list_unsubscribe.ends_with('>'));
+    assert_eq!(
+        "List-Unsubscribe=One-Click",
+        headers["List-Unsubscribe-Post"]
+    );
+}
+
+#[sqlx::test]
+async fn one_click_unsubscribe_stops_future_newsletters(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    let headers = send_issue(&app).await;
+    let unsubscribe_url = headers["List-Unsubscribe"]
+        .as_str()
+        .unwrap()
+        .trim_matches(['<', '>']);
+
+    // Act.
+    // This is what mail clients send (see RFC 8058).
+    let response = reqwest::Client::new()
+        .post(unsubscribe_url)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "unsubscribed",
+        subscription_status(&app, "ursula_le_guin@gmail.com").await
+    );
+
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(&newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[sqlx::test]
+async fn the_unsubscribe_page_asks_for_confirmation(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    let headers = send_issue(&app).await;
+    let unsubscribe_url = headers["List-Unsubscribe"]
+        .as_str()
+        .unwrap()
+        .trim_matches(['<', '>']);
+
+    // Act.
+    let response = reqwest::get(unsubscribe_url).await.unwrap();
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"<form action="/subscriptions/unsubscribe?token="#));
+    assert!(html.contains(r#"method="post""#));
+    // Only submitting the form unsubscribes.
+    assert_eq!(
+        "confirmed",
+        subscription_status(&app, "ursula_le_guin@gmail.com").await
+    );
+}
+
+#[sqlx::test]
+async fn unsubscribing_twice_succeeds(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    let headers = send_issue(&app).await;
+    let unsubscribe_url = headers["List-Unsubscribe"]
+        .as_str()
+        .unwrap()
+        .trim_matches(['<', '>'])
+        .to_string();
+    let client = reqwest::Client::new();
+    client.post(&unsubscribe_url).send().await.unwrap();
+
+    // Act.
+    let response = client.post(&unsubscribe_url).send().await.unwrap();
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn subscribers_who_unsubscribe_before_delivery_are_skipped(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    let headers = send_issue(&app).await;
+    let unsubscribe_url = headers["List-Unsubscribe"]
+        .as_str()
+        .unwrap()
+        .trim_matches(['<', '>'])
+        .to_string();
+
+    Mock::given(path("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    app.post_newsletters(&newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    reqwest::Client::new()
+        .post(&unsubscribe_url)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    let remaining_tasks =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+            .fetch_one(&app.connection_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(0, remaining_tasks);
+}
+
+#[sqlx::test]
+async fn invalid_tokens_are_rejected_with_401(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let url = format!("{}/subscriptions/unsubscribe?token=forged", app.address);
+
+    // Act.
+    let get_response = reqwest::get(&url).await.unwrap();
+    let post_response = reqwest::Client::new().post(&url).send().await.unwrap();
+
+    // Assert.
+    assert_eq!(401, get_response.status().as_u16());
+    assert_eq!(401, post_response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn unsubscribing_without_a_token_is_rejected_with_400(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let url = format!("{}/subscriptions/unsubscribe", app.address);
+
+    // Act.
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+
+    // Assert.
+    assert_eq!(400, response.status().as_u16());
+}
@@ -0,0 +1,269 @@
+use reqwest::Method;
+use serde_json::json;
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{
+    assert_is_redirect_to, newsletter_request_body, spawn_app, TestApp,
+};
+
+async fn suppress(app: &TestApp, email: &str) {
+    let response = app
+        .put_suppression(email, &json!({ "reason": "asked by phone" }))
+        .await;
+    assert_eq!(204, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn suppressed_addresses_cannot_subscribe(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    suppress(&app, "Ursula_Le_Guin@gmail.com").await;
+    app.put_list("rust", &json!({ "name": "Rust Weekly" }))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+    let list_response = app.post_list_subscriptions("rust", body.into()).await;
+
+    // Assert.
+    // The response doesn't tell whether the address is suppressed.
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(200, list_response.status().as_u16());
+    let n_subscribers =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+            .fetch_one(&app.connection_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(0, n_subscribers);
+    // Mock verifies on drop that we haven't sent the confirmation emails.
+}
+
+#[sqlx::test]
+async fn admins_can_override_a_suppression_when_subscribing(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    suppress(&app, "ursula_le_guin@gmail.com").await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com\
+            &override_suppression=true",
+        )
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let suppressions: Vec<serde_json::Value> =
+        app.get_suppressions().await.json().await.unwrap();
+    assert!(suppressions.is_empty());
+}
+
+#[sqlx::test]
+async fn anonymous_users_cannot_override_a_suppression(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    suppress(&app, "ursula_le_guin@gmail.com").await;
+
+    // Act.
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com\
+        &override_suppression=true";
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let suppressions: Vec<serde_json::Value> =
+        app.get_suppressions().await.json().await.unwrap();
+    assert_eq!(1, suppressions.len());
+}
+
+#[sqlx::test]
+async fn admins_are_told_that_an_address_is_suppressed(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    suppress(&app, "ursula_le_guin@gmail.com").await;
+
+    // Act.
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn issues_are_not_sent_to_suppressed_addresses(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    app.login().await;
+    suppress(&app, "ursula_le_guin@gmail.com").await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(202))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    let response = app.post_newsletters(&newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    assert_eq!(202, response.status().as_u16());
+    // Mock verifies on drop that we haven't sent the newsletter email.
+}
+
+#[sqlx::test]
+async fn suppressions_can_be_listed_and_lifted(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    suppress(&app, "ursula_le_guin@gmail.com").await;
+
+    // Act.
+    let suppressions: serde_json::Value =
+        app.get_suppressions().await.json().await.unwrap();
+    let response = app.delete_suppression("Ursula_Le_Guin@gmail.com").await;
+    let second_response = app.delete_suppression("ursula_le_guin@gmail.com").await;
+
+    // Assert.
+    assert_eq!("ursula_le_guin@gmail.com", suppressions[0]["email"]);
+    assert_eq!("asked by phone", suppressions[0]["reason"]);
+    assert_eq!("admin", suppressions[0]["source"]);
+    assert_eq!(204, response.status().as_u16());
+    assert_eq!(404, second_response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn suppressions_can_be_imported_from_csv(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+    suppress(&app, "ursula_le_guin@gmail.com").await;
+    let csv = "email,reason\n\
+        octavia_butler@gmail.com,complained\n\
+        not-an-email,\n\
+        ursula_le_guin@gmail.com,\n\
+        Octavia_Butler@gmail.com,again\n\
+        n_k_jemisin@gmail.com\n";
+
+    // Act.
+    let response = app.post_suppressions_import(csv).await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(2, report["imported"]);
+    assert_eq!(2, report["skipped"]);
+    assert_eq!(3, report["errors"][0]["line"]);
+    assert_eq!(1, report["errors"].as_array().unwrap().len());
+
+    let suppressions: Vec<serde_json::Value> =
+        app.get_suppressions().await.json().await.unwrap();
+    let imported = |email: &str| {
+        suppressions
+            .iter()
+            .find(|suppression| suppression["email"] == email)
+            .unwrap()
+            .clone()
+    };
+    assert_eq!("complained", imported("octavia_butler@gmail.com")["reason"]);
+    assert_eq!("import", imported("octavia_butler@gmail.com")["source"]);
+    assert_eq!("manual", imported("n_k_jemisin@gmail.com")["reason"]);
+    assert_eq!("admin", imported("ursula_le_guin@gmail.com")["source"]);
+}
+
+#[sqlx::test]
+async fn imports_without_an_email_column_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.login().await;
+
+    // Act.
+    let response = app
+        .post_suppressions_import("address\nursula_le_guin@gmail.com\n")
+        .await;
+
+    // Assert.
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn you_must_be_logged_in_to_manage_suppressions(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app
+        .put_suppression("ursula_le_guin@gmail.com", &json!({ "reason": "spam" }))
+        .await;
+
+    // Assert.
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[sqlx::test]
+async fn suppressions_can_be_managed_with_basic_auth(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let save_response = app
+        .basic_auth_request(
+            Method::PUT,
+            "/admin/suppressions/ursula_le_guin@gmail.com",
+        )
+        .json(&json!({ "reason": "asked by phone" }))
+        .send()
+        .await
+        .unwrap();
+    let list_response = app
+        .basic_auth_request(Method::GET, "/admin/suppressions")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(204, save_response.status().as_u16());
+    assert_eq!(200, list_response.status().as_u16());
+    let suppressions: serde_json::Value = list_response.json().await.unwrap();
+    assert_eq!("ursula_le_guin@gmail.com", suppressions[0]["email"]);
+}
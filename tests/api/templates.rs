@@ -0,0 +1,138 @@
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::helpers::spawn_app;
+
+fn template_body() -> serde_json::Value {
+    json!({
+        "html": "<p>Hi, {{name}}!</p>",
+        "text": "Hi, {{name}}!",
+    })
+}
+
+#[sqlx::test]
+async fn saved_templates_can_be_fetched(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let save_response = app.put_template("weekly", &template_body()).await;
+    let get_response = app.get_template("weekly").await;
+
+    // Assert.
+    assert_eq!(204, save_response.status().as_u16());
+    assert_eq!(200, get_response.status().as_u16());
+    let template: serde_json::Value = get_response.json().await.unwrap();
+    assert_eq!("weekly", template["name"]);
+    assert_eq!("<p>Hi, {{name}}!</p>", template["html"]);
+    assert_eq!("Hi, {{name}}!", template["text"]);
+}
+
+#[sqlx::test]
+async fn saving_a_template_again_replaces_it(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.put_template("weekly", &template_body()).await;
+
+    // Act.
+    let response = app
+        .put_template("weekly", &json!({ "html": "<p>Bye!</p>", "text": "Bye!" }))
+        .await;
+
+    // Assert.
+    assert_eq!(204, response.status().as_u16());
+    let template: serde_json::Value =
+        app.get_template("weekly").await.json().await.unwrap();
+    assert_eq!("Bye!", template["text"]);
+}
+
+#[sqlx::test]
+async fn templates_that_do_not_render_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let test_cases = [
+        (
+            json!({ "html": "<p>{{nmae}}</p>", "text": "Hi!" }),
+            "unknown variable",
+        ),
+        (
+            json!({ "html": "<p>Hi!</p>", "text": "{{> missing}}" }),
+            "unknown partial",
+        ),
+        (
+            json!({ "html": "{{#if name}}<p>Hi!</p>", "text": "Hi!" }),
+            "unclosed block",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act.
+        let response = app.put_template("weekly", &body).await;
+
+        // Assert.
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject a template with an {description}."
+        );
+    }
+    assert_eq!(404, app.get_template("weekly").await.status().as_u16());
+}
+
+#[sqlx::test]
+async fn invalid_template_names_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app.put_template("weekly%20digest", &template_body()).await;
+
+    // Assert.
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn fetching_an_unknown_template_returns_404(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app.get_template("missing").await;
+
+    // Assert.
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn saving_a_template_requires_authentication(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = reqwest::Client::new()
+        .put(format!("{}/templates/weekly", app.address))
+        .json(&template_body())
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn templates_can_be_saved_without_plain_text(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app
+        .put_template("weekly", &json!({ "html": "<p>Hi, {{name}}!</p>" }))
+        .await;
+
+    // Assert.
+    assert_eq!(204, response.status().as_u16());
+    let template: serde_json::Value =
+        app.get_template("weekly").await.json().await.unwrap();
+    assert_eq!(serde_json::Value::Null, template["text"]);
+}
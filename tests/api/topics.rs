@@ -0,0 +1,64 @@
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::helpers::spawn_app;
+
+#[sqlx::test]
+async fn saved_topics_are_listed_by_name(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let rust_response = app.put_topic("rust", &json!({ "name": "Rust" })).await;
+    let events_response =
+        app.put_topic("events", &json!({ "name": "Events" })).await;
+
+    // Assert.
+    assert_eq!(204, rust_response.status().as_u16());
+    assert_eq!(204, events_response.status().as_u16());
+    let topics: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/topics", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        json!([
+            { "slug": "events", "name": "Events" },
+            { "slug": "rust", "name": "Rust" },
+        ]),
+        topics
+    );
+}
+
+#[sqlx::test]
+async fn invalid_topic_slugs_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app.put_topic("Rust!", &json!({ "name": "Rust" })).await;
+
+    // Assert.
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn saving_topics_requires_authentication(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = reqwest::Client::new()
+        .put(format!("{}/topics/rust", app.address))
+        .json(&json!({ "name": "Rust" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert.
+    assert_eq!(401, response.status().as_u16());
+}
@@ -0,0 +1,215 @@
+use serde_json::json;
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{newsletter_request_body_with, spawn_app, TestApp};
+
+const FIREFOX: &str =
+    "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/112.0";
+
+fn newsletter_request_body() -> serde_json::Value {
+    newsletter_request_body_with(json!({
+        "content": {
+            "html": r#"<p>Read <a href="https://example.com/post?a=1&amp;b=2">the post</a>.</p>"#,
+        },
+    }))
+}
+
+/// Publish the issue to a confirmed subscriber and return the body of the
+/// request made to SendGrid.
+async fn publish_and_send(
+    app: &TestApp,
+    body: &serde_json::Value,
+) -> serde_json::Value {
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let (_, body) = app.publish_and_dispatch(body).await;
+    body
+}
+
+async fn get(app: &TestApp, url: &str, user_agent: &str) -> reqwest::Response {
+    app.api_client
+        .get(url)
+        .header("User-Agent", user_agent)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[sqlx::test]
+async fn links_are_redirected_through_the_click_tracker(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let body = publish_and_send(&app, &newsletter_request_body()).await;
+    let html = body["content"][0]["value"].as_str().unwrap();
+    let click_token = body["personalizations"][0]["substitutions"]
+        ["[[click_token:0]]"]
+        .as_str()
+        .unwrap();
+
+    // Act.
+    let response =
+        get(&app, &format!("{}/t/c/{click_token}", app.address), FIREFOX).await;
+
+    // Assert.
+    assert!(html.contains(r#"/t/c/[[click_token:0]]">the post</a>"#));
+    assert!(!html.contains("https://example.com/post"));
+    assert_eq!(303, response.status().as_u16());
+    assert_eq!(
+        "https://example.com/post?a=1&b=2",
+        response.headers()["Location"]
+    );
+    let event =
+        sqlx::query!("SELECT kind, link_index, bot_reason FROM engagement_events")
+            .fetch_one(&app.connection_pool)
+            .await
+            .unwrap();
+    assert_eq!("click", event.kind);
+    assert_eq!(Some(0), event.link_index);
+    assert_eq!(None, event.bot_reason);
+}
+
+#[sqlx::test]
+async fn links_with_control_characters_are_redirected_to(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let body = publish_and_send(
+        &app,
+        &newsletter_request_body_with(json!({
+            "content": {
+                "html": r#"<a href="https://example.com/a&#10;b&#0;c">A</a>"#,
+            },
+        })),
+    )
+    .await;
+    let click_token = body["personalizations"][0]["substitutions"]
+        ["[[click_token:0]]"]
+        .as_str()
+        .unwrap();
+
+    // Act.
+    let response =
+        get(&app, &format!("{}/t/c/{click_token}", app.address), FIREFOX).await;
+
+    // Assert.
+    assert_eq!(303, response.status().as_u16());
+    assert_eq!(
+        "https://example.com/ab%EF%BF%BDc",
+        response.headers()["Location"]
+    );
+}
+
+#[sqlx::test]
+async fn the_pixel_records_opens(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let body = publish_and_send(&app, &newsletter_request_body()).await;
+    let html = body["content"][0]["value"].as_str().unwrap();
+    let open_token = body["personalizations"][0]["substitutions"]
+        ["[[open_token]]"]
+        .as_str()
+        .unwrap();
+
+    // Act.
+    let response = get(
+        &app,
+        &format!("{}/t/o/{open_token}.gif", app.address),
+        FIREFOX,
+    )
+    .await;
+
+    // Assert.
+    assert!(html.contains(r#"/t/o/[[open_token]].gif" width="1""#));
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("image/gif", response.headers()["Content-Type"]);
+    let event = sqlx::query!("SELECT kind, bot_reason FROM engagement_events")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!("open", event.kind);
+    assert_eq!(None, event.bot_reason);
+}
+
+#[sqlx::test]
+async fn automated_opens_are_flagged(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let body = publish_and_send(&app, &newsletter_request_body()).await;
+    let open_token = body["personalizations"][0]["substitutions"]
+        ["[[open_token]]"]
+        .as_str()
+        .unwrap();
+
+    // Act.
+    // Apple Mail Privacy Protection prefetches images with this user agent.
+    get(
+        &app,
+        &format!("{}/t/o/{open_token}.gif", app.address),
+        "Mozilla/5.0",
+    )
+    .await;
+
+    // Assert.
+    let event = sqlx::query!("SELECT bot_reason FROM engagement_events")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        Some("apple_mail_privacy_protection"),
+        event.bot_reason.as_deref()
+    );
+}
+
+#[sqlx::test]
+async fn tracking_can_be_turned_off_per_issue(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let mut request_body = newsletter_request_body();
+    request_body["tracking"] = json!(false);
+
+    // Act.
+    let body = publish_and_send(&app, &request_body).await;
+
+    // Assert.
+    let html = body["content"][0]["value"].as_str().unwrap();
+    assert!(html.contains(r#"<a href="https://example.com/post?a=1&amp;b=2">"#));
+    assert!(!html.contains("/t/o/"));
+    assert!(
+        body["personalizations"][0]["substitutions"]["[[open_token]]"].is_null()
+    );
+}
+
+#[sqlx::test]
+async fn forged_tokens_are_not_recorded(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let click_response =
+        get(&app, &format!("{}/t/c/forged", app.address), FIREFOX).await;
+    let open_response =
+        get(&app, &format!("{}/t/o/forged.gif", app.address), FIREFOX).await;
+
+    // Assert.
+    assert_eq!(404, click_response.status().as_u16());
+    // Mail clients get an image either way.
+    assert_eq!(200, open_response.status().as_u16());
+    let n_events =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM engagement_events"#)
+            .fetch_one(&app.connection_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(0, n_events);
+}
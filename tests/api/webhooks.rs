@@ -0,0 +1,319 @@
+use serde_json::json;
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{
+    mailgun_signature, newsletter_request_body, spawn_app, TestApp,
+};
+
+fn sendgrid_bounce(email: &str) -> serde_json::Value {
+    json!([{
+        "email": email,
+        "timestamp": 1683970000,
+        "event": "bounce",
+        "type": "bounce",
+        "sg_event_id": "sg-event-1",
+        "reason": "550 5.1.1 The email account does not exist",
+    }])
+}
+
+struct Suppression {
+    reason: String,
+    source: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+async fn suppression(app: &TestApp, email: &str) -> Option<Suppression> {
+    sqlx::query_as!(
+        Suppression,
+        "SELECT reason, source, created_at FROM suppressions WHERE email = $1",
+        email
+    )
+    .fetch_optional(&app.connection_pool)
+    .await
+    .unwrap()
+}
+
+#[sqlx::test]
+async fn hard_bounces_suppress_the_address(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(202))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act.
+    // Providers report addresses in whatever case they were sent to.
+    let response = app
+        .post_webhook("sendgrid", &sendgrid_bounce("Ursula_Le_Guin@gmail.com"))
+        .await;
+    let newsletter_response =
+        app.post_newsletters(&newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(202, newsletter_response.status().as_u16());
+    let suppression = suppression(&app, "ursula_le_guin@gmail.com").await.unwrap();
+    assert_eq!(
+        "2023-05-13T09:26:40+00:00",
+        suppression.created_at.to_rfc3339()
+    );
+    assert_eq!("hard_bounce", suppression.reason);
+    assert_eq!("sendgrid", suppression.source);
+    // Mock verifies on drop that we haven't sent the newsletter email.
+}
+
+#[sqlx::test]
+async fn spam_complaints_suppress_the_address(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    // Act.
+    let response = app
+        .post_webhook(
+            "postmark",
+            &json!({
+                "RecordType": "SpamComplaint",
+                "ID": 42,
+                "Type": "SpamComplaint",
+                "Email": "ursula_le_guin@gmail.com",
+                "BouncedAt": "2023-05-13T16:33:54Z",
+            }),
+        )
+        .await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let suppression = suppression(&app, "ursula_le_guin@gmail.com").await.unwrap();
+    assert_eq!("complaint", suppression.reason);
+    assert_eq!("postmark", suppression.source);
+}
+
+#[sqlx::test]
+async fn soft_bounces_are_only_recorded(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    // Act.
+    let response = app
+        .post_webhook(
+            "mailgun",
+            &json!({
+                "id": "mailgun-event-1",
+                "event": "failed",
+                "severity": "temporary",
+                "reason": "generic",
+                "recipient": "ursula_le_guin@gmail.com",
+                "timestamp": 1683970000.25,
+                "delivery-status": { "message": "452 Mailbox full" },
+            }),
+        )
+        .await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    assert!(suppression(&app, "ursula_le_guin@gmail.com")
+        .await
+        .is_none());
+    let event = sqlx::query!("SELECT provider, kind, reason FROM email_events")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!("mailgun", event.provider);
+    assert_eq!("soft_bounce", event.kind);
+    assert_eq!(Some("452 Mailbox full"), event.reason.as_deref());
+}
+
+#[sqlx::test]
+async fn retried_events_are_recorded_once(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let body = sendgrid_bounce("ursula_le_guin@gmail.com");
+
+    // Act.
+    app.post_webhook("sendgrid", &body).await;
+    let response = app.post_webhook("sendgrid", &body).await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let n_events =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_events"#)
+            .fetch_one(&app.connection_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(1, n_events);
+}
+
+#[sqlx::test]
+async fn deliveries_count_towards_the_last_issue_sent(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/v3/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(202))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(&newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Act.
+    let response = app
+        .post_webhook(
+            "mailgun",
+            &json!({
+                "id": "mailgun-event-1",
+                "event": "delivered",
+                "recipient": "ursula_le_guin@gmail.com",
+                "timestamp": 1683970000,
+            }),
+        )
+        .await;
+
+    // Assert.
+    assert_eq!(200, response.status().as_u16());
+    let kinds = sqlx::query!(
+        r#"
+        SELECT kind
+        FROM delivery_events
+        JOIN newsletter_issues USING (newsletter_issue_id)
+        ORDER BY id
+        "#
+    )
+    .fetch_all(&app.connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        vec!["sent", "delivered"],
+        kinds
+            .into_iter()
+            .map(|record| record.kind)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[sqlx::test]
+async fn requests_that_are_not_from_the_provider_are_rejected(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let body = sendgrid_bounce("ursula_le_guin@gmail.com");
+    let test_cases = [
+        (
+            app.api_client
+                .post(format!("{}/webhooks/sendgrid", app.address))
+                .header("X-Twilio-Email-Event-Webhook-Signature", "MEUCIQ==")
+                .header("X-Twilio-Email-Event-Webhook-Timestamp", "1683970000")
+                .json(&body),
+            "a forged SendGrid signature",
+        ),
+        (
+            app.api_client
+                .post(format!("{}/webhooks/postmark", app.address))
+                .basic_auth("postmark", Some("guessed"))
+                .json(&json!({ "RecordType": "Bounce" })),
+            "wrong Postmark credentials",
+        ),
+        (
+            app.api_client
+                .post(format!("{}/webhooks/mailgun", app.address))
+                .json(&json!({
+                    "signature": {
+                        "timestamp": "1683970000",
+                        "token": "token",
+                        "signature": "00",
+                    },
+                    "event-data": {},
+                })),
+            "a forged Mailgun signature",
+        ),
+        (
+            app.api_client
+                .post(format!("{}/webhooks/mailgun", app.address))
+                .json(&json!({
+                    "signature": mailgun_signature(1683970000, "token"),
+                    "event-data": {},
+                })),
+            "an old Mailgun signature",
+        ),
+    ];
+
+    for (request, description) in test_cases {
+        // Act.
+        let response = request.send().await.unwrap();
+
+        // Assert.
+        assert_eq!(
+            401,
+            response.status().as_u16(),
+            "The API did not fail with 401 Unauthorized when given {description}."
+        );
+    }
+}
+
+#[sqlx::test]
+async fn mailgun_signatures_are_only_accepted_once(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+    let signature =
+        mailgun_signature(chrono::Utc::now().timestamp(), "a8ce0edb2dd8301d");
+    let request = |event_data: serde_json::Value| {
+        app.api_client
+            .post(format!("{}/webhooks/mailgun", app.address))
+            .json(&json!({ "signature": signature, "event-data": event_data }))
+            .send()
+    };
+    let delivered = request(json!({
+        "id": "mailgun-event-1",
+        "event": "delivered",
+        "recipient": "ursula_le_guin@gmail.com",
+        "timestamp": 1683970000,
+    }))
+    .await
+    .unwrap();
+
+    // Act.
+    let forged_complaint = request(json!({
+        "id": "mailgun-event-2",
+        "event": "complained",
+        "recipient": "ursula_le_guin@gmail.com",
+        "timestamp": 1683970000,
+    }))
+    .await
+    .unwrap();
+
+    // Assert.
+    assert_eq!(200, delivered.status().as_u16());
+    assert_eq!(401, forged_complaint.status().as_u16());
+    assert!(suppression(&app, "ursula_le_guin@gmail.com")
+        .await
+        .is_none());
+}
+
+#[sqlx::test]
+async fn unknown_providers_are_not_found(pool: PgPool) {
+    // Arrange.
+    let app = spawn_app(pool).await;
+
+    // Act.
+    let response = app.post_webhook("mailchimp", &json!([])).await;
+
+    // Assert.
+    assert_eq!(404, response.status().as_u16());
+}